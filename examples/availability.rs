@@ -0,0 +1,66 @@
+//! Looks up existing snapshots of URLs without requesting new captures.
+//!
+//! Runs against the bundled mock Wayback Machine; drop the `with_endpoints` call to
+//! query archive.org for real.
+use chrono::{Duration, Utc};
+use std::sync::Arc;
+use wayback_archiver::mock::MockWayback;
+use wayback_archiver::{ArchiveError, AvailabilityCache, WaybackClient};
+
+#[tokio::main]
+async fn main() -> Result<(), Box<dyn std::error::Error>> {
+    let mock = MockWayback::start().await?;
+    mock.add_capture("https://example.com", Utc::now() - Duration::days(400));
+    mock.add_capture("https://example.com", Utc::now() - Duration::days(20));
+
+    // Repeated lookups of the same URL are answered from the cache for an hour.
+    let client = WaybackClient::new()
+        .with_endpoints(mock.endpoints())
+        .with_availability_cache(Arc::new(AvailabilityCache::new(Duration::hours(1))));
+
+    for url in ["https://example.com", "https://example.net"] {
+        match client.check_availability(url).await? {
+            Some(snapshot) => println!("{}: latest snapshot {}", url, snapshot.url),
+            None => println!("{}: never archived", url),
+        }
+    }
+
+    let stats = client.fetch_capture_stats("https://example.com").await?;
+    if let Some(first) = stats.first_archived {
+        println!(
+            "https://example.com: {} captures since {}",
+            stats.total_captures, first
+        );
+    }
+
+    // The capture closest to a moment in the past, e.g. to cite the page as it was then.
+    let then = client
+        .fetch_snapshot_closest_to("https://example.com", Utc::now() - Duration::days(365))
+        .await?;
+    println!("https://example.com a year ago: {}", then.url);
+
+    for record in client.list_snapshots("https://example.com").await? {
+        println!(
+            "{}: {:?} {} ({})",
+            record.timestamp,
+            record.status,
+            record.mimetype,
+            record.snapshot_url(&client.endpoints())
+        );
+    }
+
+    // Errors say whether they're worth retrying.
+    let err = ArchiveError::BandwidthExceeded {
+        retry_after: Some(std::time::Duration::from_secs(60)),
+    };
+    println!(
+        "{:?}: retryable: {}, back off for {:?}",
+        err,
+        err.is_retryable(),
+        err.suggested_backoff(
+            std::time::Duration::from_secs(15),
+            std::time::Duration::from_secs(30)
+        )
+    );
+    Ok(())
+}
@@ -0,0 +1,42 @@
+//! Archives a batch of URLs concurrently, reporting what happened to each.
+//!
+//! Runs against the bundled mock Wayback Machine; drop the `with_endpoints` call to
+//! archive for real.
+use chrono::{Duration, Utc};
+use wayback_archiver::mock::MockWayback;
+use wayback_archiver::{Outcome, WaybackClient};
+
+#[tokio::main]
+async fn main() -> std::io::Result<()> {
+    let mock = MockWayback::start().await?;
+    let client = WaybackClient::new().with_endpoints(mock.endpoints());
+    // A recent capture is reused rather than archived again.
+    mock.add_capture("https://example.com/recent", Utc::now() - Duration::days(3));
+
+    let urls = [
+        "https://example.com/",
+        "https://example.com/recent",
+        "https://example.org/about",
+    ];
+    let results = client
+        .archive_urls(urls.iter().map(|url| url.to_string()), 4)
+        .await;
+
+    for (url, result) in results {
+        match result.map(|result| result.outcome) {
+            Ok(Outcome::NewCapture { url: snapshot, .. }) => {
+                println!("{}: captured as {}", url, snapshot)
+            }
+            Ok(Outcome::ExistingSnapshot { url: snapshot, ts }) => {
+                println!("{}: already archived at {} ({})", url, ts, snapshot)
+            }
+            Ok(Outcome::StaleFallback { url: snapshot, .. }) => {
+                println!("{}: couldn't be captured; using {}", url, snapshot)
+            }
+            Ok(Outcome::Failed { error }) => println!("{}: failed: {}", url, error),
+            Err(err) if err.is_retryable() => println!("{}: try again later: {}", url, err),
+            Err(err) => println!("{}: failed: {}", url, err),
+        }
+    }
+    Ok(())
+}
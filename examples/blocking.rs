@@ -0,0 +1,27 @@
+//! Archives URLs from a program that doesn't use async, with the `blocking` feature.
+//!
+//! Runs against the bundled mock Wayback Machine; drop the mock to archive URLs for
+//! real.
+use chrono::{Duration, Utc};
+use wayback_archiver::blocking;
+use wayback_archiver::mock::MockWayback;
+
+fn main() -> Result<(), Box<dyn std::error::Error>> {
+    // The mock is async, so it gets a runtime of its own (the blocking API mustn't be
+    // called from within one).
+    let runtime = tokio::runtime::Runtime::new()?;
+    let mock = runtime.block_on(MockWayback::start())?;
+    mock.add_capture("https://example.com", Utc::now() - Duration::days(400));
+
+    let client = blocking::WaybackClient::from(
+        wayback_archiver::WaybackClient::new().with_endpoints(mock.endpoints()),
+    );
+    for url in ["https://example.com", "https://example.net"] {
+        let result = client.archive_url(url)?;
+        println!("{}: {:?}", url, result.outcome);
+    }
+
+    let snapshot = client.fetch_latest_snapshot("https://example.com")?;
+    println!("https://example.com: latest snapshot {}", snapshot.url);
+    Ok(())
+}
@@ -0,0 +1,94 @@
+//! Archives URLs in an archive of your own, by implementing `ArchiveProvider`. This
+//! one keeps its captures in memory (a real one would store them somewhere durable),
+//! and reuses the Wayback Machine's snapshots when they're recent enough, so that
+//! pages archived there already aren't captured again.
+//!
+//! Runs against the bundled mock Wayback Machine; drop the `with_endpoints` call to
+//! look up snapshots on archive.org for real.
+use chrono::{Duration, Utc};
+use futures::future::{BoxFuture, FutureExt};
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use wayback_archiver::mock::MockWayback;
+use wayback_archiver::{
+    ArchiveError, ArchiveOptions, ArchiveProvider, ArchivingResult, Outcome, Snapshot,
+    WaybackClient, WaybackMachine,
+};
+
+/// An in-house archive, with the time each URL was captured.
+#[derive(Default)]
+struct InHouse {
+    captures: Mutex<HashMap<String, chrono::DateTime<Utc>>>,
+}
+
+impl ArchiveProvider for InHouse {
+    fn provider_name(&self) -> &str {
+        "in-house"
+    }
+
+    fn lookup_latest<'a>(
+        &'a self,
+        client: &'a WaybackClient,
+        url: &'a str,
+    ) -> BoxFuture<'a, Result<Option<Snapshot>, ArchiveError>> {
+        async move {
+            let ours = self
+                .captures
+                .lock()
+                .unwrap()
+                .get(url)
+                .map(|&timestamp| Snapshot {
+                    url: format!("https://archive.internal/{}", url),
+                    timestamp,
+                    status: Some(200),
+                    available: true,
+                });
+            match ours {
+                Some(snapshot) => Ok(Some(snapshot)),
+                None => WaybackMachine.lookup_latest(client, url).await,
+            }
+        }
+        .boxed()
+    }
+
+    fn save<'a>(
+        &'a self,
+        _client: &'a WaybackClient,
+        url: &'a str,
+        _options: &'a ArchiveOptions,
+        _previous: Option<&'a Snapshot>,
+    ) -> BoxFuture<'a, Result<ArchivingResult, ArchiveError>> {
+        async move {
+            let now = Utc::now();
+            self.captures.lock().unwrap().insert(url.to_string(), now);
+            Ok(ArchivingResult::new(Outcome::NewCapture {
+                url: format!("https://archive.internal/{}", url),
+                ts: now.naive_utc(),
+            }))
+        }
+        .boxed()
+    }
+}
+
+#[tokio::main]
+async fn main() -> std::io::Result<()> {
+    let mock = MockWayback::start().await?;
+    mock.add_capture("https://example.com/", Utc::now() - Duration::days(3));
+
+    let client = WaybackClient::new()
+        .with_endpoints(mock.endpoints())
+        .with_provider(Arc::new(InHouse::default()));
+    // The first URL has a recent snapshot in the Wayback Machine, so it's reused. The
+    // second is captured in-house, and that capture is reused the second time round.
+    for url in [
+        "https://example.com/",
+        "https://example.com/new",
+        "https://example.com/new",
+    ] {
+        match client.archive_url(url).await {
+            Ok(result) => println!("{}: {:?}", url, result.outcome),
+            Err(err) => println!("{}: failed: {}", url, err),
+        }
+    }
+    Ok(())
+}
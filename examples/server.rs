@@ -0,0 +1,68 @@
+//! Embeds archiving in a long-running service: request handlers hand URLs to a
+//! single archiving worker, which shares one HTTP client and availability cache
+//! across all of them and replies with each result.
+//!
+//! Runs against the bundled mock Wayback Machine; drop the `with_endpoints` call to
+//! archive for real.
+use chrono::Duration;
+use std::sync::Arc;
+use tokio::sync::{mpsc, oneshot};
+use wayback_archiver::mock::MockWayback;
+use wayback_archiver::{ArchiveError, ArchivingResult, AvailabilityCache, WaybackClient};
+
+type Reply = oneshot::Sender<Result<ArchivingResult, ArchiveError>>;
+
+/// Archives URLs one at a time, so that the service never floods Save Page Now.
+async fn worker(client: WaybackClient, mut requests: mpsc::Receiver<(String, Reply)>) {
+    while let Some((url, reply)) = requests.recv().await {
+        let result = client.archive_url(&url).await;
+        // The requester may have given up waiting.
+        let _ = reply.send(result);
+    }
+}
+
+/// What a request handler would do: submit the URL and wait for its result.
+async fn handle(
+    requests: mpsc::Sender<(String, Reply)>,
+    url: &str,
+) -> Result<ArchivingResult, ArchiveError> {
+    let (reply, result) = oneshot::channel();
+    requests
+        .send((url.to_string(), reply))
+        .await
+        .map_err(|_| ArchiveError::Unknown("archiving worker stopped".to_string()))?;
+    result
+        .await
+        .map_err(|_| ArchiveError::Unknown("archiving worker stopped".to_string()))?
+}
+
+#[tokio::main]
+async fn main() -> std::io::Result<()> {
+    let mock = MockWayback::start().await?;
+    let cache = Arc::new(AvailabilityCache::new(Duration::hours(6)));
+
+    // Archiving can take a while, but shouldn't hang forever.
+    let http = reqwest::Client::builder()
+        .timeout(std::time::Duration::from_secs(180))
+        .build()
+        .expect("HTTP client configuration is valid");
+    let (requests, queue) = mpsc::channel(100);
+    let client = WaybackClient::from(http)
+        .with_endpoints(mock.endpoints())
+        .with_availability_cache(cache.clone());
+    tokio::spawn(worker(client, queue));
+
+    // Two handlers asking for the same page: the second reuses the first's snapshot.
+    let first = handle(requests.clone(), "https://example.com/post").await;
+    let second = handle(requests.clone(), "https://example.com/post").await;
+    for result in [first, second] {
+        match result {
+            Ok(result) => println!("{:?}", result.outcome),
+            Err(err) => println!("failed: {}", err),
+        }
+    }
+
+    // A real service would keep the cache in a file (`AvailabilityCache::with_file`),
+    // saving it periodically and on shutdown.
+    Ok(())
+}
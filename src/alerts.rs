@@ -0,0 +1,257 @@
+//! Alerts for the long-running modes (`serve` and `stream`): thresholds on the latency
+//! and failure rate of recent captures, which are reported (and posted to a webhook, if
+//! one is set) when they're crossed, and again once they recover. This gets a degraded
+//! Wayback Machine noticed before a night's archiving is wasted on it.
+//!
+//! Thresholds are measured over a sliding window (`--alert-window`, 10 minutes by
+//! default), once it holds at least `MIN_CAPTURES` captures, so that a single slow or
+//! failed capture doesn't raise an alarm.
+use crate::config::{format_duration, parse_duration};
+use clap::Clap;
+use serde::Serialize;
+use std::collections::VecDeque;
+use std::time::{Duration, Instant};
+
+/// The fewest captures in the window that thresholds are measured over.
+const MIN_CAPTURES: usize = 5;
+
+#[derive(Clap)]
+pub struct AlertOpts {
+    /// Alert when the median capture time over the alert window exceeds this
+    /// (e.g. "90s").
+    #[clap(long, parse(try_from_str = parse_duration))]
+    alert_latency: Option<Duration>,
+    /// Alert when more than this percentage of the captures over the alert
+    /// window fail (e.g. 20).
+    #[clap(long)]
+    alert_failure_rate: Option<f64>,
+    /// The window that alert thresholds are measured over (e.g. "30m").
+    /// Defaults to 10 minutes.
+    #[clap(long, parse(try_from_str = parse_duration))]
+    alert_window: Option<Duration>,
+    /// A URL to POST alerts to, as JSON with the alert's message in "text" (as
+    /// Slack's and similar incoming webhooks expect). Alerts are always
+    /// written to stderr.
+    #[clap(long)]
+    alert_webhook: Option<String>,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Serialize)]
+#[serde(rename_all = "kebab-case")]
+enum Condition {
+    Latency,
+    FailureRate,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Serialize)]
+#[serde(rename_all = "kebab-case")]
+enum Status {
+    Firing,
+    Resolved,
+}
+
+/// A threshold that was crossed, or that has recovered.
+#[derive(Debug, PartialEq, Serialize)]
+struct Alert {
+    text: String,
+    alert: Condition,
+    status: Status,
+    /// The median latency in seconds, or the failure rate in percent.
+    value: f64,
+    threshold: f64,
+}
+
+struct Capture {
+    at: Instant,
+    latency: Duration,
+    failed: bool,
+}
+
+/// Tracks recent captures against the alert thresholds.
+pub struct Monitor {
+    latency: Option<Duration>,
+    failure_rate: Option<f64>,
+    window: Duration,
+    webhook: Option<String>,
+    http: reqwest::Client,
+    captures: VecDeque<Capture>,
+    /// The conditions currently alerting.
+    firing: Vec<Condition>,
+}
+
+impl Monitor {
+    pub fn new(opts: &AlertOpts) -> Monitor {
+        if opts.alert_webhook.is_some()
+            && opts.alert_latency.is_none()
+            && opts.alert_failure_rate.is_none()
+        {
+            eprintln!(
+                "--alert-webhook is set without --alert-latency or --alert-failure-rate, \
+                 so no alerts will be sent"
+            );
+        }
+        Monitor {
+            latency: opts.alert_latency,
+            failure_rate: opts.alert_failure_rate,
+            window: opts.alert_window.unwrap_or(Duration::from_secs(10 * 60)),
+            webhook: opts.alert_webhook.clone(),
+            http: reqwest::Client::new(),
+            captures: VecDeque::new(),
+            firing: Vec::new(),
+        }
+    }
+
+    /// Records a capture that took `latency`, and reports any alerts it raises or
+    /// resolves.
+    pub async fn record(&mut self, latency: Duration, failed: bool) {
+        for alert in self.evaluate(Instant::now(), latency, failed) {
+            eprintln!("Alert: {}", alert.text);
+            if let Some(webhook) = &self.webhook {
+                let sent = self
+                    .http
+                    .post(webhook)
+                    .json(&alert)
+                    .timeout(Duration::from_secs(10))
+                    .send()
+                    .await
+                    .and_then(|resp| resp.error_for_status());
+                if let Err(err) = sent {
+                    eprintln!("Unable to send alert to {}: {}", webhook, err);
+                }
+            }
+        }
+    }
+
+    /// Records a capture made at `now`, returning the alerts whose conditions started
+    /// or stopped holding.
+    fn evaluate(&mut self, now: Instant, latency: Duration, failed: bool) -> Vec<Alert> {
+        self.captures.push_back(Capture {
+            at: now,
+            latency,
+            failed,
+        });
+        while self
+            .captures
+            .front()
+            .is_some_and(|capture| now.duration_since(capture.at) > self.window)
+        {
+            self.captures.pop_front();
+        }
+        if self.captures.len() < MIN_CAPTURES {
+            return Vec::new();
+        }
+        let window = format_duration(self.window);
+        let mut alerts = Vec::new();
+        if let Some(threshold) = self.latency {
+            let mut latencies: Vec<Duration> = self.captures.iter().map(|c| c.latency).collect();
+            latencies.sort();
+            let median = latencies[latencies.len() / 2];
+            let (value, threshold) = (median.as_secs_f64(), threshold.as_secs_f64());
+            let measured = format!(
+                "median capture time {:.0}s over the last {} (threshold {:.0}s)",
+                value, window, threshold
+            );
+            alerts.extend(self.transition(Condition::Latency, value, threshold, measured));
+        }
+        if let Some(threshold) = self.failure_rate {
+            let failed = self.captures.iter().filter(|c| c.failed).count();
+            let value = 100.0 * failed as f64 / self.captures.len() as f64;
+            let measured = format!(
+                "{} of {} captures ({:.0}%) failed over the last {} (threshold {}%)",
+                failed,
+                self.captures.len(),
+                value,
+                window,
+                threshold
+            );
+            alerts.extend(self.transition(Condition::FailureRate, value, threshold, measured));
+        }
+        alerts
+    }
+
+    /// The alert for `condition`, if its `value` has just crossed `threshold` (in
+    /// either direction).
+    fn transition(
+        &mut self,
+        condition: Condition,
+        value: f64,
+        threshold: f64,
+        measured: String,
+    ) -> Option<Alert> {
+        let exceeded = value > threshold;
+        let firing = self.firing.contains(&condition);
+        let (status, text) = match (exceeded, firing) {
+            (true, false) => {
+                self.firing.push(condition);
+                (
+                    Status::Firing,
+                    format!("Wayback Machine degraded: {}", measured),
+                )
+            }
+            (false, true) => {
+                self.firing.retain(|firing| *firing != condition);
+                (
+                    Status::Resolved,
+                    format!("Wayback Machine recovered: {}", measured),
+                )
+            }
+            _ => return None,
+        };
+        Some(Alert {
+            text,
+            alert: condition,
+            status,
+            value,
+            threshold,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn fires_and_resolves_alerts() {
+        let mut monitor = Monitor::new(&AlertOpts {
+            alert_latency: Some(Duration::from_secs(90)),
+            alert_failure_rate: Some(20.0),
+            alert_window: Some(Duration::from_secs(600)),
+            alert_webhook: None,
+        });
+        let start = Instant::now();
+        let secs = Duration::from_secs;
+        let mut record = |at: u64, latency: u64, failed: bool| {
+            monitor
+                .evaluate(start + secs(at), secs(latency), failed)
+                .into_iter()
+                .map(|alert| (alert.alert, alert.status))
+                .collect::<Vec<_>>()
+        };
+        // Nothing is measured until the window has enough captures.
+        for at in 0..4 {
+            assert_eq!(record(at, 120, true), []);
+        }
+        assert_eq!(
+            record(4, 120, false),
+            [
+                (Condition::Latency, Status::Firing),
+                (Condition::FailureRate, Status::Firing)
+            ]
+        );
+        // Still degraded, so there's nothing new to report.
+        assert_eq!(record(5, 30, false), []);
+        // The slow, failed captures age out of the window.
+        for at in 600..602 {
+            assert_eq!(record(at, 30, false), []);
+        }
+        assert_eq!(
+            record(602, 30, false),
+            [(Condition::Latency, Status::Resolved)]
+        );
+        assert_eq!(
+            record(603, 30, false),
+            [(Condition::FailureRate, Status::Resolved)]
+        );
+    }
+}
@@ -0,0 +1,270 @@
+//! archive.today (also reachable as archive.ph, archive.is, ...), which captures pages
+//! the Wayback Machine can't, e.g. sites that refuse its crawler.
+//!
+//! It has no capture API: a capture is submitted through the form on its home page
+//! (which carries a one-time `submitid`), after which archive.today redirects to a
+//! "working" page (`/wip/<id>`) until the capture is done, and then to the snapshot.
+//! Its existing snapshots are looked up through its Memento TimeGate.
+use crate::memento::parse_memento;
+use crate::{
+    ArchiveError, ArchiveOptions, ArchiveProvider, ArchivingResult, MementoArchive, Outcome,
+    Snapshot, WaybackClient,
+};
+use chrono::Utc;
+use futures::future::{BoxFuture, FutureExt};
+use reqwest::header::REFRESH;
+use std::time::Duration;
+
+/// The working page is polled at this interval until the capture is done, or
+/// `POLL_TIMEOUT` has passed.
+const POLL_INTERVAL: Duration = Duration::from_secs(5);
+const POLL_TIMEOUT: Duration = Duration::from_secs(300);
+
+/// [archive.today](https://archive.today), as an `ArchiveProvider`.
+///
+/// ```no_run
+/// # async fn example() -> Result<(), wayback_archiver::ArchiveError> {
+/// use std::sync::Arc;
+/// use wayback_archiver::{ArchiveToday, WaybackClient};
+///
+/// let client = WaybackClient::new().with_provider(Arc::new(ArchiveToday::default()));
+/// let result = client.archive_url("https://example.com/").await?;
+/// # Ok(())
+/// # }
+/// ```
+#[derive(Clone, Debug, PartialEq)]
+pub struct ArchiveToday {
+    /// The mirror that's used, without a trailing slash (e.g. `https://archive.ph`).
+    pub host: String,
+}
+
+impl Default for ArchiveToday {
+    fn default() -> Self {
+        ArchiveToday::new("https://archive.ph")
+    }
+}
+
+impl ArchiveToday {
+    /// archive.today at the mirror `host` (e.g. `https://archive.is`).
+    pub fn new(host: &str) -> Self {
+        ArchiveToday {
+            host: host.trim_end_matches('/').to_string(),
+        }
+    }
+
+    /// The archive's TimeGate, for looking up existing snapshots.
+    fn timegate(&self) -> MementoArchive {
+        MementoArchive {
+            timegate: format!("{}/timegate/", self.host),
+            save: None,
+        }
+    }
+}
+
+impl WaybackClient {
+    /// Captures `url` in `archive` (archive.today), waiting for the capture to
+    /// complete.
+    async fn save_to_archive_today(
+        &self,
+        archive: &ArchiveToday,
+        url: &str,
+        previous: Option<&Snapshot>,
+    ) -> Result<ArchivingResult, ArchiveError> {
+        // The submission form carries a one-time ID, without which it's rejected.
+        let resp = self
+            .send(self.http().get(format!("{}/", archive.host)))
+            .await?;
+        check_status(&resp)?;
+        let home = self
+            .read_text(resp)
+            .await?
+            .map_err(crate::client::network_error)?;
+        let submit_id = parse_submit_id(&home).ok_or_else(|| {
+            ArchiveError::ParseError("no submitid in archive.today's form".into())
+        })?;
+
+        // `anyway` captures the URL even if it was captured recently, as the client has
+        // already decided that its latest snapshot is too old.
+        let request = self
+            .http()
+            .post(format!("{}/submit/", archive.host))
+            .form(&[
+                ("submitid", submit_id.as_str()),
+                ("url", url),
+                ("anyway", "1"),
+            ]);
+        let mut resp = self.send(request).await?;
+        let mut waited = Duration::ZERO;
+        let snapshot_url = loop {
+            check_status(&resp)?;
+            let refresh = resp
+                .headers()
+                .get(REFRESH)
+                .and_then(|value| value.to_str().ok());
+            match classify_submission(&archive.host, resp.url().as_str(), refresh) {
+                Submission::Done(snapshot_url) if snapshot_url == resp.url().as_str() => {
+                    break snapshot_url
+                }
+                // Follow the refresh, to the snapshot's own response.
+                Submission::Done(snapshot_url) => {
+                    resp = self.send(self.http().get(&snapshot_url)).await?;
+                    continue;
+                }
+                Submission::Working(working_url) => {
+                    if waited >= POLL_TIMEOUT {
+                        return Err(ArchiveError::Unknown(format!(
+                            "archive.today capture still in progress after {}s ({})",
+                            POLL_TIMEOUT.as_secs(),
+                            working_url
+                        )));
+                    }
+                    self.sleep(POLL_INTERVAL).await?;
+                    waited += POLL_INTERVAL;
+                    resp = self.send(self.http().get(&working_url)).await?;
+                }
+                Submission::Rejected => return Err(ArchiveError::UnableToArchive),
+            }
+        };
+        if previous.is_some_and(|previous| previous.url == snapshot_url) {
+            return Err(ArchiveError::UnableToArchive);
+        }
+        let memento_datetime = resp
+            .headers()
+            .get("memento-datetime")
+            .and_then(|value| value.to_str().ok());
+        // A capture that's just been made is from now, if archive.today doesn't say.
+        let ts = match parse_memento(resp.status().as_u16(), &snapshot_url, memento_datetime) {
+            Ok(Some(snapshot)) => snapshot.timestamp,
+            _ => Utc::now(),
+        };
+        Ok(ArchivingResult::new(Outcome::NewCapture {
+            url: snapshot_url,
+            ts: ts.naive_utc(),
+        }))
+    }
+}
+
+impl ArchiveProvider for ArchiveToday {
+    fn provider_name(&self) -> &str {
+        "archive.today"
+    }
+
+    fn lookup_latest<'a>(
+        &'a self,
+        client: &'a WaybackClient,
+        url: &'a str,
+    ) -> BoxFuture<'a, Result<Option<Snapshot>, ArchiveError>> {
+        async move { client.latest_memento(&self.timegate(), url).await }.boxed()
+    }
+
+    fn save<'a>(
+        &'a self,
+        client: &'a WaybackClient,
+        url: &'a str,
+        _options: &'a ArchiveOptions,
+        previous: Option<&'a Snapshot>,
+    ) -> BoxFuture<'a, Result<ArchivingResult, ArchiveError>> {
+        client.save_to_archive_today(self, url, previous).boxed()
+    }
+}
+
+/// Fails with a retryable error if archive.today is rate limiting (which it does
+/// eagerly, with a CAPTCHA page) or down.
+fn check_status(resp: &reqwest::Response) -> Result<(), ArchiveError> {
+    let status = resp.status().as_u16();
+    if status == 429 || status >= 500 {
+        return Err(ArchiveError::ServiceUnavailable {
+            status,
+            snippet: String::new(),
+            retry_after: crate::retry_after(resp.headers()),
+        });
+    }
+    Ok(())
+}
+
+/// Where a submission to archive.today stands, from a response to it.
+#[derive(Debug, PartialEq)]
+enum Submission {
+    /// The capture is in progress, at this working page.
+    Working(String),
+    /// The capture is done, at this snapshot URL.
+    Done(String),
+    /// archive.today didn't accept the URL (e.g. it's blocked, or the form was).
+    Rejected,
+}
+
+/// Interprets a response from archive.today (on `host`) while capturing, which was
+/// finally served from `final_url` (after redirects) and may have a `Refresh` header.
+fn classify_submission(host: &str, final_url: &str, refresh: Option<&str>) -> Submission {
+    // Refresh headers look like `0;url=https://archive.ph/wip/AbCdE`.
+    let target = refresh
+        .and_then(|refresh| refresh.split_once("url="))
+        .map(|(_, target)| target.trim())
+        .unwrap_or(final_url);
+    let path = match target.strip_prefix(host) {
+        Some(path) => path,
+        None => return Submission::Rejected,
+    };
+    if path.starts_with("/wip/") {
+        return Submission::Working(target.to_string());
+    }
+    // Snapshots are at short IDs directly under the host.
+    let id = path.trim_start_matches('/');
+    if !id.is_empty() && id.chars().all(|c| c.is_ascii_alphanumeric()) && id != "submit" {
+        Submission::Done(target.to_string())
+    } else {
+        Submission::Rejected
+    }
+}
+
+/// The `submitid` in archive.today's home page form, if it has one.
+fn parse_submit_id(html: &str) -> Option<String> {
+    html.split("<input").skip(1).find_map(|tag| {
+        let tag = &tag[..tag.find('>')?];
+        if !tag.contains("name=\"submitid\"") {
+            return None;
+        }
+        let value = &tag[tag.find("value=\"")? + "value=\"".len()..];
+        Some(value[..value.find('"')?].to_string())
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_submissions() {
+        let form = r#"<form id="submiturl" action="https://archive.ph/submit/" method="GET">
+<input type="hidden" name="submitid" value="a1B2+c3/D4="/>
+<input id="url" type="text" name="url" value=""/></form>"#;
+        assert_eq!(parse_submit_id(form), Some("a1B2+c3/D4=".to_string()));
+        assert_eq!(parse_submit_id("<input name=\"url\" value=\"\"/>"), None);
+
+        let host = "https://archive.ph";
+        assert_eq!(
+            classify_submission(
+                host,
+                "https://archive.ph/submit/",
+                Some("0;url=https://archive.ph/wip/AbCdE")
+            ),
+            Submission::Working("https://archive.ph/wip/AbCdE".to_string())
+        );
+        assert_eq!(
+            classify_submission(host, "https://archive.ph/wip/AbCdE", None),
+            Submission::Working("https://archive.ph/wip/AbCdE".to_string())
+        );
+        assert_eq!(
+            classify_submission(host, "https://archive.ph/AbCdE", None),
+            Submission::Done("https://archive.ph/AbCdE".to_string())
+        );
+        assert_eq!(
+            classify_submission(host, "https://archive.ph/submit/", None),
+            Submission::Rejected
+        );
+        assert_eq!(
+            classify_submission(host, "https://example.com/", None),
+            Submission::Rejected
+        );
+    }
+}
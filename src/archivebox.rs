@@ -0,0 +1,273 @@
+//! [ArchiveBox](https://archivebox.io), a self-hosted web archive, through its REST
+//! API (`/api/v1`, in ArchiveBox 0.8 and later).
+//!
+//! URLs are added with `POST /api/v1/cli/add`, which archives them before it responds,
+//! and their snapshots are looked up with `GET /api/v1/core/snapshots`. Requests are
+//! authenticated with an API token (from the instance's admin, under API Tokens).
+use crate::client::network_error;
+use crate::normalize::normalize_url;
+use crate::{
+    body_snippet, ArchiveError, ArchiveOptions, ArchiveProvider, ArchivingResult, Outcome,
+    Snapshot, WaybackClient,
+};
+use chrono::{DateTime, TimeZone, Utc};
+use futures::future::{BoxFuture, FutureExt};
+use reqwest::header::AUTHORIZATION;
+use serde::Deserialize;
+
+/// An ArchiveBox instance, as an `ArchiveProvider`. Its snapshots' URLs are their
+/// pages in the instance's web UI, and results record where they are in its data
+/// directory (`ArchivingResult::archive_path`).
+///
+/// ```no_run
+/// # async fn example() -> Result<(), wayback_archiver::ArchiveError> {
+/// use std::sync::Arc;
+/// use wayback_archiver::{ArchiveBox, WaybackClient};
+///
+/// let archivebox = ArchiveBox::new("http://localhost:8000", "0123456789abcdef");
+/// let client = WaybackClient::new().with_provider(Arc::new(archivebox));
+/// let result = client.archive_url("https://example.com/").await?;
+/// println!("Archived in {}", result.archive_path.unwrap());
+/// # Ok(())
+/// # }
+/// ```
+#[derive(Clone, PartialEq)]
+pub struct ArchiveBox {
+    base_url: String,
+    token: String,
+}
+
+impl std::fmt::Debug for ArchiveBox {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("ArchiveBox")
+            .field("base_url", &self.base_url)
+            .field("token", &"[redacted]")
+            .finish()
+    }
+}
+
+impl ArchiveBox {
+    /// The ArchiveBox instance at `base_url` (e.g. `http://localhost:8000`),
+    /// authenticated with the API `token`.
+    pub fn new(base_url: &str, token: &str) -> Self {
+        ArchiveBox {
+            base_url: base_url.trim_end_matches('/').to_string(),
+            token: token.to_string(),
+        }
+    }
+
+    fn authorization(&self) -> String {
+        format!("Bearer {}", self.token)
+    }
+}
+
+/// A snapshot, as ArchiveBox's API describes it.
+#[derive(Deserialize)]
+struct ArchiveBoxSnapshot {
+    url: String,
+    /// When the URL was added, in seconds since the epoch (e.g. `1712345678.123`),
+    /// which also names the snapshot's directory.
+    timestamp: String,
+    archive_path: String,
+}
+
+#[derive(Deserialize)]
+struct ArchiveBoxSnapshots {
+    items: Vec<ArchiveBoxSnapshot>,
+}
+
+impl ArchiveBoxSnapshot {
+    fn to_snapshot(&self, archivebox: &ArchiveBox) -> Result<Snapshot, ArchiveError> {
+        Ok(Snapshot {
+            url: format!("{}/archive/{}/", archivebox.base_url, self.timestamp),
+            timestamp: parse_archivebox_timestamp(&self.timestamp)?,
+            status: None,
+            available: true,
+        })
+    }
+}
+
+impl WaybackClient {
+    /// The newest snapshot of `url` in `archivebox`, and where it is in the data
+    /// directory, if it has one.
+    async fn latest_archivebox_snapshot(
+        &self,
+        archivebox: &ArchiveBox,
+        url: &str,
+    ) -> Result<Option<(Snapshot, String)>, ArchiveError> {
+        let request = self
+            .http()
+            .get(format!("{}/api/v1/core/snapshots", archivebox.base_url))
+            .query(&[("url", url)])
+            .header(AUTHORIZATION, archivebox.authorization());
+        let resp = self.send(request).await?;
+        let status = resp.status().as_u16();
+        if status == 429 || status >= 500 {
+            return Err(self.service_unavailable(resp).await);
+        }
+        let body = self.read_text(resp).await?.map_err(network_error)?;
+        if status != 200 {
+            return Err(ArchiveError::Http {
+                status,
+                snippet: body_snippet(&body),
+            });
+        }
+        parse_archivebox_snapshots(archivebox, &body, url)
+    }
+
+    /// Adds `url` to `archivebox`, which archives it, and returns its snapshot.
+    async fn save_to_archivebox(
+        &self,
+        archivebox: &ArchiveBox,
+        url: &str,
+        previous: Option<&Snapshot>,
+    ) -> Result<ArchivingResult, ArchiveError> {
+        // `update` re-archives a URL that's already been added, whose snapshot is
+        // reused, rather than skipping it.
+        let request = self
+            .http()
+            .post(format!("{}/api/v1/cli/add", archivebox.base_url))
+            .header(AUTHORIZATION, archivebox.authorization())
+            .json(&serde_json::json!({
+                "urls": [url],
+                "depth": 0,
+                "update": previous.is_some(),
+            }));
+        let resp = self.send(request).await?;
+        let status = resp.status().as_u16();
+        if status == 429 || status >= 500 {
+            return Err(self.service_unavailable(resp).await);
+        }
+        let body = self.read_text(resp).await?.map_err(network_error)?;
+        if status != 200 {
+            return Err(ArchiveError::Http {
+                status,
+                snippet: body_snippet(&body),
+            });
+        }
+        let (snapshot, archive_path) = self
+            .latest_archivebox_snapshot(archivebox, url)
+            .await?
+            .ok_or(ArchiveError::UnableToArchive)?;
+        // A URL that's already been added keeps its snapshot (and timestamp), which
+        // was only updated.
+        let outcome = match previous {
+            Some(previous) if previous.url == snapshot.url => Outcome::ExistingSnapshot {
+                url: snapshot.url,
+                ts: snapshot.timestamp.naive_utc(),
+            },
+            _ => Outcome::NewCapture {
+                url: snapshot.url,
+                ts: snapshot.timestamp.naive_utc(),
+            },
+        };
+        Ok(ArchivingResult {
+            archive_path: Some(archive_path),
+            ..ArchivingResult::new(outcome)
+        })
+    }
+}
+
+impl ArchiveProvider for ArchiveBox {
+    fn provider_name(&self) -> &str {
+        "archivebox"
+    }
+
+    fn lookup_latest<'a>(
+        &'a self,
+        client: &'a WaybackClient,
+        url: &'a str,
+    ) -> BoxFuture<'a, Result<Option<Snapshot>, ArchiveError>> {
+        async move {
+            let latest = client.latest_archivebox_snapshot(self, url).await?;
+            Ok(latest.map(|(snapshot, _)| snapshot))
+        }
+        .boxed()
+    }
+
+    fn save<'a>(
+        &'a self,
+        client: &'a WaybackClient,
+        url: &'a str,
+        _options: &'a ArchiveOptions,
+        previous: Option<&'a Snapshot>,
+    ) -> BoxFuture<'a, Result<ArchivingResult, ArchiveError>> {
+        client.save_to_archivebox(self, url, previous).boxed()
+    }
+}
+
+/// The newest snapshot of `url` in a page of ArchiveBox's snapshots (which are
+/// searched by substring, so may include other URLs), and its archive path.
+fn parse_archivebox_snapshots(
+    archivebox: &ArchiveBox,
+    body: &str,
+    url: &str,
+) -> Result<Option<(Snapshot, String)>, ArchiveError> {
+    let snapshots: ArchiveBoxSnapshots =
+        serde_json::from_str(body).map_err(|err| ArchiveError::ParseError(err.to_string()))?;
+    let url = normalize_url(url);
+    let mut latest: Option<(Snapshot, String)> = None;
+    for item in snapshots.items {
+        if normalize_url(&item.url) != url {
+            continue;
+        }
+        let snapshot = item.to_snapshot(archivebox)?;
+        if latest
+            .as_ref()
+            .is_none_or(|(latest, _)| latest.timestamp < snapshot.timestamp)
+        {
+            latest = Some((snapshot, item.archive_path));
+        }
+    }
+    Ok(latest)
+}
+
+/// Parses an ArchiveBox timestamp, in (fractional) seconds since the epoch.
+fn parse_archivebox_timestamp(timestamp: &str) -> Result<DateTime<Utc>, ArchiveError> {
+    let invalid = || ArchiveError::ParseError(format!("invalid timestamp {:?}", timestamp));
+    let (secs, fraction) = timestamp.split_once('.').unwrap_or((timestamp, ""));
+    let secs: i64 = secs.parse().map_err(|_| invalid())?;
+    let millis: String = fraction
+        .chars()
+        .chain(std::iter::repeat('0'))
+        .take(3)
+        .collect();
+    let millis: u32 = millis.parse().map_err(|_| invalid())?;
+    Utc.timestamp_opt(secs, millis * 1_000_000)
+        .single()
+        .ok_or_else(invalid)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_archivebox_snapshots() {
+        let archivebox = ArchiveBox::new("http://localhost:8000/", "token");
+        let body = r#"{"count": 3, "items": [
+            {"url": "https://example.com/", "timestamp": "1600000000.5", "archive_path": "archive/1600000000.5"},
+            {"url": "https://example.com/", "timestamp": "1700000000", "archive_path": "archive/1700000000"},
+            {"url": "https://example.com/about", "timestamp": "1710000000.123", "archive_path": "archive/1710000000.123"}
+        ]}"#;
+        let (snapshot, archive_path) =
+            parse_archivebox_snapshots(&archivebox, body, "https://example.com/")
+                .unwrap()
+                .unwrap();
+        assert_eq!(snapshot.url, "http://localhost:8000/archive/1700000000/");
+        assert_eq!(
+            snapshot.timestamp,
+            Utc.timestamp_opt(1700000000, 0).unwrap()
+        );
+        assert_eq!(archive_path, "archive/1700000000");
+        assert_eq!(
+            parse_archivebox_snapshots(&archivebox, body, "https://example.org/"),
+            Ok(None)
+        );
+        assert_eq!(
+            parse_archivebox_timestamp("1710000000.123"),
+            Ok(Utc.timestamp_opt(1710000000, 123_000_000).unwrap())
+        );
+        assert!(parse_archivebox_timestamp("yesterday").is_err());
+    }
+}
@@ -0,0 +1,111 @@
+use crate::provenance::Provenance;
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+use std::fs::{File, OpenOptions};
+use std::io::{self, Write};
+
+/// An append-only log of every capture decision, written as NDJSON (one JSON object
+/// per line). Separate from the results file, which only keeps the latest result of
+/// each URL.
+pub struct AuditLog {
+    file: Option<File>,
+    run_id: String,
+    actor: String,
+    host: String,
+}
+
+#[derive(Clone, Copy, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum Action {
+    /// The URL was submitted to the Wayback Machine for capture.
+    Submit,
+    /// An existing snapshot of the URL was accepted instead of submitting it.
+    Reuse,
+    /// The URL was skipped without any request to the Wayback Machine.
+    Skip,
+    /// A local copy of the URL was downloaded, per the content type policies.
+    Download,
+    /// The URL's media was captured by the `--media-handler`.
+    Media,
+}
+
+#[derive(Serialize)]
+struct Entry<'a> {
+    timestamp: DateTime<Utc>,
+    run_id: &'a str,
+    action: Action,
+    url: &'a str,
+    outcome: &'a str,
+    /// The stable code for a failure or skip (see `wayback-archiver codes`).
+    #[serde(skip_serializing_if = "Option::is_none")]
+    code: Option<&'a str>,
+    actor: &'a str,
+    host: &'a str,
+}
+
+impl AuditLog {
+    /// Opens the audit log at `path` for appending. If `path` is `None`, nothing is logged.
+    pub fn open(path: Option<&str>, run: &Provenance) -> io::Result<AuditLog> {
+        let file = match path {
+            Some(path) => Some(OpenOptions::new().append(true).create(true).open(path)?),
+            None => None,
+        };
+        Ok(AuditLog {
+            file,
+            run_id: run.run_id.clone(),
+            actor: std::env::var("USER")
+                .or_else(|_| std::env::var("USERNAME"))
+                .unwrap_or_default(),
+            host: run.host.clone(),
+        })
+    }
+
+    pub fn record(&mut self, action: Action, url: &str, outcome: &str) -> io::Result<()> {
+        self.write(action, url, outcome, None)
+    }
+
+    /// Records a failure or skip, with its `code`.
+    pub fn record_code(
+        &mut self,
+        action: Action,
+        url: &str,
+        outcome: &str,
+        code: &str,
+    ) -> io::Result<()> {
+        self.write(action, url, outcome, Some(code))
+    }
+
+    fn write(
+        &mut self,
+        action: Action,
+        url: &str,
+        outcome: &str,
+        code: Option<&str>,
+    ) -> io::Result<()> {
+        let file = match &mut self.file {
+            Some(file) => file,
+            None => return Ok(()),
+        };
+        let mut line = serde_json::to_string(&Entry {
+            timestamp: Utc::now(),
+            run_id: &self.run_id,
+            action,
+            url,
+            outcome,
+            code,
+            actor: &self.actor,
+            host: &self.host,
+        })?;
+        line.push('\n');
+        // Each entry is written with a single call, so concurrent writers don't interleave.
+        file.write_all(line.as_bytes())
+    }
+
+    /// Flushes the entries recorded so far to disk.
+    pub fn sync(&self) -> io::Result<()> {
+        match &self.file {
+            Some(file) => file.sync_data(),
+            None => Ok(()),
+        }
+    }
+}
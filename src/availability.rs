@@ -0,0 +1,235 @@
+use crate::client::default_client;
+use crate::metrics::{timed, Endpoint};
+use crate::timestamp::{format_wayback_timestamp, parse_wayback_timestamp};
+use crate::{ArchiveError, WaybackClient};
+use chrono::{DateTime, Utc};
+use futures::stream::{self, StreamExt};
+use reqwest::header::CONTENT_TYPE;
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashMap};
+use std::convert::TryFrom;
+
+/// A snapshot of a URL in the Wayback Machine.
+#[derive(Clone, Debug, Deserialize, Serialize, PartialEq)]
+pub struct Snapshot {
+    /// The URL of the snapshot itself (i.e. `https://web.archive.org/web/...`).
+    pub url: String,
+    /// When the snapshot was captured.
+    pub timestamp: DateTime<Utc>,
+    /// The HTTP status code of the page when it was captured, if known.
+    pub status: Option<u16>,
+    /// Whether the Wayback Machine is currently able to serve the snapshot.
+    pub available: bool,
+}
+
+/// The snapshots returned by an availability lookup, keyed by how they relate to the
+/// lookup (e.g. `closest`, `first`, `last`).
+#[derive(Clone, Debug, Default, Deserialize, Serialize, PartialEq)]
+#[serde(transparent)]
+pub struct Snapshots(BTreeMap<String, Snapshot>);
+
+impl Snapshots {
+    pub fn get(&self, key: &str) -> Option<&Snapshot> {
+        self.0.get(key)
+    }
+
+    /// The snapshot closest to the requested time (or the newest, if no time was requested).
+    pub fn closest(&self) -> Option<&Snapshot> {
+        self.get("closest")
+    }
+
+    /// The first ever capture of the URL.
+    pub fn first(&self) -> Option<&Snapshot> {
+        self.get("first")
+    }
+
+    /// The most recent capture of the URL.
+    pub fn last(&self) -> Option<&Snapshot> {
+        self.get("last")
+    }
+
+    /// The newest of the returned snapshots, regardless of key.
+    pub fn newest(&self) -> Option<&Snapshot> {
+        self.0.values().max_by_key(|snapshot| snapshot.timestamp)
+    }
+
+    /// The oldest of the returned snapshots, regardless of key.
+    pub fn oldest(&self) -> Option<&Snapshot> {
+        self.0.values().min_by_key(|snapshot| snapshot.timestamp)
+    }
+
+    pub fn insert<K: Into<String>>(&mut self, key: K, snapshot: Snapshot) {
+        self.0.insert(key.into(), snapshot);
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (&str, &Snapshot)> {
+        self.0
+            .iter()
+            .map(|(key, snapshot)| (key.as_str(), snapshot))
+    }
+}
+
+/// Checks whether the Wayback Machine has a snapshot of `url`, without requesting a
+/// new capture. Returns the most recent snapshot, if there is one.
+///
+/// Lookups aren't cached; to cache them, use a client with an availability cache (see
+/// `WaybackClient::with_availability_cache`).
+pub async fn check_availability(url: &str) -> Result<Option<Snapshot>, ArchiveError> {
+    default_client().check_availability(url).await
+}
+
+/// Like `check_availability`, but returns every snapshot included in the response
+/// rather than only the newest one.
+pub async fn check_snapshots(url: &str) -> Result<Snapshots, ArchiveError> {
+    default_client().check_snapshots(url).await
+}
+
+/// Fetches the snapshot of `url` captured closest to `at`, whether before or after it.
+pub async fn fetch_snapshot_closest_to(
+    url: &str,
+    at: DateTime<Utc>,
+) -> Result<Snapshot, ArchiveError> {
+    default_client().fetch_snapshot_closest_to(url, at).await
+}
+
+impl WaybackClient {
+    /// Checks whether the Wayback Machine has a snapshot of `url`, without requesting
+    /// a new capture. Returns the most recent snapshot, if there is one.
+    ///
+    /// Lookups go through the client's availability cache, if it has one (see
+    /// `with_availability_cache`).
+    pub async fn check_availability(&self, url: &str) -> Result<Option<Snapshot>, ArchiveError> {
+        Ok(self.check_snapshots(url).await?.newest().cloned())
+    }
+
+    /// Like `check_availability`, but returns every snapshot included in the response
+    /// rather than only the newest one.
+    pub async fn check_snapshots(&self, url: &str) -> Result<Snapshots, ArchiveError> {
+        let cache = match self.availability_cache() {
+            Some(cache) => cache,
+            None => return self.query_availability(url).await,
+        };
+        let endpoints = self.endpoints();
+        let availability = &endpoints.availability;
+        if let Some(entry) = cache.get_from(availability, url) {
+            return Ok(entry.snapshots);
+        }
+        let snapshots = self.query_availability(url).await?;
+        cache.insert_from(availability, url, snapshots.clone());
+        Ok(snapshots)
+    }
+
+    /// Fetches the snapshot of `url` captured closest to `at`, whether before or after
+    /// it (e.g. to cite what a page looked like at the time). Unlike other lookups, this
+    /// bypasses the availability cache, which only holds the newest snapshots.
+    pub async fn fetch_snapshot_closest_to(
+        &self,
+        url: &str,
+        at: DateTime<Utc>,
+    ) -> Result<Snapshot, ArchiveError> {
+        self.request_availability(url, Some(at))
+            .await?
+            .closest()
+            .cloned()
+            .ok_or(ArchiveError::NoExistingSnapshot)
+    }
+
+    /// Looks up the latest snapshots of `urls` with up to `concurrency` requests in
+    /// flight, populating the availability cache so that later `archive_url` calls for
+    /// these URLs don't have to wait on the availability API one at a time. Does
+    /// nothing if the client has no availability cache.
+    pub async fn warm_availability_cache<I>(&self, urls: I, concurrency: usize)
+    where
+        I: IntoIterator<Item = String>,
+    {
+        if self.availability_cache().is_none() {
+            return;
+        }
+        stream::iter(urls)
+            .for_each_concurrent(concurrency, |url| async move {
+                // Failed lookups aren't cached, and will simply be retried by `archive_url`.
+                let _ = self.check_availability(&url).await;
+            })
+            .await;
+    }
+
+    pub(crate) async fn query_availability(&self, url: &str) -> Result<Snapshots, ArchiveError> {
+        self.request_availability(url, None).await
+    }
+
+    /// Looks up `url`'s snapshots, with the one closest to `at` if it's given (or the
+    /// newest one otherwise).
+    async fn request_availability(
+        &self,
+        url: &str,
+        at: Option<DateTime<Utc>>,
+    ) -> Result<Snapshots, ArchiveError> {
+        let mut endpoint = reqwest::Url::parse(&format!(
+            "{}/wayback/available",
+            self.endpoints().availability
+        ))
+        .map_err(|err| ArchiveError::Unknown(format!("invalid availability endpoint: {}", err)))?;
+        {
+            // Encoded, so that a URL with its own query is passed whole.
+            let mut query = endpoint.query_pairs_mut();
+            if let Some(at) = at {
+                query.append_pair("timestamp", &format_wayback_timestamp(at));
+            }
+            query.append_pair("url", url);
+        }
+        let request = self.http().get(endpoint);
+        let resp = timed(Endpoint::Availability, async {
+            let resp = self.send(request).await?;
+            // When archive.org is degraded, it serves HTML error pages where JSON is expected.
+            let is_json = resp
+                .headers()
+                .get(CONTENT_TYPE)
+                .and_then(|value| value.to_str().ok())
+                .is_some_and(|content_type| content_type.contains("json"));
+            if !resp.status().is_success() || !is_json {
+                return Err(self.service_unavailable(resp).await);
+            }
+            self.read(resp.json::<AvailabilityResponse>())
+                .await?
+                .map_err(|err| ArchiveError::ParseError(err.to_string()))
+        })
+        .await?;
+
+        resp.archived_snapshots
+            .unwrap_or_default()
+            .into_iter()
+            .map(|(key, snapshot)| Ok((key, Snapshot::try_from(snapshot)?)))
+            .collect::<Result<_, _>>()
+            .map(Snapshots)
+    }
+}
+
+#[derive(Deserialize, Debug)]
+struct AvailabilityResponse {
+    archived_snapshots: Option<HashMap<String, AvailabilitySnapshot>>,
+}
+
+#[derive(Deserialize, Debug)]
+struct AvailabilitySnapshot {
+    status: String,
+    available: bool,
+    url: String,
+    timestamp: String,
+}
+
+impl TryFrom<AvailabilitySnapshot> for Snapshot {
+    type Error = ArchiveError;
+
+    fn try_from(snapshot: AvailabilitySnapshot) -> Result<Self, Self::Error> {
+        Ok(Snapshot {
+            timestamp: parse_wayback_timestamp(&snapshot.timestamp)?,
+            status: snapshot.status.parse().ok(),
+            available: snapshot.available,
+            url: snapshot.url,
+        })
+    }
+}
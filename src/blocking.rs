@@ -0,0 +1,259 @@
+//! A blocking API, for programs that don't use async: the same functions as the crate
+//! root, which block the calling thread until they're done. Only available when built
+//! with the `blocking` feature.
+//!
+//! Like `reqwest::blocking`, it drives the async client on a runtime of its own, so it
+//! must not be called from within an async runtime (which panics). The free functions
+//! share the async free functions' client, and so their pacing.
+//!
+//! ```no_run
+//! let result = wayback_archiver::blocking::archive_url("example.com")?;
+//! println!("{:?}", result.outcome.url());
+//! # Ok::<(), wayback_archiver::ArchiveError>(())
+//! ```
+use crate::{
+    ArchiveError, ArchiveOptions, ArchivingResult, CdxRecord, MementoArchive, Snapshot, Snapshots,
+};
+use chrono::{DateTime, Utc};
+use std::collections::BTreeMap;
+use std::future::Future;
+use std::sync::{Arc, LazyLock};
+use tokio::runtime::Runtime;
+
+/// The async free functions' client, so that submissions made through either are
+/// paced together.
+static DEFAULT_CLIENT: LazyLock<WaybackClient> =
+    LazyLock::new(|| WaybackClient::from(crate::client::default_client().clone()));
+
+/// A blocking `crate::WaybackClient`. Configure the async client, and convert it
+/// with `From`:
+///
+/// ```no_run
+/// # use wayback_archiver::blocking;
+/// let client = blocking::WaybackClient::from(
+///     wayback_archiver::WaybackClient::new().with_credentials("access", "secret"),
+/// );
+/// let result = client.archive_url("example.com")?;
+/// # Ok::<(), wayback_archiver::ArchiveError>(())
+/// ```
+///
+/// Clones share the async client's state (see `crate::WaybackClient`) and the runtime,
+/// so a client can be shared between threads.
+#[derive(Clone)]
+pub struct WaybackClient {
+    client: crate::WaybackClient,
+    runtime: Arc<Runtime>,
+}
+
+impl Default for WaybackClient {
+    fn default() -> Self {
+        WaybackClient::from(crate::WaybackClient::new())
+    }
+}
+
+impl From<crate::WaybackClient> for WaybackClient {
+    fn from(client: crate::WaybackClient) -> Self {
+        let runtime = tokio::runtime::Builder::new_current_thread()
+            .enable_all()
+            .build()
+            .expect("unable to start the blocking client's runtime");
+        WaybackClient {
+            client,
+            runtime: Arc::new(runtime),
+        }
+    }
+}
+
+impl WaybackClient {
+    /// A client with a default `crate::WaybackClient`.
+    pub fn new() -> Self {
+        WaybackClient::default()
+    }
+
+    fn block_on<F: Future>(&self, future: F) -> F::Output {
+        self.runtime.block_on(future)
+    }
+
+    /// See `crate::WaybackClient::archive_url`.
+    pub fn archive_url(&self, url: &str) -> Result<ArchivingResult, ArchiveError> {
+        self.block_on(self.client.archive_url(url))
+    }
+
+    /// See `crate::WaybackClient::archive_url_with_options`.
+    pub fn archive_url_with_options(
+        &self,
+        url: &str,
+        options: &ArchiveOptions,
+    ) -> Result<ArchivingResult, ArchiveError> {
+        self.block_on(self.client.archive_url_with_options(url, options))
+    }
+
+    /// See `crate::WaybackClient::archive_urls`.
+    pub fn archive_urls<I>(
+        &self,
+        urls: I,
+        concurrency: usize,
+    ) -> BTreeMap<String, Result<ArchivingResult, ArchiveError>>
+    where
+        I: IntoIterator<Item = String>,
+    {
+        self.block_on(self.client.archive_urls(urls, concurrency))
+    }
+
+    /// See `crate::WaybackClient::archive_url_in`.
+    pub fn archive_url_in(
+        &self,
+        archive: &MementoArchive,
+        url: &str,
+        options: &ArchiveOptions,
+    ) -> Result<ArchivingResult, ArchiveError> {
+        self.block_on(self.client.archive_url_in(archive, url, options))
+    }
+
+    /// See `crate::WaybackClient::check_availability`.
+    pub fn check_availability(&self, url: &str) -> Result<Option<Snapshot>, ArchiveError> {
+        self.block_on(self.client.check_availability(url))
+    }
+
+    /// See `crate::WaybackClient::check_snapshots`.
+    pub fn check_snapshots(&self, url: &str) -> Result<Snapshots, ArchiveError> {
+        self.block_on(self.client.check_snapshots(url))
+    }
+
+    /// See `crate::WaybackClient::fetch_latest_snapshot`.
+    pub fn fetch_latest_snapshot(&self, url: &str) -> Result<Snapshot, ArchiveError> {
+        self.block_on(self.client.fetch_latest_snapshot(url))
+    }
+
+    /// See `crate::WaybackClient::fetch_snapshot_closest_to`.
+    pub fn fetch_snapshot_closest_to(
+        &self,
+        url: &str,
+        at: DateTime<Utc>,
+    ) -> Result<Snapshot, ArchiveError> {
+        self.block_on(self.client.fetch_snapshot_closest_to(url, at))
+    }
+
+    /// See `crate::WaybackClient::list_snapshots`.
+    pub fn list_snapshots(&self, url: &str) -> Result<Vec<CdxRecord>, ArchiveError> {
+        self.block_on(self.client.list_snapshots(url))
+    }
+
+    /// See `crate::WaybackClient::verify_snapshot`.
+    pub fn verify_snapshot(&self, snapshot_url: &str) -> Result<bool, ArchiveError> {
+        self.block_on(self.client.verify_snapshot(snapshot_url))
+    }
+}
+
+/// See `crate::archive_url`.
+pub fn archive_url(url: &str) -> Result<ArchivingResult, ArchiveError> {
+    DEFAULT_CLIENT.archive_url(url)
+}
+
+/// See `crate::archive_url_with_options`.
+pub fn archive_url_with_options(
+    url: &str,
+    options: &ArchiveOptions,
+) -> Result<ArchivingResult, ArchiveError> {
+    DEFAULT_CLIENT.archive_url_with_options(url, options)
+}
+
+/// See `crate::archive_urls`.
+pub fn archive_urls<I>(
+    urls: I,
+    concurrency: usize,
+) -> BTreeMap<String, Result<ArchivingResult, ArchiveError>>
+where
+    I: IntoIterator<Item = String>,
+{
+    DEFAULT_CLIENT.archive_urls(urls, concurrency)
+}
+
+/// See `crate::check_availability`.
+pub fn check_availability(url: &str) -> Result<Option<Snapshot>, ArchiveError> {
+    DEFAULT_CLIENT.check_availability(url)
+}
+
+/// See `crate::check_snapshots`.
+pub fn check_snapshots(url: &str) -> Result<Snapshots, ArchiveError> {
+    DEFAULT_CLIENT.check_snapshots(url)
+}
+
+/// See `crate::fetch_latest_snapshot`.
+pub fn fetch_latest_snapshot(url: &str) -> Result<Snapshot, ArchiveError> {
+    DEFAULT_CLIENT.fetch_latest_snapshot(url)
+}
+
+/// See `crate::fetch_snapshot_closest_to`.
+pub fn fetch_snapshot_closest_to(url: &str, at: DateTime<Utc>) -> Result<Snapshot, ArchiveError> {
+    DEFAULT_CLIENT.fetch_snapshot_closest_to(url, at)
+}
+
+/// See `crate::list_snapshots`.
+pub fn list_snapshots(url: &str) -> Result<Vec<CdxRecord>, ArchiveError> {
+    DEFAULT_CLIENT.list_snapshots(url)
+}
+
+/// See `crate::verify_snapshot`.
+pub fn verify_snapshot(snapshot_url: &str) -> Result<bool, ArchiveError> {
+    DEFAULT_CLIENT.verify_snapshot(snapshot_url)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mock::MockWayback;
+    use crate::Outcome;
+    use std::time::{Duration, Instant};
+
+    #[test]
+    fn archives_urls() {
+        // The mock is async, so it runs on a runtime of its own.
+        let runtime = Runtime::new().unwrap();
+        let mock = runtime.block_on(MockWayback::start()).unwrap();
+        let client = WaybackClient::from(
+            crate::WaybackClient::new()
+                .with_endpoints(mock.endpoints())
+                .with_save_interval(Duration::ZERO),
+        );
+        let result = client.archive_url("example.com/blocking").unwrap();
+        assert!(matches!(result.outcome, Outcome::NewCapture { .. }));
+        assert_eq!(
+            client.check_availability("example.com/blocking").unwrap(),
+            Some(
+                client
+                    .fetch_latest_snapshot("example.com/blocking")
+                    .unwrap()
+            )
+        );
+        assert_eq!(
+            client.list_snapshots("example.com/blocking").unwrap().len(),
+            1
+        );
+    }
+
+    #[test]
+    fn paces_submissions_with_the_async_functions() {
+        let runtime = Runtime::new().unwrap();
+        let mock = runtime.block_on(MockWayback::start()).unwrap();
+        // The free functions' clients, pointed at the mock.
+        let client = crate::client::default_client()
+            .clone()
+            .with_endpoints(mock.endpoints());
+        let blocking = WaybackClient {
+            client: DEFAULT_CLIENT
+                .client
+                .clone()
+                .with_endpoints(mock.endpoints()),
+            runtime: DEFAULT_CLIENT.runtime.clone(),
+        };
+        runtime
+            .block_on(client.archive_url("example.com/async"))
+            .unwrap();
+        let started = Instant::now();
+        blocking
+            .archive_url("example.com/blocking-after-async")
+            .unwrap();
+        assert!(started.elapsed() >= crate::DEFAULT_SAVE_INTERVAL - Duration::from_secs(1));
+    }
+}
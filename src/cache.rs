@@ -0,0 +1,145 @@
+use crate::endpoints::Endpoints;
+#[cfg(feature = "native")]
+use crate::locked_file::{read_locked, update_locked, SetAside};
+use crate::normalize::normalize_url;
+use crate::Snapshots;
+use chrono::{Duration, NaiveDateTime, Utc};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+#[cfg(feature = "native")]
+use std::io;
+#[cfg(feature = "native")]
+use std::path::PathBuf;
+use std::sync::Mutex;
+
+/// A cache of availability lookups, keyed by normalized URL (and by the availability
+/// endpoint they were made to, if it isn't archive.org's).
+///
+/// Entries older than the cache's TTL are ignored. Negative lookups ("no snapshot
+/// exists") are cached too, since they're just as expensive to repeat.
+pub struct AvailabilityCache {
+    ttl: Duration,
+    #[cfg(feature = "native")]
+    path: Option<PathBuf>,
+    #[cfg(feature = "native")]
+    set_aside: Option<SetAside>,
+    entries: Mutex<HashMap<String, CacheEntry>>,
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct CacheEntry {
+    pub fetched_at: NaiveDateTime,
+    pub snapshots: Snapshots,
+}
+
+impl AvailabilityCache {
+    /// Creates an in-memory cache.
+    pub fn new(ttl: Duration) -> Self {
+        AvailabilityCache {
+            ttl,
+            #[cfg(feature = "native")]
+            path: None,
+            #[cfg(feature = "native")]
+            set_aside: None,
+            entries: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Creates a cache backed by the file at `path`, loading any existing entries.
+    /// Entries are only written back to disk by `save`. The file may be shared with
+    /// other processes.
+    ///
+    /// A cache file that can't be parsed (e.g. one written by an older version) is
+    /// treated as empty, and moved aside to `<path>.corrupt` (see `set_aside`).
+    #[cfg(feature = "native")]
+    pub fn with_file<P: Into<PathBuf>>(path: P, ttl: Duration) -> io::Result<Self> {
+        let path = path.into();
+        let (entries, set_aside) = read_locked(&path)?;
+        Ok(AvailabilityCache {
+            ttl,
+            path: Some(path),
+            set_aside,
+            entries: Mutex::new(entries.unwrap_or_default()),
+        })
+    }
+
+    /// The cache file, if it couldn't be parsed when the cache was loaded.
+    #[cfg(feature = "native")]
+    pub fn set_aside(&self) -> Option<&SetAside> {
+        self.set_aside.as_ref()
+    }
+
+    /// Returns the cached entry for `url` (looked up at archive.org), if one exists
+    /// and hasn't expired.
+    pub fn get(&self, url: &str) -> Option<CacheEntry> {
+        self.get_from(&Endpoints::default().availability, url)
+    }
+
+    /// Caches `snapshots` as the result of looking up `url` at archive.org.
+    pub fn insert(&self, url: &str, snapshots: Snapshots) {
+        self.insert_from(&Endpoints::default().availability, url, snapshots)
+    }
+
+    /// Returns the cached entry for `url` looked up at the availability endpoint
+    /// `availability`, if one exists and hasn't expired.
+    pub(crate) fn get_from(&self, availability: &str, url: &str) -> Option<CacheEntry> {
+        let entries = self.entries.lock().unwrap();
+        entries
+            .get(&cache_key(availability, url))
+            .filter(|entry| Utc::now().naive_utc() - entry.fetched_at < self.ttl)
+            .cloned()
+    }
+
+    pub(crate) fn insert_from(&self, availability: &str, url: &str, snapshots: Snapshots) {
+        let entry = CacheEntry {
+            fetched_at: Utc::now().naive_utc(),
+            snapshots,
+        };
+        self.entries
+            .lock()
+            .unwrap()
+            .insert(cache_key(availability, url), entry);
+    }
+
+    /// Writes unexpired entries to the cache file, merging them with any that other
+    /// processes have saved since it was loaded. Does nothing for in-memory caches.
+    ///
+    /// Returns the cache file if it had to be set aside, as it couldn't be parsed.
+    #[cfg(feature = "native")]
+    pub fn save(&self) -> io::Result<Option<SetAside>> {
+        let path = match &self.path {
+            Some(path) => path,
+            None => return Ok(None),
+        };
+        let now = Utc::now().naive_utc();
+        let entries = self.entries.lock().unwrap();
+        update_locked(path, |saved: Option<HashMap<String, CacheEntry>>| {
+            let mut merged = saved.unwrap_or_default();
+            for (url, entry) in entries.iter() {
+                match merged.get(url) {
+                    Some(saved) if saved.fetched_at >= entry.fetched_at => {}
+                    _ => {
+                        merged.insert(url.clone(), entry.clone());
+                    }
+                }
+            }
+            merged.retain(|_, entry| now - entry.fetched_at < self.ttl);
+            merged
+        })
+    }
+}
+
+/// The key of `url`'s lookups at `availability`. archive.org's are keyed by URL
+/// alone, as they were before other endpoints could be cached.
+fn cache_key(availability: &str, url: &str) -> String {
+    match availability == Endpoints::default().availability {
+        true => normalize_url(url),
+        false => format!("{} {}", availability, normalize_url(url)),
+    }
+}
+
+impl Default for AvailabilityCache {
+    fn default() -> Self {
+        AvailabilityCache::new(Duration::hours(1))
+    }
+}
@@ -0,0 +1,589 @@
+use crate::client::default_client;
+use crate::metrics::{timed, Endpoint};
+use crate::normalize::normalize_url;
+use crate::snapshot_url::snapshot_url;
+use crate::timestamp::{format_wayback_timestamp, parse_wayback_timestamp};
+use crate::{ArchiveError, Endpoints, Snapshot, WaybackClient};
+use chrono::{DateTime, Utc};
+use futures::stream::{self, Stream, StreamExt, TryStreamExt};
+use std::collections::{BTreeMap, HashMap};
+
+/// Summary of a URL's capture history, from the Wayback CDX API.
+#[derive(Clone, Debug, PartialEq)]
+pub struct CaptureStats {
+    /// When the URL was first captured, if it has been at all.
+    pub first_archived: Option<DateTime<Utc>>,
+    /// How many captures of the URL the Wayback Machine has, up to 10,000.
+    pub total_captures: u64,
+}
+
+/// A single capture of a URL in the CDX index.
+#[derive(Clone, Debug, PartialEq)]
+pub struct CdxRecord {
+    pub timestamp: DateTime<Utc>,
+    /// The URL as it was captured.
+    pub original: String,
+    /// The HTTP status the page was captured with (`None` for captures that were
+    /// deduplicated against an earlier one, which the index records without a status).
+    pub status: Option<u16>,
+    pub mimetype: String,
+    /// The base32 SHA-1 digest of the captured content. Captures with the same digest
+    /// have identical content.
+    pub digest: String,
+}
+
+/// Narrows the captures listed by `list_site_captures` and `list_site_urls`.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct CdxFilter {
+    /// Only captures made with this HTTP status (e.g. 200).
+    pub status: Option<u16>,
+    /// Only captures of this MIME type (e.g. `text/html`).
+    pub mimetype: Option<String>,
+    /// Only captures made since this time.
+    pub since: Option<DateTime<Utc>>,
+}
+
+/// How many captures are requested from the CDX index at a time.
+const PAGE_SIZE: usize = 10_000;
+
+/// How many URLs on a host `fetch_latest_captures_in_bulk` looks up by listing the
+/// host's captures, rather than one by one. A listing may also take a request per this
+/// many URLs before the rest are looked up one by one.
+const MIN_BULK_URLS: usize = 5;
+
+/// How many captures `fetch_capture_stats` counts at most, so that URLs captured
+/// every few minutes for years don't take the index a long time to list.
+const MAX_COUNTED_CAPTURES: u64 = 10_000;
+
+impl CdxRecord {
+    /// The URL of this capture in the Wayback Machine at `endpoints` (usually the
+    /// listing client's `endpoints()`).
+    pub fn snapshot_url(&self, endpoints: &Endpoints) -> String {
+        snapshot_url(endpoints, &self.original, self.timestamp)
+    }
+}
+
+/// Lists every capture of `url` in the CDX index, oldest first.
+///
+/// Note that the CDX index may lag behind recent captures by a few minutes.
+pub async fn list_snapshots(url: &str) -> Result<Vec<CdxRecord>, ArchiveError> {
+    default_client().list_snapshots(url).await
+}
+
+/// Lists every capture (matching `filter`) of every URL on `domain` and its
+/// subdomains in the CDX index, e.g. to enumerate the pages of a site that's gone.
+pub async fn list_site_captures(
+    domain: &str,
+    filter: &CdxFilter,
+) -> Result<Vec<CdxRecord>, ArchiveError> {
+    default_client().list_site_captures(domain, filter).await
+}
+
+/// Lists every URL on `domain` and its subdomains with captures (matching `filter`) in
+/// the CDX index, once each, with its first such capture. URLs are listed a page at a
+/// time, as the index returns them.
+pub fn list_site_urls(
+    domain: &str,
+    filter: &CdxFilter,
+) -> impl Stream<Item = Result<Vec<CdxRecord>, ArchiveError>> {
+    default_client().list_site_urls(domain, filter)
+}
+
+/// Fetches the capture history summary of `url`. At most 10,000 captures are
+/// counted.
+///
+/// Note that the CDX index may lag behind recent captures by a few minutes.
+pub async fn fetch_capture_stats(url: &str) -> Result<CaptureStats, ArchiveError> {
+    default_client().fetch_capture_stats(url).await
+}
+
+/// Fetches the latest successful (HTTP 200) capture of `url` from the CDX index, if
+/// there is one.
+pub async fn fetch_latest_capture(url: &str) -> Result<Option<Snapshot>, ArchiveError> {
+    default_client().fetch_latest_capture(url).await
+}
+
+impl WaybackClient {
+    /// Lists every capture of `url` in the CDX index, oldest first.
+    ///
+    /// Note that the CDX index may lag behind recent captures by a few minutes.
+    pub async fn list_snapshots(&self, url: &str) -> Result<Vec<CdxRecord>, ArchiveError> {
+        self.capture_pages(vec![("url", url.to_string())])
+            .try_concat()
+            .await
+    }
+
+    /// Lists every capture (matching `filter`) of every URL on `domain` and its
+    /// subdomains in the CDX index, e.g. to enumerate the pages of a site that's gone.
+    pub async fn list_site_captures(
+        &self,
+        domain: &str,
+        filter: &CdxFilter,
+    ) -> Result<Vec<CdxRecord>, ArchiveError> {
+        self.capture_pages(site_query(domain, filter))
+            .try_concat()
+            .await
+    }
+
+    /// Lists every URL on `domain` and its subdomains with captures (matching
+    /// `filter`) in the CDX index, once each, with its first such capture. URLs are
+    /// listed a page at a time, as the index returns them, so sites with many URLs
+    /// needn't be held in memory at once.
+    pub fn list_site_urls(
+        &self,
+        domain: &str,
+        filter: &CdxFilter,
+    ) -> impl Stream<Item = Result<Vec<CdxRecord>, ArchiveError>> + '_ {
+        let mut query = site_query(domain, filter);
+        // The index collapses each URL's captures into the first, rather than listing
+        // them all.
+        query.push(("collapse", "urlkey".to_string()));
+        self.capture_pages(query)
+    }
+
+    /// Lists the captures the CDX index has for `query`, a page of `PAGE_SIZE` at a
+    /// time, so URLs with many captures (or sites with many URLs) take a request per
+    /// page.
+    fn capture_pages(
+        &self,
+        mut query: Vec<(&'static str, String)>,
+    ) -> impl Stream<Item = Result<Vec<CdxRecord>, ArchiveError>> + '_ {
+        query.push((
+            "fl",
+            "timestamp,original,statuscode,mimetype,digest".to_string(),
+        ));
+        self.cdx_pages(query)
+            .and_then(|(records, _)| async move { parse_cdx_records(&records) })
+    }
+
+    /// Lists the lines the CDX index has for `query` a page at a time, with whether
+    /// there are more pages after each.
+    fn cdx_pages(
+        &self,
+        query: Vec<(&'static str, String)>,
+    ) -> impl Stream<Item = Result<(String, bool), ArchiveError>> + '_ {
+        let url = self.cdx_url(&query).map(|mut url| {
+            url.query_pairs_mut()
+                .append_pair("limit", &PAGE_SIZE.to_string())
+                .append_pair("showResumeKey", "true");
+            url
+        });
+        // Pages are listed until there's no resume key to list the next one with.
+        stream::try_unfold(Some(None), move |resume_key: Option<Option<String>>| {
+            let url = url.clone();
+            async move {
+                let (mut url, resume_key) = match resume_key {
+                    Some(resume_key) => (url?, resume_key),
+                    None => return Ok(None),
+                };
+                if let Some(resume_key) = &resume_key {
+                    // Resume keys are given already encoded.
+                    let query = format!(
+                        "{}&resumeKey={}",
+                        url.query().unwrap_or_default(),
+                        resume_key
+                    );
+                    url.set_query(Some(&query));
+                }
+                let body = self.query_cdx(url).await?;
+                let (records, next) = split_resume_key(&body);
+                let page = (records.to_string(), next.is_some());
+                Ok(Some((page, next.map(Some))))
+            }
+        })
+    }
+
+    /// The URL of a CDX query with the parameters in `query`.
+    fn cdx_url(&self, query: &[(&str, String)]) -> Result<reqwest::Url, ArchiveError> {
+        reqwest::Url::parse_with_params(&format!("{}/cdx/search/cdx", self.endpoints().web), query)
+            .map_err(|err| ArchiveError::Unknown(format!("invalid CDX endpoint: {}", err)))
+    }
+
+    /// Makes a CDX query, returning the response body.
+    async fn query_cdx(&self, url: reqwest::Url) -> Result<String, ArchiveError> {
+        timed(Endpoint::Cdx, async {
+            let resp = self.send(self.http().get(url)).await?;
+            if !resp.status().is_success() {
+                return Err(self.service_unavailable(resp).await);
+            }
+            self.read(resp.text())
+                .await?
+                .map_err(|err| ArchiveError::ParseError(err.to_string()))
+        })
+        .await
+    }
+
+    /// Fetches the capture history summary of `url`. At most 10,000 captures are
+    /// counted.
+    ///
+    /// Note that the CDX index may lag behind recent captures by a few minutes.
+    pub async fn fetch_capture_stats(&self, url: &str) -> Result<CaptureStats, ArchiveError> {
+        // Only the timestamp field is requested, to keep the response as small as possible.
+        // Results are returned oldest first.
+        let query = [
+            ("url", url.to_string()),
+            ("fl", "timestamp".to_string()),
+            ("limit", MAX_COUNTED_CAPTURES.to_string()),
+        ];
+        let body = self.query_cdx(self.cdx_url(&query)?).await?;
+
+        let timestamps: Vec<&str> = body
+            .lines()
+            .map(str::trim)
+            .filter(|line| !line.is_empty())
+            .collect();
+        Ok(CaptureStats {
+            first_archived: timestamps
+                .first()
+                .map(|ts| parse_wayback_timestamp(ts))
+                .transpose()?,
+            total_captures: timestamps.len() as u64,
+        })
+    }
+
+    /// Looks up the latest captures of `urls` in the CDX index with up to
+    /// `concurrency` requests in flight. URLs that have never been captured, or whose
+    /// lookup failed, are left out.
+    pub async fn fetch_latest_captures<I>(
+        &self,
+        urls: I,
+        concurrency: usize,
+    ) -> Vec<(String, Snapshot)>
+    where
+        I: IntoIterator<Item = String>,
+    {
+        stream::iter(urls)
+            .map(|url| async move {
+                match self.fetch_latest_capture(&url).await {
+                    Ok(Some(snapshot)) => Some((url, snapshot)),
+                    _ => None,
+                }
+            })
+            .buffer_unordered(concurrency)
+            .filter_map(|capture| async move { capture })
+            .collect()
+            .await
+    }
+
+    /// Looks up the latest captures of `urls` in the CDX index, like
+    /// `fetch_latest_captures`, but in bulk: the URLs are grouped by host, and each
+    /// group's captures are listed once per URL (a page of up to 10,000 per request)
+    /// under the group's common prefix, and matched against it. Hosts with only a few
+    /// of the URLs, and URLs left unmatched when a listing would take more requests than
+    /// looking them up would, are looked up one by one. Up to `concurrency` requests
+    /// are in flight.
+    pub async fn fetch_latest_captures_in_bulk<I>(
+        &self,
+        urls: I,
+        concurrency: usize,
+    ) -> Vec<(String, Snapshot)>
+    where
+        I: IntoIterator<Item = String>,
+    {
+        let mut hosts: BTreeMap<String, Vec<String>> = BTreeMap::new();
+        for url in urls {
+            let key = capture_key(&url);
+            let host = key.split(['/', '?']).next().unwrap_or_default().to_string();
+            hosts.entry(host).or_default().push(url);
+        }
+        let (listed, unlisted): (Vec<_>, Vec<_>) = hosts
+            .into_values()
+            .partition(|urls| urls.len() >= MIN_BULK_URLS);
+        let listings: Vec<_> = stream::iter(listed)
+            .map(|urls| self.list_latest_captures(urls))
+            .buffer_unordered(concurrency)
+            .collect()
+            .await;
+        let mut captures = Vec::new();
+        let mut unmatched: Vec<String> = unlisted.into_iter().flatten().collect();
+        for (found, missed) in listings {
+            captures.extend(found);
+            unmatched.extend(missed);
+        }
+        captures.extend(self.fetch_latest_captures(unmatched, concurrency).await);
+        captures
+    }
+
+    /// Matches `urls` (all on one host) against a listing of the latest successful
+    /// capture of each URL under their common prefix. Returns the URLs' captures, and
+    /// the URLs that weren't matched but may have been captured, as the listing was
+    /// cut short (after a request per `MIN_BULK_URLS` URLs) or failed.
+    async fn list_latest_captures(
+        &self,
+        urls: Vec<String>,
+    ) -> (Vec<(String, Snapshot)>, Vec<String>) {
+        let max_pages = urls.len() / MIN_BULK_URLS;
+        let mut wanted: HashMap<String, Vec<String>> = HashMap::new();
+        for url in urls {
+            wanted.entry(capture_key(&url)).or_default().push(url);
+        }
+        let query = vec![
+            ("url", common_prefix(wanted.keys())),
+            ("matchType", "prefix".to_string()),
+            // Each URL's captures are collapsed into its first, with the timestamp of
+            // the last of them.
+            ("collapse", "urlkey".to_string()),
+            ("showSkipCount", "true".to_string()),
+            ("lastSkipTimestamp", "true".to_string()),
+            ("filter", "statuscode:200".to_string()),
+            ("fl", "timestamp,original".to_string()),
+        ];
+        let mut latest: HashMap<String, DateTime<Utc>> = HashMap::new();
+        let mut complete = false;
+        let pages = self.cdx_pages(query).take(max_pages);
+        futures::pin_mut!(pages);
+        while let Some(page) = pages.next().await {
+            let (records, more) = match page {
+                Ok(page) => page,
+                Err(_) => break,
+            };
+            let page = match parse_latest_captures(&records) {
+                Ok(page) => page,
+                Err(_) => break,
+            };
+            for (original, timestamp) in page {
+                let key = capture_key(&original);
+                if wanted.contains_key(&key) {
+                    let latest = latest.entry(key).or_insert(timestamp);
+                    *latest = (*latest).max(timestamp);
+                }
+            }
+            complete = !more;
+        }
+
+        let endpoints = self.endpoints();
+        let mut captures = Vec::new();
+        let mut missed = Vec::new();
+        for (key, urls) in wanted {
+            match latest.get(&key) {
+                Some(&timestamp) => captures.extend(urls.into_iter().map(|url| {
+                    let snapshot = Snapshot {
+                        url: snapshot_url(&endpoints, &url, timestamp),
+                        timestamp,
+                        status: Some(200),
+                        available: true,
+                    };
+                    (url, snapshot)
+                })),
+                // URLs missing from a complete listing have never been captured.
+                None if !complete => missed.extend(urls),
+                None => {}
+            }
+        }
+        (captures, missed)
+    }
+
+    /// Fetches the latest successful (HTTP 200) capture of `url` from the CDX index, if
+    /// there is one.
+    pub async fn fetch_latest_capture(&self, url: &str) -> Result<Option<Snapshot>, ArchiveError> {
+        // A negative limit returns the last captures, rather than the first.
+        let query = [
+            ("url", url.to_string()),
+            ("fl", "timestamp".to_string()),
+            ("filter", "statuscode:200".to_string()),
+            ("limit", "-1".to_string()),
+        ];
+        let body = self.query_cdx(self.cdx_url(&query)?).await?;
+
+        let timestamp = match body.lines().map(str::trim).find(|line| !line.is_empty()) {
+            Some(timestamp) => parse_wayback_timestamp(timestamp)?,
+            None => return Ok(None),
+        };
+        Ok(Some(Snapshot {
+            url: snapshot_url(&self.endpoints(), url, timestamp),
+            timestamp,
+            status: Some(200),
+            available: true,
+        }))
+    }
+}
+
+/// The CDX query for the captures on `domain` and its subdomains that match `filter`.
+fn site_query(domain: &str, filter: &CdxFilter) -> Vec<(&'static str, String)> {
+    let mut query = vec![
+        ("url", domain.to_string()),
+        ("matchType", "domain".to_string()),
+    ];
+    if let Some(status) = filter.status {
+        query.push(("filter", format!("statuscode:{}", status)));
+    }
+    if let Some(mimetype) = &filter.mimetype {
+        query.push(("filter", format!("mimetype:{}", mimetype)));
+    }
+    if let Some(since) = filter.since {
+        query.push(("from", format_wayback_timestamp(since)));
+    }
+    query
+}
+
+/// Splits a page of CDX lines requested with `showResumeKey`, which ends with a blank
+/// line and the key to resume from if there are more, into the lines and the key.
+fn split_resume_key(body: &str) -> (&str, Option<String>) {
+    let body = body.trim_end();
+    match body.rsplit_once("\n\n") {
+        Some((records, resume_key)) => (records, Some(resume_key.trim().to_string())),
+        None => (body, None),
+    }
+}
+
+/// What `fetch_latest_captures_in_bulk` matches URLs and the captures listed for them
+/// by: the normalized URL without its scheme or a leading `www.`, which the CDX index
+/// doesn't tell apart either.
+fn capture_key(url: &str) -> String {
+    let normalized = normalize_url(url);
+    let key = normalized
+        .split_once("://")
+        .map_or(normalized.as_str(), |(_, key)| key);
+    key.strip_prefix("www.").unwrap_or(key).to_string()
+}
+
+/// The longest prefix of `keys` (see `capture_key`) that ends a path segment, e.g.
+/// `example.com/blog/` for posts on a blog, or `example.com/` for a whole host.
+fn common_prefix<'a>(keys: impl IntoIterator<Item = &'a String>) -> String {
+    let mut keys = keys.into_iter();
+    let first = keys.next().map(String::as_str).unwrap_or_default();
+    let len = keys.fold(first.len(), |len, key| {
+        first
+            .bytes()
+            .zip(key.bytes())
+            .take(len)
+            .take_while(|(a, b)| a == b)
+            .count()
+    });
+    let prefix = &first[..len];
+    let prefix = prefix.split('?').next().unwrap_or_default();
+    match prefix.rfind('/') {
+        Some(end) => prefix[..=end].to_string(),
+        None => format!("{}/", prefix),
+    }
+}
+
+/// Parses CDX lines of `timestamp original skipcount lastskiptimestamp`, as listed
+/// with `showSkipCount` and `lastSkipTimestamp`, into the URL and the timestamp of its
+/// last capture (the skipped ones being later than the one listed).
+fn parse_latest_captures(body: &str) -> Result<Vec<(String, DateTime<Utc>)>, ArchiveError> {
+    body.lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty())
+        .map(|line| {
+            let fields: Vec<&str> = line.split(' ').collect();
+            match fields[..] {
+                [timestamp, original, ..] => {
+                    // No captures were skipped if there's no timestamp (`-`).
+                    let last_skipped = fields
+                        .get(3)
+                        .and_then(|last_skipped| parse_wayback_timestamp(last_skipped).ok());
+                    let timestamp = match last_skipped {
+                        Some(last_skipped) => last_skipped,
+                        None => parse_wayback_timestamp(timestamp)?,
+                    };
+                    Ok((original.to_string(), timestamp))
+                }
+                _ => Err(ArchiveError::ParseError(format!(
+                    "unexpected CDX line: {}",
+                    line
+                ))),
+            }
+        })
+        .collect()
+}
+
+/// Parses CDX lines of `timestamp original statuscode mimetype digest`.
+fn parse_cdx_records(body: &str) -> Result<Vec<CdxRecord>, ArchiveError> {
+    body.lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty())
+        .map(|line| match line.split(' ').collect::<Vec<_>>()[..] {
+            [timestamp, original, status, mimetype, digest] => Ok(CdxRecord {
+                timestamp: parse_wayback_timestamp(timestamp)?,
+                original: original.to_string(),
+                status: status.parse().ok(),
+                mimetype: mimetype.to_string(),
+                digest: digest.to_string(),
+            }),
+            _ => Err(ArchiveError::ParseError(format!(
+                "unexpected CDX line: {}",
+                line
+            ))),
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_cdx_records() {
+        let records = parse_cdx_records(
+            "20160102030405 http://example.com/ 200 text/html AAAABBBBCCCCDDDD\n\
+             20170102030405 http://example.com/ - warc/revisit AAAABBBBCCCCDDDD\n",
+        )
+        .unwrap();
+        assert_eq!(records.len(), 2);
+        assert_eq!(records[0].status, Some(200));
+        assert_eq!(records[0].mimetype, "text/html");
+        assert!(records[0]
+            .snapshot_url(&Endpoints::default())
+            .ends_with("/web/20160102030405/http://example.com/"));
+        assert_eq!(records[1].status, None);
+        assert_eq!(records[1].digest, "AAAABBBBCCCCDDDD");
+
+        assert!(parse_cdx_records("20160102030405").is_err());
+        assert_eq!(parse_cdx_records(""), Ok(Vec::new()));
+    }
+
+    #[test]
+    fn splits_resume_keys_from_cdx_pages() {
+        let (records, resume_key) = split_resume_key(
+            "20160102030405 http://example.com/a 200 text/html AAAA\n\n\
+             com%2Cexample%29%2Fb+20170102030405\n",
+        );
+        assert_eq!(
+            records,
+            "20160102030405 http://example.com/a 200 text/html AAAA"
+        );
+        assert_eq!(
+            resume_key.as_deref(),
+            Some("com%2Cexample%29%2Fb+20170102030405")
+        );
+
+        let (records, resume_key) =
+            split_resume_key("20160102030405 http://example.com/a 200 text/html AAAA\n");
+        assert_eq!(parse_cdx_records(records).unwrap().len(), 1);
+        assert_eq!(resume_key, None);
+        assert_eq!(split_resume_key(""), ("", None));
+    }
+
+    #[test]
+    fn lists_under_the_common_prefix_of_urls() {
+        let keys: Vec<String> = [
+            "https://www.example.com/blog/2020/a",
+            "example.com/blog/2021/b?page=2",
+            "http://example.com/blog/2021/c",
+        ]
+        .iter()
+        .map(|url| capture_key(url))
+        .collect();
+        assert_eq!(keys[0], "example.com/blog/2020/a");
+        assert_eq!(common_prefix(&keys), "example.com/blog/");
+        assert_eq!(common_prefix(&keys[1..]), "example.com/blog/2021/");
+        let keys = [capture_key("example.com"), capture_key("example.com?a")];
+        assert_eq!(common_prefix(&keys), "example.com/");
+    }
+
+    #[test]
+    fn parses_the_last_of_collapsed_captures() {
+        let captures = parse_latest_captures(
+            "20160102030405 http://example.com/a 3 20190102030405\n\
+             20170102030405 http://example.com/b 0 -\n",
+        )
+        .unwrap();
+        let timestamps: Vec<String> = captures
+            .iter()
+            .map(|(_, timestamp)| format_wayback_timestamp(*timestamp))
+            .collect();
+        assert_eq!(timestamps, ["20190102030405", "20170102030405"]);
+        assert!(parse_latest_captures("20160102030405").is_err());
+    }
+}
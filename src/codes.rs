@@ -0,0 +1,219 @@
+//! Stable codes for why URLs failed or were skipped: `ArchiveError` codes (see
+//! `wayback_archiver::ERROR_CODES`) and the CLI's own, listed by `wayback-archiver
+//! codes`. They're recorded alongside the human-readable messages, which may change
+//! between releases, in results (`error_code`, or a skip's `reason`), the audit log,
+//! `--metrics-file`, and run summaries, so that automation can match on them.
+use crate::run_result::RunResult;
+use clap::Clap;
+use serde::Serialize;
+use std::collections::BTreeMap;
+use std::fmt::Write;
+use wayback_archiver::{Outcome, ERROR_CODES};
+
+/// The failure of a pending snapshot that never became available (with
+/// `--verify-pending`).
+pub const PENDING_UNAVAILABLE: &str = "pending-unavailable";
+
+/// The failure of a snapshot that `reverify` found is no longer served, without
+/// another to replace it.
+pub const SNAPSHOT_GONE: &str = "snapshot-gone";
+
+/// Why a run skipped a URL.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum SkipReason {
+    Duplicate,
+    InternalHost,
+    OptedOut,
+    Rule,
+    TagFilter,
+    NeverReplace,
+    AlreadyArchived,
+    Deferred,
+    ArchivedElsewhere,
+    Unchanged,
+    ContentType,
+}
+
+impl SkipReason {
+    pub const ALL: &'static [SkipReason] = &[
+        SkipReason::Duplicate,
+        SkipReason::InternalHost,
+        SkipReason::OptedOut,
+        SkipReason::Rule,
+        SkipReason::TagFilter,
+        SkipReason::NeverReplace,
+        SkipReason::AlreadyArchived,
+        SkipReason::Deferred,
+        SkipReason::ArchivedElsewhere,
+        SkipReason::Unchanged,
+        SkipReason::ContentType,
+    ];
+
+    pub fn code(self) -> &'static str {
+        match self {
+            SkipReason::Duplicate => "duplicate",
+            SkipReason::InternalHost => "internal-host",
+            SkipReason::OptedOut => "opted-out",
+            SkipReason::Rule => "rule",
+            SkipReason::TagFilter => "tag-filter",
+            SkipReason::NeverReplace => "never-replace",
+            SkipReason::AlreadyArchived => "already-archived",
+            SkipReason::Deferred => "deferred",
+            SkipReason::ArchivedElsewhere => "archived-elsewhere",
+            SkipReason::Unchanged => "unchanged",
+            SkipReason::ContentType => "content-type",
+        }
+    }
+
+    fn description(self) -> &'static str {
+        match self {
+            SkipReason::Duplicate => "the URL appeared earlier in the input",
+            SkipReason::InternalHost => "the URL is on a local network",
+            SkipReason::OptedOut => "the URL's domain is in the --exclusions-file",
+            SkipReason::Rule => "a config rule (or script) skips the URL",
+            SkipReason::TagFilter => "the URL isn't tagged with --only-tag",
+            SkipReason::NeverReplace => "the URL is never replaced, per --overrides",
+            SkipReason::AlreadyArchived => "the URL's result is recent enough",
+            SkipReason::Deferred => "the URL's last failure deferred retrying it",
+            SkipReason::ArchivedElsewhere => {
+                "a results file from --also-consult has a recent result"
+            }
+            SkipReason::Unchanged => "the live page hasn't changed (--skip-unchanged)",
+            SkipReason::ContentType => "a content type policy skips the URL",
+        }
+    }
+}
+
+#[derive(Clap)]
+pub struct CodesOpts {}
+
+/// A code, as listed by `wayback-archiver codes`.
+#[derive(Serialize)]
+struct Code {
+    code: &'static str,
+    /// `error` or `skip`.
+    kind: &'static str,
+    description: &'static str,
+}
+
+/// Lists every code, as JSON lines.
+pub fn run(_opts: &CodesOpts) -> Result<(), Box<dyn std::error::Error>> {
+    let errors = ERROR_CODES
+        .iter()
+        .copied()
+        .chain([
+            (
+                PENDING_UNAVAILABLE,
+                "a pending snapshot never became available",
+            ),
+            (SNAPSHOT_GONE, "a snapshot is no longer served"),
+        ])
+        .map(|(code, description)| Code {
+            code,
+            kind: "error",
+            description,
+        });
+    let skips = SkipReason::ALL.iter().map(|reason| Code {
+        code: reason.code(),
+        kind: "skip",
+        description: reason.description(),
+    });
+    for code in errors.chain(skips) {
+        println!("{}", serde_json::to_string(&code)?);
+    }
+    Ok(())
+}
+
+/// The code for why `result` failed or was skipped, if it was. Failures recorded
+/// before codes were are `unknown`.
+pub fn result_code(result: &RunResult) -> Option<&str> {
+    match result {
+        RunResult::Archived(result) => match result.outcome {
+            Outcome::Failed { .. } => Some(result.error_code.as_deref().unwrap_or("unknown")),
+            _ => None,
+        },
+        RunResult::Skipped(skip) => Some(skip.reason.code()),
+    }
+}
+
+/// Counts `results` by outcome and code, in the Prometheus text exposition format, as
+/// the `wayback_archiver_results_total` counter.
+pub fn format_prometheus(results: &[RunResult]) -> String {
+    const NAME: &str = "wayback_archiver_results_total";
+
+    let mut counts: BTreeMap<(&str, &str), usize> = BTreeMap::new();
+    for result in results {
+        let outcome = match result {
+            RunResult::Archived(archived) => match archived.outcome {
+                Outcome::NewCapture { .. } => "new-capture",
+                Outcome::ExistingSnapshot { .. } => "existing-snapshot",
+                Outcome::StaleFallback { .. } => "stale-fallback",
+                Outcome::Failed { .. } => "failed",
+            },
+            RunResult::Skipped(_) => "skipped",
+        };
+        *counts
+            .entry((outcome, result_code(result).unwrap_or_default()))
+            .or_default() += 1;
+    }
+    let mut out = String::new();
+    writeln!(
+        out,
+        "# HELP {} URLs archived in the run, by outcome and code.",
+        NAME
+    )
+    .unwrap();
+    writeln!(out, "# TYPE {} counter", NAME).unwrap();
+    for ((outcome, code), count) in counts {
+        writeln!(
+            out,
+            "{}{{outcome=\"{}\",code=\"{}\"}} {}",
+            NAME, outcome, code, count
+        )
+        .unwrap();
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::run_result::Skip;
+    use wayback_archiver::ArchivingResult;
+
+    #[test]
+    fn counts_results_by_code() {
+        let results = [
+            ArchivingResult::new(Outcome::NewCapture {
+                url: String::new(),
+                ts: "2021-01-01T00:00:00".parse().unwrap(),
+            })
+            .into(),
+            ArchivingResult {
+                error_code: Some("timeout".to_string()),
+                ..ArchivingResult::new(Outcome::Failed {
+                    error: "Timed out waiting for the Wayback Machine".to_string(),
+                })
+            }
+            .into(),
+            Skip {
+                reason: SkipReason::Duplicate,
+                tags: Vec::new(),
+            }
+            .into(),
+            Skip {
+                reason: SkipReason::Duplicate,
+                tags: Vec::new(),
+            }
+            .into(),
+        ];
+        let formatted = format_prometheus(&results);
+        for expected in [
+            "wayback_archiver_results_total{outcome=\"new-capture\",code=\"\"} 1\n",
+            "wayback_archiver_results_total{outcome=\"failed\",code=\"timeout\"} 1\n",
+            "wayback_archiver_results_total{outcome=\"skipped\",code=\"duplicate\"} 2\n",
+        ] {
+            assert!(formatted.contains(expected), "{}", formatted);
+        }
+    }
+}
@@ -0,0 +1,216 @@
+//! `wayback-archiver compare`, which reports how archive coverage changed between two
+//! results files (e.g. from consecutive releases): the URLs that newly succeeded, newly
+//! failed, or regressed to stale fallbacks.
+use crate::display::display_url;
+use crate::output::ResultsFile;
+use crate::remote;
+use clap::Clap;
+use serde::Serialize;
+use std::collections::BTreeMap;
+use std::path::Path;
+use wayback_archiver::{ArchivingResult, Outcome};
+
+#[derive(Clap)]
+pub struct CompareOpts {
+    /// The earlier results file.
+    old: String,
+    /// The later results file.
+    new: String,
+    /// Writes the comparison as JSON, rather than a summary.
+    #[clap(long)]
+    json: bool,
+}
+
+/// How a URL fared in one run.
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Status {
+    /// Archived (including pending snapshots and existing ones).
+    Archived,
+    StaleFallback,
+    Failed,
+}
+
+impl Status {
+    fn of(result: &ArchivingResult) -> Status {
+        match result.outcome {
+            Outcome::NewCapture { .. } | Outcome::ExistingSnapshot { .. } => Status::Archived,
+            Outcome::StaleFallback { .. } => Status::StaleFallback,
+            Outcome::Failed { .. } => Status::Failed,
+        }
+    }
+}
+
+#[derive(Debug, Default, PartialEq, Serialize)]
+pub struct Comparison {
+    /// URLs archived in the new run that had failed or fallen back to stale
+    /// snapshots in the old one.
+    pub newly_succeeded: Vec<String>,
+    /// URLs that failed in the new run, but not the old one, with their errors.
+    pub newly_failed: BTreeMap<String, String>,
+    /// URLs archived in the old run that fell back to stale snapshots in the new one.
+    pub regressed_to_stale: Vec<String>,
+    /// URLs only in the new results.
+    pub added: Vec<String>,
+    /// URLs only in the old results.
+    pub removed: Vec<String>,
+    /// The number of URLs archived in each run.
+    pub archived_before: usize,
+    pub archived_after: usize,
+}
+
+pub async fn run(opts: &CompareOpts) -> Result<(), Box<dyn std::error::Error>> {
+    for path in [&opts.old, &opts.new] {
+        // Missing results files would otherwise read as empty.
+        if !remote::is_remote(path) && !Path::new(path).exists() {
+            return Err(format!("{} doesn't exist", path).into());
+        }
+    }
+    let old = ResultsFile::read(&opts.old, false).await?;
+    let new = ResultsFile::read(&opts.new, false).await?;
+    let comparison = compare(&old.results, &new.results);
+    if opts.json {
+        println!("{}", serde_json::to_string_pretty(&comparison)?);
+        return Ok(());
+    }
+    println!(
+        "Archived: {} -> {} URLs ({:+})",
+        comparison.archived_before,
+        comparison.archived_after,
+        comparison.archived_after as i64 - comparison.archived_before as i64
+    );
+    print_urls("Newly succeeded", &comparison.newly_succeeded);
+    if !comparison.newly_failed.is_empty() {
+        println!("Newly failed ({}):", comparison.newly_failed.len());
+        for (url, error) in &comparison.newly_failed {
+            println!("  {}: {}", display_url(url), error);
+        }
+    }
+    print_urls(
+        "Regressed to stale fallbacks",
+        &comparison.regressed_to_stale,
+    );
+    println!(
+        "{} URLs added, {} removed",
+        comparison.added.len(),
+        comparison.removed.len()
+    );
+    Ok(())
+}
+
+fn print_urls(heading: &str, urls: &[String]) {
+    if urls.is_empty() {
+        return;
+    }
+    println!("{} ({}):", heading, urls.len());
+    for url in urls {
+        println!("  {}", display_url(url));
+    }
+}
+
+fn compare(
+    old: &BTreeMap<String, ArchivingResult>,
+    new: &BTreeMap<String, ArchivingResult>,
+) -> Comparison {
+    let archived = |results: &BTreeMap<String, ArchivingResult>| {
+        results
+            .values()
+            .filter(|result| Status::of(result) == Status::Archived)
+            .count()
+    };
+    let mut comparison = Comparison {
+        removed: old
+            .keys()
+            .filter(|url| !new.contains_key(*url))
+            .cloned()
+            .collect(),
+        archived_before: archived(old),
+        archived_after: archived(new),
+        ..Comparison::default()
+    };
+    for (url, result) in new {
+        let old_result = match old.get(url) {
+            Some(old_result) => old_result,
+            None => {
+                comparison.added.push(url.clone());
+                continue;
+            }
+        };
+        let (before, after) = (Status::of(old_result), Status::of(result));
+        if before == after {
+            continue;
+        }
+        match after {
+            Status::Archived => comparison.newly_succeeded.push(url.clone()),
+            Status::Failed => {
+                let error = result.outcome.error().unwrap_or_default();
+                comparison
+                    .newly_failed
+                    .insert(url.clone(), error.to_string());
+            }
+            Status::StaleFallback if before == Status::Archived => {
+                comparison.regressed_to_stale.push(url.clone())
+            }
+            Status::StaleFallback => {}
+        }
+    }
+    comparison
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn results(outcomes: &[(&str, Outcome)]) -> BTreeMap<String, ArchivingResult> {
+        outcomes
+            .iter()
+            .map(|(url, outcome)| (url.to_string(), ArchivingResult::new(outcome.clone())))
+            .collect()
+    }
+
+    #[test]
+    fn compares_runs() {
+        let ts = "2021-01-01T00:00:00".parse().unwrap();
+        let archived = || Outcome::NewCapture {
+            url: String::new(),
+            ts,
+        };
+        let stale = || Outcome::StaleFallback {
+            url: String::new(),
+            ts,
+        };
+        let failed = |error: &str| Outcome::Failed {
+            error: error.to_string(),
+        };
+        let old = results(&[
+            ("a", failed("Bandwidth Exceeded")),
+            ("b", archived()),
+            ("c", archived()),
+            ("d", stale()),
+            ("e", archived()),
+            ("gone", archived()),
+        ]);
+        let new = results(&[
+            ("a", archived()),
+            ("b", failed("Dead host: b does not resolve")),
+            ("c", stale()),
+            ("d", archived()),
+            ("e", archived()),
+            ("new", failed("Bandwidth Exceeded")),
+        ]);
+        assert_eq!(
+            compare(&old, &new),
+            Comparison {
+                newly_succeeded: vec!["a".to_string(), "d".to_string()],
+                newly_failed: BTreeMap::from([(
+                    "b".to_string(),
+                    "Dead host: b does not resolve".to_string()
+                )]),
+                regressed_to_stale: vec!["c".to_string()],
+                added: vec!["new".to_string()],
+                removed: vec!["gone".to_string()],
+                archived_before: 4,
+                archived_after: 3,
+            }
+        );
+    }
+}
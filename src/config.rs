@@ -0,0 +1,321 @@
+use crate::content_types::ContentTypePolicy;
+use crate::pacing::Pacing;
+use crate::plugins::{self, Plugin};
+use crate::rules::Rule;
+use serde::{Deserialize, Deserializer};
+use std::collections::BTreeMap;
+use std::path::PathBuf;
+use std::sync::OnceLock;
+use std::time::Duration;
+use wayback_archiver::secrets::resolve_secret;
+use wayback_archiver::{
+    ArchiveBox, ArchiveToday, Endpoints, IaItem, LocalWarc, MementoArchive, PermaCc,
+};
+
+/// Settings loaded from the file passed to `--config` (TOML).
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config {
+    pub pacing: Pacing,
+    pub endpoints: EndpointSettings,
+    pub rules: Vec<Rule>,
+    /// Archives other than the Wayback Machine that rules can archive URLs in, by
+    /// name, in addition to the built-in ones (see `Config::archive`).
+    pub archives: BTreeMap<String, MementoArchive>,
+    pub perma_cc: PermaCcSettings,
+    pub archivebox: ArchiveBoxSettings,
+    pub warc: WarcSettings,
+    pub content_types: Vec<ContentTypePolicy>,
+    /// The directory plugins are discovered in (see `plugins`).
+    pub plugin_dir: Option<PathBuf>,
+    /// The plugins in `plugin_dir`, once they've been discovered.
+    #[serde(skip)]
+    plugins: OnceLock<Result<BTreeMap<String, Plugin>, String>>,
+}
+
+/// The Wayback services to talk to, e.g. a self-hosted Wayback deployment (pywb or
+/// OpenWayback) rather than archive.org. Unset ones are archive.org's (see
+/// `wayback_archiver::Endpoints`).
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct EndpointSettings {
+    /// Base URL of the availability API (`{availability}/wayback/available`).
+    pub availability: Option<String>,
+    /// Base URL of the CDX API and snapshots (`{web}/web/`).
+    pub web: Option<String>,
+    /// Base URL of Save Page Now (`{save}/{url}`).
+    pub save: Option<String>,
+    /// Base URL of the IA-S3 API (see `[warc]`).
+    pub s3: Option<String>,
+}
+
+impl EndpointSettings {
+    /// The endpoints, if any are configured.
+    pub fn endpoints(&self) -> Option<Endpoints> {
+        let defaults = Endpoints::default();
+        let endpoint = |configured: &Option<String>, default: String| {
+            configured
+                .as_ref()
+                .map_or(default, |url| url.trim_end_matches('/').to_string())
+        };
+        let configured = [&self.availability, &self.web, &self.save, &self.s3];
+        configured
+            .iter()
+            .any(|url| url.is_some())
+            .then(|| Endpoints {
+                availability: endpoint(&self.availability, defaults.availability),
+                web: endpoint(&self.web, defaults.web),
+                save: endpoint(&self.save, defaults.save),
+                s3: endpoint(&self.s3, defaults.s3),
+            })
+    }
+}
+
+/// How URLs are captured in Perma.cc (`perma.cc`). The API key is the
+/// `PERMA_API_KEY` secret.
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct PermaCcSettings {
+    /// The ID of the folder that links are made in, rather than the account's default.
+    pub folder: Option<u64>,
+}
+
+/// The ArchiveBox instance that URLs are archived in (`archivebox`). The API token is
+/// the `ARCHIVEBOX_TOKEN` secret.
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ArchiveBoxSettings {
+    /// The instance's base URL (e.g. `http://localhost:8000`).
+    pub url: Option<String>,
+}
+
+/// Where local WARC files are written (`warc`).
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct WarcSettings {
+    /// The directory WARC files are written to.
+    pub dir: Option<PathBuf>,
+    /// Whether pages' images, scripts, and stylesheets are captured too.
+    pub assets: bool,
+    /// The identifier of an Internet Archive item to upload WARC files to, with the
+    /// archive.org keys (`IA_ACCESS_KEY` and `IA_SECRET_KEY`).
+    pub item: Option<String>,
+    /// The item's metadata (e.g. `title`, `collection`, or `subject`), set when it's
+    /// created. Fields with several values are lists.
+    pub metadata: BTreeMap<String, MetadataValues>,
+}
+
+/// An item metadata field's value, or values.
+#[derive(Debug, Deserialize)]
+#[serde(untagged)]
+pub enum MetadataValues {
+    One(String),
+    Many(Vec<String>),
+}
+
+/// Where a rule or content type policy archives URLs.
+#[derive(Clone, Debug, PartialEq)]
+pub enum Archive {
+    Wayback,
+    Memento(MementoArchive),
+    ArchiveToday(ArchiveToday),
+    PermaCc(PermaCc),
+    ArchiveBox(ArchiveBox),
+    LocalWarc(LocalWarc),
+    /// A `provider` plugin.
+    Plugin(Plugin),
+}
+
+impl Config {
+    /// Parses the `contents` of the config file at `path`.
+    pub fn parse(path: &str, contents: &[u8]) -> Result<Config, Box<dyn std::error::Error>> {
+        let config: Config = toml::from_slice(contents)
+            .map_err(|err| format!("invalid config {}: {}", path, err))?;
+        let archives = config.rules.iter().filter_map(Rule::archive).chain(
+            config
+                .content_types
+                .iter()
+                .filter_map(ContentTypePolicy::archive),
+        );
+        for name in archives {
+            config
+                .archive(name)
+                .map_err(|err| format!("invalid config {}: {}", path, err))?;
+        }
+        Ok(config)
+    }
+
+    /// The archive named `name` in a rule: the Wayback Machine (`wayback`), one of the
+    /// configured or built-in (`arquivo.pt`) archives, archive.today (`archive.today`),
+    /// Perma.cc (`perma.cc`), the configured ArchiveBox instance (`archivebox`), local
+    /// WARC files (`warc`), or a provider plugin (`plugin:<name>`).
+    pub fn archive(&self, name: &str) -> Result<Archive, String> {
+        if let Some(archive) = self.archives.get(name) {
+            return Ok(Archive::Memento(archive.clone()));
+        }
+        if let Some(plugin) = name.strip_prefix("plugin:") {
+            return self.plugin(plugin).map(Archive::Plugin);
+        }
+        match name {
+            "wayback" => Ok(Archive::Wayback),
+            "arquivo.pt" => Ok(Archive::Memento(MementoArchive::arquivo_pt())),
+            "archive.today" => Ok(Archive::ArchiveToday(ArchiveToday::default())),
+            "perma.cc" => self.perma_cc().map(Archive::PermaCc),
+            "archivebox" => self.archivebox().map(Archive::ArchiveBox),
+            "warc" => self.warc().map(Archive::LocalWarc),
+            _ => Err(format!("unknown archive {:?}", name)),
+        }
+    }
+
+    /// Perma.cc, with the `PERMA_API_KEY` secret.
+    fn perma_cc(&self) -> Result<PermaCc, String> {
+        let api_key = resolve_secret("PERMA_API_KEY")
+            .map_err(|err| err.to_string())?
+            .ok_or("perma.cc requires a PERMA_API_KEY")?;
+        let perma_cc = PermaCc::new(&api_key);
+        Ok(match self.perma_cc.folder {
+            Some(folder) => perma_cc.with_folder(folder),
+            None => perma_cc,
+        })
+    }
+
+    /// The configured ArchiveBox instance, with the `ARCHIVEBOX_TOKEN` secret.
+    fn archivebox(&self) -> Result<ArchiveBox, String> {
+        let url = self
+            .archivebox
+            .url
+            .as_ref()
+            .ok_or("archivebox requires a url under [archivebox]")?;
+        let token = resolve_secret("ARCHIVEBOX_TOKEN")
+            .map_err(|err| err.to_string())?
+            .ok_or("archivebox requires an ARCHIVEBOX_TOKEN")?;
+        Ok(ArchiveBox::new(url, &token))
+    }
+
+    /// Local WARC files, in the configured directory.
+    fn warc(&self) -> Result<LocalWarc, String> {
+        let dir = self
+            .warc
+            .dir
+            .as_ref()
+            .ok_or("warc requires a dir under [warc]")?;
+        let mut warc = LocalWarc::new(dir);
+        if self.warc.assets {
+            warc = warc.with_assets();
+        }
+        if let Some(identifier) = &self.warc.item {
+            if resolve_secret("IA_ACCESS_KEY")
+                .map_err(|err| err.to_string())?
+                .is_none()
+            {
+                return Err("warc requires archive.org keys (IA_ACCESS_KEY and IA_SECRET_KEY) to upload to an item".to_string());
+            }
+            let mut item = IaItem::new(identifier);
+            for (name, values) in &self.warc.metadata {
+                let values = match values {
+                    MetadataValues::One(value) => std::slice::from_ref(value),
+                    MetadataValues::Many(values) => values.as_slice(),
+                };
+                for value in values {
+                    item = item.with_metadata(name, value);
+                }
+            }
+            warc = warc.with_item(item);
+        }
+        Ok(warc)
+    }
+
+    /// The plugin named `name` in the plugin directory, which is only read the first
+    /// time a plugin is asked for.
+    pub fn plugin(&self, name: &str) -> Result<Plugin, String> {
+        let dir = self
+            .plugin_dir
+            .as_ref()
+            .ok_or_else(|| format!("plugin {:?} requires a plugin_dir", name))?;
+        let plugins = self
+            .plugins
+            .get_or_init(|| {
+                plugins::discover(dir)
+                    .map_err(|err| format!("unable to read plugins in {}: {}", dir.display(), err))
+            })
+            .as_ref()?;
+        plugins.get(name).cloned().ok_or_else(|| {
+            let available: Vec<&str> = plugins.keys().map(String::as_str).collect();
+            format!(
+                "no plugin {:?} in {} (found: {})",
+                name,
+                dir.display(),
+                available.join(", ")
+            )
+        })
+    }
+}
+
+/// Parses durations like `500ms`, `5s`, `15m`, `2h`, `30d`, or `1y`. A bare number is
+/// taken as seconds.
+pub fn parse_duration(s: &str) -> Result<Duration, String> {
+    let s = s.trim();
+    let split = s.find(|c: char| !c.is_ascii_digit()).unwrap_or(s.len());
+    let (amount, unit) = s.split_at(split);
+    let amount: u64 = amount
+        .parse()
+        .map_err(|_| format!("invalid duration {:?}", s))?;
+    let unit_secs = match unit.trim() {
+        "ms" => return Ok(Duration::from_millis(amount)),
+        "" | "s" => 1,
+        "m" => 60,
+        "h" => 60 * 60,
+        "d" => 24 * 60 * 60,
+        "w" => 7 * 24 * 60 * 60,
+        "y" => 365 * 24 * 60 * 60,
+        _ => return Err(format!("invalid duration unit in {:?}", s)),
+    };
+    amount
+        .checked_mul(unit_secs)
+        .map(Duration::from_secs)
+        .ok_or_else(|| format!("duration {:?} is too long", s))
+}
+
+/// Formats a duration in the largest whole unit accepted by `parse_duration`.
+pub fn format_duration(duration: Duration) -> String {
+    let secs = duration.as_secs();
+    if duration.subsec_millis() != 0 || secs == 0 {
+        return format!("{}ms", duration.as_millis());
+    }
+    for (unit, unit_secs) in &[
+        ("y", 365 * 24 * 60 * 60),
+        ("w", 7 * 24 * 60 * 60),
+        ("d", 24 * 60 * 60),
+        ("h", 60 * 60),
+        ("m", 60),
+    ] {
+        if secs.is_multiple_of(*unit_secs) {
+            return format!("{}{}", secs / unit_secs, unit);
+        }
+    }
+    format!("{}s", secs)
+}
+
+pub fn deserialize_duration<'de, D>(deserializer: D) -> Result<Duration, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    let s = String::deserialize(deserializer)?;
+    parse_duration(&s).map_err(serde::de::Error::custom)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_durations() {
+        assert_eq!(parse_duration("500ms"), Ok(Duration::from_millis(500)));
+        assert_eq!(parse_duration("90"), Ok(Duration::from_secs(90)));
+        assert_eq!(parse_duration("2h"), Ok(Duration::from_secs(2 * 60 * 60)));
+        assert_eq!(format_duration(parse_duration("30d").unwrap()), "30d");
+        assert!(parse_duration("5 fortnights").is_err());
+        assert!(parse_duration("h").is_err());
+        assert!(parse_duration("99999999999999y").is_err());
+    }
+}
@@ -0,0 +1,289 @@
+use crate::config::Config;
+use serde::{Deserialize, Deserializer};
+use sha2::{Digest, Sha256};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use wayback_archiver::ContentInfo;
+
+/// The most that's downloaded of a page, unless a policy's `max_download` says
+/// otherwise (1GB).
+pub const DEFAULT_MAX_DOWNLOAD: u64 = 1_000_000_000;
+
+/// A policy from the `[[content_types]]` sections of the config file, applied to every
+/// URL whose live page has a matching content type (looked up with a HEAD request
+/// before archiving).
+///
+/// `type` is a MIME type (`application/pdf`), a wildcard subtype (`video/*`), or `*`.
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ContentTypePolicy {
+    #[serde(rename = "type")]
+    content_type: String,
+    /// If set, the policy only applies to pages larger than this.
+    #[serde(default, deserialize_with = "deserialize_optional_size")]
+    larger_than: Option<u64>,
+    /// Never archive the matching URLs.
+    #[serde(default)]
+    skip: bool,
+    /// Don't wait for the page's JavaScript to run before capturing it (with SPN2),
+    /// which only slows down capturing documents and media.
+    #[serde(default)]
+    skip_js: bool,
+    /// A directory to also download a local copy of the page into.
+    #[serde(default)]
+    download: Option<PathBuf>,
+    /// The largest page that's downloaded (`DEFAULT_MAX_DOWNLOAD` by default), whatever
+    /// size the HEAD request reported.
+    #[serde(default, deserialize_with = "deserialize_optional_size")]
+    max_download: Option<u64>,
+    /// The archive to archive the matching URLs in (see `Config::archive`),
+    /// overriding config rules.
+    #[serde(default)]
+    archive: Option<String>,
+}
+
+/// The combined effect of all the policies matching a page. Where policies conflict,
+/// the later one wins.
+#[derive(Debug, Default, PartialEq)]
+pub struct ContentTypeOutcome {
+    pub skip: bool,
+    pub skip_js: bool,
+    pub download: Option<PathBuf>,
+    pub max_download: Option<u64>,
+    pub archive: Option<String>,
+}
+
+impl ContentTypePolicy {
+    pub fn archive(&self) -> Option<&str> {
+        self.archive.as_deref()
+    }
+
+    fn matches(&self, info: &ContentInfo) -> bool {
+        let content_type = info.content_type.as_deref().unwrap_or_default();
+        let type_matches = match self.content_type.strip_suffix("/*") {
+            _ if self.content_type == "*" => true,
+            Some(prefix) => content_type
+                .split_once('/')
+                .is_some_and(|(type_, _)| type_.eq_ignore_ascii_case(prefix)),
+            None => content_type.eq_ignore_ascii_case(&self.content_type),
+        };
+        let size_matches = self.larger_than.is_none_or(|larger_than| {
+            info.content_length
+                .is_some_and(|length| length > larger_than)
+        });
+        type_matches && size_matches
+    }
+}
+
+/// Evaluates the config's content type policies against a live page's `info`.
+pub fn evaluate(config: &Config, info: &ContentInfo) -> ContentTypeOutcome {
+    let mut outcome = ContentTypeOutcome::default();
+    for policy in config
+        .content_types
+        .iter()
+        .filter(|policy| policy.matches(info))
+    {
+        outcome.skip |= policy.skip;
+        outcome.skip_js |= policy.skip_js;
+        outcome.download = policy.download.clone().or(outcome.download);
+        outcome.max_download = policy.max_download.or(outcome.max_download);
+        outcome.archive = policy.archive.clone().or(outcome.archive);
+    }
+    outcome
+}
+
+/// Downloads `url` into `dir` with `http`, returning the path it was saved to, unless
+/// it's larger than `max_size` bytes. Files are named after the URL's last path
+/// segment, prefixed with a hash of the whole URL so that different URLs don't
+/// overwrite each other. URLs that were already downloaded aren't downloaded again.
+pub async fn download(
+    http: &reqwest::Client,
+    url: &str,
+    dir: &Path,
+    max_size: u64,
+) -> Result<PathBuf, Box<dyn std::error::Error>> {
+    let name: String = reqwest::Url::parse(url)?
+        .path_segments()
+        .and_then(|mut segments| segments.next_back())
+        .filter(|name| !name.is_empty())
+        .unwrap_or("index")
+        .chars()
+        .map(|c| match c {
+            '/' | '\\' | ':' | '*' | '?' | '"' | '<' | '>' | '|' => '_',
+            c => c,
+        })
+        .collect();
+    let hash: String = Sha256::digest(url.as_bytes())[..6]
+        .iter()
+        .map(|byte| format!("{:02x}", byte))
+        .collect();
+    let path = dir.join(format!("{}-{}", hash, name));
+    if path.exists() {
+        return Ok(path);
+    }
+    let too_large = || format!("larger than the {} byte download limit", max_size);
+    let mut resp = http.get(url).send().await?.error_for_status()?;
+    if resp
+        .content_length()
+        .is_some_and(|length| length > max_size)
+    {
+        return Err(too_large().into());
+    }
+    std::fs::create_dir_all(dir)?;
+    // Downloaded to a temporary file first, so that an interrupted or abandoned
+    // download isn't mistaken for a finished one.
+    let partial = dir.join(format!("{}-{}.part", hash, name));
+    let mut file = std::fs::File::create(&partial)?;
+    let mut size = 0;
+    while let Some(chunk) = resp.chunk().await? {
+        size += chunk.len() as u64;
+        if size > max_size {
+            drop(file);
+            std::fs::remove_file(&partial)?;
+            return Err(too_large().into());
+        }
+        file.write_all(&chunk)?;
+    }
+    file.sync_all()?;
+    std::fs::rename(&partial, &path)?;
+    Ok(path)
+}
+
+/// Parses sizes like `500KB`, `100MB`, or `2GB` (in powers of 1000). A bare number
+/// is taken as bytes.
+pub fn parse_size(s: &str) -> Result<u64, String> {
+    let s = s.trim();
+    let split = s.find(|c: char| !c.is_ascii_digit()).unwrap_or(s.len());
+    let (amount, unit) = s.split_at(split);
+    let amount: u64 = amount
+        .parse()
+        .map_err(|_| format!("invalid size {:?}", s))?;
+    let unit_bytes = match unit.trim().to_ascii_uppercase().as_str() {
+        "" | "B" => 1,
+        "KB" => 1_000,
+        "MB" => 1_000_000,
+        "GB" => 1_000_000_000,
+        _ => return Err(format!("invalid size unit in {:?}", s)),
+    };
+    amount
+        .checked_mul(unit_bytes)
+        .ok_or_else(|| format!("size {:?} is too large", s))
+}
+
+fn deserialize_optional_size<'de, D>(deserializer: D) -> Result<Option<u64>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    let s = String::deserialize(deserializer)?;
+    parse_size(&s).map(Some).map_err(serde::de::Error::custom)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn applies_matching_policies() {
+        let config = Config::parse(
+            "test.toml",
+            br#"
+            [[content_types]]
+            type = "application/pdf"
+            skip_js = true
+            download = "pdfs"
+
+            [[content_types]]
+            type = "video/*"
+            skip_js = true
+
+            [[content_types]]
+            type = "video/*"
+            larger_than = "100MB"
+            archive = "arquivo.pt"
+
+            [[content_types]]
+            type = "image/gif"
+            skip = true
+            "#,
+        )
+        .unwrap();
+        let info = |content_type: &str, content_length| ContentInfo {
+            content_type: Some(content_type.to_string()),
+            content_length,
+        };
+        assert_eq!(
+            evaluate(&config, &info("application/pdf", None)),
+            ContentTypeOutcome {
+                skip_js: true,
+                download: Some(PathBuf::from("pdfs")),
+                ..ContentTypeOutcome::default()
+            }
+        );
+        assert_eq!(
+            evaluate(&config, &info("video/mp4", Some(50_000_000))),
+            ContentTypeOutcome {
+                skip_js: true,
+                ..ContentTypeOutcome::default()
+            }
+        );
+        assert_eq!(
+            evaluate(&config, &info("video/mp4", Some(500_000_000))).archive,
+            Some("arquivo.pt".to_string())
+        );
+        assert!(evaluate(&config, &info("image/gif", None)).skip);
+        assert_eq!(
+            evaluate(&config, &info("text/html", Some(500_000_000))),
+            ContentTypeOutcome::default()
+        );
+        assert_eq!(
+            evaluate(&config, &ContentInfo::default()),
+            ContentTypeOutcome::default()
+        );
+    }
+
+    #[test]
+    fn parses_sizes() {
+        assert_eq!(parse_size("512"), Ok(512));
+        assert_eq!(parse_size("500KB"), Ok(500_000));
+        assert_eq!(parse_size("100 MB"), Ok(100_000_000));
+        assert_eq!(parse_size("2gb"), Ok(2_000_000_000));
+        assert!(parse_size("1TB").is_err());
+        assert!(parse_size("MB").is_err());
+        assert!(parse_size("20000000000GB").is_err());
+    }
+
+    /// Serves `body` to the first request, and then stops.
+    async fn serve_once(body: &'static [u8]) -> String {
+        use tokio::io::{AsyncReadExt, AsyncWriteExt};
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(async move {
+            let (mut connection, _) = listener.accept().await.unwrap();
+            let _ = connection.read(&mut [0; 4096]).await;
+            // Without a Content-Length, so that the size is only known by reading it.
+            let head = "HTTP/1.1 200 OK\r\nConnection: close\r\n\r\n";
+            connection.write_all(head.as_bytes()).await.unwrap();
+            connection.write_all(body).await.unwrap();
+        });
+        format!("http://{}/files/report.pdf", addr)
+    }
+
+    #[tokio::test]
+    async fn downloads_within_the_size_limit_once() {
+        let dir = std::env::temp_dir().join(format!("downloads-{}", std::process::id()));
+        let http = reqwest::Client::new();
+
+        let url = serve_once(b"0123456789").await;
+        let err = download(&http, &url, &dir, 5).await.unwrap_err();
+        assert!(err.to_string().contains("download limit"), "{}", err);
+        assert_eq!(std::fs::read_dir(&dir).unwrap().count(), 0);
+
+        let url = serve_once(b"0123456789").await;
+        let path = download(&http, &url, &dir, 10).await.unwrap();
+        assert!(path.to_string_lossy().ends_with("-report.pdf"));
+        assert_eq!(std::fs::read(&path).unwrap(), b"0123456789");
+        // The server has stopped, so this only succeeds if it isn't downloaded again.
+        assert_eq!(download(&http, &url, &dir, 10).await.unwrap(), path);
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,258 @@
+//! A PostgreSQL results backend, so that workers can share state and results can be
+//! queried directly (e.g. by dashboards). Only available when built with the
+//! `postgres` feature.
+//!
+//! Three tables are kept, and created if they don't exist:
+//!
+//! - `urls`: the latest result for each URL, upserted after every attempt.
+//! - `attempts`: one row per archiving attempt, including failures.
+//! - `snapshots`: every snapshot an attempt produced or reused.
+use std::collections::BTreeMap;
+use wayback_archiver::ArchivingResult;
+#[cfg(feature = "postgres")]
+use wayback_archiver::Outcome;
+
+#[cfg(feature = "postgres")]
+const SCHEMA: &str = "
+CREATE TABLE IF NOT EXISTS urls (
+    url TEXT PRIMARY KEY,
+    outcome TEXT NOT NULL,
+    snapshot_url TEXT,
+    last_archived TIMESTAMP,
+    pending BOOLEAN NOT NULL,
+    etag TEXT,
+    last_modified TEXT,
+    error TEXT,
+    first_archived TIMESTAMP,
+    total_captures BIGINT,
+    run_id TEXT,
+    updated_at TIMESTAMPTZ NOT NULL DEFAULT now()
+);
+ALTER TABLE urls ADD COLUMN IF NOT EXISTS retry_after TIMESTAMP;
+ALTER TABLE urls ADD COLUMN IF NOT EXISTS failures INTEGER NOT NULL DEFAULT 0;
+CREATE TABLE IF NOT EXISTS attempts (
+    id BIGSERIAL PRIMARY KEY,
+    url TEXT NOT NULL REFERENCES urls (url),
+    run_id TEXT,
+    attempted_at TIMESTAMPTZ NOT NULL DEFAULT now(),
+    outcome TEXT NOT NULL,
+    snapshot_url TEXT,
+    error TEXT
+);
+-- Databases created before outcomes were recorded have an existing_snapshot flag
+-- instead, and require a snapshot for every URL.
+ALTER TABLE urls ADD COLUMN IF NOT EXISTS outcome TEXT;
+ALTER TABLE urls ALTER COLUMN last_archived DROP NOT NULL;
+DO $$
+BEGIN
+    IF EXISTS (
+        SELECT 1 FROM information_schema.columns
+        WHERE table_schema = current_schema()
+            AND table_name = 'urls'
+            AND column_name = 'existing_snapshot'
+    ) THEN
+        UPDATE urls SET outcome = CASE
+            WHEN snapshot_url IS NULL THEN 'failed'
+            WHEN existing_snapshot AND NOT pending THEN 'existing-snapshot'
+            ELSE 'new-capture'
+        END
+        WHERE outcome IS NULL;
+        ALTER TABLE urls ALTER COLUMN existing_snapshot DROP NOT NULL;
+    END IF;
+END $$;
+UPDATE urls SET outcome = CASE
+    WHEN snapshot_url IS NULL THEN 'failed'
+    ELSE 'new-capture'
+END
+WHERE outcome IS NULL;
+ALTER TABLE urls ALTER COLUMN outcome SET NOT NULL;
+ALTER TABLE attempts ADD COLUMN IF NOT EXISTS outcome TEXT;
+UPDATE attempts SET outcome = CASE
+    WHEN snapshot_url IS NULL THEN 'failed'
+    ELSE 'new-capture'
+END
+WHERE outcome IS NULL;
+ALTER TABLE attempts ALTER COLUMN outcome SET NOT NULL;
+CREATE TABLE IF NOT EXISTS snapshots (
+    snapshot_url TEXT PRIMARY KEY,
+    url TEXT NOT NULL REFERENCES urls (url),
+    archived_at TIMESTAMP NOT NULL,
+    pending BOOLEAN NOT NULL
+);
+";
+
+#[cfg(feature = "postgres")]
+pub struct Database {
+    client: tokio_postgres::Client,
+}
+
+#[cfg(feature = "postgres")]
+impl Database {
+    /// Connects to the database at `url` (a `postgres://` connection string) and
+    /// creates the schema if needed.
+    pub async fn connect(url: &str) -> Result<Database, Box<dyn std::error::Error>> {
+        let tls = postgres_native_tls::MakeTlsConnector::new(native_tls::TlsConnector::new()?);
+        let (client, connection) = tokio_postgres::connect(url, tls).await?;
+        tokio::spawn(async move {
+            if let Err(err) = connection.await {
+                eprintln!("Database connection error: {}", err);
+            }
+        });
+        client.batch_execute(SCHEMA).await?;
+        Ok(Database { client })
+    }
+
+    /// Returns the latest result for every URL in the database.
+    pub async fn load_results(
+        &self,
+    ) -> Result<BTreeMap<String, ArchivingResult>, Box<dyn std::error::Error>> {
+        let rows = self
+            .client
+            .query(
+                "SELECT url, outcome, snapshot_url, last_archived, pending, etag, last_modified,
+                        error, first_archived, total_captures, retry_after, failures, run_id
+                 FROM urls",
+                &[],
+            )
+            .await?;
+        let mut results = BTreeMap::new();
+        for row in rows {
+            let etag: Option<String> = row.get("etag");
+            let last_modified: Option<String> = row.get("last_modified");
+            let validators = wayback_archiver::Validators {
+                etag,
+                last_modified,
+            };
+            let total_captures: Option<i64> = row.get("total_captures");
+            let failures: i32 = row.get("failures");
+            let snapshot = match (row.get("snapshot_url"), row.get("last_archived")) {
+                (Some(url), Some(ts)) => Some((url, ts)),
+                _ => None,
+            };
+            let outcome = match (row.get("outcome"), snapshot) {
+                ("new-capture", Some((url, ts))) => Outcome::NewCapture { url, ts },
+                ("existing-snapshot", Some((url, ts))) => Outcome::ExistingSnapshot { url, ts },
+                ("stale-fallback", Some((url, ts))) => Outcome::StaleFallback { url, ts },
+                _ => Outcome::Failed {
+                    error: row
+                        .get::<_, Option<String>>("error")
+                        .unwrap_or_else(|| "No snapshot was recorded".to_string()),
+                },
+            };
+            let result = ArchivingResult {
+                pending: row.get("pending"),
+                validators: Some(validators).filter(|validators| !validators.is_empty()),
+                first_archived: row.get("first_archived"),
+                total_captures: total_captures.map(|total| total as u64),
+                retry_after: row.get("retry_after"),
+                failures: failures as u32,
+                run_id: row.get("run_id"),
+                ..ArchivingResult::new(outcome)
+            };
+            results.insert(row.get("url"), result);
+        }
+        Ok(results)
+    }
+
+    /// Records an attempt to archive `url`, upserting its latest result. The rows are
+    /// written in one transaction, so a failure leaves none of them behind.
+    pub async fn record(
+        &mut self,
+        url: &str,
+        result: &ArchivingResult,
+    ) -> Result<(), Box<dyn std::error::Error>> {
+        let validators = result.validators.clone().unwrap_or_default();
+        let total_captures = result.total_captures.map(|total| total as i64);
+        let failures = result.failures as i32;
+        let outcome = match result.outcome {
+            Outcome::NewCapture { .. } => "new-capture",
+            Outcome::ExistingSnapshot { .. } => "existing-snapshot",
+            Outcome::StaleFallback { .. } => "stale-fallback",
+            Outcome::Failed { .. } => "failed",
+        };
+        let snapshot_url = result.outcome.url();
+        let last_archived = result.outcome.ts();
+        let error = result.outcome.error();
+        let transaction = self.client.transaction().await?;
+        transaction
+            .execute(
+                "INSERT INTO urls (url, outcome, snapshot_url, last_archived, pending, etag,
+                                   last_modified, error, first_archived, total_captures,
+                                   retry_after, failures, run_id)
+                 VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10, $11, $12, $13)
+                 ON CONFLICT (url) DO UPDATE SET
+                     outcome = EXCLUDED.outcome,
+                     snapshot_url = EXCLUDED.snapshot_url,
+                     last_archived = EXCLUDED.last_archived,
+                     pending = EXCLUDED.pending,
+                     etag = EXCLUDED.etag,
+                     last_modified = EXCLUDED.last_modified,
+                     error = EXCLUDED.error,
+                     first_archived = EXCLUDED.first_archived,
+                     total_captures = EXCLUDED.total_captures,
+                     retry_after = EXCLUDED.retry_after,
+                     failures = EXCLUDED.failures,
+                     run_id = EXCLUDED.run_id,
+                     updated_at = now()",
+                &[
+                    &url,
+                    &outcome,
+                    &snapshot_url,
+                    &last_archived,
+                    &result.pending,
+                    &validators.etag,
+                    &validators.last_modified,
+                    &error,
+                    &result.first_archived,
+                    &total_captures,
+                    &result.retry_after,
+                    &failures,
+                    &result.run_id,
+                ],
+            )
+            .await?;
+        transaction
+            .execute(
+                "INSERT INTO attempts (url, run_id, outcome, snapshot_url, error)
+                 VALUES ($1, $2, $3, $4, $5)",
+                &[&url, &result.run_id, &outcome, &snapshot_url, &error],
+            )
+            .await?;
+        if let (Some(snapshot_url), Some(archived_at)) = (snapshot_url, last_archived) {
+            transaction
+                .execute(
+                    "INSERT INTO snapshots (snapshot_url, url, archived_at, pending)
+                     VALUES ($1, $2, $3, $4)
+                     ON CONFLICT (snapshot_url) DO UPDATE SET pending = EXCLUDED.pending",
+                    &[&snapshot_url, &url, &archived_at, &result.pending],
+                )
+                .await?;
+        }
+        transaction.commit().await?;
+        Ok(())
+    }
+}
+
+#[cfg(not(feature = "postgres"))]
+pub struct Database;
+
+#[cfg(not(feature = "postgres"))]
+impl Database {
+    pub async fn connect(_url: &str) -> Result<Database, Box<dyn std::error::Error>> {
+        Err("--db requires wayback-archiver to be built with the postgres feature".into())
+    }
+
+    pub async fn load_results(
+        &self,
+    ) -> Result<BTreeMap<String, ArchivingResult>, Box<dyn std::error::Error>> {
+        unreachable!("Database can't be constructed without the postgres feature")
+    }
+
+    pub async fn record(
+        &mut self,
+        _url: &str,
+        _result: &ArchivingResult,
+    ) -> Result<(), Box<dyn std::error::Error>> {
+        unreachable!("Database can't be constructed without the postgres feature")
+    }
+}
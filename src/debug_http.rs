@@ -0,0 +1,225 @@
+//! Transcripts of the HTTP exchanges of failed captures (see
+//! `WaybackClient::with_debug_http`), so that new ways in which the Wayback Machine
+//! fails can be reported upstream with evidence.
+//!
+//! While a capture is attempted, the client's requests, responses, and the bodies it
+//! reads are recorded in a task-local transcript, which is written out if the capture
+//! fails. Credentials and cookies are redacted.
+use chrono::Utc;
+use std::cell::RefCell;
+use std::fmt::Write;
+use std::future::Future;
+use std::path::Path;
+
+tokio::task_local! {
+    static TRANSCRIPT: RefCell<Vec<Exchange>>;
+}
+
+/// Headers whose values are replaced in transcripts.
+const REDACTED_HEADERS: &[&str] = &[
+    "authorization",
+    "proxy-authorization",
+    "cookie",
+    "set-cookie",
+];
+
+/// A request, and what came of it.
+#[derive(Default)]
+struct Exchange {
+    request: String,
+    response: Option<String>,
+    body: Option<String>,
+    error: Option<String>,
+}
+
+/// Runs `capture` (of `url`), writing a transcript of its requests to `dir` if it
+/// fails, as judged by `failed`. Without a `dir`, it's simply run.
+pub(crate) async fn record<T, F>(
+    dir: Option<&Path>,
+    url: &str,
+    failed: impl Fn(&T) -> Option<String>,
+    capture: F,
+) -> T
+where
+    F: Future<Output = T>,
+{
+    let dir = match dir {
+        Some(dir) => dir,
+        None => return capture.await,
+    };
+    let started = Utc::now();
+    let (result, exchanges) = TRANSCRIPT
+        .scope(RefCell::new(Vec::new()), async {
+            let result = capture.await;
+            (result, TRANSCRIPT.with(|transcript| transcript.take()))
+        })
+        .await;
+    if let Some(error) = failed(&result) {
+        let name = format!(
+            "{}-{}.txt",
+            started.format("%Y%m%dT%H%M%S%.3fZ"),
+            file_name_part(url)
+        );
+        let path = dir.join(name);
+        let written = std::fs::create_dir_all(dir)
+            .and_then(|()| std::fs::write(&path, format_transcript(url, &error, &exchanges)));
+        if let Err(err) = written {
+            eprintln!("Unable to write {}: {}", path.display(), err);
+        }
+    }
+    result
+}
+
+/// Records a request that's about to be sent.
+pub(crate) fn request(request: &reqwest::Request) {
+    let mut text = format!("> {} {}\n", request.method(), request.url());
+    write_headers(&mut text, "> ", request.headers());
+    if let Some(body) = request.body().and_then(|body| body.as_bytes()) {
+        let _ = writeln!(text, ">\n{}", String::from_utf8_lossy(body));
+    }
+    push(Exchange {
+        request: text,
+        ..Exchange::default()
+    });
+}
+
+/// Records the response to the last request.
+pub(crate) fn response(response: &reqwest::Response) {
+    let mut text = format!("< {:?} {}\n", response.version(), response.status());
+    let _ = writeln!(text, "< (from {})", response.url());
+    write_headers(&mut text, "< ", response.headers());
+    update(|exchange| exchange.response = Some(text));
+}
+
+/// Records the body of the response to the last request.
+pub(crate) fn body(body: &str) {
+    if body.is_empty() {
+        return;
+    }
+    update(|exchange| exchange.body = Some(body.to_string()));
+}
+
+/// Records that the last request failed without a response (or body).
+pub(crate) fn error(error: &dyn std::fmt::Display) {
+    update(|exchange| exchange.error = Some(error.to_string()));
+}
+
+fn push(exchange: Exchange) {
+    // Outside of `record`, nothing is recorded.
+    let _ = TRANSCRIPT.try_with(|transcript| transcript.borrow_mut().push(exchange));
+}
+
+fn update(f: impl FnOnce(&mut Exchange)) {
+    let _ = TRANSCRIPT.try_with(|transcript| {
+        if let Some(exchange) = transcript.borrow_mut().last_mut() {
+            f(exchange);
+        }
+    });
+}
+
+fn write_headers(text: &mut String, prefix: &str, headers: &reqwest::header::HeaderMap) {
+    for (name, value) in headers {
+        let value = if REDACTED_HEADERS.contains(&name.as_str()) {
+            "[redacted]".into()
+        } else {
+            String::from_utf8_lossy(value.as_bytes())
+        };
+        let _ = writeln!(text, "{}{}: {}", prefix, name, value);
+    }
+}
+
+fn format_transcript(url: &str, error: &str, exchanges: &[Exchange]) -> String {
+    let mut text = format!("# Failed capture of {}: {}\n", url, error);
+    for exchange in exchanges {
+        text.push('\n');
+        text.push_str(&exchange.request);
+        if let Some(response) = &exchange.response {
+            text.push_str(response);
+        }
+        if let Some(body) = &exchange.body {
+            let _ = writeln!(text, "<\n{}", body);
+        }
+        if let Some(error) = &exchange.error {
+            let _ = writeln!(text, "! {}", error);
+        }
+    }
+    text
+}
+
+/// `url`, shortened and with anything that isn't alphanumeric replaced, for file names.
+fn file_name_part(url: &str) -> String {
+    let url = url
+        .trim_start_matches("https://")
+        .trim_start_matches("http://");
+    url.chars()
+        .take(80)
+        .map(|c| if c.is_ascii_alphanumeric() { c } else { '_' })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::WaybackClient;
+
+    #[tokio::test]
+    async fn records_exchanges_with_secrets_redacted() {
+        use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+        // A Wayback Machine that's down for maintenance.
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(async move {
+            let (mut connection, _) = listener.accept().await.unwrap();
+            let mut request = vec![0; 4096];
+            let _ = connection.read(&mut request).await.unwrap();
+            connection
+                .write_all(
+                    b"HTTP/1.1 503 Service Unavailable\r\nset-cookie: session=secret\r\n\
+                      content-length: 11\r\n\r\nMaintenance",
+                )
+                .await
+                .unwrap();
+        });
+        let save_url = format!("http://{}/save/example.com", addr);
+        let client = WaybackClient::new().with_debug_http("debug");
+        let exchanges = TRANSCRIPT
+            .scope(RefCell::new(Vec::new()), async {
+                let request = client
+                    .http()
+                    .get(&save_url)
+                    .header(reqwest::header::AUTHORIZATION, "LOW access:secret");
+                let resp = client.send(request).await.unwrap();
+                assert_eq!(
+                    client.read_text(resp).await.unwrap().unwrap(),
+                    "Maintenance"
+                );
+                TRANSCRIPT.with(|transcript| transcript.take())
+            })
+            .await;
+        let transcript = format_transcript("example.com", "Service unavailable", &exchanges);
+        assert!(
+            transcript.starts_with("# Failed capture of example.com: Service unavailable\n"),
+            "{}",
+            transcript
+        );
+        for expected in [
+            format!("> GET {}\n", save_url),
+            "> authorization: [redacted]\n".to_string(),
+            "< HTTP/1.1 503 Service Unavailable\n".to_string(),
+            "< set-cookie: [redacted]\n".to_string(),
+            "<\nMaintenance\n".to_string(),
+        ] {
+            assert!(transcript.contains(&expected), "{}", transcript);
+        }
+        assert!(!transcript.contains("secret"), "{}", transcript);
+    }
+
+    #[test]
+    fn names_files_after_urls() {
+        assert_eq!(
+            file_name_part("https://example.com/a?b=c"),
+            "example_com_a_b_c"
+        );
+    }
+}
@@ -0,0 +1,86 @@
+//! How URLs are shown to people (in progress lines, summaries, and served pages), as
+//! opposed to machine outputs (results, exports, logs), which always keep them exact.
+//! Internationalized hosts are shown in Unicode, with their punycode alongside, and
+//! very long URLs can be shortened.
+use wayback_archiver::normalize_url;
+
+/// URLs in progress lines are shortened to this many characters.
+pub const PROGRESS_WIDTH: usize = 100;
+
+/// `url`, with its host in Unicode and the punycode alongside, if it's an
+/// internationalized domain name, e.g. `https://bücher.example/ [xn--bcher-kva.example]`.
+pub fn display_url(url: &str) -> String {
+    let parsed = match reqwest::Url::parse(&normalize_url(url)) {
+        Ok(parsed) => parsed,
+        Err(_) => return url.to_string(),
+    };
+    let ascii = match parsed.host_str() {
+        Some(host) if host.split('.').any(|label| label.starts_with("xn--")) => host,
+        _ => return url.to_string(),
+    };
+    let (unicode, result) = idna::domain_to_unicode(ascii);
+    if result.is_err() {
+        return url.to_string();
+    }
+    // URLs may be written with either form of the host.
+    format!("{} [{}]", url.replacen(ascii, &unicode, 1), ascii)
+}
+
+/// `text` (usually a URL), shortened to at most `width` characters by eliding the
+/// middle of its path, so that the host and the end of the path (which usually tell
+/// pages apart) are kept, e.g. `https://example.com/docs/…/install.html`.
+pub fn ellipsize(text: &str, width: usize) -> String {
+    let len = text.chars().count();
+    if len <= width {
+        return text.to_string();
+    }
+    // Everything up to the path, e.g. `https://example.com`.
+    let head_len = text
+        .find("://")
+        .and_then(|scheme_end| {
+            let host_start = scheme_end + "://".len();
+            text[host_start..].find('/').map(|path| host_start + path)
+        })
+        .map_or(0, |head_end| text[..head_end].chars().count());
+    let tail_len = width.saturating_sub(head_len + 1);
+    // Too little would be left of the path to be worth keeping the host whole.
+    if tail_len < width / 4 {
+        let kept: String = text.chars().take(width.saturating_sub(1)).collect();
+        return format!("{}…", kept);
+    }
+    let head: String = text.chars().take(head_len).collect();
+    let tail: String = text.chars().skip(len - tail_len).collect();
+    format!("{}…{}", head, tail)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn displays_urls() {
+        assert_eq!(
+            display_url("https://xn--bcher-kva.example/katalog"),
+            "https://bücher.example/katalog [xn--bcher-kva.example]"
+        );
+        assert_eq!(
+            display_url("https://bücher.example/"),
+            "https://bücher.example/ [xn--bcher-kva.example]"
+        );
+        assert_eq!(
+            display_url("https://example.com/xn--path"),
+            "https://example.com/xn--path"
+        );
+        assert_eq!(display_url("not a url"), "not a url");
+
+        let url = "https://example.com/docs/guides/getting-started/installation/linux.html";
+        assert_eq!(ellipsize(url, 100), url);
+        let shortened = ellipsize(url, 40);
+        assert_eq!(shortened, "https://example.com…tallation/linux.html");
+        assert_eq!(shortened.chars().count(), 40);
+        assert_eq!(
+            ellipsize("https://a-very-long-host-name.example.com/page", 30),
+            "https://a-very-long-host-name…"
+        );
+    }
+}
@@ -0,0 +1,174 @@
+use crate::locked_file::{read_locked, update_locked, SetAside};
+use crate::normalize::normalize_url;
+use crate::ArchiveError;
+use chrono::{Duration, NaiveDateTime, Utc};
+use futures::stream::{self, StreamExt};
+use reqwest::Url;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::io;
+use std::path::PathBuf;
+use std::sync::Mutex;
+
+/// Caches DNS resolution of target hosts, so that URLs on vanished domains can be
+/// skipped instead of timing out on each one.
+///
+/// A host is considered dead once its lookup has failed `max_failures` times.
+pub struct DnsCache {
+    max_failures: u32,
+    path: Option<PathBuf>,
+    set_aside: Option<SetAside>,
+    hosts: Mutex<HashMap<String, HostState>>,
+}
+
+/// A host's failed lookups, as saved to a DNS cache file.
+#[derive(Deserialize, Serialize)]
+struct SavedFailures {
+    failures: u32,
+    last_failed: NaiveDateTime,
+}
+
+#[derive(Clone, Copy)]
+enum HostState {
+    Resolved,
+    Failed(u32),
+}
+
+impl DnsCache {
+    /// How long saved lookup failures are remembered, since hosts do come back.
+    const FAILURE_TTL_HOURS: i64 = 24;
+
+    pub fn new(max_failures: u32) -> Self {
+        DnsCache {
+            max_failures,
+            path: None,
+            set_aside: None,
+            hosts: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Creates a cache backed by the file at `path`, loading lookup failures saved
+    /// by previous (or concurrent) runs. Failures are only written back by `save`.
+    ///
+    /// A cache file that can't be parsed is treated as empty, and moved aside to
+    /// `<path>.corrupt` (see `set_aside`).
+    pub fn with_file<P: Into<PathBuf>>(path: P, max_failures: u32) -> io::Result<Self> {
+        let path = path.into();
+        let (saved, set_aside) = read_locked::<HashMap<String, SavedFailures>>(&path)?;
+        let now = Utc::now().naive_utc();
+        let hosts = saved
+            .unwrap_or_default()
+            .into_iter()
+            .filter(|(_, saved)| now - saved.last_failed < Duration::hours(Self::FAILURE_TTL_HOURS))
+            .map(|(host, saved)| (host, HostState::Failed(saved.failures)))
+            .collect();
+        Ok(DnsCache {
+            max_failures,
+            path: Some(path),
+            set_aside,
+            hosts: Mutex::new(hosts),
+        })
+    }
+
+    /// The cache file, if it couldn't be parsed when the cache was loaded.
+    pub fn set_aside(&self) -> Option<&SetAside> {
+        self.set_aside.as_ref()
+    }
+
+    /// Writes lookup failures to the cache file, merging them with any that other
+    /// processes have saved. Does nothing for in-memory caches.
+    ///
+    /// Returns the cache file if it had to be set aside, as it couldn't be parsed.
+    pub fn save(&self) -> io::Result<Option<SetAside>> {
+        let path = match &self.path {
+            Some(path) => path,
+            None => return Ok(None),
+        };
+        let now = Utc::now().naive_utc();
+        let hosts = self.hosts.lock().unwrap();
+        update_locked(path, |saved: Option<HashMap<String, SavedFailures>>| {
+            let mut merged = saved.unwrap_or_default();
+            for (host, state) in hosts.iter() {
+                match state {
+                    HostState::Resolved => {
+                        merged.remove(host);
+                    }
+                    HostState::Failed(failures) => match merged.get(host) {
+                        Some(saved) if saved.failures >= *failures => {}
+                        _ => {
+                            merged.insert(
+                                host.clone(),
+                                SavedFailures {
+                                    failures: *failures,
+                                    last_failed: now,
+                                },
+                            );
+                        }
+                    },
+                }
+            }
+            merged.retain(|_, saved| {
+                now - saved.last_failed < Duration::hours(Self::FAILURE_TTL_HOURS)
+            });
+            merged
+        })
+    }
+
+    /// Resolves the hosts of `urls` with up to `concurrency` lookups in flight.
+    pub async fn preresolve<I>(&self, urls: I, concurrency: usize)
+    where
+        I: IntoIterator<Item = String>,
+    {
+        let mut hosts: Vec<String> = urls.into_iter().filter_map(|url| host_of(&url)).collect();
+        hosts.sort();
+        hosts.dedup();
+        stream::iter(hosts)
+            .for_each_concurrent(concurrency, |host| async move {
+                self.lookup(&host).await;
+            })
+            .await;
+    }
+
+    /// Checks that the host of `url` resolves, returning `ArchiveError::DeadHost` if its
+    /// lookups have failed too many times. URLs without a host are always accepted.
+    pub async fn check(&self, url: &str) -> Result<(), ArchiveError> {
+        let host = match host_of(url) {
+            Some(host) => host,
+            None => return Ok(()),
+        };
+        let state = self.hosts.lock().unwrap().get(&host).copied();
+        let state = match state {
+            Some(HostState::Resolved) => HostState::Resolved,
+            Some(HostState::Failed(n)) if n >= self.max_failures => HostState::Failed(n),
+            _ => self.lookup(&host).await,
+        };
+        match state {
+            HostState::Failed(n) if n >= self.max_failures => Err(ArchiveError::DeadHost(host)),
+            _ => Ok(()),
+        }
+    }
+
+    async fn lookup(&self, host: &str) -> HostState {
+        let resolved = tokio::net::lookup_host((host, 80))
+            .await
+            .map(|mut addrs| addrs.next().is_some())
+            .unwrap_or(false);
+        let mut hosts = self.hosts.lock().unwrap();
+        let state = if resolved {
+            HostState::Resolved
+        } else {
+            match hosts.get(host) {
+                Some(HostState::Failed(n)) => HostState::Failed(n + 1),
+                _ => HostState::Failed(1),
+            }
+        };
+        hosts.insert(host.to_string(), state);
+        state
+    }
+}
+
+fn host_of(url: &str) -> Option<String> {
+    Url::parse(&normalize_url(url))
+        .ok()
+        .and_then(|url| url.host_str().map(|host| host.to_string()))
+}
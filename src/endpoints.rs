@@ -0,0 +1,29 @@
+/// The base URLs of the Wayback Machine services the library talks to. Defaults to
+/// archive.org; override them per client (with `WaybackClient::with_endpoints`) to use
+/// a mirror, a self-hosted Wayback deployment (e.g. pywb or OpenWayback), or a mock
+/// server.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Endpoints {
+    /// Base URL of the availability API (`{availability}/wayback/available`).
+    pub availability: String,
+    /// Base URL of the CDX API (`{web}/cdx/search/cdx`) and snapshots themselves
+    /// (`{web}/web/`).
+    pub web: String,
+    /// Base URL of Save Page Now (`{save}/{url}`, and SPN2's `{save}` and
+    /// `{save}/status/`).
+    pub save: String,
+    /// Base URL of the IA-S3 API, which files are uploaded to items with
+    /// (`{s3}/{identifier}/{filename}`).
+    pub s3: String,
+}
+
+impl Default for Endpoints {
+    fn default() -> Self {
+        Endpoints {
+            availability: "http://archive.org".to_string(),
+            web: "https://web.archive.org".to_string(),
+            save: "https://web.archive.org/save".to_string(),
+            s3: "https://s3.us.archive.org".to_string(),
+        }
+    }
+}
@@ -0,0 +1,127 @@
+use std::collections::VecDeque;
+use std::time::Duration;
+
+/// How many recent captures the rolling averages are taken over.
+const WINDOW: usize = 50;
+
+/// Estimates how long the rest of a run will take from how long recent URLs took.
+///
+/// New captures (which include cooldowns and retries) and reused existing snapshots
+/// take very different amounts of time, so they're averaged separately and weighted
+/// by how often each has occurred. URLs skipped without any request are assumed to
+/// take no time.
+#[derive(Default)]
+pub struct Eta {
+    new_captures: Samples,
+    existing_snapshots: Samples,
+}
+
+#[derive(Default)]
+struct Samples {
+    count: usize,
+    recent: VecDeque<Duration>,
+}
+
+impl Samples {
+    fn record(&mut self, elapsed: Duration) {
+        self.count += 1;
+        if self.recent.len() == WINDOW {
+            self.recent.pop_front();
+        }
+        self.recent.push_back(elapsed);
+    }
+
+    fn average(&self) -> Duration {
+        match self.recent.len() {
+            0 => Duration::ZERO,
+            len => self.recent.iter().sum::<Duration>() / len as u32,
+        }
+    }
+}
+
+impl Eta {
+    /// Records how long archiving a URL took.
+    pub fn record(&mut self, existing_snapshot: bool, elapsed: Duration) {
+        if existing_snapshot {
+            self.existing_snapshots.record(elapsed);
+        } else {
+            self.new_captures.record(elapsed);
+        }
+    }
+
+    /// Estimates the time left for `remaining` URLs, given that `processed` URLs
+    /// (including skipped ones) have been handled so far. Returns `None` until
+    /// there's something to go on.
+    pub fn estimate(&self, processed: usize, remaining: usize) -> Option<Duration> {
+        if self.new_captures.count + self.existing_snapshots.count == 0 || processed == 0 {
+            return None;
+        }
+        let total: Duration = self.new_captures.average() * self.new_captures.count as u32
+            + self.existing_snapshots.average() * self.existing_snapshots.count as u32;
+        Some(total.mul_f64(remaining as f64 / processed as f64))
+    }
+}
+
+/// Formats an estimate coarsely, e.g. `3h12m`, `45m`, or `30s`.
+pub fn format_estimate(estimate: Duration) -> String {
+    let secs = estimate.as_secs();
+    match (secs / 3600, secs / 60 % 60) {
+        (0, 0) => format!("{}s", secs),
+        (0, mins) => format!("{}m", mins),
+        (hours, mins) => format!("{}h{:02}m", hours, mins),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn secs(secs: u64) -> Duration {
+        Duration::from_secs(secs)
+    }
+
+    #[test]
+    fn weights_captures_and_existing_snapshots_by_how_often_they_occur() {
+        let mut eta = Eta::default();
+        assert_eq!(eta.estimate(0, 10), None);
+        eta.record(false, secs(10));
+        for _ in 0..3 {
+            eta.record(true, secs(1));
+        }
+        // 13s for 4 URLs, so 26s for 8 more.
+        assert_eq!(eta.estimate(4, 8), Some(secs(26)));
+        // Skipped URLs take no time, bringing the average down.
+        assert_eq!(eta.estimate(13, 8), Some(secs(8)));
+        assert_eq!(eta.estimate(4, 0), Some(Duration::ZERO));
+    }
+
+    #[test]
+    fn averages_recent_captures() {
+        let mut eta = Eta::default();
+        for _ in 0..WINDOW {
+            eta.record(false, secs(60));
+        }
+        for _ in 0..WINDOW {
+            eta.record(false, secs(6));
+        }
+        // Every capture counts, but at the recent captures' pace.
+        assert_eq!(eta.estimate(2 * WINDOW, 10), Some(secs(60)));
+    }
+
+    #[test]
+    fn handles_urls_that_take_no_time() {
+        let mut eta = Eta::default();
+        eta.record(true, Duration::ZERO);
+        assert_eq!(eta.estimate(1, 100), Some(Duration::ZERO));
+        // Nothing processed yet, so no rate to go on (rather than dividing by zero).
+        assert_eq!(eta.estimate(0, 100), None);
+    }
+
+    #[test]
+    fn formats_estimates_coarsely() {
+        assert_eq!(format_estimate(secs(30)), "30s");
+        assert_eq!(format_estimate(secs(45 * 60 + 30)), "45m");
+        assert_eq!(format_estimate(secs(3 * 3600 + 12 * 60)), "3h12m");
+        assert_eq!(format_estimate(secs(3600 + 5 * 60)), "1h05m");
+    }
+}
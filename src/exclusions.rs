@@ -0,0 +1,207 @@
+//! Hosts that must never be archived: internal hosts, which are always excluded, and
+//! the domains listed in an opt-out file (`--exclusions-file`).
+//!
+//! Opt-out files may be written in any of these formats, or a mix of them:
+//!
+//! - Plain domains, one per line (`example.com`, or `*.example.com`).
+//! - Hosts files (`0.0.0.0 example.com`).
+//! - uBlock/Adblock-style domain rules (`||example.com^`).
+//!
+//! Comments (`#` or `!`) are ignored, as are rules that don't exclude a whole domain
+//! (e.g. cosmetic filters), which are counted so they can be reported.
+use reqwest::Url;
+use std::collections::HashSet;
+use std::fs;
+use std::io;
+use std::net::IpAddr;
+use wayback_archiver::normalize_url;
+
+/// Suffixes of hostnames that are only meaningful on a local network.
+const INTERNAL_SUFFIXES: &[&str] = &["localhost", "local", "internal", "lan", "home.arpa"];
+
+#[derive(Debug, Default)]
+pub struct Exclusions {
+    domains: HashSet<String>,
+    /// Lines of the opt-out file that were neither comments nor domain rules.
+    pub ignored_lines: usize,
+}
+
+/// Why a URL is excluded.
+#[derive(Debug, PartialEq)]
+pub enum Exclusion {
+    /// The URL is on a local network, which the Wayback Machine can't reach.
+    InternalHost,
+    /// The URL's domain is on the opt-out list.
+    OptedOut,
+}
+
+impl Exclusions {
+    /// Loads the opt-out file at `path`.
+    pub fn load(path: &str) -> io::Result<Exclusions> {
+        Ok(Exclusions::parse(&fs::read_to_string(path)?))
+    }
+
+    fn parse(contents: &str) -> Exclusions {
+        let mut exclusions = Exclusions::default();
+        for line in contents.lines() {
+            // `#` only starts a comment at the start of a line or after whitespace, as
+            // it's also part of cosmetic filters (`example.com##.banner`).
+            let line = match line.find(" #").or_else(|| line.find("\t#")) {
+                Some(comment) => &line[..comment],
+                None => line,
+            }
+            .trim();
+            if line.is_empty() || line.starts_with(['#', '!', '[']) {
+                continue;
+            }
+            let domains = parse_rule(line);
+            if domains.is_empty() {
+                exclusions.ignored_lines += 1;
+            }
+            exclusions.domains.extend(domains);
+        }
+        exclusions
+    }
+
+    /// Returns why `url` must not be archived, if it mustn't.
+    pub fn check(&self, url: &str) -> Option<Exclusion> {
+        let url = Url::parse(&normalize_url(url)).ok()?;
+        let host = url.host_str()?.trim_end_matches('.').to_ascii_lowercase();
+        if is_internal(&host) {
+            return Some(Exclusion::InternalHost);
+        }
+        // Check the host and each of its parent domains.
+        let mut domain = host.as_str();
+        loop {
+            if self.domains.contains(domain) {
+                return Some(Exclusion::OptedOut);
+            }
+            match domain.split_once('.') {
+                Some((_, parent)) => domain = parent,
+                None => return None,
+            }
+        }
+    }
+}
+
+/// The domains excluded by a single (non-comment) line of an opt-out file.
+fn parse_rule(line: &str) -> Vec<String> {
+    // uBlock/Adblock: `||example.com^`, possibly followed by `$options`.
+    if let Some(rule) = line.strip_prefix("||") {
+        let end = rule.find(['^', '$', '/']).unwrap_or(rule.len());
+        let is_whole_domain = rule[end..].is_empty() || rule[end..].starts_with('^');
+        return match parse_domain(&rule[..end]) {
+            Some(domain) if is_whole_domain => vec![domain],
+            _ => Vec::new(),
+        };
+    }
+    let mut fields = line.split_whitespace();
+    let first = fields.next().unwrap_or_default();
+    if first.parse::<IpAddr>().is_ok() {
+        // Hosts file: an address followed by the hostnames mapped to it.
+        return fields.filter_map(parse_domain).collect();
+    }
+    match (parse_domain(first), fields.next()) {
+        (Some(domain), None) => vec![domain],
+        _ => Vec::new(),
+    }
+}
+
+fn parse_domain(domain: &str) -> Option<String> {
+    let domain = domain
+        .trim_start_matches("*.")
+        .trim_start_matches('.')
+        .trim_end_matches('.')
+        .to_ascii_lowercase();
+    let is_domain = !domain.is_empty()
+        && domain
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '.');
+    Some(domain).filter(|_| is_domain)
+}
+
+fn is_internal(host: &str) -> bool {
+    let ip = host.trim_start_matches('[').trim_end_matches(']');
+    match ip.parse::<IpAddr>() {
+        Ok(ip) => is_internal_address(ip),
+        Err(_) => {
+            !host.contains('.')
+                || INTERNAL_SUFFIXES
+                    .iter()
+                    .any(|suffix| host == *suffix || host.ends_with(&format!(".{}", suffix)))
+        }
+    }
+}
+
+/// Whether `ip` is on a local network (or the host itself), e.g. what an external
+/// hostname resolves to.
+pub fn is_internal_address(ip: IpAddr) -> bool {
+    match ip {
+        IpAddr::V4(ip) => {
+            ip.is_loopback() || ip.is_private() || ip.is_link_local() || ip.is_unspecified()
+        }
+        IpAddr::V6(ip) => match ip.to_ipv4_mapped() {
+            Some(ip) => is_internal_address(IpAddr::V4(ip)),
+            // Loopback, unspecified, unique local (fc00::/7), and link-local (fe80::/10).
+            None => {
+                ip.is_loopback()
+                    || ip.is_unspecified()
+                    || (ip.segments()[0] & 0xfe00) == 0xfc00
+                    || (ip.segments()[0] & 0xffc0) == 0xfe80
+            }
+        },
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_mixed_formats() {
+        let exclusions = Exclusions::parse(
+            "# Opt-outs\n\
+             example.com\n\
+             *.wildcard.org\n\
+             0.0.0.0 hosts.net other.net # from a hosts file\n\
+             ! uBlock rules\n\
+             ||ublock.io^$third-party\n\
+             ||example.net/ads/*\n\
+             example.org##.banner\n",
+        );
+        let check = |url| exclusions.check(url);
+        assert_eq!(check("https://example.com/a"), Some(Exclusion::OptedOut));
+        assert_eq!(check("www.example.com"), Some(Exclusion::OptedOut));
+        assert_eq!(check("a.wildcard.org"), Some(Exclusion::OptedOut));
+        assert_eq!(check("other.net"), Some(Exclusion::OptedOut));
+        assert_eq!(check("https://ublock.io/"), Some(Exclusion::OptedOut));
+        assert_eq!(check("notexample.com"), None);
+        // Rules for parts of a domain don't exclude all of it.
+        assert_eq!(check("example.net"), None);
+        assert_eq!(check("example.org"), None);
+        assert_eq!(exclusions.ignored_lines, 2);
+    }
+
+    #[test]
+    fn excludes_internal_hosts() {
+        let exclusions = Exclusions::default();
+        for url in [
+            "http://localhost:8080/",
+            "intranet/wiki",
+            "printer.local",
+            "https://10.1.2.3/",
+            "http://192.168.0.1/",
+            "http://[::1]/",
+            "http://[::ffff:10.0.0.1]/",
+        ] {
+            assert_eq!(
+                exclusions.check(url),
+                Some(Exclusion::InternalHost),
+                "{}",
+                url
+            );
+        }
+        assert_eq!(exclusions.check("https://example.com/"), None);
+        assert_eq!(exclusions.check("https://8.8.8.8/"), None);
+    }
+}
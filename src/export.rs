@@ -0,0 +1,611 @@
+//! Flattens results into one row per URL (or aggregates them per domain), for loading
+//! into analytics tools.
+use crate::output::ResultsFile;
+use crate::overrides::Overrides;
+use crate::run_result::RunResult;
+use chrono::{NaiveDateTime, Utc};
+use clap::Clap;
+use serde::Serialize;
+use std::collections::BTreeMap;
+use std::fs;
+use std::io::{self, Write};
+use std::str::FromStr;
+use wayback_archiver::{normalize_url, ArchivingResult, Endpoints, Outcome};
+
+#[derive(Clap)]
+pub struct ExportOpts {
+    /// The results file to export.
+    #[clap(short, long = "in")]
+    input: String,
+    /// The format to export: csv, json, or parquet (requires the parquet
+    /// feature).
+    #[clap(long, default_value = "csv")]
+    format: Format,
+    /// If set, exports aggregates rather than individual URLs: per "domain",
+    /// or per "tag".
+    #[clap(long)]
+    rollup: Option<Rollup>,
+    /// If set, only URLs with this tag are exported.
+    #[clap(long)]
+    only_tag: Option<String>,
+    /// A file of manual overrides for specific URLs (see --overrides when
+    /// archiving): pinned snapshots and alternate URLs' snapshots are exported
+    /// in place of the results', along with notes.
+    #[clap(long)]
+    overrides: Option<String>,
+    /// Where to write the export. Defaults to stdout for CSV and JSON.
+    #[clap(short, long)]
+    out: Option<String>,
+}
+
+pub enum Format {
+    Csv,
+    Json,
+    Parquet,
+}
+
+impl FromStr for Format {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "csv" => Ok(Format::Csv),
+            "json" => Ok(Format::Json),
+            "parquet" => Ok(Format::Parquet),
+            _ => Err(format!("unknown export format: {}", s)),
+        }
+    }
+}
+
+pub enum Rollup {
+    Domain,
+    Tag,
+}
+
+impl FromStr for Rollup {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "domain" => Ok(Rollup::Domain),
+            "tag" => Ok(Rollup::Tag),
+            _ => Err(format!("unknown rollup: {}", s)),
+        }
+    }
+}
+
+/// A single URL's result.
+#[derive(Serialize)]
+pub struct Row<'a> {
+    pub url: &'a str,
+    pub domain: Option<String>,
+    pub snapshot_url: Option<&'a str>,
+    pub last_archived: Option<NaiveDateTime>,
+    pub first_archived: Option<NaiveDateTime>,
+    pub total_captures: Option<u64>,
+    /// One of `captured`, `pending`, `existing-snapshot`, `stale-fallback`, or
+    /// `failed`.
+    pub status: &'static str,
+    /// The failure's code (see `wayback-archiver codes`), or for results recorded
+    /// before codes were, its classified error message.
+    pub error_class: Option<&'a str>,
+    /// The URL's note in the overrides file, if any.
+    pub note: Option<&'a str>,
+}
+
+impl<'a> Row<'a> {
+    fn new(url: &'a str, result: &'a ArchivingResult) -> Self {
+        let status = match result.outcome {
+            Outcome::NewCapture { .. } if result.pending => "pending",
+            Outcome::NewCapture { .. } => "captured",
+            Outcome::ExistingSnapshot { .. } => "existing-snapshot",
+            Outcome::StaleFallback { .. } => "stale-fallback",
+            Outcome::Failed { .. } => "failed",
+        };
+        Row {
+            url,
+            domain: reqwest::Url::parse(&normalize_url(url))
+                .ok()
+                .and_then(|parsed| parsed.host_str().map(str::to_string)),
+            snapshot_url: result.outcome.url(),
+            last_archived: result.outcome.ts(),
+            first_archived: result.first_archived,
+            total_captures: result.total_captures,
+            status,
+            error_class: result.outcome.error().map(|error| {
+                result
+                    .error_code
+                    .as_deref()
+                    .unwrap_or_else(|| error_class(error))
+            }),
+            note: None,
+        }
+    }
+}
+
+/// `url`'s result, with its overrides applied: a pinned snapshot (in the Wayback
+/// Machine at `endpoints`), or failing that the result of its alternate URL (in
+/// `results`), replaces its own.
+fn apply_overrides(
+    url: &str,
+    result: &ArchivingResult,
+    results: &BTreeMap<String, ArchivingResult>,
+    overrides: &Overrides,
+    endpoints: &Endpoints,
+) -> ArchivingResult {
+    let overridden = match overrides.get(url) {
+        Some(overridden) => overridden,
+        None => return result.clone(),
+    };
+    if let Some(pinned) = overridden.pinned_result(endpoints, url) {
+        return ArchivingResult {
+            outcome: pinned.outcome,
+            pending: false,
+            ..result.clone()
+        };
+    }
+    match overridden
+        .alternate
+        .as_ref()
+        .and_then(|alternate| results.get(alternate))
+    {
+        Some(alternate) => ArchivingResult {
+            outcome: alternate.outcome.clone(),
+            pending: alternate.pending,
+            ..result.clone()
+        },
+        None => result.clone(),
+    }
+}
+
+/// Classifies an error message written by `ArchiveError`'s `Display` impl.
+fn error_class(message: &str) -> &'static str {
+    const CLASSES: &[(&str, &str)] = &[
+        ("Bandwidth Exceeded", "bandwidth-exceeded"),
+        ("Wayback Machine unable to archive", "unable-to-archive"),
+        ("No existing snapshots", "no-existing-snapshot"),
+        ("Dead host", "dead-host"),
+        ("Origin unreachable", "origin-unreachable"),
+        ("Wayback Machine requires logging in", "login-required"),
+        ("Wayback Machine unavailable", "service-unavailable"),
+        ("Unexpected response", "http-error"),
+        ("Network error", "network-error"),
+        ("Timed out", "timeout"),
+        ("Cancelled", "cancelled"),
+        ("Parse error", "parse-error"),
+    ];
+    CLASSES
+        .iter()
+        .find(|(prefix, _)| message.starts_with(prefix))
+        .map(|(_, class)| *class)
+        .unwrap_or("unknown")
+}
+
+/// Aggregates of the results for a single domain.
+#[derive(Debug, PartialEq, Serialize)]
+pub struct DomainRollup {
+    pub domain: String,
+    pub urls: usize,
+    /// URLs with a snapshot (including pending ones).
+    pub archived: usize,
+    pub archived_percent: f64,
+    /// The median age of the domain's snapshots, in days.
+    pub median_snapshot_age_days: Option<f64>,
+    pub failures: usize,
+}
+
+/// Aggregates `rows` by domain, measuring snapshot ages relative to `now`.
+fn rollup_by_domain(rows: &[Row], now: NaiveDateTime) -> Vec<DomainRollup> {
+    let mut by_domain: BTreeMap<&str, Vec<&Row>> = BTreeMap::new();
+    for row in rows {
+        by_domain
+            .entry(row.domain.as_deref().unwrap_or_default())
+            .or_default()
+            .push(row);
+    }
+    by_domain
+        .into_iter()
+        .map(|(domain, rows)| {
+            let failures = rows.iter().filter(|row| row.status == "failed").count();
+            let mut ages: Vec<f64> = rows
+                .iter()
+                .filter_map(|row| row.last_archived)
+                .map(|ts| (now - ts).num_seconds() as f64 / 86400.0)
+                .collect();
+            ages.sort_by(|a, b| a.partial_cmp(b).expect("ages are finite"));
+            DomainRollup {
+                domain: domain.to_string(),
+                urls: rows.len(),
+                archived: ages.len(),
+                archived_percent: 100.0 * ages.len() as f64 / rows.len() as f64,
+                median_snapshot_age_days: median(&ages),
+                failures,
+            }
+        })
+        .collect()
+}
+
+/// Aggregates of the results for a single tag.
+#[derive(Debug, PartialEq, Serialize)]
+pub struct TagRollup {
+    pub tag: String,
+    pub urls: usize,
+    /// URLs with a new or existing snapshot (including pending ones).
+    pub archived: usize,
+    /// URLs that fell back to an old snapshot.
+    pub stale: usize,
+    pub failed: usize,
+    /// URLs skipped by a run. Skips aren't saved to results files, so this is only
+    /// counted in run summaries.
+    #[serde(skip)]
+    pub skipped: usize,
+}
+
+impl TagRollup {
+    pub fn new(tag: &str) -> Self {
+        TagRollup {
+            tag: tag.to_string(),
+            urls: 0,
+            archived: 0,
+            stale: 0,
+            failed: 0,
+            skipped: 0,
+        }
+    }
+
+    pub fn add(&mut self, result: &RunResult) {
+        self.urls += 1;
+        let result = match result {
+            RunResult::Archived(result) => result,
+            RunResult::Skipped(_) => {
+                self.skipped += 1;
+                return;
+            }
+        };
+        match result.outcome {
+            Outcome::NewCapture { .. } | Outcome::ExistingSnapshot { .. } => self.archived += 1,
+            Outcome::StaleFallback { .. } => self.stale += 1,
+            Outcome::Failed { .. } => self.failed += 1,
+        }
+    }
+}
+
+/// Aggregates `results` by tag. Results with several tags count towards each of
+/// them, and untagged results aren't counted.
+pub fn rollup_by_tag<'a, I>(results: I) -> Vec<TagRollup>
+where
+    I: IntoIterator<Item = &'a RunResult>,
+{
+    let mut by_tag: BTreeMap<&str, TagRollup> = BTreeMap::new();
+    for result in results {
+        for tag in result.tags() {
+            by_tag
+                .entry(tag)
+                .or_insert_with(|| TagRollup::new(tag))
+                .add(result);
+        }
+    }
+    by_tag.into_values().collect()
+}
+
+fn median(sorted: &[f64]) -> Option<f64> {
+    let mid = sorted.len() / 2;
+    match sorted.len() {
+        0 => None,
+        len if len % 2 == 0 => Some((sorted[mid - 1] + sorted[mid]) / 2.0),
+        _ => Some(sorted[mid]),
+    }
+}
+
+pub async fn run(
+    opts: &ExportOpts,
+    endpoints: &Endpoints,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let file = ResultsFile::read(&opts.input, false).await?;
+    let overrides = match &opts.overrides {
+        Some(path) => Overrides::load(path)?,
+        None => Overrides::default(),
+    };
+    let results: Vec<(&String, ArchivingResult)> = file
+        .results
+        .iter()
+        .filter(|(_, result)| {
+            opts.only_tag
+                .as_ref()
+                .is_none_or(|tag| result.tags.contains(tag))
+        })
+        .map(|(url, result)| {
+            (
+                url,
+                apply_overrides(url, result, &file.results, &overrides, endpoints),
+            )
+        })
+        .collect();
+    let rows: Vec<Row> = results
+        .iter()
+        .map(|(url, result)| Row {
+            note: overrides
+                .get(url)
+                .and_then(|overridden| overridden.note.as_deref()),
+            ..Row::new(url, result)
+        })
+        .collect();
+    match opts.rollup {
+        Some(Rollup::Domain) => {
+            let rollups = rollup_by_domain(&rows, Utc::now().naive_utc());
+            return write_rollups(&rollups, opts);
+        }
+        Some(Rollup::Tag) => {
+            let results: Vec<RunResult> = results
+                .into_iter()
+                .map(|(_, result)| result.into())
+                .collect();
+            let rollups = rollup_by_tag(&results);
+            return write_rollups(&rollups, opts);
+        }
+        None => {}
+    }
+    match opts.format {
+        Format::Csv => write_csv(&rows, open_out(&opts.out)?),
+        Format::Json => write_json(&rows, open_out(&opts.out)?),
+        Format::Parquet => {
+            let path = opts
+                .out
+                .as_ref()
+                .ok_or("--out is required for Parquet exports")?;
+            write_parquet(&rows, path)
+        }
+    }
+}
+
+fn write_rollups<T: Serialize>(
+    rollups: &[T],
+    opts: &ExportOpts,
+) -> Result<(), Box<dyn std::error::Error>> {
+    match opts.format {
+        Format::Csv => write_csv(rollups, open_out(&opts.out)?),
+        Format::Json => write_json(rollups, open_out(&opts.out)?),
+        Format::Parquet => Err("Rollups can't be exported as Parquet".into()),
+    }
+}
+
+/// Opens the file at `path` for writing, or stdout if there isn't one.
+fn open_out(path: &Option<String>) -> io::Result<Box<dyn Write>> {
+    Ok(match path {
+        Some(path) => Box::new(fs::File::create(path)?),
+        None => Box::new(io::stdout()),
+    })
+}
+
+fn write_csv<T: Serialize>(
+    records: &[T],
+    out: Box<dyn Write>,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let mut writer = csv::Writer::from_writer(out);
+    for record in records {
+        writer.serialize(record)?;
+    }
+    writer.flush()?;
+    Ok(())
+}
+
+fn write_json<T: Serialize>(
+    records: &[T],
+    mut out: Box<dyn Write>,
+) -> Result<(), Box<dyn std::error::Error>> {
+    serde_json::to_writer_pretty(&mut out, records)?;
+    writeln!(out)?;
+    Ok(())
+}
+
+#[cfg(feature = "parquet")]
+fn write_parquet(rows: &[Row], path: &str) -> Result<(), Box<dyn std::error::Error>> {
+    use arrow_array::{ArrayRef, RecordBatch, StringArray, TimestampSecondArray, UInt64Array};
+    use arrow_schema::{DataType, Field, Schema, TimeUnit};
+    use parquet::arrow::ArrowWriter;
+    use std::sync::Arc;
+
+    let timestamp = DataType::Timestamp(TimeUnit::Second, None);
+    let schema = Arc::new(Schema::new(vec![
+        Field::new("url", DataType::Utf8, false),
+        Field::new("domain", DataType::Utf8, true),
+        Field::new("snapshot_url", DataType::Utf8, true),
+        Field::new("last_archived", timestamp.clone(), true),
+        Field::new("first_archived", timestamp, true),
+        Field::new("total_captures", DataType::UInt64, true),
+        Field::new("status", DataType::Utf8, false),
+        Field::new("error_class", DataType::Utf8, true),
+        Field::new("note", DataType::Utf8, true),
+    ]));
+    let columns: Vec<ArrayRef> = vec![
+        Arc::new(StringArray::from_iter_values(
+            rows.iter().map(|row| row.url),
+        )),
+        Arc::new(
+            rows.iter()
+                .map(|row| row.domain.as_deref())
+                .collect::<StringArray>(),
+        ),
+        Arc::new(
+            rows.iter()
+                .map(|row| row.snapshot_url)
+                .collect::<StringArray>(),
+        ),
+        Arc::new(
+            rows.iter()
+                .map(|row| row.last_archived.map(|ts| ts.and_utc().timestamp()))
+                .collect::<TimestampSecondArray>(),
+        ),
+        Arc::new(
+            rows.iter()
+                .map(|row| row.first_archived.map(|ts| ts.and_utc().timestamp()))
+                .collect::<TimestampSecondArray>(),
+        ),
+        Arc::new(
+            rows.iter()
+                .map(|row| row.total_captures)
+                .collect::<UInt64Array>(),
+        ),
+        Arc::new(StringArray::from_iter_values(
+            rows.iter().map(|row| row.status),
+        )),
+        Arc::new(
+            rows.iter()
+                .map(|row| row.error_class)
+                .collect::<StringArray>(),
+        ),
+        Arc::new(rows.iter().map(|row| row.note).collect::<StringArray>()),
+    ];
+    let batch = RecordBatch::try_new(schema.clone(), columns)?;
+    let mut writer = ArrowWriter::try_new(fs::File::create(path)?, schema, None)?;
+    writer.write(&batch)?;
+    writer.close()?;
+    Ok(())
+}
+
+#[cfg(not(feature = "parquet"))]
+fn write_parquet(_rows: &[Row], _path: &str) -> Result<(), Box<dyn std::error::Error>> {
+    Err("Parquet exports require wayback-archiver to be built with the parquet feature".into())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::codes::SkipReason;
+    use crate::run_result::Skip;
+
+    fn result(last_archived: &str, error: Option<&str>) -> ArchivingResult {
+        ArchivingResult::new(match error {
+            Some(error) => Outcome::Failed {
+                error: error.to_string(),
+            },
+            None => Outcome::NewCapture {
+                url: String::new(),
+                ts: last_archived.parse().unwrap(),
+            },
+        })
+    }
+
+    #[test]
+    fn rolls_up_by_domain() {
+        let results = [
+            ("https://a.com/1", result("2021-01-01T00:00:00", None)),
+            ("https://a.com/2", result("2021-01-03T00:00:00", None)),
+            (
+                "https://a.com/3",
+                result("2021-01-04T00:00:00", Some("Bandwidth Exceeded")),
+            ),
+            ("b.org", result("2021-01-05T00:00:00", None)),
+        ];
+        let rows: Vec<Row> = results
+            .iter()
+            .map(|(url, result)| Row::new(url, result))
+            .collect();
+        let now = "2021-01-11T00:00:00".parse().unwrap();
+        assert_eq!(
+            rollup_by_domain(&rows, now),
+            vec![
+                DomainRollup {
+                    domain: "a.com".to_string(),
+                    urls: 3,
+                    archived: 2,
+                    archived_percent: 200.0 / 3.0,
+                    median_snapshot_age_days: Some(9.0),
+                    failures: 1,
+                },
+                DomainRollup {
+                    domain: "b.org".to_string(),
+                    urls: 1,
+                    archived: 1,
+                    archived_percent: 100.0,
+                    median_snapshot_age_days: Some(6.0),
+                    failures: 0,
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn applies_overrides() {
+        let overrides = Overrides::parse(
+            r#"
+["https://a.com/pinned"]
+pin = "2019"
+
+["https://a.com/moved"]
+alternate = "https://b.com/moved"
+"#,
+        )
+        .unwrap();
+        let results: BTreeMap<String, ArchivingResult> = vec![
+            ("https://a.com/pinned", result("2021-01-01T00:00:00", None)),
+            (
+                "https://a.com/moved",
+                result("", Some("Wayback Machine unable to archive this URL")),
+            ),
+            ("https://b.com/moved", result("2021-01-02T00:00:00", None)),
+        ]
+        .into_iter()
+        .map(|(url, result)| (url.to_string(), result))
+        .collect();
+        let applied = |url: &str| {
+            apply_overrides(
+                url,
+                &results[url],
+                &results,
+                &overrides,
+                &Endpoints::default(),
+            )
+        };
+        assert_eq!(
+            applied("https://a.com/pinned").outcome.ts(),
+            Some("2019-01-01T00:00:00".parse().unwrap())
+        );
+        assert_eq!(
+            applied("https://a.com/moved").outcome,
+            results["https://b.com/moved"].outcome
+        );
+        assert_eq!(
+            applied("https://b.com/moved").outcome,
+            results["https://b.com/moved"].outcome
+        );
+    }
+
+    #[test]
+    fn rolls_up_by_tag() {
+        let tagged = |result: ArchivingResult, tags: &[&str]| ArchivingResult {
+            tags: tags.iter().map(|tag| tag.to_string()).collect(),
+            ..result
+        };
+        let results = [
+            tagged(result("2021-01-01T00:00:00", None), &["research", "news"]).into(),
+            tagged(result("", Some("Bandwidth Exceeded")), &["research"]).into(),
+            RunResult::Skipped(Skip {
+                reason: SkipReason::AlreadyArchived,
+                tags: vec!["news".to_string()],
+            }),
+            result("2021-01-01T00:00:00", None).into(),
+        ];
+        assert_eq!(
+            rollup_by_tag(&results),
+            vec![
+                TagRollup {
+                    tag: "news".to_string(),
+                    urls: 2,
+                    archived: 1,
+                    stale: 0,
+                    failed: 0,
+                    skipped: 1,
+                },
+                TagRollup {
+                    tag: "research".to_string(),
+                    urls: 2,
+                    archived: 1,
+                    stale: 0,
+                    failed: 1,
+                    skipped: 0,
+                },
+            ]
+        );
+    }
+}
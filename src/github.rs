@@ -0,0 +1,246 @@
+//! GitHub push webhooks for `wayback-archiver serve` (`POST /github`): the links newly
+//! added to a repository's Markdown and HTML files by each push to its default branch
+//! are archived, keeping a docs repository's external links preserved without CI.
+//!
+//! Deliveries are verified against the webhook's secret (`GITHUB_WEBHOOK_SECRET`, see
+//! `wayback_archiver::secrets`). Files are fetched from raw.githubusercontent.com,
+//! with `GITHUB_TOKEN` if it's set, so that private repositories can be read.
+use hmac::{Hmac, Mac};
+use regex::Regex;
+use serde::Deserialize;
+use sha2::Sha256;
+use std::collections::BTreeSet;
+use std::sync::LazyLock;
+
+/// Extensions of the files whose links are archived.
+const DOCUMENT_EXTENSIONS: &[&str] = &["md", "markdown", "html", "htm"];
+
+/// The parts of a push event that are needed to find the added links.
+#[derive(Debug, Deserialize)]
+pub struct PushEvent {
+    #[serde(rename = "ref")]
+    git_ref: String,
+    /// The commit before the push (all zeros for a new branch).
+    before: String,
+    after: String,
+    repository: Repository,
+    #[serde(default)]
+    commits: Vec<Commit>,
+}
+
+#[derive(Debug, Deserialize)]
+struct Repository {
+    full_name: String,
+    default_branch: String,
+}
+
+#[derive(Debug, Deserialize)]
+struct Commit {
+    #[serde(default)]
+    added: Vec<String>,
+    #[serde(default)]
+    modified: Vec<String>,
+}
+
+/// A Markdown or HTML file changed by a push.
+#[derive(Debug, PartialEq)]
+struct ChangedFile {
+    path: String,
+    /// Whether the file existed before the push, in which case only links that it
+    /// didn't already have are new.
+    existed: bool,
+}
+
+impl PushEvent {
+    /// Whether the push was to the repository's default branch. Pushes to other
+    /// branches (e.g. drafts) are ignored.
+    pub fn is_to_default_branch(&self) -> bool {
+        self.git_ref == format!("refs/heads/{}", self.repository.default_branch)
+    }
+
+    fn changed_files(&self) -> Vec<ChangedFile> {
+        let is_new_branch = self.before.chars().all(|c| c == '0');
+        let mut added = BTreeSet::new();
+        let mut modified = BTreeSet::new();
+        for commit in &self.commits {
+            added.extend(commit.added.iter().filter(|path| is_document(path)));
+            modified.extend(commit.modified.iter().filter(|path| is_document(path)));
+        }
+        let mut files: Vec<ChangedFile> = added
+            .iter()
+            .map(|path| ChangedFile {
+                path: path.to_string(),
+                existed: false,
+            })
+            .collect();
+        // A file that was added and then modified within the push is still new.
+        files.extend(modified.difference(&added).map(|path| ChangedFile {
+            path: path.to_string(),
+            existed: !is_new_branch,
+        }));
+        files
+    }
+
+    /// The links added to the repository's documents by this push.
+    pub async fn added_links(
+        &self,
+        http: &reqwest::Client,
+        token: Option<&str>,
+    ) -> Result<BTreeSet<String>, Box<dyn std::error::Error + Send + Sync>> {
+        let mut added = BTreeSet::new();
+        for file in self.changed_files() {
+            let links = match self.fetch(http, token, &self.after, &file.path).await? {
+                Some(contents) => extract_links(&contents),
+                // Removed again later in the push.
+                None => continue,
+            };
+            let previous = if file.existed {
+                match self.fetch(http, token, &self.before, &file.path).await? {
+                    Some(contents) => extract_links(&contents),
+                    None => BTreeSet::new(),
+                }
+            } else {
+                BTreeSet::new()
+            };
+            added.extend(links.difference(&previous).cloned());
+        }
+        Ok(added)
+    }
+
+    /// Fetches the file at `path` as of `commit`, or `None` if it doesn't exist there.
+    async fn fetch(
+        &self,
+        http: &reqwest::Client,
+        token: Option<&str>,
+        commit: &str,
+        path: &str,
+    ) -> Result<Option<String>, Box<dyn std::error::Error + Send + Sync>> {
+        let mut request = http.get(format!(
+            "https://raw.githubusercontent.com/{}/{}/{}",
+            self.repository.full_name, commit, path
+        ));
+        if let Some(token) = token {
+            request = request.header(reqwest::header::AUTHORIZATION, format!("token {}", token));
+        }
+        let resp = request.send().await?;
+        if resp.status() == reqwest::StatusCode::NOT_FOUND {
+            return Ok(None);
+        }
+        Ok(Some(resp.error_for_status()?.text().await?))
+    }
+}
+
+fn is_document(path: &str) -> bool {
+    path.rsplit_once('.').is_some_and(|(_, extension)| {
+        DOCUMENT_EXTENSIONS.contains(&extension.to_ascii_lowercase().as_str())
+    })
+}
+
+/// The absolute http(s) links in a Markdown or HTML document.
+fn extract_links(contents: &str) -> BTreeSet<String> {
+    static LINK: LazyLock<Regex> =
+        LazyLock::new(|| Regex::new(r#"https?://[^\s<>"'`()\[\]{}]+"#).unwrap());
+    LINK.find_iter(contents)
+        // Punctuation ending a sentence isn't part of the link.
+        .map(|link| {
+            link.as_str()
+                .trim_end_matches(['.', ',', ';', ':', '!', '?', '*'])
+        })
+        .map(str::to_string)
+        .collect()
+}
+
+/// Checks a delivery's `X-Hub-Signature-256` header (`sha256=<hex HMAC of the body>`)
+/// against the webhook's `secret`.
+pub fn verify_signature(secret: &str, body: &[u8], signature: Option<&str>) -> bool {
+    let expected = match signature
+        .and_then(|signature| signature.strip_prefix("sha256="))
+        .and_then(decode_hex)
+    {
+        Some(expected) => expected,
+        None => return false,
+    };
+    let mut mac = match Hmac::<Sha256>::new_from_slice(secret.as_bytes()) {
+        Ok(mac) => mac,
+        Err(_) => return false,
+    };
+    mac.update(body);
+    // Compares in constant time.
+    mac.verify_slice(&expected).is_ok()
+}
+
+fn decode_hex(hex: &str) -> Option<Vec<u8>> {
+    (0..hex.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(hex.get(i..i + 2)?, 16).ok())
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn verifies_signatures() {
+        // RFC 4231, test case 2.
+        let signature = "sha256=5bdcc146bf60754e6a042426089575c75a003f089d2739839dec58b964ec3843";
+        let body = b"what do ya want for nothing?";
+        assert!(verify_signature("Jefe", body, Some(signature)));
+        let uppercase = format!("sha256={}", signature[7..].to_uppercase());
+        assert!(verify_signature("Jefe", body, Some(&uppercase)));
+        assert!(!verify_signature("Jefe", body, Some(&signature[..70])));
+        assert!(!verify_signature("Jeff", body, Some(signature)));
+        assert!(!verify_signature(
+            "Jefe",
+            b"what do you want?",
+            Some(signature)
+        ));
+        assert!(!verify_signature("Jefe", body, None));
+    }
+
+    #[test]
+    fn extracts_links() {
+        let links = extract_links(
+            "See [the docs](https://example.com/docs), or <a href=\"http://example.org/a?b=c\">this</a>.\n\
+             More at https://example.net/page. Not ftp://example.com.",
+        );
+        assert_eq!(
+            links.into_iter().collect::<Vec<_>>(),
+            [
+                "http://example.org/a?b=c",
+                "https://example.com/docs",
+                "https://example.net/page"
+            ]
+        );
+    }
+
+    #[test]
+    fn finds_changed_documents() {
+        let event: PushEvent = serde_json::from_str(
+            r#"{
+                "ref": "refs/heads/main",
+                "before": "abc",
+                "after": "def",
+                "repository": {"full_name": "owner/docs", "default_branch": "main"},
+                "commits": [
+                    {"added": ["new.md", "image.png"], "modified": ["README.md"]},
+                    {"added": [], "modified": ["new.md", "site/index.HTML"]}
+                ]
+            }"#,
+        )
+        .unwrap();
+        assert!(event.is_to_default_branch());
+        let changed = |path: &str, existed| ChangedFile {
+            path: path.to_string(),
+            existed,
+        };
+        assert_eq!(
+            event.changed_files(),
+            [
+                changed("new.md", false),
+                changed("README.md", true),
+                changed("site/index.HTML", true)
+            ]
+        );
+    }
+}
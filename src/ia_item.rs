@@ -0,0 +1,177 @@
+//! Uploads to [Internet Archive items](https://archive.org/developers/items.html)
+//! through the IA-S3 API (archive.org's S3-like upload API), e.g. to publish locally
+//! captured WARCs (see `LocalWarc::with_item`) as part of a personal web collection.
+use crate::client::network_error;
+use crate::{body_snippet, ArchiveError, WaybackClient};
+use reqwest::header::AUTHORIZATION;
+use std::path::Path;
+
+/// Where uploaded files are downloaded from.
+const DOWNLOAD: &str = "https://archive.org/download";
+
+/// An Internet Archive item that files are uploaded to. It's created, with its
+/// metadata, by the first upload; later uploads add files to it.
+///
+/// ```no_run
+/// # async fn example() -> Result<(), wayback_archiver::ArchiveError> {
+/// use wayback_archiver::{IaItem, WaybackClient};
+///
+/// let item = IaItem::new("my-web-collection-2024")
+///     .with_metadata("title", "My web collection (2024)")
+///     .with_metadata("subject", "bookmarks")
+///     .with_metadata("subject", "web archive");
+/// let client = WaybackClient::new().with_credentials("access", "secret");
+/// let url = client
+///     .upload_to_item(&item, "warcs/20240501120000-example.com.warc".as_ref())
+///     .await?;
+/// println!("Uploaded to {}", url);
+/// # Ok(())
+/// # }
+/// ```
+#[derive(Clone, Debug, PartialEq)]
+pub struct IaItem {
+    identifier: String,
+    metadata: Vec<(String, String)>,
+}
+
+impl IaItem {
+    /// The item with `identifier` (the `my-item` of `https://archive.org/details/my-item`).
+    pub fn new(identifier: &str) -> Self {
+        IaItem {
+            identifier: identifier.to_string(),
+            metadata: Vec::new(),
+        }
+    }
+
+    /// Sets a metadata field (e.g. `title`, `collection`, or `subject`) of the item,
+    /// when it's created. Fields with several values (like `subject`) may be set more
+    /// than once. Items are web items (`mediatype` `web`) unless set otherwise.
+    pub fn with_metadata(mut self, name: &str, value: &str) -> Self {
+        self.metadata.push((name.to_string(), value.to_string()));
+        self
+    }
+
+    /// The item's metadata, as IA-S3 headers.
+    fn metadata_headers(&self) -> Vec<(String, String)> {
+        let mut headers = Vec::new();
+        if !self.metadata.iter().any(|(name, _)| name == "mediatype") {
+            headers.push(("x-archive-meta-mediatype".to_string(), "web".to_string()));
+        }
+        let count = |fields: &[(String, String)], name: &str| {
+            fields.iter().filter(|(other, _)| other == name).count()
+        };
+        for (i, (name, value)) in self.metadata.iter().enumerate() {
+            // Repeated fields are numbered, e.g. `x-archive-meta01-subject`.
+            let header = match count(&self.metadata, name) {
+                1 => format!("x-archive-meta-{}", name),
+                _ => format!(
+                    "x-archive-meta{:02}-{}",
+                    count(&self.metadata[..i], name) + 1,
+                    name
+                ),
+            };
+            headers.push((header, encode_header_value(value)));
+        }
+        headers
+    }
+}
+
+impl WaybackClient {
+    /// Uploads the file at `path` to `item` (creating the item if it doesn't exist),
+    /// with the client's credentials (see `with_credentials`). Returns the uploaded
+    /// file's download URL.
+    ///
+    /// Newly created items take a few minutes to appear on archive.org.
+    pub async fn upload_to_item(&self, item: &IaItem, path: &Path) -> Result<String, ArchiveError> {
+        let credentials = self.credentials().ok_or(ArchiveError::LoginRequired)?;
+        let filename = path
+            .file_name()
+            .map(|filename| filename.to_string_lossy().into_owned())
+            .ok_or_else(|| ArchiveError::Unknown(format!("Invalid upload path {:?}", path)))?;
+        let contents = std::fs::read(path).map_err(|err| {
+            ArchiveError::Unknown(format!("Unable to read {}: {}", path.display(), err))
+        })?;
+        let mut request = self
+            .http()
+            .put(format!(
+                "{}/{}/{}",
+                self.endpoints().s3,
+                item.identifier,
+                filename
+            ))
+            .header(
+                AUTHORIZATION,
+                format!("LOW {}:{}", credentials.access_key, credentials.secret_key),
+            )
+            .header("x-amz-auto-make-bucket", "1")
+            .body(contents);
+        for (name, value) in item.metadata_headers() {
+            request = request.header(name.as_str(), value);
+        }
+        let resp = self.send(request).await?;
+        let status = resp.status().as_u16();
+        // IA-S3 asks clients to slow down with a 503.
+        if status == 429 || status >= 500 {
+            return Err(self.service_unavailable(resp).await);
+        }
+        let body = self.read_text(resp).await?.map_err(network_error)?;
+        match status {
+            200..=299 => Ok(format!("{}/{}/{}", DOWNLOAD, item.identifier, filename)),
+            _ => Err(ArchiveError::Http {
+                status,
+                snippet: body_snippet(&body),
+            }),
+        }
+    }
+}
+
+/// `value` as a header value: non-ASCII values are percent-encoded and wrapped in
+/// `uri(...)`, which IA-S3 decodes.
+fn encode_header_value(value: &str) -> String {
+    if value.chars().all(|c| c.is_ascii() && !c.is_ascii_control()) {
+        return value.to_string();
+    }
+    let encoded: String = value
+        .bytes()
+        .map(|byte| match byte {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'_' | b'.' | b'~' => {
+                (byte as char).to_string()
+            }
+            _ => format!("%{:02X}", byte),
+        })
+        .collect();
+    format!("uri({})", encoded)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn formats_item_metadata() {
+        let item = IaItem::new("my-web-collection")
+            .with_metadata("title", "Café links")
+            .with_metadata("subject", "bookmarks")
+            .with_metadata("subject", "web");
+        assert_eq!(
+            item.metadata_headers(),
+            vec![
+                ("x-archive-meta-mediatype".to_string(), "web".to_string()),
+                (
+                    "x-archive-meta-title".to_string(),
+                    "uri(Caf%C3%A9%20links)".to_string()
+                ),
+                (
+                    "x-archive-meta01-subject".to_string(),
+                    "bookmarks".to_string()
+                ),
+                ("x-archive-meta02-subject".to_string(), "web".to_string()),
+            ]
+        );
+        let texts = IaItem::new("my-texts").with_metadata("mediatype", "texts");
+        assert_eq!(
+            texts.metadata_headers(),
+            vec![("x-archive-meta-mediatype".to_string(), "texts".to_string())]
+        );
+    }
+}
@@ -0,0 +1,83 @@
+use crossbeam_channel::Sender;
+use std::fs::File;
+use std::io::{self, BufRead, BufReader};
+use std::sync::atomic::{AtomicUsize, Ordering::SeqCst};
+use std::sync::Arc;
+
+/// Exit code for an input file that can't be opened (`EX_NOINPUT` in sysexits.h).
+const EXIT_NO_INPUT: i32 = 66;
+/// Exit code for an input that fails partway through reading (`EX_IOERR`).
+const EXIT_IO_ERROR: i32 = 74;
+
+#[derive(Debug)]
+pub enum InputError {
+    Open { path: String, error: io::Error },
+    Read(io::Error),
+}
+
+impl InputError {
+    pub fn exit_code(&self) -> i32 {
+        match self {
+            InputError::Open { .. } => EXIT_NO_INPUT,
+            InputError::Read(_) => EXIT_IO_ERROR,
+        }
+    }
+}
+
+impl std::fmt::Display for InputError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            InputError::Open { path, error } => write!(f, "Unable to open {}: {}", path, error),
+            InputError::Read(error) => write!(f, "Unable to read input: {}", error),
+        }
+    }
+}
+
+impl std::error::Error for InputError {}
+
+/// Opens the file of URLs at `path`, or stdin if there isn't one.
+pub fn open(path: Option<&str>) -> Result<Box<dyn BufRead + Send>, InputError> {
+    match path {
+        Some(path) => File::open(path)
+            .map(|file| Box::new(BufReader::new(file)) as Box<dyn BufRead + Send>)
+            .map_err(|error| InputError::Open {
+                path: path.to_string(),
+                error,
+            }),
+        None => Ok(Box::new(BufReader::new(io::stdin()))),
+    }
+}
+
+/// Sends each line of `input` to `tx`, counting them in `count`. Lines that aren't
+/// valid UTF-8 are sent with the invalid bytes replaced, along with a warning.
+pub fn read_lines(
+    mut input: Box<dyn BufRead + Send>,
+    tx: Sender<String>,
+    count: Arc<AtomicUsize>,
+) -> Result<(), InputError> {
+    let mut buf = Vec::new();
+    for line_number in 1.. {
+        buf.clear();
+        if input
+            .read_until(b'\n', &mut buf)
+            .map_err(InputError::Read)?
+            == 0
+        {
+            break;
+        }
+        let line = String::from_utf8_lossy(&buf);
+        if let std::borrow::Cow::Owned(_) = line {
+            eprintln!(
+                "Warning: line {} of the input isn't valid UTF-8; invalid bytes were replaced",
+                line_number
+            );
+        }
+        let line = line.trim_end_matches(&['\r', '\n'][..]).to_string();
+        // The receiver only goes away if archiving has stopped, so stop reading too.
+        if tx.send(line).is_err() {
+            break;
+        }
+        count.fetch_add(1, SeqCst);
+    }
+    Ok(())
+}
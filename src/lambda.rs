@@ -0,0 +1,317 @@
+//! `wayback-archiver lambda`, an AWS Lambda handler that archives the URLs in each
+//! invocation's payload, for event-driven archiving (e.g. whenever a CMS publishes a
+//! page) without a long-running process. Only available when built with the `lambda`
+//! feature.
+//!
+//! Payloads look like `{"urls": ["https://example.com/a", ...]}` (or `{"url": ...}` for
+//! one), and the response like `{"results": {...}, "unprocessed": [...]}`: results in
+//! the same format as results files, and the URLs that weren't started because the
+//! invocation was running out of time, to be resubmitted. URLs go through the same
+//! checks as a run's (see `prearchive`), and those skipped are returned as skipped
+//! results.
+//!
+//! With `--state`, results are kept between invocations in a results file in S3, so
+//! URLs archived recently are skipped. Each invocation adds its results to the file
+//! with a conditional write (`If-Match` on the ETag it read), so that concurrent
+//! invocations don't drop each other's results: if another invocation wrote the file
+//! in the meantime, it's read again and the results are added to that, up to
+//! `STATE_ATTEMPTS` times.
+use crate::config::{parse_duration, Config};
+use crate::exclusions::Exclusions;
+use clap::Clap;
+
+#[derive(Clap)]
+pub struct LambdaOpts {
+    /// If set, results are kept in this results file (e.g.
+    /// s3://bucket/results.json) between invocations, and URLs archived
+    /// recently are skipped.
+    #[clap(long)]
+    state: Option<String>,
+    /// How much of the invocation's time is kept in reserve, for writing the
+    /// state and responding. No URL is started with less time than this
+    /// left, and archivals still running when it's reached are abandoned.
+    #[clap(long, default_value = "30s", parse(try_from_str = parse_duration))]
+    reserve: std::time::Duration,
+}
+
+/// How many times an invocation tries to add its results to the state, if other
+/// invocations keep changing it.
+#[cfg(feature = "lambda")]
+const STATE_ATTEMPTS: usize = 5;
+
+#[cfg(feature = "lambda")]
+type Results = std::collections::BTreeMap<String, wayback_archiver::ArchivingResult>;
+
+#[cfg(feature = "lambda")]
+#[derive(serde::Deserialize)]
+struct Invocation {
+    #[serde(default)]
+    url: Option<String>,
+    #[serde(default)]
+    urls: Vec<String>,
+}
+
+#[cfg(feature = "lambda")]
+#[derive(serde::Serialize)]
+struct Response {
+    results: std::collections::BTreeMap<String, crate::run_result::RunResult>,
+    /// URLs that weren't archived before the time budget ran out.
+    unprocessed: Vec<String>,
+}
+
+#[cfg(feature = "lambda")]
+pub async fn run(
+    opts: &LambdaOpts,
+    client: wayback_archiver::WaybackClient,
+    config: &Config,
+    exclusions: &Exclusions,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let handler =
+        lambda_runtime::service_fn(|event| handle(opts, &client, config, exclusions, event));
+    lambda_runtime::run(handler)
+        .await
+        .map_err(|err| err.to_string())?;
+    Ok(())
+}
+
+#[cfg(feature = "lambda")]
+async fn handle(
+    opts: &LambdaOpts,
+    client: &wayback_archiver::WaybackClient,
+    config: &Config,
+    exclusions: &Exclusions,
+    event: lambda_runtime::LambdaEvent<Invocation>,
+) -> Result<Response, Box<dyn std::error::Error>> {
+    use std::collections::BTreeMap;
+    use std::time::SystemTime;
+
+    let deadline = event.context.deadline();
+    let time_left = || {
+        deadline
+            .duration_since(SystemTime::now())
+            .unwrap_or_default()
+            .saturating_sub(opts.reserve)
+    };
+    let (state, version) = match &opts.state {
+        Some(path) => read_state(path).await?,
+        None => (Results::new(), None),
+    };
+    let Invocation { url, urls } = event.payload;
+    let mut response = Response {
+        results: BTreeMap::new(),
+        unprocessed: Vec::new(),
+    };
+    let mut archived = Results::new();
+    for url in url.into_iter().chain(urls) {
+        let rule = match crate::prearchive::check(exclusions, config, &url) {
+            Ok(rule) => rule,
+            Err(skip) => {
+                response.results.insert(url, skip.into());
+                continue;
+            }
+        };
+        if let Some(existing) = state.get(&url) {
+            if crate::is_fresh(existing, rule.every) {
+                response.results.insert(url, existing.clone().into());
+                continue;
+            }
+        }
+        let time_left = time_left();
+        if time_left.is_zero() {
+            response.unprocessed.push(url);
+            continue;
+        }
+        let archiving = crate::prearchive::archive(client, config, &url, rule);
+        let result = match tokio::time::timeout(time_left, archiving).await {
+            Ok(result) => result,
+            Err(_) => {
+                response.unprocessed.push(url);
+                continue;
+            }
+        };
+        archived.insert(url.clone(), result.clone());
+        response.results.insert(url, result.into());
+    }
+    if let Some(path) = &opts.state {
+        if !archived.is_empty() {
+            save_state(path, state, version, &archived).await?;
+        }
+    }
+    Ok(response)
+}
+
+/// The state's results, and if it's in an object store, the version they were read
+/// from.
+#[cfg(feature = "lambda")]
+async fn read_state(
+    path: &str,
+) -> Result<(Results, Option<crate::remote::Version>), Box<dyn std::error::Error>> {
+    use crate::output::ResultsFile;
+    use crate::remote::{self, Object};
+
+    if !remote::is_remote(path) {
+        return Ok((ResultsFile::read(path, false).await?.results, None));
+    }
+    match Object::open(path)?.read().await? {
+        Some((contents, version)) => {
+            Ok((ResultsFile::parse(&contents, false)?.results, Some(version)))
+        }
+        None => Ok((Results::new(), None)),
+    }
+}
+
+/// Adds the `archived` results to the state at `path`, which held `state` at
+/// `version` when it was read.
+#[cfg(feature = "lambda")]
+async fn save_state(
+    path: &str,
+    mut state: Results,
+    version: Option<crate::remote::Version>,
+    archived: &Results,
+) -> Result<(), Box<dyn std::error::Error>> {
+    use crate::remote::{self, Object};
+
+    if !remote::is_remote(path) {
+        state.extend(archived.clone());
+        return STATE_OUTPUT.write(&state, path).await;
+    }
+    add_to_object(&Object::open(path)?, state, version, archived).await
+}
+
+#[cfg(feature = "lambda")]
+const STATE_OUTPUT: crate::output::Output = crate::output::Output {
+    minify: false,
+    runs: None,
+    fsync: false,
+};
+
+/// Adds the `archived` results to the state in `object`, which held `state` at
+/// `version` when it was read, re-reading it if it has changed since.
+#[cfg(feature = "lambda")]
+async fn add_to_object(
+    object: &crate::remote::Object,
+    mut state: Results,
+    mut version: Option<crate::remote::Version>,
+    archived: &Results,
+) -> Result<(), Box<dyn std::error::Error>> {
+    use crate::output::ResultsFile;
+
+    for _ in 0..STATE_ATTEMPTS {
+        state.extend(archived.clone());
+        if object
+            .replace(STATE_OUTPUT.format(&state)?, version.as_ref())
+            .await?
+        {
+            return Ok(());
+        }
+        // Another invocation saved its results first, so add these to them.
+        (state, version) = match object.read().await? {
+            Some((contents, current)) => {
+                (ResultsFile::parse(&contents, false)?.results, Some(current))
+            }
+            None => (Results::new(), None),
+        };
+    }
+    Err(format!(
+        "the state kept changing, so {} results weren't saved",
+        archived.len()
+    )
+    .into())
+}
+
+#[cfg(not(feature = "lambda"))]
+pub async fn run(
+    _opts: &LambdaOpts,
+    _client: wayback_archiver::WaybackClient,
+    _config: &Config,
+    _exclusions: &Exclusions,
+) -> Result<(), Box<dyn std::error::Error>> {
+    Err("lambda requires wayback-archiver to be built with the lambda feature".into())
+}
+
+#[cfg(all(test, feature = "lambda"))]
+mod tests {
+    use super::*;
+    use crate::output::ResultsFile;
+    use crate::remote::Object;
+    use wayback_archiver::{ArchivingResult, Outcome};
+
+    fn results(urls: &[&str]) -> Results {
+        urls.iter()
+            .map(|url| {
+                let result = ArchivingResult::new(Outcome::NewCapture {
+                    url: format!("https://web.archive.org/web/2021/{}", url),
+                    ts: "2021-09-07T12:34:56".parse().unwrap(),
+                });
+                (url.to_string(), result)
+            })
+            .collect()
+    }
+
+    async fn saved(object: &Object) -> Vec<String> {
+        let (contents, _) = object.read().await.unwrap().unwrap();
+        let state = ResultsFile::parse(&contents, false).unwrap().results;
+        state.into_keys().collect()
+    }
+
+    #[tokio::test]
+    async fn keeps_concurrent_invocations_results() {
+        let object = Object::in_memory();
+        add_to_object(
+            &object,
+            Results::new(),
+            None,
+            &results(&["https://a.example/"]),
+        )
+        .await
+        .unwrap();
+        assert_eq!(saved(&object).await, ["https://a.example/"]);
+
+        // Two invocations read the same state...
+        let (contents, version) = object.read().await.unwrap().unwrap();
+        let state = ResultsFile::parse(&contents, false).unwrap().results;
+        // ...and the first saves its results before the second does.
+        let first = results(&["https://b.example/"]);
+        add_to_object(&object, state.clone(), Some(version.clone()), &first)
+            .await
+            .unwrap();
+        let second = results(&["https://c.example/"]);
+        add_to_object(&object, state, Some(version), &second)
+            .await
+            .unwrap();
+        assert_eq!(
+            saved(&object).await,
+            [
+                "https://a.example/",
+                "https://b.example/",
+                "https://c.example/"
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn creates_the_state_only_if_it_still_doesnt_exist() {
+        let object = Object::in_memory();
+        // Another invocation created the state after this one found none.
+        add_to_object(
+            &object,
+            Results::new(),
+            None,
+            &results(&["https://a.example/"]),
+        )
+        .await
+        .unwrap();
+        add_to_object(
+            &object,
+            Results::new(),
+            None,
+            &results(&["https://b.example/"]),
+        )
+        .await
+        .unwrap();
+        assert_eq!(
+            saved(&object).await,
+            ["https://a.example/", "https://b.example/"]
+        );
+    }
+}
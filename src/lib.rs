@@ -1,134 +1,568 @@
-use chrono::{Duration, NaiveDateTime, TimeZone, Utc};
-use lazy_static::lazy_static;
-use regex::Regex;
+use chrono::{DateTime, Duration, NaiveDateTime, Utc};
 use serde::{Deserialize, Serialize};
-use std::collections::HashMap;
+use std::collections::BTreeMap;
+use std::convert::TryFrom;
 
-pub async fn archive_url(url: &str) -> Result<ArchivingResult, ArchiveError> {
-    // Check to see if there's an existing archive of the requested URL.
-    let latest_snapshot = fetch_latest_snapshot(url).await;
-    if let Ok(ref snapshot) = latest_snapshot {
-        // Only accept the existing snapshot if it was made recently.
-        if (Utc::now() - Duration::days(90)).naive_utc() < snapshot.last_archived {
-            return latest_snapshot;
+#[cfg(feature = "providers")]
+mod archive_today;
+#[cfg(feature = "providers")]
+mod archivebox;
+mod availability;
+#[cfg(feature = "blocking")]
+pub mod blocking;
+mod cache;
+mod cdx;
+mod client;
+#[cfg(feature = "native")]
+mod debug_http;
+#[cfg(feature = "native")]
+mod dns;
+mod endpoints;
+#[cfg(feature = "native")]
+mod ia_item;
+#[cfg(feature = "native")]
+mod live;
+#[cfg(feature = "native")]
+mod locked_file;
+mod memento;
+pub mod metrics;
+#[cfg(any(test, feature = "mock"))]
+pub mod mock;
+mod normalize;
+mod observer;
+#[cfg(feature = "providers")]
+mod perma_cc;
+mod provider;
+mod retry;
+mod save;
+pub mod secrets;
+mod snapshot_url;
+mod spn2;
+pub mod timestamp;
+mod timetravel;
+#[cfg(feature = "native")]
+mod warc;
+
+#[cfg(feature = "providers")]
+pub use crate::archive_today::ArchiveToday;
+#[cfg(feature = "providers")]
+pub use crate::archivebox::ArchiveBox;
+pub use crate::availability::{
+    check_availability, check_snapshots, fetch_snapshot_closest_to, Snapshot, Snapshots,
+};
+pub use crate::cache::{AvailabilityCache, CacheEntry};
+pub use crate::cdx::{
+    fetch_capture_stats, fetch_latest_capture, list_site_captures, list_site_urls, list_snapshots,
+    CaptureStats, CdxFilter, CdxRecord,
+};
+use crate::client::default_client;
+#[cfg(feature = "native")]
+pub use crate::client::ProxyError;
+pub use crate::client::{Timeouts, WaybackClient, DEFAULT_SAVE_INTERVAL, DEFAULT_USER_AGENT};
+#[cfg(feature = "native")]
+pub use crate::dns::DnsCache;
+pub use crate::endpoints::Endpoints;
+#[cfg(feature = "native")]
+pub use crate::ia_item::IaItem;
+#[cfg(feature = "native")]
+pub use crate::live::{check_content_type, check_live_page, ContentInfo, LiveCheck};
+#[cfg(feature = "native")]
+pub use crate::locked_file::SetAside;
+pub use crate::memento::MementoArchive;
+pub use crate::normalize::normalize_url;
+pub use crate::observer::ArchiveObserver;
+#[cfg(feature = "providers")]
+pub use crate::perma_cc::PermaCc;
+pub use crate::provider::{ArchiveProvider, WaybackMachine};
+pub use crate::retry::RetryPolicy;
+use crate::save::{classify_spn_error, SaveOutcome};
+pub use crate::spn2::{Capture, CaptureStatus, SaveJob};
+use crate::timestamp::parse_wayback_timestamp;
+#[cfg(feature = "native")]
+pub use crate::warc::LocalWarc;
+#[cfg(feature = "cancellation")]
+pub use tokio_util::sync::CancellationToken;
+
+pub use crate::snapshot_url::{latest_snapshot_url, raw_snapshot_url, snapshot_url};
+
+/// When `archive_url` falls back to an older snapshot (as `Outcome::StaleFallback`)
+/// if a new one can't be made.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum StaleFallbackPolicy {
+    /// Never: the failure is returned instead.
+    Never,
+    /// Only when the Wayback Machine is unable to archive the URL.
+    #[default]
+    WhenUnableToArchive,
+    /// Whenever archiving fails. Temporary failures (see the client's `RetryPolicy`)
+    /// only fall back once they're no longer retried.
+    Always,
+}
+
+/// Options for archiving a URL (see `archive_url_with_options`).
+///
+/// ```
+/// # use wayback_archiver::ArchiveOptions;
+/// let options = ArchiveOptions {
+///     max_snapshot_age: chrono::Duration::days(7),
+///     ..ArchiveOptions::default()
+/// };
+/// ```
+#[derive(Clone, Debug, PartialEq)]
+pub struct ArchiveOptions {
+    /// How old an existing snapshot may be and still be reused, rather than capturing
+    /// a new one. Defaults to 90 days.
+    pub max_snapshot_age: Duration,
+    /// Whether to also capture the pages the URL links to, which are reported in
+    /// `ArchivingResult::outlink_jobs`. Only supported by SPN2, so it's ignored unless
+    /// the client has credentials (see `WaybackClient::with_credentials`).
+    pub capture_outlinks: bool,
+    /// Whether to also take a screenshot of the page, whose URL is reported in
+    /// `ArchivingResult::screenshot_url`. Like `capture_outlinks`, only supported by
+    /// SPN2.
+    pub capture_screenshot: bool,
+    /// How long SPN2 runs the page's JavaScript for before capturing it, if not its
+    /// default. `Duration::ZERO` skips it, which speeds up capturing PDFs and media.
+    pub js_behavior_timeout: Option<std::time::Duration>,
+    /// Whether to look for a recent enough snapshot in every public web archive,
+    /// through the Memento TimeTravel aggregator, before capturing a new one. One
+    /// found is reused like the archive's own, with `ArchivingResult::held_by`
+    /// recording which archive holds it.
+    pub consult_timetravel: bool,
+    /// If set, a new capture isn't submitted before this time (e.g. to space out
+    /// captures of the same site). Recent enough snapshots are still reused at once.
+    pub submit_after: Option<std::time::Instant>,
+    /// When an older snapshot is used (as `Outcome::StaleFallback`) if a new one
+    /// can't be made. Defaults to `StaleFallbackPolicy::WhenUnableToArchive`.
+    pub stale_fallback: StaleFallbackPolicy,
+}
+
+impl Default for ArchiveOptions {
+    fn default() -> Self {
+        ArchiveOptions {
+            max_snapshot_age: Duration::days(90),
+            capture_outlinks: false,
+            capture_screenshot: false,
+            js_behavior_timeout: None,
+            consult_timetravel: false,
+            submit_after: None,
+            stale_fallback: StaleFallbackPolicy::default(),
         }
     }
+}
+
+/// Archives `url`, reusing a recent snapshot if there is one.
+///
+/// Concurrent calls for the same URL (after normalization) are coalesced: the URL is
+/// only submitted once, and every caller gets that submission's result.
+///
+/// Uses a client shared by all of the library's free functions; see `WaybackClient`
+/// to configure the HTTP client.
+pub async fn archive_url(url: &str) -> Result<ArchivingResult, ArchiveError> {
+    default_client().archive_url(url).await
+}
 
-    // Request a new snapshot of the URL.
-    let resp = reqwest::get(format!("https://web.archive.org/save/{}", url))
+/// Like `archive_url`, with `options` instead of the defaults.
+pub async fn archive_url_with_options(
+    url: &str,
+    options: &ArchiveOptions,
+) -> Result<ArchivingResult, ArchiveError> {
+    default_client()
+        .archive_url_with_options(url, options)
         .await
-        .map_err(|err| ArchiveError::Unknown(err.to_string()))?;
-    let archive_url: Result<String, ArchiveError> = match resp.status().as_u16() {
-        // Return the redirected URL (which is the archive snapshot URL).
-        200 => Ok(resp.url().clone().to_string()),
-        404 => {
-            // Sometimes, the snapshot URL returns a 404, even though the archival was successful.
-            // Probably due to a race condition in the Wayback machine; these URLs do (eventually) exist.
-            if resp.url().path().starts_with("/web") {
-                Ok(resp.url().to_string())
-            } else {
-                Err(ArchiveError::Unknown(format!(
-                    "Unexpected HTTP 404 at {:#?}",
-                    resp.url().to_string()
-                )))
-            }
-        }
-        509 => Err(ArchiveError::BandwidthExceeded),
-        // There may be more status codes that indicate archive failure, but these were the most common.
-        403 | 520 | 523 => Err(ArchiveError::UnableToArchive),
-        _ => {
-            dbg!(&resp);
-            Err(ArchiveError::Unknown(format!(
-                "Got status {}: {:#?}",
-                resp.status(),
-                resp
-            )))
-        }
-    };
-    let result = archive_url.and_then(|url| {
-        Ok(ArchivingResult {
-            last_archived: timestamp_from_archive_url(&url)?,
-            url: Some(url),
-            existing_snapshot: false,
-        })
-    });
-    match result {
-        Err(ArchiveError::UnableToArchive) => {
-            // If we weren't able to archive the URL, but a valid (if old) snapshot exists,
-            // then return that older snapshot.
-            latest_snapshot.map_err(|_| ArchiveError::UnableToArchive)
-        }
-        _ => result,
-    }
 }
 
-fn timestamp_from_archive_url(url: &str) -> Result<NaiveDateTime, ArchiveError> {
-    lazy_static! {
-        static ref RE: Regex = Regex::new(r"/web/(\d+)/").unwrap();
-    }
-    let timestamp_url_component = RE
-        .captures(url)
-        .and_then(|cap| cap.get(1).map(|ts_str| ts_str.as_str()))
+/// Archives `urls` with up to `concurrency` archivals in progress at once, returning
+/// each URL's result. See `WaybackClient::archive_urls`.
+pub async fn archive_urls<I>(
+    urls: I,
+    concurrency: usize,
+) -> BTreeMap<String, Result<ArchivingResult, ArchiveError>>
+where
+    I: IntoIterator<Item = String>,
+{
+    default_client().archive_urls(urls, concurrency).await
+}
+
+/// Archives the URLs from `urls` as they arrive, with up to `concurrency` archivals in
+/// progress at once, yielding each URL's result as soon as it's done. See
+/// `WaybackClient::archive_stream`.
+pub fn archive_stream<'a, S>(
+    urls: S,
+    concurrency: usize,
+) -> impl futures::Stream<Item = (String, Result<ArchivingResult, ArchiveError>)> + 'a
+where
+    S: futures::Stream<Item = String> + 'a,
+{
+    default_client().archive_stream(urls, concurrency)
+}
+
+/// The newest snapshot of `url` that the Wayback Machine can serve, with its capture
+/// time and the HTTP status the page was captured with. Only looks up existing
+/// snapshots, without requesting a new capture; returns
+/// `ArchiveError::NoExistingSnapshot` if there isn't one.
+///
+/// ```no_run
+/// # async fn example() -> Result<(), wayback_archiver::ArchiveError> {
+/// let snapshot = wayback_archiver::fetch_latest_snapshot("example.com").await?;
+/// println!("{} ({:?}) at {}", snapshot.url, snapshot.status, snapshot.timestamp);
+/// # Ok(())
+/// # }
+/// ```
+pub async fn fetch_latest_snapshot(url: &str) -> Result<Snapshot, ArchiveError> {
+    default_client().fetch_latest_snapshot(url).await
+}
+
+/// Checks whether the snapshot at `snapshot_url` can be served by the Wayback Machine.
+pub async fn verify_snapshot(snapshot_url: &str) -> Result<bool, ArchiveError> {
+    default_client().verify_snapshot(snapshot_url).await
+}
+
+/// Looks up the latest captures of `urls` in the CDX index with up to `concurrency`
+/// requests in flight. URLs that have never been captured, or whose lookup failed,
+/// are left out.
+pub async fn fetch_latest_captures<I>(urls: I, concurrency: usize) -> Vec<(String, Snapshot)>
+where
+    I: IntoIterator<Item = String>,
+{
+    default_client()
+        .fetch_latest_captures(urls, concurrency)
+        .await
+}
+
+fn timestamp_from_archive_url(url: &str) -> Result<DateTime<Utc>, ArchiveError> {
+    // The first `/web/<digits>/` in the URL.
+    let timestamp_url_component = url
+        .match_indices("/web/")
+        .filter_map(|(start, prefix)| url[start + prefix.len()..].split_once('/'))
+        .map(|(timestamp, _)| timestamp)
+        .find(|timestamp| !timestamp.is_empty() && timestamp.bytes().all(|b| b.is_ascii_digit()))
         .ok_or_else(|| ArchiveError::ParseError("unable to extract timestamp from url".into()))?;
     parse_wayback_timestamp(timestamp_url_component)
 }
 
-async fn fetch_latest_snapshot(url: &str) -> Result<ArchivingResult, ArchiveError> {
-    let resp = reqwest::get(format!("http://archive.org/wayback/available?url={}", url))
-        .await
-        .map_err(|err| ArchiveError::Unknown(err.to_string()))?
-        .json::<WaybackAvailabilityResponse>()
-        .await
-        .map_err(|err| ArchiveError::ParseError(err.to_string()))?;
+/// Falls back to `latest_snapshot` after an attempt to archive failed with `err`, if
+/// `policy` allows it. Errors that may be retried are left to the retry loop.
+fn stale_fallback(
+    policy: StaleFallbackPolicy,
+    retryable: bool,
+    err: ArchiveError,
+    latest_snapshot: Result<Snapshot, ArchiveError>,
+) -> Result<ArchivingResult, ArchiveError> {
+    let fall_back = match policy {
+        StaleFallbackPolicy::Never => false,
+        StaleFallbackPolicy::WhenUnableToArchive => err == ArchiveError::UnableToArchive,
+        StaleFallbackPolicy::Always => !retryable,
+    };
+    match latest_snapshot {
+        Ok(snapshot) if fall_back => Ok(ArchivingResult::new(Outcome::StaleFallback {
+            url: snapshot.url,
+            ts: snapshot.timestamp.naive_utc(),
+        })),
+        _ => Err(err),
+    }
+}
 
-    if let Some(snapshots) = resp.archived_snapshots {
-        if let Some((_, latest)) = snapshots
-            .iter()
-            .max_by_key(|(_, snapshot)| &snapshot.timestamp)
-        {
-            return Ok(ArchivingResult {
-                existing_snapshot: true,
-                last_archived: parse_wayback_timestamp(&latest.timestamp)?,
-                url: Some(latest.url.clone()),
-            });
+/// What came of archiving a URL.
+#[derive(Deserialize, Serialize, Debug, Clone, PartialEq)]
+#[serde(tag = "outcome", rename_all = "kebab-case")]
+pub enum Outcome {
+    /// A new snapshot was captured on request.
+    NewCapture { url: String, ts: NaiveDateTime },
+    /// A recent snapshot already existed, so no new one was requested.
+    ExistingSnapshot { url: String, ts: NaiveDateTime },
+    /// A new snapshot couldn't be captured, so an older one is used instead.
+    StaleFallback { url: String, ts: NaiveDateTime },
+    /// Archiving failed, and there's no snapshot to fall back on.
+    Failed { error: String },
+}
+
+impl Outcome {
+    /// The URL of the snapshot, if there is one.
+    pub fn url(&self) -> Option<&str> {
+        match self {
+            Outcome::NewCapture { url, .. }
+            | Outcome::ExistingSnapshot { url, .. }
+            | Outcome::StaleFallback { url, .. } => Some(url),
+            Outcome::Failed { .. } => None,
+        }
+    }
+
+    /// When the snapshot was captured, if there is one.
+    pub fn ts(&self) -> Option<NaiveDateTime> {
+        match self {
+            Outcome::NewCapture { ts, .. }
+            | Outcome::ExistingSnapshot { ts, .. }
+            | Outcome::StaleFallback { ts, .. } => Some(*ts),
+            Outcome::Failed { .. } => None,
+        }
+    }
+
+    /// Why archiving failed, if it did.
+    pub fn error(&self) -> Option<&str> {
+        match self {
+            Outcome::Failed { error } => Some(error),
+            _ => None,
         }
     }
-    Err(ArchiveError::NoExistingSnapshot)
 }
 
-fn parse_wayback_timestamp(ts: &str) -> Result<NaiveDateTime, ArchiveError> {
-    let naive_utc = NaiveDateTime::parse_from_str(ts, "%Y%m%d%H%M%S")
-        .map_err(|err| ArchiveError::ParseError(err.to_string()))?;
-    Ok(Utc.from_utc_datetime(&naive_utc).naive_local())
+/// HTTP cache validators of a live page, as returned by its server.
+#[derive(Clone, Debug, Default, Deserialize, Serialize, PartialEq)]
+pub struct Validators {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub etag: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub last_modified: Option<String>,
 }
 
-#[derive(Deserialize, Debug)]
-struct WaybackAvailabilityResponse {
-    url: String,
-    archived_snapshots: Option<HashMap<String, WaybackSnapshot>>,
+#[derive(Deserialize, Serialize, Debug, Clone, PartialEq)]
+#[serde(try_from = "StoredResult")]
+pub struct ArchivingResult {
+    #[serde(flatten)]
+    pub outcome: Outcome,
+    /// Whether the snapshot was accepted before the Wayback Machine could serve it.
+    /// Such snapshots almost always become available shortly after; see `verify_snapshot`.
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub pending: bool,
+    /// Validators of the live page when it was last checked, used to detect
+    /// unchanged pages with conditional requests.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub validators: Option<Validators>,
+    /// When the URL was first captured by the Wayback Machine (only set when enriched
+    /// with `fetch_capture_stats`).
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub first_archived: Option<NaiveDateTime>,
+    /// How many captures of the URL the Wayback Machine has, up to 10,000 (only set
+    /// when enriched with `fetch_capture_stats`).
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub total_captures: Option<u64>,
+    /// For failures that are expected to clear up eventually (see
+    /// `ArchiveError::suggested_deferral`), when the URL is worth trying again.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub retry_after: Option<NaiveDateTime>,
+    /// How many times in a row archiving the URL has failed, including this time (0
+    /// unless it failed). Recorded so that backoff from URLs that keep failing (e.g.
+    /// the CLI's, which sets `retry_after`) survives restarts.
+    #[serde(default, skip_serializing_if = "is_zero")]
+    pub failures: u32,
+    /// When the snapshot was last checked to still be served (e.g. by the CLI's
+    /// `reverify`), if it has been since it was recorded.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub verified_at: Option<NaiveDateTime>,
+    /// The SPN2 capture jobs of the pages the URL links to, by URL, when captured with
+    /// `ArchiveOptions::capture_outlinks`. Their progress can be checked with
+    /// `WaybackClient::capture_status`.
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub outlink_jobs: BTreeMap<String, String>,
+    /// A screenshot of the page, when captured with `ArchiveOptions::capture_screenshot`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub screenshot_url: Option<String>,
+    /// Where the snapshot is in the archive's data directory (e.g.
+    /// `archive/1712345678.123`), when archived with `ArchiveBox`, or the WARC file's
+    /// path, when archived with `LocalWarc`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub archive_path: Option<String>,
+    /// The host of the archive holding the snapshot (e.g. `archive.ph`), when it was
+    /// found in another archive (see `ArchiveOptions::consult_timetravel`).
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub held_by: Option<String>,
+    /// The results of also archiving the URL in other archives, by archive name (e.g.
+    /// with the CLI's `--redundant`), so that it doesn't depend on a single archive.
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub redundant: BTreeMap<String, ArchivingResult>,
+    /// Why archiving failed, as a stable code (see `ArchiveError::code`), if it did.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub error_code: Option<String>,
+    /// The ID of the run that produced this result, if recorded.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub run_id: Option<String>,
+    /// Labels attached to the URL (e.g. by the CLI's config rules).
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub tags: Vec<String>,
+    /// Fields this version doesn't know about (e.g. added by hand, or by a newer
+    /// version), kept so that they survive a `--merge`.
+    #[serde(flatten)]
+    pub unknown_fields: BTreeMap<String, serde_json::Value>,
 }
 
-#[derive(Deserialize, Debug)]
-struct WaybackSnapshot {
-    status: String,
-    available: bool,
-    url: String,
-    timestamp: String,
+impl ArchivingResult {
+    pub fn new(outcome: Outcome) -> Self {
+        ArchivingResult {
+            outcome,
+            pending: false,
+            validators: None,
+            first_archived: None,
+            total_captures: None,
+            retry_after: None,
+            failures: 0,
+            verified_at: None,
+            outlink_jobs: BTreeMap::new(),
+            screenshot_url: None,
+            archive_path: None,
+            held_by: None,
+            redundant: BTreeMap::new(),
+            error_code: None,
+            run_id: None,
+            tags: Vec::new(),
+            unknown_fields: BTreeMap::new(),
+        }
+    }
 }
 
-#[derive(Deserialize, Serialize, Debug)]
-pub struct ArchivingResult {
-    pub url: Option<String>,
-    pub last_archived: NaiveDateTime,
-    #[serde(skip)]
-    pub existing_snapshot: bool,
+fn is_zero(n: &u32) -> bool {
+    *n == 0
+}
+
+/// An `ArchivingResult` as stored in a results file, which may predate `Outcome`.
+#[derive(Deserialize)]
+struct StoredResult {
+    #[serde(default)]
+    outcome: Option<String>,
+    #[serde(default)]
+    url: Option<String>,
+    #[serde(default)]
+    ts: Option<NaiveDateTime>,
+    #[serde(default)]
+    error: Option<String>,
+    /// Written instead of `ts` before results had an outcome.
+    #[serde(default)]
+    last_archived: Option<NaiveDateTime>,
+    /// Written before results had an outcome.
+    #[serde(default)]
+    existing_snapshot: bool,
+    #[serde(default)]
+    pending: bool,
+    #[serde(default)]
+    validators: Option<Validators>,
+    #[serde(default)]
+    first_archived: Option<NaiveDateTime>,
+    #[serde(default)]
+    total_captures: Option<u64>,
+    #[serde(default)]
+    retry_after: Option<NaiveDateTime>,
+    #[serde(default)]
+    failures: u32,
+    #[serde(default)]
+    verified_at: Option<NaiveDateTime>,
+    #[serde(default)]
+    outlink_jobs: BTreeMap<String, String>,
+    #[serde(default)]
+    screenshot_url: Option<String>,
+    #[serde(default)]
+    archive_path: Option<String>,
+    #[serde(default)]
+    held_by: Option<String>,
+    #[serde(default)]
+    redundant: BTreeMap<String, ArchivingResult>,
+    #[serde(default)]
+    error_code: Option<String>,
+    #[serde(default)]
+    run_id: Option<String>,
+    #[serde(default)]
+    tags: Vec<String>,
+    #[serde(flatten)]
+    unknown_fields: BTreeMap<String, serde_json::Value>,
 }
 
-#[derive(Debug, PartialEq)]
+impl TryFrom<StoredResult> for ArchivingResult {
+    type Error = String;
+
+    fn try_from(stored: StoredResult) -> Result<Self, Self::Error> {
+        let snapshot = |outcome: &str| match (stored.url.clone(), stored.ts) {
+            (Some(url), Some(ts)) => Ok((url, ts)),
+            _ => Err(format!("{} outcome without a url and ts", outcome)),
+        };
+        let outcome = match stored.outcome.as_deref() {
+            Some("new-capture") => {
+                let (url, ts) = snapshot("new-capture")?;
+                Outcome::NewCapture { url, ts }
+            }
+            Some("existing-snapshot") => {
+                let (url, ts) = snapshot("existing-snapshot")?;
+                Outcome::ExistingSnapshot { url, ts }
+            }
+            Some("stale-fallback") => {
+                let (url, ts) = snapshot("stale-fallback")?;
+                Outcome::StaleFallback { url, ts }
+            }
+            Some("failed") => Outcome::Failed {
+                error: stored.error.ok_or("failed outcome without an error")?,
+            },
+            Some(outcome) => return Err(format!("unknown outcome {:?}", outcome)),
+            // Results written before outcomes were recorded.
+            None => match (stored.error, stored.url, stored.ts.or(stored.last_archived)) {
+                (Some(error), _, _) => Outcome::Failed { error },
+                (None, Some(url), Some(ts)) if stored.existing_snapshot => {
+                    Outcome::ExistingSnapshot { url, ts }
+                }
+                (None, Some(url), Some(ts)) => Outcome::NewCapture { url, ts },
+                _ => Outcome::Failed {
+                    error: "No snapshot was recorded".to_string(),
+                },
+            },
+        };
+        Ok(ArchivingResult {
+            outcome,
+            pending: stored.pending,
+            validators: stored.validators,
+            first_archived: stored.first_archived,
+            total_captures: stored.total_captures,
+            retry_after: stored.retry_after,
+            failures: stored.failures,
+            verified_at: stored.verified_at,
+            outlink_jobs: stored.outlink_jobs,
+            screenshot_url: stored.screenshot_url,
+            archive_path: stored.archive_path,
+            held_by: stored.held_by,
+            redundant: stored.redundant,
+            error_code: stored.error_code,
+            run_id: stored.run_id,
+            tags: stored.tags,
+            unknown_fields: stored.unknown_fields,
+        })
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
 pub enum ArchiveError {
-    BandwidthExceeded,
+    /// Save Page Now's rate limit was hit. `retry_after` is how long the Wayback
+    /// Machine asked to wait before trying again (its `Retry-After` header), if it
+    /// said.
+    BandwidthExceeded {
+        retry_after: Option<std::time::Duration>,
+    },
     UnableToArchive,
     NoExistingSnapshot,
+    /// The URL's host (this one) didn't resolve, in as many lookups as a `DnsCache`
+    /// allows before giving up on it.
+    DeadHost(String),
+    /// The Wayback Machine's crawler couldn't reach the page's site (which is down,
+    /// or refused the crawler), for this reason (e.g. `error:no-access`).
+    OriginUnreachable(String),
+    /// Save Page Now redirected to the archive.org login page.
+    LoginRequired,
+    /// The Wayback Machine returned an error page (typically HTML) instead of the
+    /// expected response, usually because it's degraded or down for maintenance.
+    ServiceUnavailable {
+        status: u16,
+        snippet: String,
+        retry_after: Option<std::time::Duration>,
+    },
+    /// The Wayback Machine responded with an HTTP status that isn't otherwise
+    /// accounted for, and the start of the response's body.
+    Http {
+        status: u16,
+        snippet: String,
+    },
+    /// The request failed without a response (e.g. the connection was refused).
+    Network(String),
+    /// The Wayback Machine didn't respond within the client's timeouts (see
+    /// `WaybackClient::with_timeouts`).
+    Timeout,
+    /// The client's cancellation token was cancelled (see
+    /// `WaybackClient::with_cancellation`).
+    Cancelled,
     ParseError(String),
     Unknown(String),
 }
@@ -136,15 +570,941 @@ pub enum ArchiveError {
 impl std::fmt::Display for ArchiveError {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         match self {
-            ArchiveError::BandwidthExceeded => write!(f, "Bandwidth Exceeded"),
+            ArchiveError::BandwidthExceeded { retry_after: None } => {
+                write!(f, "Bandwidth Exceeded")
+            }
+            ArchiveError::BandwidthExceeded {
+                retry_after: Some(retry_after),
+            } => write!(
+                f,
+                "Bandwidth Exceeded (retry after {}s)",
+                retry_after.as_secs()
+            ),
             ArchiveError::UnableToArchive => {
                 write!(f, "Wayback Machine unable to archive this URL")
             }
             ArchiveError::NoExistingSnapshot => write!(f, "No existing snapshots"),
+            ArchiveError::DeadHost(host) => write!(f, "Dead host: {} does not resolve", host),
+            ArchiveError::OriginUnreachable(reason) => write!(
+                f,
+                "Origin unreachable by the Wayback Machine's crawler ({})",
+                reason
+            ),
+            ArchiveError::LoginRequired => {
+                write!(f, "Wayback Machine requires logging in to archive this URL")
+            }
+            ArchiveError::ServiceUnavailable {
+                status, snippet, ..
+            } => write!(
+                f,
+                "Wayback Machine unavailable (HTTP {}): {}",
+                status, snippet
+            ),
+            ArchiveError::Http { status, snippet } => write!(
+                f,
+                "Unexpected response from the Wayback Machine (HTTP {}): {}",
+                status, snippet
+            ),
+            ArchiveError::Network(err) => write!(f, "Network error: {}", err),
+            ArchiveError::Timeout => write!(f, "Timed out waiting for the Wayback Machine"),
+            ArchiveError::Cancelled => write!(f, "Cancelled"),
             ArchiveError::ParseError(err) => write!(f, "Parse error: {}", err),
             ArchiveError::Unknown(err) => write!(f, "Unknown error: {}", err),
         }
     }
 }
 
+impl ArchiveError {
+    /// Whether the same request may succeed if retried later.
+    pub fn is_retryable(&self) -> bool {
+        matches!(
+            self,
+            ArchiveError::BandwidthExceeded { .. }
+                | ArchiveError::ServiceUnavailable { .. }
+                | ArchiveError::Timeout
+                | ArchiveError::Network(_)
+        )
+    }
+
+    /// A stable code for the kind of error (e.g. `bandwidth-exceeded`), listed in
+    /// `ERROR_CODES`. Unlike the error's message, which may change between releases,
+    /// codes are kept, so automation should match on them.
+    pub fn code(&self) -> &'static str {
+        match self {
+            ArchiveError::BandwidthExceeded { .. } => "bandwidth-exceeded",
+            ArchiveError::UnableToArchive => "unable-to-archive",
+            ArchiveError::NoExistingSnapshot => "no-existing-snapshot",
+            ArchiveError::DeadHost(_) => "dead-host",
+            ArchiveError::OriginUnreachable(_) => "origin-unreachable",
+            ArchiveError::LoginRequired => "login-required",
+            ArchiveError::ServiceUnavailable { .. } => "service-unavailable",
+            ArchiveError::Http { .. } => "http-error",
+            ArchiveError::Network(_) => "network-error",
+            ArchiveError::Timeout => "timeout",
+            ArchiveError::Cancelled => "cancelled",
+            ArchiveError::ParseError(_) => "parse-error",
+            ArchiveError::Unknown(_) => "unknown",
+        }
+    }
+
+    /// Whether the same request will keep failing, however long it's retried for.
+    ///
+    /// Errors may be neither retryable nor permanent, when it's unclear whether
+    /// retrying would help (e.g. `Unknown` errors).
+    pub fn is_permanent(&self) -> bool {
+        matches!(
+            self,
+            ArchiveError::UnableToArchive | ArchiveError::DeadHost(_) | ArchiveError::LoginRequired
+        )
+    }
+
+    /// The HTTP status of the response the error came from, if it's known.
+    pub fn status(&self) -> Option<u16> {
+        match self {
+            ArchiveError::ServiceUnavailable { status, .. } | ArchiveError::Http { status, .. } => {
+                Some(*status)
+            }
+            _ => None,
+        }
+    }
+
+    /// How long the Wayback Machine asked to wait before retrying (its `Retry-After`
+    /// header), if it did.
+    pub fn retry_after(&self) -> Option<std::time::Duration> {
+        match self {
+            ArchiveError::BandwidthExceeded { retry_after }
+            | ArchiveError::ServiceUnavailable { retry_after, .. } => *retry_after,
+            _ => None,
+        }
+    }
+
+    /// How long to wait before retrying, for retryable errors: as long as the Wayback
+    /// Machine asked, if it did, or else `bandwidth_exceeded_wait` after bandwidth
+    /// limits were exceeded and `retry_wait` after other temporary failures.
+    pub fn suggested_backoff(
+        &self,
+        bandwidth_exceeded_wait: std::time::Duration,
+        retry_wait: std::time::Duration,
+    ) -> Option<std::time::Duration> {
+        match self {
+            ArchiveError::BandwidthExceeded { retry_after } => {
+                Some(retry_after.unwrap_or(bandwidth_exceeded_wait))
+            }
+            ArchiveError::ServiceUnavailable { retry_after, .. } => {
+                Some(retry_after.unwrap_or(retry_wait))
+            }
+            ArchiveError::Timeout | ArchiveError::Network(_) => Some(retry_wait),
+            _ => None,
+        }
+    }
+
+    /// How long to put off trying again, for errors that won't clear up within a run,
+    /// but aren't permanent either.
+    pub fn suggested_deferral(&self) -> Option<std::time::Duration> {
+        match self {
+            // Sites that are down tend to stay down for a while, if they come back.
+            ArchiveError::OriginUnreachable(_) => {
+                Some(std::time::Duration::from_secs(3 * 24 * 60 * 60))
+            }
+            _ => None,
+        }
+    }
+}
+
 impl std::error::Error for ArchiveError {}
+
+/// Every code that `ArchiveError::code` returns, with what it means.
+pub const ERROR_CODES: &[(&str, &str)] = &[
+    (
+        "bandwidth-exceeded",
+        "Save Page Now's rate limit was hit; retry later",
+    ),
+    ("unable-to-archive", "the archive couldn't capture the URL"),
+    ("no-existing-snapshot", "the URL has no snapshots"),
+    ("dead-host", "the URL's host doesn't resolve"),
+    (
+        "origin-unreachable",
+        "the archive's crawler couldn't reach the URL's site",
+    ),
+    ("login-required", "capturing the URL requires logging in"),
+    (
+        "service-unavailable",
+        "the archive is degraded or down for maintenance",
+    ),
+    (
+        "http-error",
+        "the archive responded with an unexpected HTTP status",
+    ),
+    ("network-error", "a request failed without a response"),
+    ("timeout", "the archive didn't respond in time"),
+    ("cancelled", "archiving was cancelled"),
+    (
+        "parse-error",
+        "the archive's response couldn't be understood",
+    ),
+    ("unknown", "any other error"),
+];
+
+/// The error reported by a Save Page Now error code in `body` (of a response with
+/// `status` and `retry_after`), if any.
+fn spn_code_error(
+    status: u16,
+    retry_after: Option<std::time::Duration>,
+    body: &str,
+) -> Option<ArchiveError> {
+    match classify_spn_error(body)? {
+        SaveOutcome::OriginUnreachable(reason) => Some(ArchiveError::OriginUnreachable(reason)),
+        SaveOutcome::BandwidthExceeded => Some(ArchiveError::BandwidthExceeded { retry_after }),
+        SaveOutcome::ServiceUnavailable => Some(ArchiveError::ServiceUnavailable {
+            status,
+            snippet: body_snippet(body),
+            retry_after,
+        }),
+        _ => Some(ArchiveError::UnableToArchive),
+    }
+}
+
+/// The start of a response's body, with its whitespace collapsed, for error messages.
+fn body_snippet(body: &str) -> String {
+    const SNIPPET_LEN: usize = 200;
+
+    let body = body.split_whitespace().collect::<Vec<_>>().join(" ");
+    let mut snippet: String = body.chars().take(SNIPPET_LEN).collect();
+    if snippet.len() < body.len() {
+        snippet.push_str("...");
+    }
+    snippet
+}
+
+/// How long a response's `Retry-After` header asks to wait, if it has one.
+fn retry_after(headers: &reqwest::header::HeaderMap) -> Option<std::time::Duration> {
+    let value = headers.get(reqwest::header::RETRY_AFTER)?.to_str().ok()?;
+    parse_retry_after(value, Utc::now())
+}
+
+/// The longest `Retry-After` that's honored, so that a misconfigured or hostile
+/// server can't stall a client indefinitely (an hour).
+const MAX_RETRY_AFTER: std::time::Duration = std::time::Duration::from_secs(60 * 60);
+
+/// Parses a `Retry-After` value, which is either a number of seconds or an HTTP date
+/// (relative to `now`), capped at `MAX_RETRY_AFTER`.
+fn parse_retry_after(value: &str, now: DateTime<Utc>) -> Option<std::time::Duration> {
+    let value = value.trim();
+    let retry_after = match value.parse() {
+        Ok(seconds) => std::time::Duration::from_secs(seconds),
+        Err(_) => {
+            let date = DateTime::parse_from_rfc2822(value).ok()?;
+            (date.with_timezone(&Utc) - now)
+                .to_std()
+                .unwrap_or_default()
+        }
+    };
+    Some(retry_after.min(MAX_RETRY_AFTER))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::{NaiveDate, TimeZone};
+    use futures::StreamExt;
+    use proptest::prelude::*;
+    use std::sync::Arc;
+
+    fn datetime() -> impl Strategy<Value = NaiveDateTime> {
+        (
+            1996i32..2100,
+            1u32..=12,
+            1u32..=28,
+            0u32..24,
+            0u32..60,
+            0u32..60,
+            0u32..1_000_000_000,
+        )
+            .prop_map(|(y, mo, d, h, mi, s, ns)| {
+                NaiveDate::from_ymd_opt(y, mo, d)
+                    .and_then(|date| date.and_hms_nano_opt(h, mi, s, ns))
+                    .unwrap()
+            })
+    }
+
+    fn outcome() -> impl Strategy<Value = Outcome> {
+        prop_oneof![
+            (".*", datetime()).prop_map(|(url, ts)| Outcome::NewCapture { url, ts }),
+            (".*", datetime()).prop_map(|(url, ts)| Outcome::ExistingSnapshot { url, ts }),
+            (".*", datetime()).prop_map(|(url, ts)| Outcome::StaleFallback { url, ts }),
+            ".*".prop_map(|error| Outcome::Failed { error }),
+        ]
+    }
+
+    fn archiving_result() -> impl Strategy<Value = ArchivingResult> {
+        (
+            (
+                outcome(),
+                any::<bool>(),
+                proptest::option::of("[0-9a-f-]{36}"),
+            ),
+            proptest::option::of((proptest::option::of(".*"), proptest::option::of(".*"))),
+            proptest::option::of(datetime()),
+            (
+                proptest::option::of(any::<u64>()),
+                proptest::option::of(datetime()),
+                0..5u32,
+                proptest::option::of(datetime()),
+            ),
+            (
+                proptest::collection::btree_map(".*", "spn2-[0-9a-f]{8}", 0..3),
+                proptest::option::of(".*"),
+                proptest::option::of("archive/[0-9]{10}"),
+                proptest::option::of("[a-z.]{1,20}"),
+                proptest::option::of("[a-z-]{1,20}"),
+                proptest::collection::vec(".*", 0..3),
+            ),
+            proptest::collection::btree_map(
+                "[a-z.]{1,12}",
+                outcome().prop_map(ArchivingResult::new),
+                0..3,
+            ),
+            proptest::collection::btree_map("x_[a-z_]{1,10}", ".*", 0..3),
+        )
+            .prop_map(
+                |(
+                    (outcome, pending, run_id),
+                    validators,
+                    first_archived,
+                    (total_captures, retry_after, failures, verified_at),
+                    (outlink_jobs, screenshot_url, archive_path, held_by, error_code, tags),
+                    redundant,
+                    unknown_fields,
+                )| ArchivingResult {
+                    outcome,
+                    pending,
+                    validators: validators.map(|(etag, last_modified)| Validators {
+                        etag,
+                        last_modified,
+                    }),
+                    first_archived,
+                    total_captures,
+                    retry_after,
+                    failures,
+                    verified_at,
+                    outlink_jobs,
+                    screenshot_url,
+                    archive_path,
+                    held_by,
+                    redundant,
+                    error_code,
+                    run_id,
+                    tags,
+                    unknown_fields: unknown_fields
+                        .into_iter()
+                        .map(|(key, value)| (key, serde_json::Value::String(value)))
+                        .collect(),
+                },
+            )
+    }
+
+    proptest! {
+        #[test]
+        fn results_round_trip(results in proptest::collection::btree_map(".*", archiving_result(), 0..8)) {
+            let written = serde_json::to_string_pretty(&results).unwrap();
+            let read: BTreeMap<String, ArchivingResult> = serde_json::from_str(&written).unwrap();
+            prop_assert_eq!(read, results);
+        }
+    }
+
+    #[tokio::test]
+    async fn coalesces_concurrent_archivals() {
+        let mock = mock::MockWayback::start().await.unwrap();
+        let client = WaybackClient::new().with_endpoints(mock.endpoints());
+        let (first, second) = futures::join!(
+            client.archive_url("example.com/page"),
+            client.archive_url("http://example.com/page#top")
+        );
+        assert!(matches!(
+            first,
+            Ok(ArchivingResult {
+                outcome: Outcome::NewCapture { .. },
+                ..
+            })
+        ));
+        assert_eq!(first, second);
+        assert_eq!(mock.captures("example.com/page").len(), 1);
+    }
+
+    /// A provider whose captures fail with its error, and that has a year-old snapshot
+    /// of every URL.
+    struct Failing(ArchiveError);
+
+    impl ArchiveProvider for Failing {
+        fn provider_name(&self) -> &str {
+            "failing"
+        }
+
+        fn lookup_latest<'a>(
+            &'a self,
+            _client: &'a WaybackClient,
+            url: &'a str,
+        ) -> futures::future::BoxFuture<'a, Result<Option<Snapshot>, ArchiveError>> {
+            let snapshot = Snapshot {
+                url: format!("https://archive.example/{}", url),
+                timestamp: Utc::now() - Duration::days(365),
+                status: Some(200),
+                available: true,
+            };
+            Box::pin(async move { Ok(Some(snapshot)) })
+        }
+
+        fn save<'a>(
+            &'a self,
+            _client: &'a WaybackClient,
+            _url: &'a str,
+            _options: &'a ArchiveOptions,
+            _previous: Option<&'a Snapshot>,
+        ) -> futures::future::BoxFuture<'a, Result<ArchivingResult, ArchiveError>> {
+            Box::pin(async move { Err(self.0.clone()) })
+        }
+    }
+
+    #[tokio::test]
+    async fn falls_back_per_the_options_stale_fallback_policy() {
+        let client = |err: ArchiveError| {
+            WaybackClient::new()
+                .with_retry_policy(RetryPolicy::never())
+                .with_provider(Arc::new(Failing(err)))
+        };
+        let options = |stale_fallback| ArchiveOptions {
+            stale_fallback,
+            ..ArchiveOptions::default()
+        };
+        let unavailable = ArchiveError::ServiceUnavailable {
+            status: 503,
+            snippet: String::new(),
+            retry_after: None,
+        };
+        let is_stale = |result: Result<ArchivingResult, ArchiveError>| {
+            matches!(
+                result.map(|result| result.outcome),
+                Ok(Outcome::StaleFallback { .. })
+            )
+        };
+        for (err, policy, stale) in [
+            (
+                ArchiveError::UnableToArchive,
+                StaleFallbackPolicy::default(),
+                true,
+            ),
+            (
+                ArchiveError::UnableToArchive,
+                StaleFallbackPolicy::Never,
+                false,
+            ),
+            (
+                ArchiveError::LoginRequired,
+                StaleFallbackPolicy::default(),
+                false,
+            ),
+            (
+                ArchiveError::LoginRequired,
+                StaleFallbackPolicy::Always,
+                true,
+            ),
+            (unavailable.clone(), StaleFallbackPolicy::default(), false),
+            // Once it's no longer retried.
+            (unavailable.clone(), StaleFallbackPolicy::Always, true),
+        ] {
+            let result = client(err.clone())
+                .archive_url_with_options("example.com/stale", &options(policy))
+                .await;
+            assert_eq!(is_stale(result), stale, "{:?} with {:?}", err, policy);
+        }
+    }
+
+    #[tokio::test]
+    async fn archives_with_client_endpoints() {
+        let mock = mock::MockWayback::start().await.unwrap();
+        let client = WaybackClient::new()
+            .with_endpoints(mock.endpoints())
+            .with_save_interval(std::time::Duration::ZERO);
+        let result = client.archive_url("example.net/self-hosted").await.unwrap();
+        assert!(result
+            .outcome
+            .url()
+            .is_some_and(|url| url.starts_with(&mock.endpoints().web)));
+        assert_eq!(mock.captures("example.net/self-hosted").len(), 1);
+        // Snapshot URLs built from the CDX index point at the client's endpoints too.
+        let latest = client
+            .fetch_latest_capture("example.net/self-hosted")
+            .await
+            .unwrap()
+            .unwrap();
+        assert!(latest.url.starts_with(&mock.endpoints().web));
+    }
+
+    #[tokio::test]
+    async fn caches_availability_only_when_asked() {
+        let mock = mock::MockWayback::start().await.unwrap();
+        let uncached = WaybackClient::new().with_endpoints(mock.endpoints());
+        let cache = Arc::new(AvailabilityCache::new(Duration::hours(1)));
+        let cached = uncached.clone().with_availability_cache(cache.clone());
+        for client in [&uncached, &cached] {
+            assert_eq!(
+                client.check_availability("example.com/cache").await,
+                Ok(None)
+            );
+        }
+        mock.add_capture("example.com/cache", Utc::now() - Duration::days(3));
+        assert!(uncached
+            .check_availability("example.com/cache")
+            .await
+            .unwrap()
+            .is_some());
+        assert_eq!(
+            cached.check_availability("example.com/cache").await,
+            Ok(None)
+        );
+
+        // Lookups at other endpoints are cached separately.
+        let other = mock::MockWayback::start().await.unwrap();
+        other.add_capture("example.com/cache", Utc::now() - Duration::days(3));
+        let other = WaybackClient::new()
+            .with_endpoints(other.endpoints())
+            .with_availability_cache(cache);
+        assert!(other
+            .check_availability("example.com/cache")
+            .await
+            .unwrap()
+            .is_some());
+    }
+
+    #[tokio::test]
+    async fn fetches_the_snapshot_closest_to_a_time() {
+        let mock = mock::MockWayback::start().await.unwrap();
+        let client = WaybackClient::new().with_endpoints(mock.endpoints());
+        // The URL's own query is passed whole, rather than taken for the API's.
+        let url = "https://example.com/search?q=a&page=2";
+        let at = |ts: &str| ts.parse::<DateTime<Utc>>().unwrap();
+        mock.add_capture(url, at("2019-01-01T00:00:00Z"));
+        mock.add_capture(url, at("2021-01-01T00:00:00Z"));
+        let snapshot = client
+            .fetch_snapshot_closest_to(url, at("2020-10-01T00:00:00Z"))
+            .await
+            .unwrap();
+        assert_eq!(snapshot.timestamp, at("2021-01-01T00:00:00Z"));
+        assert!(snapshot.url.ends_with(url), "{}", snapshot.url);
+        let snapshot = client
+            .fetch_snapshot_closest_to(url, at("2019-03-01T00:00:00Z"))
+            .await
+            .unwrap();
+        assert_eq!(snapshot.timestamp, at("2019-01-01T00:00:00Z"));
+    }
+
+    #[tokio::test]
+    async fn archives_batches() {
+        let mock = mock::MockWayback::start().await.unwrap();
+        mock.add_capture("example.com/recent", Utc::now() - Duration::days(3));
+        let client = WaybackClient::new()
+            .with_endpoints(mock.endpoints())
+            .with_save_interval(std::time::Duration::ZERO);
+        let urls = ["example.com/recent", "example.com/new", "example.org/"];
+        let results = client
+            .archive_urls(urls.iter().map(|url| url.to_string()), 2)
+            .await;
+        assert_eq!(results.keys().collect::<Vec<_>>(), {
+            let mut urls = urls.to_vec();
+            urls.sort_unstable();
+            urls
+        });
+        assert!(matches!(
+            results["example.com/recent"],
+            Ok(ArchivingResult {
+                outcome: Outcome::ExistingSnapshot { .. },
+                ..
+            })
+        ));
+        assert!(matches!(
+            results["example.com/new"],
+            Ok(ArchivingResult {
+                outcome: Outcome::NewCapture { .. },
+                ..
+            })
+        ));
+        assert_eq!(mock.captures("example.org/").len(), 1);
+    }
+
+    #[tokio::test]
+    async fn archives_streams_incrementally() {
+        let mock = mock::MockWayback::start().await.unwrap();
+        let client = WaybackClient::new()
+            .with_endpoints(mock.endpoints())
+            .with_save_interval(std::time::Duration::ZERO);
+        let (urls, queued) = futures::channel::mpsc::unbounded();
+        let mut results = Box::pin(client.archive_stream(queued, 2));
+        // Each result arrives while the input is still open.
+        for url in ["example.com/a", "example.com/b"] {
+            urls.unbounded_send(url.to_string()).unwrap();
+            let (archived, result) = results.next().await.unwrap();
+            assert_eq!(archived, url);
+            assert!(result.is_ok_and(|result| result.outcome.url().is_some()));
+        }
+        drop(urls);
+        assert!(results.next().await.is_none());
+    }
+
+    /// Serves a page of CDX records with each of `bodies` in turn, returning the
+    /// server's URL and the targets it was sent.
+    async fn serve_cdx_pages(
+        bodies: &'static [&'static str],
+    ) -> (String, tokio::task::JoinHandle<Vec<String>>) {
+        use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let server = tokio::spawn(async move {
+            let mut targets = Vec::new();
+            for body in bodies {
+                let (mut connection, _) = listener.accept().await.unwrap();
+                let mut request = vec![0; 4096];
+                let read = connection.read(&mut request).await.unwrap();
+                let request = String::from_utf8_lossy(&request[..read]).to_string();
+                targets.push(request.split(' ').nth(1).unwrap().to_string());
+                let response = format!(
+                    "HTTP/1.1 200 OK\r\nconnection: close\r\ncontent-length: {}\r\n\r\n{}",
+                    body.len(),
+                    body
+                );
+                connection.write_all(response.as_bytes()).await.unwrap();
+            }
+            targets
+        });
+        (format!("http://{}", addr), server)
+    }
+
+    #[tokio::test]
+    async fn lists_snapshots_page_by_page() {
+        let (base, server) = serve_cdx_pages(&[
+            "20160102030405 http://example.com/?a=1&b=2 200 text/html AAAA\n\ncom%2Cexample%29%2F+1\n",
+            "20170102030405 http://example.com/?a=1&b=2 200 text/html BBBB\n",
+        ])
+        .await;
+        let client = WaybackClient::new().with_endpoints(Endpoints {
+            web: base,
+            ..Endpoints::default()
+        });
+        let records = client
+            .list_snapshots("http://example.com/?a=1&b=2")
+            .await
+            .unwrap();
+        assert_eq!(records.len(), 2);
+        let targets = server.await.unwrap();
+        // The URL's own query is encoded, rather than taken for the CDX query's.
+        assert!(
+            targets[0]
+                .starts_with("/cdx/search/cdx?url=http%3A%2F%2Fexample.com%2F%3Fa%3D1%26b%3D2&"),
+            "{}",
+            targets[0]
+        );
+        assert!(
+            targets[1].ends_with("&resumeKey=com%2Cexample%29%2F+1"),
+            "{}",
+            targets[1]
+        );
+    }
+
+    #[tokio::test]
+    async fn looks_up_latest_captures_in_bulk() {
+        let (base, server) = serve_cdx_pages(&[
+            "20160102030405 http://example.com/blog/a 2 20190102030405\n\
+             20170102030405 https://www.example.com/blog/b 0 -\n\
+             20170102030405 http://example.com/blog/z 0 -\n",
+            "20180102030405\n",
+        ])
+        .await;
+        let client = WaybackClient::new().with_endpoints(Endpoints {
+            web: base,
+            ..Endpoints::default()
+        });
+        let mut urls: Vec<String> = ["a", "b", "c", "d", "e"]
+            .iter()
+            .map(|page| format!("https://example.com/blog/{}", page))
+            .collect();
+        urls.push("http://other.example/".to_string());
+        let mut captures = client.fetch_latest_captures_in_bulk(urls, 2).await;
+        captures.sort_by(|a, b| a.0.cmp(&b.0));
+        let timestamps: Vec<(&str, i64)> = captures
+            .iter()
+            .map(|(url, snapshot)| (url.as_str(), snapshot.timestamp.timestamp()))
+            .collect();
+        assert_eq!(
+            timestamps,
+            [
+                ("http://other.example/", 1514862245),
+                ("https://example.com/blog/a", 1546398245),
+                ("https://example.com/blog/b", 1483326245),
+            ]
+        );
+        // One listing of the blog's captures, then a lookup of the other host's URL.
+        let targets = server.await.unwrap();
+        assert!(
+            targets[0].starts_with(
+                "/cdx/search/cdx?url=example.com%2Fblog%2F&matchType=prefix&collapse=urlkey&"
+            ),
+            "{}",
+            targets[0]
+        );
+        assert!(targets[1].starts_with("/cdx/search/cdx?url=http%3A%2F%2Fother.example%2F&"));
+    }
+
+    #[tokio::test]
+    async fn lists_site_captures_page_by_page() {
+        let (base, server) = serve_cdx_pages(&[
+            "20160102030405 http://example.com/a 200 text/html AAAA\n\ncom%2Cexample%29%2Fb+1\n",
+            "20170102030405 http://www.example.com/b 200 text/html BBBB\n",
+        ])
+        .await;
+        let client = WaybackClient::new().with_endpoints(Endpoints {
+            web: base,
+            ..Endpoints::default()
+        });
+        let filter = CdxFilter {
+            status: Some(200),
+            mimetype: Some("text/html".to_string()),
+            since: None,
+        };
+        let records = client
+            .list_site_captures("example.com", &filter)
+            .await
+            .unwrap();
+        assert_eq!(
+            records
+                .iter()
+                .map(|record| record.original.as_str())
+                .collect::<Vec<_>>(),
+            ["http://example.com/a", "http://www.example.com/b"]
+        );
+        let targets = server.await.unwrap();
+        assert!(
+            targets[0].contains("matchType=domain")
+                && targets[0].contains("filter=statuscode%3A200")
+                && targets[0].contains("filter=mimetype%3Atext%2Fhtml"),
+            "{}",
+            targets[0]
+        );
+        // The resume key is passed back as it was given.
+        assert!(
+            targets[1].ends_with("&resumeKey=com%2Cexample%29%2Fb+1"),
+            "{}",
+            targets[1]
+        );
+    }
+
+    #[tokio::test]
+    async fn lists_site_urls_a_page_at_a_time() {
+        use futures::TryStreamExt;
+
+        let (base, server) = serve_cdx_pages(&[
+            "20160102030405 http://example.com/a 200 text/html AAAA\n\ncom%2Cexample%29%2Fb+1\n",
+            "20170102030405 http://www.example.com/b 200 text/html BBBB\n",
+        ])
+        .await;
+        let client = WaybackClient::new().with_endpoints(Endpoints {
+            web: base,
+            ..Endpoints::default()
+        });
+        let filter = CdxFilter {
+            since: Some("2016-01-01T00:00:00Z".parse().unwrap()),
+            ..CdxFilter::default()
+        };
+        let pages: Vec<Vec<CdxRecord>> = client
+            .list_site_urls("example.com", &filter)
+            .try_collect()
+            .await
+            .unwrap();
+        assert_eq!(
+            pages.iter().map(|page| page.len()).collect::<Vec<_>>(),
+            [1, 1]
+        );
+        let targets = server.await.unwrap();
+        assert!(
+            targets[0].contains("collapse=urlkey") && targets[0].contains("from=20160101000000"),
+            "{}",
+            targets[0]
+        );
+    }
+
+    #[tokio::test]
+    async fn notifies_observers() {
+        #[derive(Default)]
+        struct Recorder(std::sync::Mutex<Vec<String>>);
+
+        impl ArchiveObserver for Recorder {
+            fn on_lookup(&self, url: &str) {
+                self.0.lock().unwrap().push(format!("lookup {}", url));
+            }
+
+            fn on_submit(&self, url: &str) {
+                self.0.lock().unwrap().push(format!("submit {}", url));
+            }
+
+            fn on_complete(&self, url: &str, result: &Result<ArchivingResult, ArchiveError>) {
+                let ok = result.is_ok();
+                self.0
+                    .lock()
+                    .unwrap()
+                    .push(format!("complete {} {}", url, ok));
+            }
+        }
+
+        let mock = mock::MockWayback::start().await.unwrap();
+        mock.add_capture("example.com/observed", Utc::now() - Duration::days(3));
+        let recorder = Arc::new(Recorder::default());
+        let client = WaybackClient::new()
+            .with_endpoints(mock.endpoints())
+            .with_save_interval(std::time::Duration::ZERO)
+            .with_observer(recorder.clone());
+        client.archive_url("example.com/observed").await.unwrap();
+        client.archive_url("example.com/unobserved").await.unwrap();
+        assert_eq!(
+            *recorder.0.lock().unwrap(),
+            [
+                "lookup example.com/observed",
+                "complete example.com/observed true",
+                "lookup example.com/unobserved",
+                "submit example.com/unobserved",
+                "complete example.com/unobserved true",
+            ]
+        );
+    }
+
+    #[test]
+    fn classifies_errors_consistently() {
+        let errors = vec![
+            ArchiveError::BandwidthExceeded { retry_after: None },
+            ArchiveError::BandwidthExceeded {
+                retry_after: Some(std::time::Duration::from_secs(60)),
+            },
+            ArchiveError::UnableToArchive,
+            ArchiveError::NoExistingSnapshot,
+            ArchiveError::DeadHost("example.invalid".into()),
+            ArchiveError::OriginUnreachable("error:no-access".into()),
+            ArchiveError::LoginRequired,
+            ArchiveError::ServiceUnavailable {
+                status: 503,
+                snippet: String::new(),
+                retry_after: None,
+            },
+            ArchiveError::Http {
+                status: 418,
+                snippet: String::new(),
+            },
+            ArchiveError::Network(String::new()),
+            ArchiveError::Timeout,
+            ArchiveError::Cancelled,
+            ArchiveError::ParseError(String::new()),
+            ArchiveError::Unknown(String::new()),
+        ];
+        let mut codes = std::collections::BTreeSet::new();
+        for err in errors {
+            assert!(
+                ERROR_CODES.iter().any(|(code, _)| *code == err.code()),
+                "{:?}",
+                err
+            );
+            codes.insert(err.code());
+            assert!(!(err.is_retryable() && err.is_permanent()), "{:?}", err);
+            assert_eq!(
+                err.is_retryable(),
+                err.suggested_backoff(
+                    std::time::Duration::from_secs(15),
+                    std::time::Duration::from_secs(30)
+                )
+                .is_some(),
+                "{:?}",
+                err
+            );
+            if err.suggested_deferral().is_some() {
+                assert!(!err.is_retryable() && !err.is_permanent(), "{:?}", err);
+            }
+        }
+        // Every registered code is used (by a variant that's in the list above).
+        assert_eq!(codes.len(), ERROR_CODES.len());
+    }
+
+    #[test]
+    fn parses_retry_after() {
+        let now = Utc.with_ymd_and_hms(2021, 9, 7, 12, 0, 0).unwrap();
+        let secs = |secs| Some(std::time::Duration::from_secs(secs));
+        assert_eq!(parse_retry_after("120", now), secs(120));
+        assert_eq!(
+            parse_retry_after("Tue, 07 Sep 2021 12:01:30 GMT", now),
+            secs(90)
+        );
+        assert_eq!(
+            parse_retry_after("Tue, 07 Sep 2021 11:00:00 GMT", now),
+            secs(0)
+        );
+        assert_eq!(parse_retry_after("soon", now), None);
+        // Absurd waits are capped at an hour.
+        assert_eq!(parse_retry_after("99999999999", now), secs(3600));
+        assert_eq!(
+            parse_retry_after("Fri, 07 Sep 2029 12:00:00 GMT", now),
+            secs(3600)
+        );
+    }
+
+    #[test]
+    fn reads_results_without_optional_fields() {
+        let result: ArchivingResult = serde_json::from_str(
+            r#"{"outcome": "new-capture", "url": "https://web.archive.org/web/20210907123456/https://example.com", "ts": "2021-09-07T12:34:56"}"#,
+        )
+        .unwrap();
+        assert_eq!(
+            result,
+            ArchivingResult::new(Outcome::NewCapture {
+                url: "https://web.archive.org/web/20210907123456/https://example.com".into(),
+                ts: "2021-09-07T12:34:56".parse().unwrap(),
+            })
+        );
+    }
+
+    #[test]
+    fn reads_results_written_before_outcomes() {
+        let read = |json: &str| {
+            serde_json::from_str::<ArchivingResult>(json)
+                .unwrap()
+                .outcome
+        };
+        let ts: NaiveDateTime = "2021-09-07T12:34:56".parse().unwrap();
+        assert_eq!(
+            read(
+                r#"{"url": "https://web.archive.org/web/x", "last_archived": "2021-09-07T12:34:56"}"#
+            ),
+            Outcome::NewCapture {
+                url: "https://web.archive.org/web/x".into(),
+                ts
+            }
+        );
+        assert_eq!(
+            read(
+                r#"{"url": "https://web.archive.org/web/x", "last_archived": "2021-09-07T12:34:56", "existing_snapshot": true}"#
+            ),
+            Outcome::ExistingSnapshot {
+                url: "https://web.archive.org/web/x".into(),
+                ts
+            }
+        );
+        assert_eq!(
+            read(
+                r#"{"url": null, "last_archived": "2021-09-07T12:34:56", "error": "Bandwidth Exceeded"}"#
+            ),
+            Outcome::Failed {
+                error: "Bandwidth Exceeded".into()
+            }
+        );
+        assert_eq!(
+            read(r#"{"url": null, "last_archived": "2021-09-07T12:34:56"}"#),
+            Outcome::Failed {
+                error: "No snapshot was recorded".into()
+            }
+        );
+    }
+}
@@ -0,0 +1,90 @@
+use crate::{ArchiveError, Validators};
+use reqwest::header::{
+    CONTENT_LENGTH, CONTENT_TYPE, ETAG, IF_MODIFIED_SINCE, IF_NONE_MATCH, LAST_MODIFIED,
+};
+use reqwest::StatusCode;
+
+impl Validators {
+    pub fn is_empty(&self) -> bool {
+        self.etag.is_none() && self.last_modified.is_none()
+    }
+}
+
+#[derive(Debug, PartialEq)]
+pub enum LiveCheck {
+    /// The server responded with HTTP 304, so the page hasn't changed.
+    Unchanged,
+    /// The page was (possibly) modified; contains its current validators.
+    Changed(Validators),
+}
+
+/// Fetches the live page at `url` with `http` (e.g. a `WaybackClient`'s, to go through
+/// its proxy), with a conditional request built from `validators`.
+///
+/// Responses are not read past their headers, so an unchanged page costs only a
+/// round trip.
+pub async fn check_live_page(
+    http: &reqwest::Client,
+    url: &str,
+    validators: &Validators,
+) -> Result<LiveCheck, ArchiveError> {
+    let mut req = http.get(url);
+    if let Some(etag) = &validators.etag {
+        req = req.header(IF_NONE_MATCH, etag);
+    }
+    if let Some(last_modified) = &validators.last_modified {
+        req = req.header(IF_MODIFIED_SINCE, last_modified);
+    }
+    let resp = req
+        .send()
+        .await
+        .map_err(|err| ArchiveError::Network(err.to_string()))?;
+    if resp.status() == StatusCode::NOT_MODIFIED {
+        return Ok(LiveCheck::Unchanged);
+    }
+
+    let header = |name| {
+        resp.headers()
+            .get(name)
+            .and_then(|value| value.to_str().ok())
+            .map(|value| value.to_string())
+    };
+    Ok(LiveCheck::Changed(Validators {
+        etag: header(ETAG),
+        last_modified: header(LAST_MODIFIED),
+    }))
+}
+
+/// The type and size of a live page, as reported by its server.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct ContentInfo {
+    /// The page's MIME type, lowercase and without parameters (e.g. `application/pdf`).
+    pub content_type: Option<String>,
+    /// The page's size in bytes.
+    pub content_length: Option<u64>,
+}
+
+/// Looks up the type and size of the live page at `url` with a HEAD request sent with
+/// `http`, without downloading it.
+pub async fn check_content_type(
+    http: &reqwest::Client,
+    url: &str,
+) -> Result<ContentInfo, ArchiveError> {
+    let resp = http
+        .head(url)
+        .send()
+        .await
+        .map_err(|err| ArchiveError::Network(err.to_string()))?;
+    let header = |name| {
+        resp.headers()
+            .get(name)
+            .and_then(|value| value.to_str().ok())
+    };
+    Ok(ContentInfo {
+        content_type: header(CONTENT_TYPE).map(|content_type| {
+            let essence = content_type.split(';').next().unwrap_or_default();
+            essence.trim().to_ascii_lowercase()
+        }),
+        content_length: header(CONTENT_LENGTH).and_then(|length| length.trim().parse().ok()),
+    })
+}
@@ -0,0 +1,167 @@
+//! JSON files that may be shared by several processes at once (e.g. caches in a
+//! shared cache directory), guarded by advisory locks on a `<path>.lock` file next to
+//! them.
+//!
+//! Updates are written to a temporary file and renamed over the original, so a crash
+//! mid-write never leaves a truncated file behind. A file that can't be parsed anyway
+//! (e.g. one written by an older version) is set aside as `<path>.corrupt` rather than
+//! overwritten, and the caller is told about it.
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+use std::fmt;
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+
+/// A file that couldn't be parsed, and so was set aside (and treated as empty).
+#[derive(Clone, Debug, PartialEq)]
+pub struct SetAside {
+    pub path: PathBuf,
+    pub moved_to: PathBuf,
+    /// Why it couldn't be parsed.
+    pub reason: String,
+}
+
+impl fmt::Display for SetAside {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{} can't be parsed ({}), so it's been moved to {}",
+            self.path.display(),
+            self.reason,
+            self.moved_to.display()
+        )
+    }
+}
+
+/// Reads the JSON file at `path` under a shared lock. Returns `None` if the file
+/// doesn't exist or can't be parsed, in which case it's set aside.
+pub(crate) fn read_locked<T: DeserializeOwned>(
+    path: &Path,
+) -> io::Result<(Option<T>, Option<SetAside>)> {
+    if !path.exists() {
+        return Ok((None, None));
+    }
+    let lock = open_lock(path)?;
+    lock.lock_shared()?;
+    let (current, set_aside) = read(path)?;
+    if set_aside.is_some() {
+        fs::rename(path, corrupt_path(path))?;
+    }
+    lock.unlock()?;
+    Ok((current, set_aside))
+}
+
+/// Replaces the JSON file at `path` with the result of `update`, which is passed the
+/// file's current contents (as `read_locked` would return them). The file is locked
+/// exclusively throughout, so concurrent updates from other processes aren't lost.
+pub(crate) fn update_locked<T, F>(path: &Path, update: F) -> io::Result<Option<SetAside>>
+where
+    T: Serialize + DeserializeOwned,
+    F: FnOnce(Option<T>) -> T,
+{
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent)?;
+    }
+    let lock = open_lock(path)?;
+    lock.lock()?;
+    let (current, set_aside) = read(path)?;
+    if set_aside.is_some() {
+        fs::rename(path, corrupt_path(path))?;
+    }
+    let updated = update(current);
+    let mut temp_path = path.as_os_str().to_owned();
+    temp_path.push(format!(".{}.tmp", std::process::id()));
+    let temp_path = PathBuf::from(temp_path);
+    let mut temp = File::create(&temp_path)?;
+    temp.write_all(serde_json::to_string(&updated)?.as_bytes())?;
+    temp.sync_all()?;
+    fs::rename(&temp_path, path)?;
+    lock.unlock()?;
+    Ok(set_aside)
+}
+
+/// Opens (creating if need be) the file that guards `path`. The lock can't be taken on
+/// `path` itself, since renaming over it would leave waiting processes locking the
+/// replaced file.
+fn open_lock(path: &Path) -> io::Result<File> {
+    let mut lock_path = path.as_os_str().to_owned();
+    lock_path.push(".lock");
+    OpenOptions::new()
+        .write(true)
+        .create(true)
+        .truncate(false)
+        .open(PathBuf::from(lock_path))
+}
+
+/// Reads and parses the file at `path`, which may not exist. If it can't be parsed,
+/// returns why (the caller sets it aside).
+fn read<T: DeserializeOwned>(path: &Path) -> io::Result<(Option<T>, Option<SetAside>)> {
+    let contents = match fs::read_to_string(path) {
+        Ok(contents) => contents,
+        Err(error) if error.kind() == io::ErrorKind::NotFound => return Ok((None, None)),
+        Err(error) => return Err(error),
+    };
+    if contents.is_empty() {
+        return Ok((None, None));
+    }
+    match serde_json::from_str(&contents) {
+        Ok(parsed) => Ok((Some(parsed), None)),
+        Err(err) => Ok((
+            None,
+            Some(SetAside {
+                path: path.to_owned(),
+                moved_to: corrupt_path(path),
+                reason: err.to_string(),
+            }),
+        )),
+    }
+}
+
+/// Where the file at `path` is set aside if it can't be parsed.
+fn corrupt_path(path: &Path) -> PathBuf {
+    let mut corrupt = path.as_os_str().to_owned();
+    corrupt.push(".corrupt");
+    PathBuf::from(corrupt)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashMap;
+
+    #[test]
+    fn sets_corrupt_files_aside() {
+        let dir = std::env::temp_dir().join(format!("locked-file-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("cache.json");
+        let corrupt = dir.join("cache.json.corrupt");
+
+        fs::write(&path, "{\"a\": 1,").unwrap();
+        let (read, set_aside) = read_locked::<HashMap<String, u32>>(&path).unwrap();
+        assert_eq!(read, None);
+        assert_eq!(set_aside.unwrap().moved_to, corrupt);
+        assert!(!path.exists());
+        assert_eq!(fs::read_to_string(&corrupt).unwrap(), "{\"a\": 1,");
+
+        fs::write(&path, "[]").unwrap();
+        let set_aside = update_locked(&path, |current: Option<HashMap<String, u32>>| {
+            assert_eq!(current, None);
+            HashMap::from([("b".to_string(), 2)])
+        })
+        .unwrap();
+        assert!(set_aside.is_some());
+        assert_eq!(fs::read_to_string(&corrupt).unwrap(), "[]");
+        assert_eq!(
+            read_locked::<HashMap<String, u32>>(&path).unwrap(),
+            (Some(HashMap::from([("b".to_string(), 2)])), None)
+        );
+        let names: Vec<_> = fs::read_dir(&dir)
+            .unwrap()
+            .map(|entry| entry.unwrap().file_name().into_string().unwrap())
+            .filter(|name| name.ends_with(".tmp"))
+            .collect();
+        assert!(names.is_empty(), "left behind {:?}", names);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
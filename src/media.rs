@@ -0,0 +1,126 @@
+use std::process::Stdio;
+use std::str::FromStr;
+use tokio::process::{Child, Command};
+
+/// Hosts whose pages are video or audio, which a Wayback snapshot of the page doesn't
+/// preserve. Subdomains (e.g. m.youtube.com) are included.
+const MEDIA_HOSTS: &[&str] = &[
+    "youtube.com",
+    "youtu.be",
+    "vimeo.com",
+    "dailymotion.com",
+    "twitch.tv",
+    "soundcloud.com",
+    "tiktok.com",
+];
+
+/// An external command that captures the media on media pages (e.g. `yt-dlp {url}`),
+/// run alongside the archival of the page itself. `{url}` in its arguments is replaced
+/// with the page's URL, which is appended if there is no `{url}`.
+///
+/// Arguments are split on whitespace, without a shell, so URLs can't inject commands.
+#[derive(Debug, PartialEq)]
+pub struct MediaHandler {
+    program: String,
+    args: Vec<String>,
+}
+
+impl FromStr for MediaHandler {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut words = s.split_whitespace().map(str::to_string);
+        let program = words
+            .next()
+            .ok_or_else(|| "the media handler is empty".to_string())?;
+        let mut args: Vec<String> = words.collect();
+        if !args.iter().any(|arg| arg.contains("{url}")) {
+            args.push("{url}".to_string());
+        }
+        Ok(MediaHandler { program, args })
+    }
+}
+
+impl MediaHandler {
+    fn args(&self, url: &str) -> Vec<String> {
+        self.args
+            .iter()
+            .map(|arg| arg.replace("{url}", url))
+            .collect()
+    }
+
+    /// Starts the handler for `url`. Its output is captured, so that it doesn't garble
+    /// the progress display.
+    pub fn spawn(&self, url: &str) -> std::io::Result<Child> {
+        Command::new(&self.program)
+            .args(self.args(url))
+            .stdin(Stdio::null())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .kill_on_drop(true)
+            .spawn()
+    }
+}
+
+/// Waits for a handler started with `MediaHandler::spawn` to exit, returning the last
+/// line it wrote to stderr if it failed.
+pub async fn wait(child: Child) -> Result<(), String> {
+    let output = child
+        .wait_with_output()
+        .await
+        .map_err(|err| err.to_string())?;
+    if output.status.success() {
+        return Ok(());
+    }
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    let last_line = stderr.lines().rev().find(|line| !line.trim().is_empty());
+    Err(match last_line {
+        Some(line) => format!("{} ({})", output.status, line.trim()),
+        None => output.status.to_string(),
+    })
+}
+
+/// Whether `url` is on one of the well-known video and audio hosts.
+pub fn is_media_url(url: &str) -> bool {
+    let url = match reqwest::Url::parse(&wayback_archiver::normalize_url(url)) {
+        Ok(url) => url,
+        Err(_) => return false,
+    };
+    let host = url.host_str().unwrap_or_default().to_ascii_lowercase();
+    MEDIA_HOSTS.iter().any(|media_host| {
+        host == *media_host
+            || host
+                .strip_suffix(media_host)
+                .is_some_and(|subdomain| subdomain.ends_with('.'))
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn substitutes_urls() {
+        let handler: MediaHandler = "yt-dlp -P videos/ {url}".parse().unwrap();
+        assert_eq!(handler.program, "yt-dlp");
+        assert_eq!(
+            handler.args("https://youtu.be/x"),
+            ["-P", "videos/", "https://youtu.be/x"]
+        );
+        let handler: MediaHandler = "yt-dlp --no-progress".parse().unwrap();
+        assert_eq!(
+            handler.args("https://youtu.be/x"),
+            ["--no-progress", "https://youtu.be/x"]
+        );
+        assert!(" ".parse::<MediaHandler>().is_err());
+    }
+
+    #[test]
+    fn recognizes_media_urls() {
+        assert!(is_media_url("https://www.youtube.com/watch?v=x"));
+        assert!(is_media_url("youtu.be/x"));
+        assert!(is_media_url("https://VIMEO.com/123"));
+        assert!(!is_media_url("https://notyoutube.com/watch"));
+        assert!(!is_media_url("https://example.com/youtube.com"));
+    }
+}
@@ -0,0 +1,198 @@
+use crate::client::WaybackClient;
+use crate::{ArchiveError, ArchiveOptions, ArchiveProvider, ArchivingResult, Outcome, Snapshot};
+use chrono::{DateTime, Utc};
+use futures::future::{BoxFuture, FutureExt};
+use serde::Deserialize;
+
+/// A web archive other than the Wayback Machine (e.g. a national archive, which may
+/// preserve regional content better), reached through its
+/// [Memento](https://mementoweb.org/) TimeGate and, if it has one, a Save Page Now-like
+/// endpoint that captures pages on request.
+///
+/// ```no_run
+/// # async fn example() -> Result<(), wayback_archiver::ArchiveError> {
+/// use wayback_archiver::{ArchiveOptions, MementoArchive, WaybackClient};
+///
+/// let client = WaybackClient::new();
+/// let archive = MementoArchive::arquivo_pt();
+/// let options = ArchiveOptions::default();
+/// let result = client
+///     .archive_url_in(&archive, "https://www.example.pt/", &options)
+///     .await?;
+/// # Ok(())
+/// # }
+/// ```
+#[derive(Clone, Debug, Deserialize, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct MementoArchive {
+    /// The prefix of the archive's TimeGate URLs, which the URL is appended to (e.g.
+    /// `https://arquivo.pt/wayback/`).
+    pub timegate: String,
+    /// The prefix of URLs that capture the URL appended to them, if the archive
+    /// captures pages on request. Archives without one can only be searched for
+    /// existing snapshots.
+    #[serde(default)]
+    pub save: Option<String>,
+}
+
+impl MementoArchive {
+    /// [Arquivo.pt](https://arquivo.pt), the Portuguese web archive. It doesn't capture
+    /// pages on request through an API, so only its existing snapshots are used.
+    pub fn arquivo_pt() -> Self {
+        MementoArchive {
+            timegate: "https://arquivo.pt/wayback/".to_string(),
+            save: None,
+        }
+    }
+}
+
+impl WaybackClient {
+    /// The newest snapshot of `url` in `archive`, if it has one.
+    pub async fn latest_memento(
+        &self,
+        archive: &MementoArchive,
+        url: &str,
+    ) -> Result<Option<Snapshot>, ArchiveError> {
+        // The TimeGate redirects to the newest memento, as no Accept-Datetime is sent.
+        let request = self.http().head(format!("{}{}", archive.timegate, url));
+        let resp = self.send(request).await?;
+        let memento_datetime = resp
+            .headers()
+            .get("memento-datetime")
+            .and_then(|value| value.to_str().ok());
+        parse_memento(
+            resp.status().as_u16(),
+            resp.url().as_str(),
+            memento_datetime,
+        )
+    }
+
+    /// Like `archive_url_with_options`, but archives `url` in `archive` rather than
+    /// the Wayback Machine, once (without the client's retries). A recent enough
+    /// existing snapshot is reused; otherwise the URL is captured, if the archive
+    /// captures pages on request. If it doesn't, the newest existing snapshot is
+    /// returned as a stale fallback (if the stale fallback policy allows it), or
+    /// `ArchiveError::UnableToArchive`.
+    ///
+    /// To archive every URL in `archive`, with retries, use `with_provider` instead.
+    pub async fn archive_url_in(
+        &self,
+        archive: &MementoArchive,
+        url: &str,
+        options: &ArchiveOptions,
+    ) -> Result<ArchivingResult, ArchiveError> {
+        self.submit(archive, url, options).await
+    }
+
+    /// Captures `url` in `archive`, telling the capture apart from the `previous`
+    /// newest snapshot.
+    async fn save_memento(
+        &self,
+        archive: &MementoArchive,
+        url: &str,
+        previous: Option<&Snapshot>,
+    ) -> Result<ArchivingResult, ArchiveError> {
+        let save = archive.save.as_ref().ok_or(ArchiveError::UnableToArchive)?;
+        let resp = self
+            .send(self.http().get(format!("{}{}", save, url)))
+            .await?;
+        let status = resp.status().as_u16();
+        if status == 429 || status >= 500 {
+            return Err(self.service_unavailable(resp).await);
+        }
+        if !resp.status().is_success() {
+            return Err(ArchiveError::UnableToArchive);
+        }
+        match self.latest_memento(archive, url).await? {
+            Some(snapshot) if previous.is_none_or(|old| old.url != snapshot.url) => {
+                Ok(ArchivingResult::new(Outcome::NewCapture {
+                    url: snapshot.url,
+                    ts: snapshot.timestamp.naive_utc(),
+                }))
+            }
+            // The capture hasn't been indexed yet.
+            _ => Err(ArchiveError::UnableToArchive),
+        }
+    }
+}
+
+impl ArchiveProvider for MementoArchive {
+    /// The archive's TimeGate, which identifies it.
+    fn provider_name(&self) -> &str {
+        &self.timegate
+    }
+
+    fn lookup_latest<'a>(
+        &'a self,
+        client: &'a WaybackClient,
+        url: &'a str,
+    ) -> BoxFuture<'a, Result<Option<Snapshot>, ArchiveError>> {
+        client.latest_memento(self, url).boxed()
+    }
+
+    fn captures_on_request(&self) -> bool {
+        self.save.is_some()
+    }
+
+    fn save<'a>(
+        &'a self,
+        client: &'a WaybackClient,
+        url: &'a str,
+        _options: &'a ArchiveOptions,
+        previous: Option<&'a Snapshot>,
+    ) -> BoxFuture<'a, Result<ArchivingResult, ArchiveError>> {
+        client.save_memento(self, url, previous).boxed()
+    }
+}
+
+/// Interprets a TimeGate's response (with `status`, from `final_url` after redirects,
+/// and its `Memento-Datetime` header).
+pub(crate) fn parse_memento(
+    status: u16,
+    final_url: &str,
+    memento_datetime: Option<&str>,
+) -> Result<Option<Snapshot>, ArchiveError> {
+    match status {
+        404 => return Ok(None),
+        200..=299 => {}
+        _ => {
+            return Err(ArchiveError::Http {
+                status,
+                snippet: String::new(),
+            })
+        }
+    }
+    let memento_datetime = memento_datetime
+        .ok_or_else(|| ArchiveError::ParseError("memento without a Memento-Datetime".into()))?;
+    let timestamp = DateTime::parse_from_rfc2822(memento_datetime)
+        .map_err(|err| ArchiveError::ParseError(err.to_string()))?;
+    Ok(Some(Snapshot {
+        url: final_url.to_string(),
+        timestamp: timestamp.with_timezone(&Utc),
+        status: None,
+        available: true,
+    }))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::TimeZone;
+
+    #[test]
+    fn parses_mementos() {
+        let memento = "https://arquivo.pt/wayback/20210907123456/https://www.example.pt/";
+        assert_eq!(
+            parse_memento(200, memento, Some("Tue, 07 Sep 2021 12:34:56 GMT")),
+            Ok(Some(Snapshot {
+                url: memento.to_string(),
+                timestamp: Utc.with_ymd_and_hms(2021, 9, 7, 12, 34, 56).unwrap(),
+                status: None,
+                available: true,
+            }))
+        );
+        assert_eq!(parse_memento(404, memento, None), Ok(None));
+        assert!(parse_memento(200, memento, None).is_err());
+        assert!(parse_memento(503, memento, None).is_err());
+    }
+}
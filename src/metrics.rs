@@ -0,0 +1,180 @@
+//! Latency histograms of requests to the Wayback Machine, kept separately for each
+//! endpoint, so that slow runs can be traced to the service that's slow.
+use std::collections::BTreeMap;
+use std::convert::TryFrom;
+use std::fmt::Write;
+use std::future::Future;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+/// Upper bounds of the histogram buckets, in seconds. Save Page Now regularly takes
+/// tens of seconds, while lookups usually take well under one.
+const BUCKETS: &[f64] = &[0.1, 0.25, 0.5, 1.0, 2.5, 5.0, 10.0, 30.0, 60.0, 120.0];
+
+/// A Wayback Machine service the library makes requests to.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum Endpoint {
+    /// Save Page Now (`/save/`).
+    Save,
+    /// Save Page Now 2's capture status (`/save/status/`).
+    SaveStatus,
+    /// The availability API (`/wayback/available`).
+    Availability,
+    /// The CDX API (`/cdx/search/cdx`).
+    Cdx,
+}
+
+impl Endpoint {
+    pub fn name(self) -> &'static str {
+        match self {
+            Endpoint::Save => "save",
+            Endpoint::SaveStatus => "save-status",
+            Endpoint::Availability => "availability",
+            Endpoint::Cdx => "cdx",
+        }
+    }
+}
+
+/// The distribution of an endpoint's request latencies.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Histogram {
+    /// The number of requests in each of `BUCKETS` (not cumulative), followed by the
+    /// number slower than the last bucket.
+    counts: [u64; BUCKETS.len() + 1],
+    pub count: u64,
+    pub sum: Duration,
+    pub max: Duration,
+}
+
+impl Histogram {
+    pub fn observe(&mut self, latency: Duration) {
+        let secs = latency.as_secs_f64();
+        let bucket = BUCKETS
+            .iter()
+            .position(|bound| secs <= *bound)
+            .unwrap_or(BUCKETS.len());
+        self.counts[bucket] += 1;
+        self.count += 1;
+        self.sum += latency;
+        self.max = self.max.max(latency);
+    }
+
+    pub fn mean(&self) -> Option<Duration> {
+        u32::try_from(self.count)
+            .ok()
+            .filter(|count| *count > 0)
+            .map(|count| self.sum / count)
+    }
+
+    /// An upper bound of the `q` quantile (e.g. 0.95), from the bucket it falls in.
+    pub fn quantile(&self, q: f64) -> Option<Duration> {
+        let rank = (q * self.count as f64).ceil().max(1.0) as u64;
+        let mut seen = 0;
+        for (bucket, count) in self.counts.iter().enumerate() {
+            seen += count;
+            if seen >= rank {
+                return Some(match BUCKETS.get(bucket) {
+                    Some(bound) => Duration::from_secs_f64(*bound).min(self.max),
+                    None => self.max,
+                });
+            }
+        }
+        None
+    }
+}
+
+static LATENCIES: Mutex<BTreeMap<Endpoint, Histogram>> = Mutex::new(BTreeMap::new());
+
+/// The latencies of the requests made so far, by endpoint. Endpoints that haven't
+/// been requested are omitted.
+pub fn latencies() -> BTreeMap<Endpoint, Histogram> {
+    LATENCIES.lock().unwrap().clone()
+}
+
+/// Runs `request`, recording how long it took against `endpoint`. Requests read their
+/// response's body within it, so that slow downloads are counted too.
+pub(crate) async fn timed<F: Future>(endpoint: Endpoint, request: F) -> F::Output {
+    let started = Instant::now();
+    let output = request.await;
+    LATENCIES
+        .lock()
+        .unwrap()
+        .entry(endpoint)
+        .or_default()
+        .observe(started.elapsed());
+    output
+}
+
+/// Formats `latencies` in the Prometheus text exposition format, as the
+/// `wayback_request_duration_seconds` histogram.
+pub fn format_prometheus(latencies: &BTreeMap<Endpoint, Histogram>) -> String {
+    const NAME: &str = "wayback_request_duration_seconds";
+
+    let mut out = String::new();
+    writeln!(
+        out,
+        "# HELP {} Latency of requests to the Wayback Machine, by endpoint.",
+        NAME
+    )
+    .unwrap();
+    writeln!(out, "# TYPE {} histogram", NAME).unwrap();
+    for (endpoint, histogram) in latencies {
+        let endpoint = endpoint.name();
+        let mut cumulative = 0;
+        for (bound, count) in BUCKETS.iter().zip(&histogram.counts) {
+            cumulative += count;
+            writeln!(
+                out,
+                "{}_bucket{{endpoint=\"{}\",le=\"{}\"}} {}",
+                NAME, endpoint, bound, cumulative
+            )
+            .unwrap();
+        }
+        writeln!(
+            out,
+            "{}_bucket{{endpoint=\"{}\",le=\"+Inf\"}} {}",
+            NAME, endpoint, histogram.count
+        )
+        .unwrap();
+        writeln!(
+            out,
+            "{}_sum{{endpoint=\"{}\"}} {}",
+            NAME,
+            endpoint,
+            histogram.sum.as_secs_f64()
+        )
+        .unwrap();
+        writeln!(
+            out,
+            "{}_count{{endpoint=\"{}\"}} {}",
+            NAME, endpoint, histogram.count
+        )
+        .unwrap();
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn summarizes_latencies() {
+        let mut histogram = Histogram::default();
+        for millis in [50, 200, 300, 4000] {
+            histogram.observe(Duration::from_millis(millis));
+        }
+        assert_eq!(histogram.mean(), Some(Duration::from_micros(1_137_500)));
+        assert_eq!(histogram.quantile(0.5), Some(Duration::from_millis(250)));
+        assert_eq!(histogram.quantile(0.95), Some(Duration::from_millis(4000)));
+
+        let latencies = BTreeMap::from([(Endpoint::Cdx, histogram)]);
+        let formatted = format_prometheus(&latencies);
+        assert!(formatted
+            .contains("wayback_request_duration_seconds_bucket{endpoint=\"cdx\",le=\"0.25\"} 2\n"));
+        assert!(formatted
+            .contains("wayback_request_duration_seconds_bucket{endpoint=\"cdx\",le=\"+Inf\"} 4\n"));
+        assert!(formatted.contains("wayback_request_duration_seconds_sum{endpoint=\"cdx\"} 4.55\n"));
+        assert!(formatted.contains("wayback_request_duration_seconds_count{endpoint=\"cdx\"} 4\n"));
+    }
+}
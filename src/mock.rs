@@ -0,0 +1,240 @@
+//! A mock Wayback Machine, for running examples and tests without touching archive.org.
+//!
+//! The mock serves just enough of the availability API, Save Page Now, the CDX API, and
+//! snapshots for the library's requests to succeed. Captures are kept in memory: saving
+//! a URL records a capture at the current time, and later lookups find it.
+//!
+//! ```no_run
+//! # async fn example() -> std::io::Result<()> {
+//! let mock = wayback_archiver::mock::MockWayback::start().await?;
+//! let client = wayback_archiver::WaybackClient::new().with_endpoints(mock.endpoints());
+//! let result = client.archive_url("example.com").await;
+//! # Ok(())
+//! # }
+//! ```
+use crate::normalize::normalize_url;
+use crate::timestamp::{format_wayback_timestamp, parse_wayback_timestamp};
+use crate::Endpoints;
+use chrono::{DateTime, Utc};
+use std::collections::HashMap;
+use std::io;
+use std::net::SocketAddr;
+use std::sync::{Arc, Mutex};
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::{TcpListener, TcpStream};
+
+type Captures = Arc<Mutex<HashMap<String, Vec<DateTime<Utc>>>>>;
+
+/// A running mock Wayback Machine.
+pub struct MockWayback {
+    addr: SocketAddr,
+    captures: Captures,
+}
+
+impl MockWayback {
+    /// Starts the mock on an unused local port. It serves requests until the Tokio
+    /// runtime shuts down.
+    pub async fn start() -> io::Result<MockWayback> {
+        let listener = TcpListener::bind("127.0.0.1:0").await?;
+        let addr = listener.local_addr()?;
+        let captures = Captures::default();
+        let served = captures.clone();
+        let base = format!("http://{}", addr);
+        tokio::spawn(async move {
+            while let Ok((stream, _)) = listener.accept().await {
+                tokio::spawn(serve(stream, served.clone(), base.clone()));
+            }
+        });
+        Ok(MockWayback { addr, captures })
+    }
+
+    /// Endpoints that point every request at the mock (see
+    /// `WaybackClient::with_endpoints`).
+    pub fn endpoints(&self) -> Endpoints {
+        let base = format!("http://{}", self.addr);
+        Endpoints {
+            availability: base.clone(),
+            web: base.clone(),
+            save: format!("{}/save", base),
+            s3: base,
+        }
+    }
+
+    /// Records a capture of `url` at `timestamp`, as if it had been archived earlier.
+    pub fn add_capture(&self, url: &str, timestamp: DateTime<Utc>) {
+        add_capture(&self.captures, url, timestamp);
+    }
+
+    /// The timestamps of all captures of `url`, oldest first.
+    pub fn captures(&self, url: &str) -> Vec<DateTime<Utc>> {
+        let captures = self.captures.lock().unwrap();
+        captures
+            .get(&normalize_url(url))
+            .cloned()
+            .unwrap_or_default()
+    }
+}
+
+fn add_capture(captures: &Captures, url: &str, timestamp: DateTime<Utc>) {
+    // Snapshots only have second precision.
+    let timestamp = parse_wayback_timestamp(&format_wayback_timestamp(timestamp))
+        .expect("formatted timestamps parse");
+    let mut captures = captures.lock().unwrap();
+    let timestamps = captures.entry(normalize_url(url)).or_default();
+    timestamps.push(timestamp);
+    timestamps.sort();
+}
+
+fn latest_capture(captures: &Captures, url: &str) -> Option<DateTime<Utc>> {
+    let captures = captures.lock().unwrap();
+    captures
+        .get(&normalize_url(url))
+        .and_then(|timestamps| timestamps.last().copied())
+}
+
+fn closest_capture(captures: &Captures, url: &str, at: DateTime<Utc>) -> Option<DateTime<Utc>> {
+    let captures = captures.lock().unwrap();
+    captures.get(&normalize_url(url)).and_then(|timestamps| {
+        timestamps
+            .iter()
+            .min_by_key(|timestamp| (**timestamp - at).num_seconds().abs())
+            .copied()
+    })
+}
+
+async fn serve(mut stream: TcpStream, captures: Captures, base: String) -> io::Result<()> {
+    // Requests are all bodiless, so reading up to the end of the headers is enough.
+    let mut request = Vec::new();
+    let mut chunk = [0; 1024];
+    while !request.windows(4).any(|window| window == b"\r\n\r\n") {
+        let read = stream.read(&mut chunk).await?;
+        if read == 0 {
+            break;
+        }
+        request.extend_from_slice(&chunk[..read]);
+    }
+    let request = String::from_utf8_lossy(&request);
+    let mut request_line = request.split_whitespace();
+    let method = request_line.next().unwrap_or_default();
+    let target = request_line.next().unwrap_or("/");
+
+    let response = respond(&captures, &base, target);
+    let mut written = format!(
+        "HTTP/1.1 {}\r\nContent-Type: {}\r\nContent-Length: {}\r\nConnection: close\r\n",
+        response.status,
+        response.content_type,
+        response.body.len()
+    );
+    if let Some(location) = &response.location {
+        written.push_str(&format!("Location: {}\r\n", location));
+    }
+    written.push_str("\r\n");
+    if method != "HEAD" {
+        written.push_str(&response.body);
+    }
+    stream.write_all(written.as_bytes()).await?;
+    stream.shutdown().await
+}
+
+struct Response {
+    status: &'static str,
+    content_type: &'static str,
+    location: Option<String>,
+    body: String,
+}
+
+impl Response {
+    fn text(status: &'static str, body: String) -> Self {
+        Response {
+            status,
+            content_type: "text/plain",
+            location: None,
+            body,
+        }
+    }
+}
+
+fn respond(captures: &Captures, base: &str, target: &str) -> Response {
+    if target.starts_with("/wayback/available?") {
+        let params: HashMap<String, String> = reqwest::Url::parse(&format!("{}{}", base, target))
+            .map(|target| target.query_pairs().into_owned().collect())
+            .unwrap_or_default();
+        let url = params.get("url").map(String::as_str).unwrap_or_default();
+        let at = params
+            .get("timestamp")
+            .and_then(|at| parse_wayback_timestamp(at).ok());
+        let capture = match at {
+            Some(at) => closest_capture(captures, url, at),
+            None => latest_capture(captures, url),
+        };
+        let snapshots = match capture {
+            Some(timestamp) => serde_json::json!({
+                "closest": {
+                    "status": "200",
+                    "available": true,
+                    "url": format!("{}/web/{}/{}", base, format_wayback_timestamp(timestamp), url),
+                    "timestamp": format_wayback_timestamp(timestamp),
+                }
+            }),
+            None => serde_json::json!({}),
+        };
+        Response {
+            content_type: "application/json",
+            ..Response::text(
+                "200 OK",
+                serde_json::json!({ "url": url, "archived_snapshots": snapshots }).to_string(),
+            )
+        }
+    } else if let Some(url) = target.strip_prefix("/save/") {
+        let now = Utc::now();
+        add_capture(captures, url, now);
+        Response {
+            location: Some(format!("/web/{}/{}", format_wayback_timestamp(now), url)),
+            ..Response::text("302 Found", String::new())
+        }
+    } else if target.starts_with("/cdx/search/cdx?") {
+        let params: HashMap<String, String> = reqwest::Url::parse(&format!("{}{}", base, target))
+            .map(|target| target.query_pairs().into_owned().collect())
+            .unwrap_or_default();
+        let param = |name: &str| params.get(name).map(String::as_str).unwrap_or_default();
+        let url = param("url");
+        let timestamps = captures
+            .lock()
+            .unwrap()
+            .get(&normalize_url(url))
+            .cloned()
+            .unwrap_or_default();
+        let skip = if param("limit") == "-1" {
+            timestamps.len().saturating_sub(1)
+        } else {
+            0
+        };
+        let full_records = param("fl").starts_with("timestamp,original");
+        let lines: Vec<String> = timestamps[skip..]
+            .iter()
+            .map(|timestamp| {
+                let timestamp = format_wayback_timestamp(*timestamp);
+                if full_records {
+                    format!("{} {} 200 text/html MOCK{}", timestamp, url, timestamp)
+                } else {
+                    timestamp
+                }
+            })
+            .collect();
+        Response::text("200 OK", lines.join("\n"))
+    } else if let Some(snapshot) = target.strip_prefix("/web/") {
+        let url = snapshot
+            .split_once('/')
+            .map(|(_, url)| url)
+            .unwrap_or_default();
+        match latest_capture(captures, url) {
+            Some(_) => Response {
+                content_type: "text/html",
+                ..Response::text("200 OK", format!("<html>Snapshot of {}</html>", url))
+            },
+            None => Response::text("404 Not Found", String::new()),
+        }
+    } else {
+        Response::text("404 Not Found", String::new())
+    }
+}
@@ -0,0 +1,26 @@
+use reqwest::Url;
+
+/// Normalizes `url` so that trivially different spellings of the same page
+/// (scheme/host casing, default ports, fragments, a bare trailing slash) map to
+/// the same key. URLs without a scheme are assumed to be `http://`.
+///
+/// Inputs that cannot be parsed as URLs are returned trimmed but otherwise unchanged.
+pub fn normalize_url(url: &str) -> String {
+    let trimmed = url.trim();
+    let parsed = if trimmed.contains("://") {
+        Url::parse(trimmed)
+    } else {
+        Url::parse(&format!("http://{}", trimmed))
+    };
+    match parsed {
+        Ok(mut parsed) => {
+            parsed.set_fragment(None);
+            let mut normalized = parsed.to_string();
+            if parsed.path() == "/" && parsed.query().is_none() {
+                normalized.pop();
+            }
+            normalized
+        }
+        Err(_) => trimmed.to_string(),
+    }
+}
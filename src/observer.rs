@@ -0,0 +1,45 @@
+use crate::{ArchiveError, ArchivingResult};
+use std::time::Duration;
+
+/// Notified of a `WaybackClient`'s progress as it archives URLs (see
+/// `WaybackClient::with_observer`), so that frontends can display it. Every method
+/// does nothing by default, so implement only those you need.
+///
+/// Methods are called from whichever task is archiving, so they should return
+/// quickly (e.g. by updating a progress bar, or sending a message to a UI thread).
+///
+/// ```
+/// use std::sync::Arc;
+/// use wayback_archiver::{ArchiveError, ArchiveObserver, ArchivingResult, WaybackClient};
+///
+/// struct Log;
+///
+/// impl ArchiveObserver for Log {
+///     fn on_submit(&self, url: &str) {
+///         println!("Capturing {}...", url);
+///     }
+///
+///     fn on_complete(&self, url: &str, result: &Result<ArchivingResult, ArchiveError>) {
+///         println!("{}: {:?}", url, result.as_ref().map(|result| &result.outcome));
+///     }
+/// }
+///
+/// let client = WaybackClient::new().with_observer(Arc::new(Log));
+/// ```
+pub trait ArchiveObserver: Send + Sync {
+    /// The URL's existing snapshots are being looked up, to see whether a recent one
+    /// can be reused.
+    fn on_lookup(&self, _url: &str) {}
+
+    /// The URL is being submitted to Save Page Now for a new capture (including on
+    /// retries). With SPN2, this covers waiting for the capture to complete.
+    fn on_submit(&self, _url: &str) {}
+
+    /// Attempt number `attempt` (from 1) to archive the URL failed with `error`, and
+    /// will be retried after `delay`.
+    fn on_retry(&self, _url: &str, _attempt: u32, _error: &ArchiveError, _delay: Duration) {}
+
+    /// Archiving the URL is done, with `result`. Called once for each call to
+    /// `archive_url` (and its variants), including calls coalesced with another.
+    fn on_complete(&self, _url: &str, _result: &Result<ArchivingResult, ArchiveError>) {}
+}
@@ -0,0 +1,233 @@
+use crate::provenance::Provenance;
+use crate::remote;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::fs;
+use std::io::{self, Write};
+use wayback_archiver::ArchivingResult;
+
+/// The contents of a results file: either just the results, keyed by URL, or an
+/// envelope that also records the runs that produced them.
+#[derive(Debug, Default, Deserialize)]
+pub struct ResultsFile {
+    #[serde(default)]
+    pub runs: Vec<Provenance>,
+    pub results: BTreeMap<String, ArchivingResult>,
+}
+
+/// Just enough of a results file to tell an envelope (which has `results`) from bare
+/// results, so that each is then parsed as what it is and its errors are reported.
+#[derive(Deserialize)]
+struct Format {
+    results: Option<serde::de::IgnoredAny>,
+}
+
+#[derive(Serialize)]
+struct Envelope<'a> {
+    runs: &'a [Provenance],
+    results: &'a BTreeMap<String, ArchivingResult>,
+}
+
+impl ResultsFile {
+    /// Reads the results file at `path` (a local path or an object store URL), which
+    /// is treated as empty if it doesn't exist.
+    pub async fn read(path: &str, strict: bool) -> Result<ResultsFile, Box<dyn std::error::Error>> {
+        let contents = if remote::is_remote(path) {
+            match remote::read(path).await? {
+                Some(contents) => contents,
+                None => return Ok(ResultsFile::default()),
+            }
+        } else {
+            match fs::read_to_string(path) {
+                Ok(contents) => contents,
+                Err(error) if error.kind() == io::ErrorKind::NotFound => {
+                    return Ok(ResultsFile::default())
+                }
+                Err(error) => return Err(error.into()),
+            }
+        };
+        ResultsFile::parse(&contents, strict)
+    }
+
+    /// Parses the `contents` of a results file.
+    pub fn parse(contents: &str, strict: bool) -> Result<ResultsFile, Box<dyn std::error::Error>> {
+        let format: Format = serde_json::from_str(contents)?;
+        let file = match format.results {
+            Some(_) => serde_json::from_str(contents)?,
+            None => ResultsFile {
+                runs: Vec::new(),
+                results: serde_json::from_str(contents)?,
+            },
+        };
+        if strict {
+            check_no_unknown_fields(&file.results)?;
+        }
+        Ok(file)
+    }
+}
+
+/// How results are written.
+pub struct Output {
+    pub minify: bool,
+    /// If set, results are wrapped in an envelope along with these runs.
+    pub runs: Option<Vec<Provenance>>,
+    /// If set, local results files are fsynced once written.
+    pub fsync: bool,
+}
+
+impl Output {
+    /// Formats results as JSON, with a trailing newline. Output is deterministic: URLs
+    /// are sorted, and fields are always written in the same order.
+    pub fn format(
+        &self,
+        results: &BTreeMap<String, ArchivingResult>,
+    ) -> Result<String, serde_json::Error> {
+        let mut formatted = match &self.runs {
+            Some(runs) => self.to_string(&Envelope { runs, results })?,
+            None => self.to_string(results)?,
+        };
+        formatted.push('\n');
+        Ok(formatted)
+    }
+
+    /// Writes results to `path`, which may be a local path or an object store URL.
+    pub async fn write(
+        &self,
+        results: &BTreeMap<String, ArchivingResult>,
+        path: &str,
+    ) -> Result<(), Box<dyn std::error::Error>> {
+        let formatted_urls = self.format(results)?;
+        if remote::is_remote(path) {
+            return remote::write(path, formatted_urls).await;
+        }
+        if self.fsync {
+            return write_durably(path, formatted_urls.as_bytes());
+        }
+        let mut file = fs::OpenOptions::new()
+            .write(true)
+            .create(true)
+            .truncate(true)
+            .open(path)?;
+        file.write_all(formatted_urls.as_bytes())?;
+        Ok(())
+    }
+
+    fn to_string<T: Serialize>(&self, value: &T) -> Result<String, serde_json::Error> {
+        if self.minify {
+            serde_json::to_string(value)
+        } else {
+            serde_json::to_string_pretty(value)
+        }
+    }
+}
+
+/// Replaces the file at `path` with `contents`, so that once this returns, the new
+/// contents survive a crash or power loss. They're written to a temporary file that's
+/// synced and then renamed into place (so `path` is never left half-written), and the
+/// directory is synced too, so that the rename itself is durable.
+pub fn write_durably(path: &str, contents: &[u8]) -> Result<(), Box<dyn std::error::Error>> {
+    let temp = format!("{}.tmp", path);
+    let mut file = fs::File::create(&temp)?;
+    file.write_all(contents)?;
+    file.sync_all()?;
+    fs::rename(&temp, path)?;
+    // Directories can't be opened (let alone synced) this way on Windows.
+    #[cfg(unix)]
+    {
+        let dir = match std::path::Path::new(path).parent() {
+            Some(dir) if !dir.as_os_str().is_empty() => dir,
+            _ => std::path::Path::new("."),
+        };
+        fs::File::open(dir)?.sync_all()?;
+    }
+    Ok(())
+}
+
+/// Expands the placeholders in an output path template: `{date}` (the UTC date the
+/// run started), `{hostname}`, `{run_id}`, and `{shard}` (the `--shard` label).
+pub fn expand_path_template(
+    template: &str,
+    run: &Provenance,
+    shard: Option<&str>,
+) -> Result<String, String> {
+    let mut expanded = String::new();
+    let mut rest = template;
+    while let Some(start) = rest.find('{') {
+        expanded.push_str(&rest[..start]);
+        let end = rest[start..]
+            .find('}')
+            .map(|end| start + end)
+            .ok_or_else(|| format!("unclosed placeholder in {:?}", template))?;
+        match &rest[start + 1..end] {
+            "date" => expanded.push_str(&run.started_at.format("%Y-%m-%d").to_string()),
+            "hostname" => expanded.push_str(&run.host),
+            "run_id" => expanded.push_str(&run.run_id),
+            "shard" => {
+                expanded.push_str(shard.ok_or_else(|| {
+                    format!("{:?} uses {{shard}}, but --shard isn't set", template)
+                })?)
+            }
+            placeholder => {
+                return Err(format!(
+                    "unknown placeholder {{{}}} in {:?}",
+                    placeholder, template
+                ))
+            }
+        }
+        rest = &rest[end + 1..];
+    }
+    expanded.push_str(rest);
+    Ok(expanded)
+}
+
+/// Rejects results with fields this version doesn't know about, which are otherwise
+/// carried through merges untouched.
+fn check_no_unknown_fields(
+    results: &BTreeMap<String, ArchivingResult>,
+) -> Result<(), Box<dyn std::error::Error>> {
+    for (url, result) in results {
+        if let Some(field) = result.unknown_fields.keys().next() {
+            return Err(format!("unknown field {:?} in result for {}", field, url).into());
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_both_formats_and_reports_their_errors() {
+        let result = r#"{"outcome": "failed", "error": "Timed out"}"#;
+        let file =
+            ResultsFile::parse(&format!(r#"{{"https://a.example/": {}}}"#, result), false).unwrap();
+        assert!(file.runs.is_empty());
+        assert!(file.results.contains_key("https://a.example/"));
+        let file = ResultsFile::parse(
+            &format!(
+                r#"{{"runs": [], "results": {{"https://a.example/": {}}}}}"#,
+                result
+            ),
+            false,
+        )
+        .unwrap();
+        assert!(file.results.contains_key("https://a.example/"));
+
+        // Rather than "data did not match any variant".
+        let err = ResultsFile::parse(
+            r#"{"runs": [], "results": {"https://a.example/": 1}}"#,
+            false,
+        )
+        .unwrap_err();
+        assert!(err.to_string().contains("line 1"), "{}", err);
+        let err = ResultsFile::parse(r#"{"https://a.example/": {"outcome": "lost"}}"#, false)
+            .unwrap_err();
+        assert!(
+            err.to_string()
+                .contains("unknown outcome \"lost\" at line 1"),
+            "{}",
+            err
+        );
+    }
+}
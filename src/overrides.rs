@@ -0,0 +1,144 @@
+//! Manual overrides for specific URLs (`--overrides`), for when the archiver's
+//! automated choices are wrong: a TOML file with a table per URL, e.g.
+//!
+//! ```toml
+//! ["https://example.com/pricing"]
+//! note = "Later snapshots are a login wall"
+//! pin = "20190501120000"
+//!
+//! ["https://example.com/blog/launch"]
+//! never_replace = true
+//!
+//! ["https://example.com/old-docs"]
+//! alternate = "https://docs.example.com/"
+//! ```
+//!
+//! Overrides are consulted when archiving and exporting.
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Deserializer};
+use std::collections::HashMap;
+use std::fs;
+use wayback_archiver::timestamp::parse_wayback_timestamp;
+use wayback_archiver::{normalize_url, snapshot_url, ArchivingResult, Endpoints, Outcome};
+
+/// The overrides for each URL, by normalized URL.
+#[derive(Debug, Default)]
+pub struct Overrides(HashMap<String, Override>);
+
+#[derive(Debug, Default, Deserialize, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct Override {
+    /// An annotation, included in exports.
+    #[serde(default)]
+    pub note: Option<String>,
+    /// The Wayback timestamp of the snapshot to always use, instead of archiving the
+    /// URL.
+    #[serde(default, deserialize_with = "deserialize_pin")]
+    pub pin: Option<DateTime<Utc>>,
+    /// If set, the URL isn't archived again once it has a snapshot.
+    #[serde(default)]
+    pub never_replace: bool,
+    /// A URL whose snapshots stand in for this one's (e.g. where its content moved).
+    #[serde(default)]
+    pub alternate: Option<String>,
+}
+
+impl Overrides {
+    /// Loads the overrides file at `path`.
+    pub fn load(path: &str) -> Result<Overrides, String> {
+        let contents =
+            fs::read_to_string(path).map_err(|err| format!("Unable to read {}: {}", path, err))?;
+        Overrides::parse(&contents).map_err(|err| format!("Invalid overrides {}: {}", path, err))
+    }
+
+    pub fn parse(contents: &str) -> Result<Overrides, toml::de::Error> {
+        let overrides: HashMap<String, Override> = toml::from_str(contents)?;
+        Ok(Overrides(
+            overrides
+                .into_iter()
+                .map(|(url, overridden)| (normalize_url(&url), overridden))
+                .collect(),
+        ))
+    }
+
+    /// The overrides for `url`, if it has any.
+    pub fn get(&self, url: &str) -> Option<&Override> {
+        self.0.get(&normalize_url(url))
+    }
+}
+
+impl Override {
+    /// The URL archived in place of `url`.
+    pub fn target<'a>(&'a self, url: &'a str) -> &'a str {
+        self.alternate.as_deref().unwrap_or(url)
+    }
+
+    /// The result for `url`'s pinned snapshot (in the Wayback Machine at `endpoints`),
+    /// if it has one.
+    pub fn pinned_result(&self, endpoints: &Endpoints, url: &str) -> Option<ArchivingResult> {
+        let pin = self.pin?;
+        Some(ArchivingResult::new(Outcome::ExistingSnapshot {
+            url: snapshot_url(endpoints, self.target(url), pin),
+            ts: pin.naive_utc(),
+        }))
+    }
+}
+
+fn deserialize_pin<'de, D>(deserializer: D) -> Result<Option<DateTime<Utc>>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    let ts = String::deserialize(deserializer)?;
+    parse_wayback_timestamp(&ts)
+        .map(Some)
+        .map_err(serde::de::Error::custom)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_overrides() {
+        let overrides = Overrides::parse(
+            r#"
+["https://example.com/pricing"]
+note = "Later snapshots are a login wall"
+pin = "20190501120000"
+
+["https://example.com/old-docs"]
+alternate = "https://docs.example.com/"
+never_replace = true
+"#,
+        )
+        .unwrap();
+        let pricing = overrides.get("https://EXAMPLE.com/pricing#plans").unwrap();
+        assert_eq!(
+            pricing.note.as_deref(),
+            Some("Later snapshots are a login wall")
+        );
+        assert_eq!(
+            pricing.pinned_result(&Endpoints::default(), "https://example.com/pricing"),
+            Some(ArchivingResult::new(Outcome::ExistingSnapshot {
+                url: "https://web.archive.org/web/20190501120000/https://example.com/pricing"
+                    .to_string(),
+                ts: "2019-05-01T12:00:00".parse().unwrap(),
+            }))
+        );
+
+        let old_docs = overrides.get("https://example.com/old-docs").unwrap();
+        assert!(old_docs.never_replace);
+        assert_eq!(
+            old_docs.target("https://example.com/old-docs"),
+            "https://docs.example.com/"
+        );
+        assert_eq!(
+            old_docs.pinned_result(&Endpoints::default(), "https://example.com/old-docs"),
+            None
+        );
+        assert!(overrides.get("https://example.com/").is_none());
+
+        assert!(Overrides::parse("[\"https://example.com/\"]\npin = \"yesterday\"\n").is_err());
+        assert!(Overrides::parse("[\"https://example.com/\"]\nreplace = false\n").is_err());
+    }
+}
@@ -0,0 +1,211 @@
+use crate::config::{deserialize_duration, format_duration};
+use chrono::NaiveTime;
+use serde::Deserialize;
+use std::collections::HashMap;
+use std::str::FromStr;
+use std::time::{Duration, Instant};
+use wayback_archiver::{normalize_url, ArchiveError, RetryPolicy};
+
+/// All the waits the CLI makes between requests to the Wayback Machine.
+///
+/// Configured by the `[pacing]` section of the config file.
+#[derive(Clone, Debug, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Pacing {
+    /// Minimum time between newly captured snapshots of the same domain.
+    #[serde(deserialize_with = "deserialize_duration")]
+    pub capture_cooldown: Duration,
+    /// Pause before retrying after the Wayback Machine reports that the
+    /// bandwidth limit has been exceeded.
+    #[serde(deserialize_with = "deserialize_duration")]
+    pub bandwidth_exceeded_wait: Duration,
+    /// Pause before the first retry after other temporary failures (e.g. the
+    /// Wayback Machine being unavailable), doubling with each retry after it.
+    #[serde(deserialize_with = "deserialize_duration")]
+    pub retry_wait: Duration,
+    /// How many times a URL is retried after temporary failures, not counting
+    /// bandwidth-exceeded responses (which are always waited out).
+    pub max_retries: u32,
+    /// How long a URL that failed is put off before it's tried again (by a later
+    /// run, even after a restart), doubling with each failure in a row.
+    #[serde(deserialize_with = "deserialize_duration")]
+    pub failure_backoff: Duration,
+    /// The longest a URL that keeps failing is put off for.
+    #[serde(deserialize_with = "deserialize_duration")]
+    pub max_failure_backoff: Duration,
+}
+
+impl Default for Pacing {
+    fn default() -> Self {
+        Pacing {
+            capture_cooldown: Duration::from_secs(5),
+            bandwidth_exceeded_wait: Duration::from_secs(15),
+            retry_wait: Duration::from_secs(30),
+            max_retries: 3,
+            failure_backoff: Duration::from_secs(10 * 60),
+            max_failure_backoff: Duration::from_secs(7 * 24 * 60 * 60),
+        }
+    }
+}
+
+impl std::fmt::Display for Pacing {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "capture cooldown {} per domain, bandwidth-exceeded wait {}, retry wait {} (up to {} retries), failure backoff {} (up to {})",
+            format_duration(self.capture_cooldown),
+            format_duration(self.bandwidth_exceeded_wait),
+            format_duration(self.retry_wait),
+            self.max_retries,
+            format_duration(self.failure_backoff),
+            format_duration(self.max_failure_backoff)
+        )
+    }
+}
+
+impl Pacing {
+    /// How long to put off a URL that's failed `failures` times in a row.
+    pub fn backoff_after(&self, failures: u32) -> Duration {
+        self.failure_backoff
+            .saturating_mul(2u32.saturating_pow(failures.saturating_sub(1)))
+            .min(self.max_failure_backoff)
+    }
+
+    /// How the client retries temporary failures: up to `max_retries` times, waiting
+    /// `retry_wait` before the first retry.
+    pub fn retry_policy(&self) -> RetryPolicy {
+        let default = RetryPolicy::default();
+        RetryPolicy {
+            max_attempts: self.max_retries.saturating_add(1),
+            base_delay: self.retry_wait,
+            max_delay: self.retry_wait.max(default.max_delay),
+            ..default
+        }
+    }
+
+    /// How long to wait before retrying after `err`, if it's worth retrying: as long
+    /// as the Wayback Machine asked, or else the configured wait.
+    pub fn backoff_for(&self, err: &ArchiveError) -> Option<Duration> {
+        err.suggested_backoff(self.bandwidth_exceeded_wait, self.retry_wait)
+    }
+}
+
+/// Tracks when snapshots of each domain were last captured, so that the capture
+/// cooldown only delays captures of the same domain. Reusing an existing snapshot
+/// doesn't count as a capture.
+#[derive(Default)]
+pub struct DomainCooldowns {
+    last_capture: HashMap<String, Instant>,
+}
+
+impl DomainCooldowns {
+    /// Returns how much longer to wait before capturing `url`, if captures of its
+    /// domain must be `cooldown` apart.
+    pub fn remaining(&self, url: &str, cooldown: Duration) -> Duration {
+        match self.last_capture.get(&domain(url)) {
+            Some(last) => cooldown.saturating_sub(last.elapsed()),
+            None => Duration::ZERO,
+        }
+    }
+
+    pub fn record_capture(&mut self, url: &str) {
+        self.last_capture.insert(domain(url), Instant::now());
+    }
+}
+
+/// A daily window (in local time) during which captures may be submitted, e.g.
+/// `01:00-07:00`. Windows may wrap past midnight (`22:00-06:00`).
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct ActiveHours {
+    start: NaiveTime,
+    end: NaiveTime,
+}
+
+impl ActiveHours {
+    /// Returns how long to wait from `now` until the window is next open.
+    pub fn wait_from(&self, now: NaiveTime) -> Duration {
+        let open = if self.start <= self.end {
+            self.start <= now && now < self.end
+        } else {
+            now >= self.start || now < self.end
+        };
+        if open {
+            return Duration::ZERO;
+        }
+        let until_start = (self.start - now).num_seconds().rem_euclid(24 * 60 * 60);
+        Duration::from_secs(until_start as u64)
+    }
+}
+
+impl FromStr for ActiveHours {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid = || format!("invalid active hours {:?} (expected e.g. 01:00-07:00)", s);
+        let (start, end) = s.split_once('-').ok_or_else(invalid)?;
+        let parse =
+            |time: &str| NaiveTime::parse_from_str(time.trim(), "%H:%M").map_err(|_| invalid());
+        Ok(ActiveHours {
+            start: parse(start)?,
+            end: parse(end)?,
+        })
+    }
+}
+
+impl std::fmt::Display for ActiveHours {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{}-{}",
+            self.start.format("%H:%M"),
+            self.end.format("%H:%M")
+        )
+    }
+}
+
+fn domain(url: &str) -> String {
+    let normalized = normalize_url(url);
+    reqwest::Url::parse(&normalized)
+        .ok()
+        .and_then(|parsed| parsed.host_str().map(str::to_string))
+        .unwrap_or(normalized)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn at(time: &str) -> NaiveTime {
+        NaiveTime::parse_from_str(time, "%H:%M").unwrap()
+    }
+
+    #[test]
+    fn waits_for_active_hours() {
+        let night: ActiveHours = "01:00-07:00".parse().unwrap();
+        assert_eq!(night.wait_from(at("03:00")), Duration::ZERO);
+        assert_eq!(night.wait_from(at("00:30")), Duration::from_secs(30 * 60));
+        assert_eq!(
+            night.wait_from(at("07:00")),
+            Duration::from_secs(18 * 60 * 60)
+        );
+
+        let wrapping: ActiveHours = "22:00-06:00".parse().unwrap();
+        assert_eq!(wrapping.wait_from(at("23:00")), Duration::ZERO);
+        assert_eq!(wrapping.wait_from(at("05:59")), Duration::ZERO);
+        assert_eq!(
+            wrapping.wait_from(at("12:00")),
+            Duration::from_secs(10 * 60 * 60)
+        );
+
+        assert!("1am-7am".parse::<ActiveHours>().is_err());
+    }
+
+    #[test]
+    fn backs_off_repeated_failures() {
+        let pacing = Pacing::default();
+        assert_eq!(pacing.backoff_after(1), Duration::from_secs(10 * 60));
+        assert_eq!(pacing.backoff_after(3), Duration::from_secs(40 * 60));
+        assert_eq!(pacing.backoff_after(20), pacing.max_failure_backoff);
+        assert_eq!(pacing.backoff_after(u32::MAX), pacing.max_failure_backoff);
+    }
+}
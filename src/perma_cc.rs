@@ -0,0 +1,277 @@
+//! [Perma.cc](https://perma.cc), which makes permanent links for citations (in court
+//! opinions, law reviews, and the like), captured through its REST API.
+//!
+//! Requests are authenticated with an account's API key (see
+//! https://perma.cc/settings/tools). Captures are made in the account's folder, and
+//! complete asynchronously: the permalink is assigned as soon as the capture is
+//! submitted.
+use crate::client::network_error;
+use crate::normalize::normalize_url;
+use crate::{
+    body_snippet, ArchiveError, ArchiveOptions, ArchiveProvider, ArchivingResult, Outcome,
+    Snapshot, WaybackClient,
+};
+use chrono::{DateTime, Utc};
+use futures::future::{BoxFuture, FutureExt};
+use reqwest::header::AUTHORIZATION;
+use serde::Deserialize;
+
+const API: &str = "https://api.perma.cc/v1";
+const PERMALINK_PREFIX: &str = "https://perma.cc/";
+
+/// Perma.cc, as an `ArchiveProvider`, capturing into an account's folder.
+///
+/// ```no_run
+/// # async fn example() -> Result<(), wayback_archiver::ArchiveError> {
+/// use std::sync::Arc;
+/// use wayback_archiver::{PermaCc, WaybackClient};
+///
+/// let perma_cc = PermaCc::new("0123456789abcdef").with_folder(1234);
+/// let client = WaybackClient::new().with_provider(Arc::new(perma_cc));
+/// let result = client.archive_url("https://example.com/").await?;
+/// println!("Cite {}", result.outcome.url().unwrap());
+/// # Ok(())
+/// # }
+/// ```
+#[derive(Clone, PartialEq)]
+pub struct PermaCc {
+    api_key: String,
+    folder: Option<u64>,
+}
+
+impl std::fmt::Debug for PermaCc {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("PermaCc")
+            .field("api_key", &"[redacted]")
+            .field("folder", &self.folder)
+            .finish()
+    }
+}
+
+impl PermaCc {
+    /// Perma.cc, authenticated with `api_key`, capturing into the account's default
+    /// folder.
+    pub fn new(api_key: &str) -> Self {
+        PermaCc {
+            api_key: api_key.to_string(),
+            folder: None,
+        }
+    }
+
+    /// Captures into the folder with ID `folder` instead (e.g. an organization's
+    /// folder, or one per publication).
+    pub fn with_folder(self, folder: u64) -> Self {
+        PermaCc {
+            folder: Some(folder),
+            ..self
+        }
+    }
+
+    fn authorization(&self) -> String {
+        format!("ApiKey {}", self.api_key)
+    }
+}
+
+/// A Perma.cc archive (a "Perma Link"), as the API describes it.
+#[derive(Deserialize)]
+struct PermaArchive {
+    guid: String,
+    url: String,
+    creation_timestamp: DateTime<Utc>,
+    #[serde(default)]
+    capture_job: Option<CaptureJob>,
+}
+
+#[derive(Deserialize)]
+struct CaptureJob {
+    status: String,
+}
+
+#[derive(Deserialize)]
+struct PermaArchives {
+    objects: Vec<PermaArchive>,
+}
+
+impl PermaArchive {
+    fn failed(&self) -> bool {
+        self.capture_job
+            .as_ref()
+            .is_some_and(|job| job.status == "failed")
+    }
+
+    fn pending(&self) -> bool {
+        self.capture_job
+            .as_ref()
+            .is_some_and(|job| job.status == "pending" || job.status == "in_progress")
+    }
+
+    fn permalink(&self) -> String {
+        format!("{}{}", PERMALINK_PREFIX, self.guid)
+    }
+}
+
+impl WaybackClient {
+    /// The newest of the account's Perma.cc archives of `url`, if it has one.
+    async fn latest_perma_link(
+        &self,
+        perma_cc: &PermaCc,
+        url: &str,
+    ) -> Result<Option<Snapshot>, ArchiveError> {
+        let request = self
+            .http()
+            .get(format!("{}/user/archives/", API))
+            .query(&[("url", url)])
+            .header(AUTHORIZATION, perma_cc.authorization());
+        let resp = self.send(request).await?;
+        let status = resp.status().as_u16();
+        if status == 429 || status >= 500 {
+            return Err(self.service_unavailable(resp).await);
+        }
+        let body = self.read_text(resp).await?.map_err(network_error)?;
+        if status != 200 {
+            return Err(ArchiveError::Http {
+                status,
+                snippet: body_snippet(&body),
+            });
+        }
+        parse_perma_links(&body, url)
+    }
+
+    /// Captures `url` in Perma.cc, returning its new permalink.
+    async fn save_to_perma_cc(
+        &self,
+        perma_cc: &PermaCc,
+        url: &str,
+    ) -> Result<ArchivingResult, ArchiveError> {
+        let mut archive = serde_json::json!({ "url": url });
+        if let Some(folder) = perma_cc.folder {
+            archive["folder"] = folder.into();
+        }
+        let request = self
+            .http()
+            .post(format!("{}/archives/", API))
+            .header(AUTHORIZATION, perma_cc.authorization())
+            .json(&archive);
+        let resp = self.send(request).await?;
+        let status = resp.status().as_u16();
+        if status == 429 || status >= 500 {
+            return Err(self.service_unavailable(resp).await);
+        }
+        let body = self.read_text(resp).await?.map_err(network_error)?;
+        parse_new_perma_link(status, &body)
+    }
+}
+
+impl ArchiveProvider for PermaCc {
+    fn provider_name(&self) -> &str {
+        "perma.cc"
+    }
+
+    fn lookup_latest<'a>(
+        &'a self,
+        client: &'a WaybackClient,
+        url: &'a str,
+    ) -> BoxFuture<'a, Result<Option<Snapshot>, ArchiveError>> {
+        client.latest_perma_link(self, url).boxed()
+    }
+
+    fn save<'a>(
+        &'a self,
+        client: &'a WaybackClient,
+        url: &'a str,
+        _options: &'a ArchiveOptions,
+        _previous: Option<&'a Snapshot>,
+    ) -> BoxFuture<'a, Result<ArchivingResult, ArchiveError>> {
+        client.save_to_perma_cc(self, url).boxed()
+    }
+}
+
+/// The newest archive of `url` in a page of the account's archives, skipping failed
+/// captures.
+fn parse_perma_links(body: &str, url: &str) -> Result<Option<Snapshot>, ArchiveError> {
+    let archives: PermaArchives =
+        serde_json::from_str(body).map_err(|err| ArchiveError::ParseError(err.to_string()))?;
+    let url = normalize_url(url);
+    Ok(archives
+        .objects
+        .into_iter()
+        .filter(|archive| !archive.failed() && normalize_url(&archive.url) == url)
+        .max_by_key(|archive| archive.creation_timestamp)
+        .map(|archive| Snapshot {
+            url: archive.permalink(),
+            timestamp: archive.creation_timestamp,
+            status: None,
+            available: !archive.pending(),
+        }))
+}
+
+/// Interprets Perma.cc's response (with `status`) to a capture request.
+fn parse_new_perma_link(status: u16, body: &str) -> Result<ArchivingResult, ArchiveError> {
+    match status {
+        200 | 201 => {}
+        // Perma.cc rejects URLs it can't capture (and captures beyond the account's
+        // quota) as bad requests.
+        400 => return Err(ArchiveError::UnableToArchive),
+        _ => {
+            return Err(ArchiveError::Http {
+                status,
+                snippet: body_snippet(body),
+            })
+        }
+    }
+    let archive: PermaArchive =
+        serde_json::from_str(body).map_err(|err| ArchiveError::ParseError(err.to_string()))?;
+    if archive.failed() {
+        return Err(ArchiveError::UnableToArchive);
+    }
+    Ok(ArchivingResult {
+        pending: archive.pending(),
+        ..ArchivingResult::new(Outcome::NewCapture {
+            url: archive.permalink(),
+            ts: archive.creation_timestamp.naive_utc(),
+        })
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_perma_links() {
+        let created = r#"{"guid": "AB12-CD34", "url": "https://example.com/",
+            "creation_timestamp": "2024-05-01T12:00:00.123456Z",
+            "capture_job": {"status": "pending"}}"#;
+        let result = parse_new_perma_link(201, created).unwrap();
+        assert_eq!(result.outcome.url(), Some("https://perma.cc/AB12-CD34"));
+        assert!(result.pending);
+        assert_eq!(
+            parse_new_perma_link(400, r#"{"url": ["Couldn't load URL"]}"#),
+            Err(ArchiveError::UnableToArchive)
+        );
+        assert!(matches!(
+            parse_new_perma_link(401, r#"{"detail": "Invalid token."}"#),
+            Err(ArchiveError::Http { status: 401, .. })
+        ));
+
+        let archives = r#"{"meta": {}, "objects": [
+            {"guid": "OLD1-AAAA", "url": "https://example.com/",
+             "creation_timestamp": "2020-01-01T00:00:00Z", "capture_job": {"status": "completed"}},
+            {"guid": "FAIL-BBBB", "url": "https://example.com/",
+             "creation_timestamp": "2024-01-01T00:00:00Z", "capture_job": {"status": "failed"}},
+            {"guid": "NEW1-CCCC", "url": "https://example.com/",
+             "creation_timestamp": "2022-01-01T00:00:00Z", "capture_job": {"status": "completed"}},
+            {"guid": "ELSE-DDDD", "url": "https://example.org/",
+             "creation_timestamp": "2023-01-01T00:00:00Z"}
+        ]}"#;
+        let latest = parse_perma_links(archives, "https://example.com/")
+            .unwrap()
+            .unwrap();
+        assert_eq!(latest.url, "https://perma.cc/NEW1-CCCC");
+        assert!(latest.available);
+        assert_eq!(
+            parse_perma_links(archives, "https://example.net/"),
+            Ok(None)
+        );
+    }
+}
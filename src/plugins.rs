@@ -0,0 +1,320 @@
+//! Plugins: executables in the config file's `plugin_dir` that extend the archiver,
+//! written in any language. A plugin is run with its role as its only argument, and
+//! speaks JSON lines (one JSON object per line) over stdin and stdout:
+//!
+//! - `input` plugins (`--input-plugin`) write the URLs to archive, as `{"url": ...}`.
+//! - `provider` plugins (`archive = "plugin:<name>"` in rules and content type
+//!   policies) archive URLs elsewhere. They're run once per URL, read
+//!   `{"url": ..., "max_snapshot_age_secs": ...}`, and write either
+//!   `{"snapshot_url": ..., "timestamp": ..., "new_capture": ...}` (the timestamp in
+//!   RFC 3339) or `{"error": ...}`.
+//! - `sink` plugins (`--sink-plugin`) read each result as it's made, as
+//!   `{"url": ..., "result": ...}` with the result as it's written to results files.
+//!
+//! Anything a plugin writes to stderr is passed through, and a plugin that exits
+//! unsuccessfully fails the run (or, for providers, the URL). Providers that take
+//! longer than `PROVIDER_TIMEOUT` are killed, failing the URL. Sinks' stdout is
+//! discarded, so it can't mix with results written to stdout; if a sink stops
+//! reading results, it's reported and no more are sent to it, and the run carries
+//! on.
+use chrono::{DateTime, Utc};
+use crossbeam_channel::Sender;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::io::{self, BufRead, BufReader, Write};
+use std::path::{Path, PathBuf};
+use std::process::{Child, ChildStdin, Command, Stdio};
+use std::sync::atomic::{AtomicUsize, Ordering::SeqCst};
+use std::sync::Arc;
+use tokio::io::AsyncWriteExt;
+use wayback_archiver::{ArchiveError, ArchiveOptions, ArchivingResult, Outcome};
+
+/// How long a provider plugin has to archive a URL.
+const PROVIDER_TIMEOUT: std::time::Duration = std::time::Duration::from_secs(10 * 60);
+
+/// A plugin executable, by name (its file name in the plugin directory).
+#[derive(Clone, Debug, PartialEq)]
+pub struct Plugin {
+    pub name: String,
+    path: PathBuf,
+}
+
+/// The executables in `dir`, by name.
+pub fn discover(dir: &Path) -> io::Result<BTreeMap<String, Plugin>> {
+    let mut plugins = BTreeMap::new();
+    for entry in std::fs::read_dir(dir)? {
+        let path = entry?.path();
+        if !is_executable(&path) {
+            continue;
+        }
+        if let Some(name) = path.file_name().and_then(|name| name.to_str()) {
+            let name = name.to_string();
+            plugins.insert(name.clone(), Plugin { name, path });
+        }
+    }
+    Ok(plugins)
+}
+
+#[cfg(unix)]
+fn is_executable(path: &Path) -> bool {
+    use std::os::unix::fs::PermissionsExt;
+    std::fs::metadata(path)
+        .is_ok_and(|metadata| metadata.is_file() && metadata.permissions().mode() & 0o111 != 0)
+}
+
+#[cfg(not(unix))]
+fn is_executable(path: &Path) -> bool {
+    path.is_file()
+}
+
+#[derive(Deserialize)]
+struct InputLine {
+    url: String,
+}
+
+#[derive(Serialize)]
+struct ProviderRequest<'a> {
+    url: &'a str,
+    max_snapshot_age_secs: i64,
+}
+
+#[derive(Debug, Deserialize, PartialEq)]
+#[serde(untagged)]
+enum ProviderResponse {
+    Snapshot {
+        snapshot_url: String,
+        timestamp: DateTime<Utc>,
+        #[serde(default)]
+        new_capture: bool,
+    },
+    Error {
+        error: String,
+    },
+}
+
+#[derive(Serialize)]
+struct SinkLine<'a> {
+    url: &'a str,
+    result: &'a ArchivingResult,
+}
+
+impl Plugin {
+    fn command(&self, role: &str) -> Command {
+        let mut command = Command::new(&self.path);
+        command.arg(role).stderr(Stdio::inherit());
+        command
+    }
+
+    fn error(&self, message: impl std::fmt::Display) -> io::Error {
+        io::Error::other(format!("{} plugin: {}", self.name, message))
+    }
+
+    /// Runs the plugin as an input source, sending the URLs it writes to `tx` and
+    /// counting them in `count`.
+    pub fn read_urls(&self, tx: Sender<String>, count: Arc<AtomicUsize>) -> io::Result<()> {
+        let mut child = self
+            .command("input")
+            .stdin(Stdio::null())
+            .stdout(Stdio::piped())
+            .spawn()
+            .map_err(|err| self.error(err))?;
+        let stdout = child.stdout.take().expect("piped stdout");
+        for (line_number, line) in BufReader::new(stdout).lines().enumerate() {
+            let line = line?;
+            if line.trim().is_empty() {
+                continue;
+            }
+            let input: InputLine = serde_json::from_str(&line)
+                .map_err(|err| self.error(format!("line {}: {}", line_number + 1, err)))?;
+            // The receiver only goes away if archiving has stopped.
+            if tx.send(input.url).is_err() {
+                let _ = child.kill();
+                break;
+            }
+            count.fetch_add(1, SeqCst);
+        }
+        match child.wait()? {
+            status if status.success() => Ok(()),
+            status => Err(self.error(status)),
+        }
+    }
+
+    /// Runs the plugin as a provider, archiving `url`.
+    pub async fn archive(
+        &self,
+        url: &str,
+        options: &ArchiveOptions,
+    ) -> Result<ArchivingResult, ArchiveError> {
+        let failed =
+            |message: String| ArchiveError::Unknown(format!("{} plugin: {}", self.name, message));
+        let mut child = tokio::process::Command::from(self.command("provider"))
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .kill_on_drop(true)
+            .spawn()
+            .map_err(|err| failed(err.to_string()))?;
+        let request = ProviderRequest {
+            url,
+            max_snapshot_age_secs: options.max_snapshot_age.num_seconds(),
+        };
+        let mut request = serde_json::to_vec(&request).expect("serializable request");
+        request.push(b'\n');
+        let mut stdin = child.stdin.take().expect("piped stdin");
+        stdin
+            .write_all(&request)
+            .await
+            .map_err(|err| failed(err.to_string()))?;
+        drop(stdin);
+        // The child is killed if it's dropped when the timeout elapses.
+        let output = tokio::time::timeout(PROVIDER_TIMEOUT, child.wait_with_output())
+            .await
+            .map_err(|_| failed(format!("timed out after {:?}", PROVIDER_TIMEOUT)))?
+            .map_err(|err| failed(err.to_string()))?;
+        if !output.status.success() {
+            return Err(failed(output.status.to_string()));
+        }
+        parse_provider_response(&output.stdout).map_err(failed)
+    }
+
+    /// Starts the plugin as a sink.
+    pub fn open_sink(&self) -> io::Result<Sink> {
+        let mut child = self
+            .command("sink")
+            .stdin(Stdio::piped())
+            .stdout(Stdio::null())
+            .spawn()
+            .map_err(|err| self.error(err))?;
+        let stdin = child.stdin.take();
+        Ok(Sink {
+            plugin: self.clone(),
+            child,
+            stdin,
+        })
+    }
+}
+
+/// Interprets the first line a provider plugin wrote.
+fn parse_provider_response(stdout: &[u8]) -> Result<ArchivingResult, String> {
+    let line = stdout
+        .split(|byte| *byte == b'\n')
+        .find(|line| !line.iter().all(u8::is_ascii_whitespace))
+        .ok_or("no response")?;
+    match serde_json::from_slice(line).map_err(|err| err.to_string())? {
+        ProviderResponse::Snapshot {
+            snapshot_url,
+            timestamp,
+            new_capture: true,
+        } => Ok(ArchivingResult::new(Outcome::NewCapture {
+            url: snapshot_url,
+            ts: timestamp.naive_utc(),
+        })),
+        ProviderResponse::Snapshot {
+            snapshot_url,
+            timestamp,
+            new_capture: false,
+        } => Ok(ArchivingResult::new(Outcome::ExistingSnapshot {
+            url: snapshot_url,
+            ts: timestamp.naive_utc(),
+        })),
+        ProviderResponse::Error { error } => Err(error),
+    }
+}
+
+/// A sink plugin that's running, which results are written to.
+pub struct Sink {
+    plugin: Plugin,
+    child: Child,
+    /// The plugin's stdin, until writing to it fails.
+    stdin: Option<ChildStdin>,
+}
+
+impl Sink {
+    /// Writes `url`'s result to the plugin. If that fails (e.g. the plugin exited),
+    /// it's reported, and later results aren't sent.
+    pub fn record(&mut self, url: &str, result: &ArchivingResult) {
+        let stdin = match self.stdin.as_mut() {
+            Some(stdin) => stdin,
+            None => return,
+        };
+        let written = serde_json::to_writer(&mut *stdin, &SinkLine { url, result })
+            .map_err(io::Error::from)
+            .and_then(|()| stdin.write_all(b"\n"))
+            .and_then(|()| stdin.flush());
+        if let Err(err) = written {
+            eprintln!(
+                "{}; no more results will be sent to it",
+                self.plugin.error(err)
+            );
+            self.stdin = None;
+        }
+    }
+
+    /// Closes the plugin's stdin, and waits for it to exit.
+    pub fn finish(mut self) -> io::Result<()> {
+        drop(self.stdin.take());
+        match self.child.wait()? {
+            status if status.success() => Ok(()),
+            status => Err(self.plugin.error(status)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_provider_responses() {
+        let ts = "2021-09-07T12:34:56".parse().unwrap();
+        assert_eq!(
+            parse_provider_response(
+                b"\n{\"snapshot_url\": \"https://cms.example/s/1\", \
+                  \"timestamp\": \"2021-09-07T12:34:56Z\", \"new_capture\": true}\n"
+            ),
+            Ok(ArchivingResult::new(Outcome::NewCapture {
+                url: "https://cms.example/s/1".to_string(),
+                ts,
+            }))
+        );
+        assert_eq!(
+            parse_provider_response(
+                b"{\"snapshot_url\": \"https://cms.example/s/1\", \
+                  \"timestamp\": \"2021-09-07T12:34:56Z\"}"
+            ),
+            Ok(ArchivingResult::new(Outcome::ExistingSnapshot {
+                url: "https://cms.example/s/1".to_string(),
+                ts,
+            }))
+        );
+        assert_eq!(
+            parse_provider_response(b"{\"error\": \"quota exceeded\"}"),
+            Err("quota exceeded".to_string())
+        );
+        assert!(parse_provider_response(b"").is_err());
+        assert!(parse_provider_response(b"not json").is_err());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn stops_writing_to_sinks_that_exit() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let dir = std::env::temp_dir().join(format!("plugins-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("quitter");
+        std::fs::write(&path, "#!/bin/sh\nexit 0\n").unwrap();
+        std::fs::set_permissions(&path, std::fs::Permissions::from_mode(0o755)).unwrap();
+        let plugin = discover(&dir).unwrap().remove("quitter").unwrap();
+
+        let mut sink = plugin.open_sink().unwrap();
+        sink.child.wait().unwrap();
+        let result = ArchivingResult::new(Outcome::Failed {
+            error: "unreachable".to_string(),
+        });
+        sink.record("https://example.com/", &result);
+        assert!(sink.stdin.is_none());
+        sink.record("https://example.com/", &result);
+        sink.finish().unwrap();
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
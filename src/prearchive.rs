@@ -0,0 +1,143 @@
+//! The checks a URL goes through before it's archived, however it arrives: from the
+//! input of a run, a `stream` message, a `lambda` invocation, or `serve` (a GitHub
+//! push or the bookmarklet). Excluded URLs (see `exclusions`) are skipped, as are
+//! those the config rules skip, and the rest are archived as the rules say.
+use crate::codes::SkipReason;
+use crate::config::{Archive, Config};
+use crate::exclusions::{Exclusion, Exclusions};
+use crate::rules::{self, RuleAction, RuleOutcome};
+use crate::run_result::{RunResult, Skip};
+use wayback_archiver::{ArchiveOptions, ArchivingResult, Outcome, WaybackClient};
+
+/// Why `url` is excluded from archiving, if it is.
+pub fn excluded(exclusions: &Exclusions, url: &str) -> Option<SkipReason> {
+    exclusions.check(url).map(|exclusion| match exclusion {
+        Exclusion::InternalHost => SkipReason::InternalHost,
+        Exclusion::OptedOut => SkipReason::OptedOut,
+    })
+}
+
+/// Checks `url` against the exclusions and then the config rules, returning the
+/// rules' outcome for it unless it's skipped.
+pub fn check(exclusions: &Exclusions, config: &Config, url: &str) -> Result<RuleOutcome, Skip> {
+    if let Some(reason) = excluded(exclusions, url) {
+        return Err(Skip {
+            reason,
+            tags: Vec::new(),
+        });
+    }
+    let rule = rules::evaluate(&config.rules, url);
+    if rule.action == Some(RuleAction::Skip) {
+        return Err(Skip {
+            reason: SkipReason::Rule,
+            tags: rule.tags,
+        });
+    }
+    Ok(rule)
+}
+
+/// Archives `url` as the config rules' outcome for it says: in the archive it names,
+/// replacing snapshots older than its `every`, with its tags. Failures are returned
+/// as failed results.
+pub async fn archive(
+    client: &WaybackClient,
+    config: &Config,
+    url: &str,
+    rule: RuleOutcome,
+) -> ArchivingResult {
+    let mut options = ArchiveOptions::default();
+    if let Some(every) = rule
+        .every
+        .and_then(|every| chrono::Duration::from_std(every).ok())
+    {
+        options.max_snapshot_age = every;
+    }
+    let archive = match rule.archive.as_deref().map(|name| config.archive(name)) {
+        Some(Ok(archive)) => archive,
+        Some(Err(err)) => {
+            return ArchivingResult {
+                tags: rule.tags,
+                ..ArchivingResult::new(Outcome::Failed { error: err })
+            }
+        }
+        None => Archive::Wayback,
+    };
+    let result = match crate::archive_isolated(client, url, options, archive).await {
+        Ok(result) => result,
+        Err(err) => crate::failed_result(&err),
+    };
+    ArchivingResult {
+        tags: rule.tags,
+        ..result
+    }
+}
+
+/// Archives `url` unless it's skipped, in which case the skip is its result.
+pub async fn check_and_archive(
+    client: &WaybackClient,
+    config: &Config,
+    exclusions: &Exclusions,
+    url: &str,
+) -> RunResult {
+    match check(exclusions, config, url) {
+        Ok(rule) => archive(client, config, url, rule).await.into(),
+        Err(skip) => skip.into(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use wayback_archiver::mock::MockWayback;
+
+    #[tokio::test]
+    async fn skips_excluded_urls_and_archives_the_rest() {
+        let mock = MockWayback::start().await.unwrap();
+        let client = WaybackClient::new()
+            .with_endpoints(mock.endpoints())
+            .with_save_interval(std::time::Duration::ZERO);
+        let config = Config::parse(
+            "test.toml",
+            br#"
+            [[rules]]
+            host = "skipped.example.com"
+            action = "skip"
+            tag = "noise"
+
+            [[rules]]
+            host = "example.com"
+            tag = "docs"
+            "#,
+        )
+        .unwrap();
+        let exclusions = Exclusions::default();
+        let archive = |url| check_and_archive(&client, &config, &exclusions, url);
+
+        let result = archive("http://localhost:8080/admin").await;
+        assert_eq!(
+            result,
+            RunResult::Skipped(Skip {
+                reason: SkipReason::InternalHost,
+                tags: Vec::new(),
+            })
+        );
+        let result = archive("https://skipped.example.com/").await;
+        assert_eq!(
+            result,
+            RunResult::Skipped(Skip {
+                reason: SkipReason::Rule,
+                tags: vec!["noise".to_string()],
+            })
+        );
+
+        let result = archive("https://example.com/page").await;
+        let result = match result {
+            RunResult::Archived(result) => result,
+            skipped => panic!("expected a capture, got {:?}", skipped),
+        };
+        assert!(matches!(result.outcome, Outcome::NewCapture { .. }));
+        assert_eq!(result.tags, vec!["docs".to_string()]);
+        assert_eq!(mock.captures("https://example.com/page").len(), 1);
+        assert!(mock.captures("https://skipped.example.com/").is_empty());
+    }
+}
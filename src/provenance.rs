@@ -0,0 +1,38 @@
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+
+/// Where and how a run of the tool happened, so that results can be traced back to it.
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct Provenance {
+    pub run_id: String,
+    pub started_at: DateTime<Utc>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub finished_at: Option<DateTime<Utc>>,
+    pub tool_version: String,
+    /// SHA-256 of the config file used for the run, if any.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub config_hash: Option<String>,
+    pub host: String,
+}
+
+impl Provenance {
+    /// Starts a new run, using the contents of its config file (if any).
+    pub fn start(config: Option<&[u8]>) -> Provenance {
+        Provenance {
+            run_id: uuid::Uuid::new_v4().to_string(),
+            started_at: Utc::now(),
+            finished_at: None,
+            tool_version: env!("CARGO_PKG_VERSION").to_string(),
+            config_hash: config.map(|contents| {
+                Sha256::digest(contents)
+                    .iter()
+                    .map(|byte| format!("{:02x}", byte))
+                    .collect()
+            }),
+            host: hostname::get()
+                .map(|host| host.to_string_lossy().into_owned())
+                .unwrap_or_default(),
+        }
+    }
+}
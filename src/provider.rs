@@ -0,0 +1,209 @@
+use crate::{ArchiveError, ArchiveOptions, ArchivingResult, Snapshot, WaybackClient};
+use futures::future::{BoxFuture, FutureExt};
+
+/// A web archive that `WaybackClient` can archive URLs in (see
+/// `WaybackClient::with_provider`). The client looks up the newest snapshot of a URL
+/// with `lookup_latest`, reuses it if it's recent enough, and otherwise captures the
+/// URL with `save`, falling back to the old snapshot if that fails (per the stale
+/// fallback policy). Retries, coalescing, and cancellation are handled by the client.
+///
+/// The Wayback Machine (`WaybackMachine`, the default) and Memento archives
+/// (`MementoArchive`) are built in. Providers are given the client that's archiving,
+/// whose public methods may be useful to them.
+///
+/// ```
+/// use futures::future::{BoxFuture, FutureExt};
+/// use std::sync::Arc;
+/// use wayback_archiver::{
+///     ArchiveError, ArchiveOptions, ArchiveProvider, ArchivingResult, Snapshot, WaybackClient,
+///     WaybackMachine,
+/// };
+///
+/// /// The Wayback Machine, without making new captures.
+/// struct LookupOnly;
+///
+/// impl ArchiveProvider for LookupOnly {
+///     fn provider_name(&self) -> &str {
+///         "wayback-lookup-only"
+///     }
+///
+///     fn lookup_latest<'a>(
+///         &'a self,
+///         client: &'a WaybackClient,
+///         url: &'a str,
+///     ) -> BoxFuture<'a, Result<Option<Snapshot>, ArchiveError>> {
+///         WaybackMachine.lookup_latest(client, url)
+///     }
+///
+///     fn captures_on_request(&self) -> bool {
+///         false
+///     }
+///
+///     fn save<'a>(
+///         &'a self,
+///         _client: &'a WaybackClient,
+///         _url: &'a str,
+///         _options: &'a ArchiveOptions,
+///         _previous: Option<&'a Snapshot>,
+///     ) -> BoxFuture<'a, Result<ArchivingResult, ArchiveError>> {
+///         async { Err(ArchiveError::UnableToArchive) }.boxed()
+///     }
+/// }
+///
+/// let client = WaybackClient::new().with_provider(Arc::new(LookupOnly));
+/// ```
+pub trait ArchiveProvider: Send + Sync {
+    /// A short name for the archive (e.g. `wayback`), for messages.
+    fn provider_name(&self) -> &str;
+
+    /// The newest snapshot of `url` in the archive, if it has one.
+    fn lookup_latest<'a>(
+        &'a self,
+        client: &'a WaybackClient,
+        url: &'a str,
+    ) -> BoxFuture<'a, Result<Option<Snapshot>, ArchiveError>>;
+
+    /// Whether the archive captures pages on request. Archives that don't can only be
+    /// searched, so their newest snapshot is used if there's no recent one, as a stale
+    /// fallback, and `save` isn't called.
+    fn captures_on_request(&self) -> bool {
+        true
+    }
+
+    /// Captures `url` in the archive. `previous` is the newest snapshot that
+    /// `lookup_latest` found, if any, which a new capture should be told apart from.
+    /// Return `ArchiveError::UnableToArchive` if the archive can't capture it.
+    fn save<'a>(
+        &'a self,
+        client: &'a WaybackClient,
+        url: &'a str,
+        options: &'a ArchiveOptions,
+        previous: Option<&'a Snapshot>,
+    ) -> BoxFuture<'a, Result<ArchivingResult, ArchiveError>>;
+}
+
+/// The Internet Archive's Wayback Machine, where captures are made with Save Page Now
+/// (SPN2, if the client has credentials).
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct WaybackMachine;
+
+impl ArchiveProvider for WaybackMachine {
+    fn provider_name(&self) -> &str {
+        "wayback"
+    }
+
+    fn lookup_latest<'a>(
+        &'a self,
+        client: &'a WaybackClient,
+        url: &'a str,
+    ) -> BoxFuture<'a, Result<Option<Snapshot>, ArchiveError>> {
+        async move {
+            match client.fetch_latest_snapshot(url).await {
+                Ok(snapshot) => Ok(Some(snapshot)),
+                Err(ArchiveError::NoExistingSnapshot) => Ok(None),
+                Err(err) => Err(err),
+            }
+        }
+        .boxed()
+    }
+
+    fn save<'a>(
+        &'a self,
+        client: &'a WaybackClient,
+        url: &'a str,
+        options: &'a ArchiveOptions,
+        _previous: Option<&'a Snapshot>,
+    ) -> BoxFuture<'a, Result<ArchivingResult, ArchiveError>> {
+        client.save_to_wayback(url, options).boxed()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Outcome;
+    use chrono::{Duration, Utc};
+    use std::sync::Arc;
+
+    /// An archive with one old snapshot of every URL, which may or may not capture.
+    struct OldSnapshots {
+        captures: bool,
+    }
+
+    impl ArchiveProvider for OldSnapshots {
+        fn provider_name(&self) -> &str {
+            "old-snapshots"
+        }
+
+        fn lookup_latest<'a>(
+            &'a self,
+            _client: &'a WaybackClient,
+            url: &'a str,
+        ) -> BoxFuture<'a, Result<Option<Snapshot>, ArchiveError>> {
+            async move {
+                if url.contains("down") {
+                    return Err(ArchiveError::Network("unreachable".to_string()));
+                }
+                Ok(Some(Snapshot {
+                    url: format!("https://archive.example/old/{}", url),
+                    timestamp: Utc::now() - Duration::days(365),
+                    status: Some(200),
+                    available: true,
+                }))
+            }
+            .boxed()
+        }
+
+        fn captures_on_request(&self) -> bool {
+            self.captures
+        }
+
+        fn save<'a>(
+            &'a self,
+            _client: &'a WaybackClient,
+            url: &'a str,
+            _options: &'a ArchiveOptions,
+            previous: Option<&'a Snapshot>,
+        ) -> BoxFuture<'a, Result<ArchivingResult, ArchiveError>> {
+            assert!(previous.is_some());
+            let new = format!("https://archive.example/new/{}", url);
+            async move {
+                Ok(ArchivingResult::new(Outcome::NewCapture {
+                    url: new,
+                    ts: Utc::now().naive_utc(),
+                }))
+            }
+            .boxed()
+        }
+    }
+
+    #[tokio::test]
+    async fn dispatches_to_providers() {
+        let client = WaybackClient::new().with_retry_policy(crate::RetryPolicy::never());
+        let capturing = client
+            .clone()
+            .with_provider(Arc::new(OldSnapshots { captures: true }));
+        assert_eq!(capturing.provider().provider_name(), "old-snapshots");
+        let result = capturing.archive_url("example.com").await.unwrap();
+        assert_eq!(
+            result.outcome.url(),
+            Some("https://archive.example/new/example.com")
+        );
+
+        // Archives that can only be searched fall back to their newest snapshot, and
+        // report failed lookups.
+        let searching = client.with_provider(Arc::new(OldSnapshots { captures: false }));
+        let result = searching.archive_url("example.com").await;
+        assert!(matches!(
+            result,
+            Ok(ArchivingResult {
+                outcome: Outcome::StaleFallback { .. },
+                ..
+            })
+        ));
+        assert_eq!(
+            searching.archive_url("down.example.com").await,
+            Err(ArchiveError::Network("unreachable".to_string()))
+        );
+    }
+}
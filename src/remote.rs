@@ -0,0 +1,145 @@
+//! Results files stored in S3 (`s3://bucket/path`) or GCS (`gs://bucket/path`),
+//! for environments where the local filesystem doesn't outlive the job.
+//!
+//! Credentials are read from the environment (e.g. `AWS_ACCESS_KEY_ID`,
+//! `GOOGLE_APPLICATION_CREDENTIALS`). Remote paths are only supported when built
+//! with the `object-store` feature.
+
+/// Returns whether `path` names an object in a remote store rather than a local file.
+pub fn is_remote(path: &str) -> bool {
+    path.starts_with("s3://") || path.starts_with("gs://")
+}
+
+/// Reads the object at `url`, returning `None` if it doesn't exist.
+#[cfg(feature = "object-store")]
+pub async fn read(url: &str) -> Result<Option<String>, Box<dyn std::error::Error>> {
+    let (store, path) = open(url)?;
+    match store.get(&path).await {
+        Ok(object) => Ok(Some(String::from_utf8(object.bytes().await?.to_vec())?)),
+        Err(object_store::Error::NotFound { .. }) => Ok(None),
+        Err(err) => Err(err.into()),
+    }
+}
+
+/// Replaces the object at `url` with `contents`.
+#[cfg(feature = "object-store")]
+pub async fn write(url: &str, contents: String) -> Result<(), Box<dyn std::error::Error>> {
+    let (store, path) = open(url)?;
+    store.put(&path, contents.into()).await?;
+    Ok(())
+}
+
+/// An object in a remote store, which can be replaced only if it hasn't changed since
+/// it was read, for state shared by concurrent writers.
+#[cfg(feature = "lambda")]
+pub struct Object {
+    store: Box<dyn object_store::ObjectStore>,
+    path: object_store::path::Path,
+}
+
+/// The version of an `Object` that was read.
+#[cfg(feature = "lambda")]
+pub type Version = object_store::UpdateVersion;
+
+#[cfg(feature = "lambda")]
+impl Object {
+    /// The object at `url`.
+    pub fn open(url: &str) -> Result<Object, Box<dyn std::error::Error>> {
+        let (store, path) = open(url)?;
+        Ok(Object { store, path })
+    }
+
+    /// An object in an in-memory store, for tests.
+    #[cfg(test)]
+    pub fn in_memory() -> Object {
+        Object {
+            store: Box::new(object_store::memory::InMemory::new()),
+            path: object_store::path::Path::from("state.json"),
+        }
+    }
+
+    /// Reads the object, and its version, returning `None` if it doesn't exist.
+    pub async fn read(&self) -> Result<Option<(String, Version)>, Box<dyn std::error::Error>> {
+        match self.store.get(&self.path).await {
+            Ok(object) => {
+                let version = Version {
+                    e_tag: object.meta.e_tag.clone(),
+                    version: object.meta.version.clone(),
+                };
+                let contents = String::from_utf8(object.bytes().await?.to_vec())?;
+                Ok(Some((contents, version)))
+            }
+            Err(object_store::Error::NotFound { .. }) => Ok(None),
+            Err(err) => Err(err.into()),
+        }
+    }
+
+    /// Replaces the object with `contents` if it's still at `version` (with S3, using
+    /// `If-Match` on its ETag), or if it still doesn't exist if `version` is `None`.
+    /// Returns whether it was replaced.
+    pub async fn replace(
+        &self,
+        contents: String,
+        version: Option<&Version>,
+    ) -> Result<bool, Box<dyn std::error::Error>> {
+        use object_store::PutMode;
+
+        let mode = match version {
+            Some(version) => PutMode::Update(version.clone()),
+            None => PutMode::Create,
+        };
+        match self
+            .store
+            .put_opts(&self.path, contents.into(), mode.into())
+            .await
+        {
+            Ok(_) => Ok(true),
+            Err(object_store::Error::Precondition { .. })
+            | Err(object_store::Error::AlreadyExists { .. }) => Ok(false),
+            Err(err) => Err(err.into()),
+        }
+    }
+}
+
+#[cfg(feature = "object-store")]
+fn open(
+    url: &str,
+) -> Result<
+    (Box<dyn object_store::ObjectStore>, object_store::path::Path),
+    Box<dyn std::error::Error>,
+> {
+    use object_store::aws::AmazonS3Builder;
+    use object_store::gcp::GoogleCloudStorageBuilder;
+
+    let parsed = reqwest::Url::parse(url)?;
+    let store: Box<dyn object_store::ObjectStore> = match parsed.scheme() {
+        "s3" => Box::new(AmazonS3Builder::from_env().with_url(url).build()?),
+        "gs" => Box::new(
+            GoogleCloudStorageBuilder::from_env()
+                .with_url(url)
+                .build()?,
+        ),
+        scheme => return Err(format!("unsupported object store scheme: {}", scheme).into()),
+    };
+    let path = object_store::path::Path::from_url_path(parsed.path())?;
+    Ok((store, path))
+}
+
+#[cfg(not(feature = "object-store"))]
+pub async fn read(url: &str) -> Result<Option<String>, Box<dyn std::error::Error>> {
+    Err(unsupported(url))
+}
+
+#[cfg(not(feature = "object-store"))]
+pub async fn write(url: &str, _contents: String) -> Result<(), Box<dyn std::error::Error>> {
+    Err(unsupported(url))
+}
+
+#[cfg(not(feature = "object-store"))]
+fn unsupported(url: &str) -> Box<dyn std::error::Error> {
+    format!(
+        "can't use {}: wayback-archiver was built without the object-store feature",
+        url
+    )
+    .into()
+}
@@ -0,0 +1,526 @@
+//! `wayback-archiver rescue`, which archives a site that's announced it's going offline
+//! before its `--deadline`. Breadth comes before depth: every URL is captured once (or
+//! found to have a recent snapshot) before any is captured again, and URLs that failed
+//! are retried before anything is re-captured. Re-captures then go to the URLs with the
+//! fewest, oldest captures, up to `--depth` per URL.
+//!
+//! Captures are made as fast as the Wayback Machine allows: more are made at once while
+//! they succeed, and half as many whenever it asks to back off (e.g. when a rate limit
+//! or quota is hit). Each result is reported with the share of URLs captured so far, the
+//! time left until the deadline, and when the rest should be done at the current pace.
+use crate::config::parse_duration;
+use crate::eta::format_estimate;
+use crate::input;
+use crate::output::{Output, ResultsFile};
+use crate::pacing::Pacing;
+use chrono::{DateTime, NaiveDate, Utc};
+use clap::Clap;
+use futures::stream::{FuturesUnordered, StreamExt};
+use std::cmp::Reverse;
+use std::collections::{BTreeMap, BinaryHeap, VecDeque};
+use std::io::BufRead;
+use std::time::{Duration, Instant};
+use wayback_archiver::{
+    ArchiveError, ArchiveOptions, ArchivingResult, Outcome, RetryPolicy, WaybackClient,
+};
+
+/// How many times a URL is tried before it's given up on.
+const MAX_ATTEMPTS: u32 = 3;
+/// How many results are made between writes of the results file.
+const WRITE_EVERY: usize = 10;
+
+#[derive(Clap)]
+pub struct RescueOpts {
+    /// When the site goes offline: a date (e.g. 2024-07-01, from midnight
+    /// UTC) or an RFC 3339 time. No captures are started after it.
+    #[clap(long, parse(try_from_str = parse_deadline))]
+    deadline: DateTime<Utc>,
+    /// A file of URLs to rescue, one per line (e.g. from `seed`). URLs can
+    /// also be given as arguments, or using stdin.
+    #[clap(long)]
+    urls_file: Option<String>,
+    /// Writes the results to this file as they're made. Results already in
+    /// it count towards the rescue, so an interrupted rescue can be resumed.
+    #[clap(long)]
+    out: Option<String>,
+    /// Existing snapshots newer than this (e.g. "7d") count as a URL's
+    /// capture. Defaults to 30 days.
+    #[clap(long, parse(try_from_str = parse_duration))]
+    max_age: Option<Duration>,
+    /// How many times each URL is re-captured, once every URL has been
+    /// captured.
+    #[clap(long, default_value = "1")]
+    depth: u32,
+    /// The least time between captures of a URL (e.g. "12h"). Defaults to a
+    /// day.
+    #[clap(long, parse(try_from_str = parse_duration))]
+    recapture_after: Option<Duration>,
+    /// The most captures made at once.
+    #[clap(long, default_value = "8")]
+    max_concurrency: usize,
+    /// URLs to rescue.
+    urls: Vec<String>,
+}
+
+fn parse_deadline(s: &str) -> Result<DateTime<Utc>, String> {
+    if let Ok(date) = NaiveDate::parse_from_str(s, "%Y-%m-%d") {
+        return Ok(date.and_hms_opt(0, 0, 0).unwrap().and_utc());
+    }
+    DateTime::parse_from_rfc3339(s)
+        .map(|deadline| deadline.with_timezone(&Utc))
+        .map_err(|_| format!("invalid deadline {:?} (expected e.g. 2024-07-01)", s))
+}
+
+/// Whether an archival captures a URL for the first time in the rescue, or again.
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Pass {
+    Breadth,
+    Depth,
+}
+
+#[derive(Default)]
+struct UrlState {
+    captures: u32,
+    attempts: u32,
+}
+
+/// The order in which URLs are captured.
+struct Plan {
+    urls: BTreeMap<String, UrlState>,
+    /// URLs that haven't been captured yet, with failed ones at the back.
+    breadth: VecDeque<String>,
+    /// URLs due to be re-captured, by fewest captures and then oldest capture.
+    depth: BinaryHeap<Reverse<(u32, DateTime<Utc>, String)>>,
+    max_captures: u32,
+    recapture_after: chrono::Duration,
+    /// How many URLs have been captured at least once.
+    covered: usize,
+}
+
+impl Plan {
+    fn new(urls: Vec<String>, depth: u32, recapture_after: chrono::Duration) -> Plan {
+        let mut plan = Plan {
+            urls: BTreeMap::new(),
+            breadth: VecDeque::new(),
+            depth: BinaryHeap::new(),
+            max_captures: 1 + depth,
+            recapture_after,
+            covered: 0,
+        };
+        for url in urls {
+            if !plan.urls.contains_key(&url) {
+                plan.urls.insert(url.clone(), UrlState::default());
+                plan.breadth.push_back(url);
+            }
+        }
+        plan
+    }
+
+    /// Counts a capture of `url` made before the rescue (e.g. by an interrupted one).
+    fn resume(&mut self, url: &str, ts: DateTime<Utc>) {
+        if self.urls.get(url).is_some_and(|state| state.captures == 0) {
+            self.breadth.retain(|queued| queued != url);
+            self.captured(url, ts);
+        }
+    }
+
+    /// The next URL to capture at `now`, if any is due.
+    fn next(&mut self, now: DateTime<Utc>) -> Option<(String, Pass)> {
+        if let Some(url) = self.breadth.pop_front() {
+            return Some((url, Pass::Breadth));
+        }
+        match self.depth.peek() {
+            Some(Reverse((_, latest, _))) if *latest + self.recapture_after <= now => {
+                let Reverse((_, _, url)) = self.depth.pop().unwrap();
+                Some((url, Pass::Depth))
+            }
+            _ => None,
+        }
+    }
+
+    /// When the next re-capture is due, if there's one waiting.
+    fn next_due(&self) -> Option<DateTime<Utc>> {
+        self.depth
+            .peek()
+            .map(|Reverse((_, latest, _))| *latest + self.recapture_after)
+    }
+
+    fn record(&mut self, url: &str, pass: Pass, result: &Result<ArchivingResult, ArchiveError>) {
+        let captured = match result {
+            Ok(ArchivingResult {
+                outcome: Outcome::NewCapture { ts, .. } | Outcome::ExistingSnapshot { ts, .. },
+                ..
+            }) => Some(ts.and_utc()),
+            _ => None,
+        };
+        if let Some(ts) = captured {
+            self.captured(url, ts);
+            return;
+        }
+        let state = self.urls.get_mut(url).expect("planned URL");
+        state.attempts += 1;
+        let permanent = matches!(result, Err(err) if err.is_permanent());
+        if permanent || state.attempts >= MAX_ATTEMPTS {
+            return;
+        }
+        match pass {
+            Pass::Breadth => self.breadth.push_back(url.to_string()),
+            // Tried again after another `recapture_after`.
+            Pass::Depth => {
+                let captures = state.captures;
+                self.depth
+                    .push(Reverse((captures, Utc::now(), url.to_string())));
+            }
+        }
+    }
+
+    fn captured(&mut self, url: &str, ts: DateTime<Utc>) {
+        let state = self.urls.get_mut(url).expect("planned URL");
+        state.captures += 1;
+        state.attempts = 0;
+        if state.captures == 1 {
+            self.covered += 1;
+        }
+        if state.captures < self.max_captures {
+            self.depth
+                .push(Reverse((state.captures, ts, url.to_string())));
+        }
+    }
+
+    fn covered(&self) -> usize {
+        self.covered
+    }
+}
+
+/// How many captures are made at once: one more after as many successes in a row,
+/// and half as many (after a pause) when the Wayback Machine asks to back off.
+struct Pace {
+    pacing: Pacing,
+    concurrency: usize,
+    max_concurrency: usize,
+    successes: usize,
+    paused_until: Option<Instant>,
+}
+
+impl Pace {
+    fn new(max_concurrency: usize, pacing: Pacing) -> Pace {
+        Pace {
+            pacing,
+            concurrency: 1,
+            max_concurrency: max_concurrency.max(1),
+            successes: 0,
+            paused_until: None,
+        }
+    }
+
+    /// How many captures may be in progress at `now`.
+    fn allowed(&self, now: Instant) -> usize {
+        match self.paused_until {
+            Some(until) if now < until => 0,
+            _ => self.concurrency,
+        }
+    }
+
+    fn record(&mut self, result: &Result<ArchivingResult, ArchiveError>, now: Instant) {
+        match result {
+            Err(err) => {
+                if let Some(backoff) = self.pacing.backoff_for(err) {
+                    self.concurrency = (self.concurrency / 2).max(1);
+                    self.successes = 0;
+                    self.paused_until = Some(now + backoff);
+                }
+            }
+            Ok(_) => {
+                self.successes += 1;
+                if self.successes >= self.concurrency {
+                    self.concurrency = (self.concurrency + 1).min(self.max_concurrency);
+                    self.successes = 0;
+                }
+            }
+        }
+    }
+}
+
+pub async fn run(
+    opts: &RescueOpts,
+    client: WaybackClient,
+    pacing: &Pacing,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let mut urls = opts.urls.clone();
+    if urls.is_empty() {
+        for line in input::open(opts.urls_file.as_deref())?.lines() {
+            let line = line?;
+            if !line.trim().is_empty() {
+                urls.push(line.trim().to_string());
+            }
+        }
+    }
+    if opts.deadline <= Utc::now() {
+        return Err(format!("The deadline, {}, has already passed", opts.deadline).into());
+    }
+    let recapture_after = opts
+        .recapture_after
+        .unwrap_or(Duration::from_secs(24 * 60 * 60));
+    let mut plan = Plan::new(
+        urls,
+        opts.depth,
+        chrono::Duration::from_std(recapture_after)?,
+    );
+    let mut results = match &opts.out {
+        Some(path) => ResultsFile::read(path, false).await?.results,
+        None => BTreeMap::new(),
+    };
+    for (url, result) in &results {
+        if let Outcome::NewCapture { ts, .. } | Outcome::ExistingSnapshot { ts, .. } =
+            result.outcome
+        {
+            plan.resume(url, ts.and_utc());
+        }
+    }
+    let output = Output {
+        minify: false,
+        runs: None,
+        fsync: true,
+    };
+    // Failures are retried by the plan, after the other URLs.
+    let client = client.with_retry_policy(RetryPolicy::never());
+    let breadth_options = ArchiveOptions {
+        max_snapshot_age: match opts.max_age {
+            Some(max_age) => chrono::Duration::from_std(max_age)?,
+            None => chrono::Duration::days(30),
+        },
+        ..ArchiveOptions::default()
+    };
+    let depth_options = ArchiveOptions {
+        max_snapshot_age: plan.recapture_after,
+        ..ArchiveOptions::default()
+    };
+    eprintln!(
+        "Rescuing {} URLs ({} captured already) before {}",
+        plan.urls.len(),
+        plan.covered(),
+        opts.deadline
+    );
+
+    let started = Instant::now();
+    let covered_before = plan.covered();
+    let mut pace = Pace::new(opts.max_concurrency, pacing.clone());
+    let mut in_flight = FuturesUnordered::new();
+    let mut unwritten = 0;
+    loop {
+        let now = Utc::now();
+        if now < opts.deadline {
+            while in_flight.len() < pace.allowed(Instant::now()) {
+                let (url, pass) = match plan.next(now) {
+                    Some(next) => next,
+                    None => break,
+                };
+                let options = match pass {
+                    Pass::Breadth => breadth_options.clone(),
+                    Pass::Depth => depth_options.clone(),
+                };
+                let client = client.clone();
+                in_flight.push(async move {
+                    let result = client.archive_url_with_options(&url, &options).await;
+                    (url, pass, result)
+                });
+            }
+        }
+        // Wake for the deadline, the end of a pause, or the next re-capture.
+        let mut wake = opts.deadline;
+        if let Some(until) = pace.paused_until {
+            let pause =
+                chrono::Duration::from_std(until.saturating_duration_since(Instant::now()))?;
+            wake = wake.min(now + pause);
+        }
+        if let Some(due) = plan.next_due() {
+            wake = wake.min(due);
+        }
+        let wait = (wake - now).to_std().unwrap_or(Duration::ZERO);
+        if in_flight.is_empty() {
+            let idle = plan.breadth.is_empty() && plan.next_due().is_none();
+            if now >= opts.deadline || idle {
+                break;
+            }
+            tokio::time::sleep(wait.max(Duration::from_secs(1))).await;
+            continue;
+        }
+        let (url, pass, result) = tokio::select! {
+            Some(done) = in_flight.next() => done,
+            _ = tokio::time::sleep(wait.max(Duration::from_secs(1))) => continue,
+        };
+        pace.record(&result, Instant::now());
+        plan.record(&url, pass, &result);
+        let message = match &result {
+            Ok(result) => match result.outcome.url() {
+                Some(snapshot_url) => snapshot_url.to_string(),
+                None => format!("{:?}", result.outcome),
+            },
+            Err(err) => format!("failed: {}", err),
+        };
+        eprintln!(
+            "[{}] {} -> {}",
+            progress(&plan, covered_before, started.elapsed(), opts.deadline),
+            url,
+            message
+        );
+        let result = result.unwrap_or_else(|err| crate::failed_result(&err));
+        // A failed re-capture doesn't replace the capture before it.
+        if pass == Pass::Breadth || result.outcome.url().is_some() {
+            results.insert(url, result);
+            unwritten += 1;
+        }
+        if unwritten >= WRITE_EVERY {
+            if let Some(path) = &opts.out {
+                output.write(&results, path).await?;
+            }
+            unwritten = 0;
+        }
+    }
+    if let Some(path) = &opts.out {
+        output.write(&results, path).await?;
+    }
+    let missed = plan.urls.len() - plan.covered();
+    eprintln!(
+        "Captured {} of {} URLs{}",
+        plan.covered(),
+        plan.urls.len(),
+        if missed > 0 && Utc::now() >= opts.deadline {
+            format!("; {} were missed by the deadline", missed)
+        } else {
+            String::new()
+        }
+    );
+    Ok(())
+}
+
+/// Describes how far the rescue has come, e.g. `42% captured, 3h12m left, done in 1h05m`.
+fn progress(
+    plan: &Plan,
+    covered_before: usize,
+    elapsed: Duration,
+    deadline: DateTime<Utc>,
+) -> String {
+    let covered = plan.covered();
+    let total = plan.urls.len().max(1);
+    let left = (deadline - Utc::now()).to_std().unwrap_or(Duration::ZERO);
+    let mut progress = format!(
+        "{}% captured, {} left",
+        100 * covered / total,
+        format_estimate(left)
+    );
+    let remaining = plan.urls.len() - covered;
+    let rescued = covered - covered_before.min(covered);
+    if remaining > 0 && rescued > 0 {
+        let estimate = elapsed.mul_f64(remaining as f64 / rescued as f64);
+        progress.push_str(&format!(", done in {}", format_estimate(estimate)));
+        if estimate > left {
+            progress.push_str(" (behind)");
+        }
+    }
+    progress
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn captured(url: &str, ts: DateTime<Utc>) -> Result<ArchivingResult, ArchiveError> {
+        Ok(ArchivingResult::new(Outcome::NewCapture {
+            url: format!("https://web.archive.org/web/{}", url),
+            ts: ts.naive_utc(),
+        }))
+    }
+
+    #[test]
+    fn parses_deadlines() {
+        assert_eq!(
+            parse_deadline("2024-07-01"),
+            Ok("2024-07-01T00:00:00Z".parse().unwrap())
+        );
+        assert_eq!(
+            parse_deadline("2024-07-01T12:00:00+02:00"),
+            Ok("2024-07-01T10:00:00Z".parse().unwrap())
+        );
+        assert!(parse_deadline("July").is_err());
+    }
+
+    #[test]
+    fn captures_everything_before_recapturing() {
+        let now = Utc::now();
+        let day = chrono::Duration::days(1);
+        let urls = vec!["a".to_string(), "b".to_string(), "c".to_string()];
+        let mut plan = Plan::new(urls, 1, day);
+        plan.resume("c", now - day * 3);
+        assert_eq!(plan.covered(), 1);
+
+        assert_eq!(plan.next(now), Some(("a".to_string(), Pass::Breadth)));
+        plan.record("a", Pass::Breadth, &captured("a", now));
+        assert_eq!(plan.next(now), Some(("b".to_string(), Pass::Breadth)));
+        plan.record("b", Pass::Breadth, &Err(ArchiveError::Timeout));
+        // Failures are retried before anything is re-captured.
+        assert_eq!(plan.next(now), Some(("b".to_string(), Pass::Breadth)));
+        plan.record("b", Pass::Breadth, &captured("b", now - day * 2));
+
+        // Then the oldest captures are re-captured, once they're due.
+        assert_eq!(plan.next(now), Some(("c".to_string(), Pass::Depth)));
+        assert_eq!(plan.next(now), Some(("b".to_string(), Pass::Depth)));
+        assert_eq!(plan.next(now), None);
+        assert_eq!(plan.next_due(), Some(now + day));
+        assert_eq!(plan.next(now + day), Some(("a".to_string(), Pass::Depth)));
+
+        // With --depth 1, URLs are captured twice at most.
+        plan.record("c", Pass::Depth, &captured("c", now));
+        assert_eq!(plan.next_due(), None);
+        assert_eq!(plan.urls["c"].captures, 2);
+    }
+
+    #[test]
+    fn gives_up_on_urls_eventually() {
+        let now = Utc::now();
+        let mut plan = Plan::new(vec!["a".to_string()], 1, chrono::Duration::days(1));
+        for _ in 0..MAX_ATTEMPTS {
+            let (url, pass) = plan.next(now).unwrap();
+            plan.record(&url, pass, &Err(ArchiveError::Timeout));
+        }
+        assert_eq!(plan.next(now), None);
+
+        let mut plan = Plan::new(vec!["a".to_string()], 1, chrono::Duration::days(1));
+        plan.next(now);
+        plan.record("a", Pass::Breadth, &Err(ArchiveError::UnableToArchive));
+        assert_eq!(plan.next(now), None);
+    }
+
+    #[test]
+    fn paces_by_the_wayback_machine() {
+        let now = Instant::now();
+        let mut pace = Pace::new(4, Pacing::default());
+        for _ in 0..1 + 2 + 3 {
+            pace.record(&captured("a", Utc::now()), now);
+        }
+        assert_eq!(pace.allowed(now), 4);
+        pace.record(&captured("a", Utc::now()), now);
+        assert_eq!(pace.allowed(now), 4);
+
+        let rate_limited = Err(ArchiveError::BandwidthExceeded {
+            retry_after: Some(Duration::from_secs(60)),
+        });
+        pace.record(&rate_limited, now);
+        assert_eq!(pace.allowed(now), 0);
+        assert_eq!(pace.allowed(now + Duration::from_secs(60)), 2);
+        // Other failures don't change the pace.
+        pace.record(&Err(ArchiveError::UnableToArchive), now);
+        assert_eq!(pace.allowed(now + Duration::from_secs(60)), 2);
+
+        // Without a Retry-After, the configured wait is used.
+        let later = now + Duration::from_secs(60);
+        let unavailable = Err(ArchiveError::ServiceUnavailable {
+            status: 503,
+            snippet: String::new(),
+            retry_after: None,
+        });
+        pace.record(&unavailable, later);
+        assert_eq!(pace.allowed(later + Pacing::default().retry_wait), 1);
+        assert_eq!(pace.allowed(later + Duration::from_secs(1)), 0);
+    }
+}
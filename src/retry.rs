@@ -0,0 +1,145 @@
+use crate::ArchiveError;
+use std::collections::hash_map::RandomState;
+use std::hash::{BuildHasher, Hasher};
+use std::time::Duration;
+
+/// How a `WaybackClient` retries archivals that fail transiently (see
+/// `WaybackClient::with_retry_policy`).
+///
+/// ```
+/// # use std::time::Duration;
+/// # use wayback_archiver::{RetryPolicy, WaybackClient};
+/// let client = WaybackClient::new().with_retry_policy(RetryPolicy {
+///     max_attempts: 5,
+///     base_delay: Duration::from_secs(10),
+///     ..RetryPolicy::default()
+/// });
+/// ```
+#[derive(Clone, Copy, Debug)]
+pub struct RetryPolicy {
+    /// The most times to try archiving a URL, including the first. 1 disables
+    /// retries. Defaults to 3.
+    pub max_attempts: u32,
+    /// How long to wait before the first retry. Each later retry waits twice as long
+    /// as the one before, up to `max_delay`. If the Wayback Machine asked to wait
+    /// longer (with `Retry-After`), that's how long is waited instead, up to
+    /// `max_delay`. Defaults to 5 seconds.
+    pub base_delay: Duration,
+    /// Defaults to a minute.
+    pub max_delay: Duration,
+    /// Up to how much longer to wait than that, as a fraction of the delay (e.g. 0.5
+    /// for up to 50% longer), so that clients that failed at once don't all retry at
+    /// once. Clamped to between 0 and 1. Defaults to 0.25.
+    pub jitter: f64,
+    /// Which errors are retried. Defaults to `ArchiveError::is_retryable`.
+    pub retryable: fn(&ArchiveError) -> bool,
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        RetryPolicy {
+            max_attempts: 3,
+            base_delay: Duration::from_secs(5),
+            max_delay: Duration::from_secs(60),
+            jitter: 0.25,
+            retryable: ArchiveError::is_retryable,
+        }
+    }
+}
+
+impl RetryPolicy {
+    /// A policy that never retries.
+    pub fn never() -> Self {
+        RetryPolicy {
+            max_attempts: 1,
+            ..RetryPolicy::default()
+        }
+    }
+
+    /// Whether to retry after the `attempt`th attempt (from 1) failed with `err`.
+    pub(crate) fn should_retry(&self, attempt: u32, err: &ArchiveError) -> bool {
+        attempt < self.max_attempts && (self.retryable)(err)
+    }
+
+    /// How long to wait after the `attempt`th attempt (from 1) failed with `err`.
+    pub(crate) fn delay(&self, attempt: u32, err: &ArchiveError) -> Duration {
+        let delay = self
+            .base_delay
+            .saturating_mul(2u32.saturating_pow(attempt.saturating_sub(1)))
+            .min(self.max_delay);
+        let jitter = match self.jitter {
+            jitter if jitter.is_finite() => jitter.clamp(0.0, 1.0),
+            _ => 0.0,
+        };
+        let delay =
+            Duration::try_from_secs_f64(delay.as_secs_f64() * (1.0 + jitter * random_fraction()))
+                .unwrap_or(Duration::MAX);
+        let retry_after = err.retry_after().unwrap_or_default().min(self.max_delay);
+        delay.max(retry_after)
+    }
+}
+
+/// A random number in [0, 1), from the random keys of the standard library's hasher.
+fn random_fraction() -> f64 {
+    RandomState::new().build_hasher().finish() as f64 / (u64::MAX as f64 + 1.0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn backs_off_exponentially() {
+        let policy = RetryPolicy {
+            jitter: 0.0,
+            ..RetryPolicy::default()
+        };
+        let err = ArchiveError::BandwidthExceeded { retry_after: None };
+        let delays: Vec<u64> = (1..=6)
+            .map(|attempt| policy.delay(attempt, &err).as_secs())
+            .collect();
+        assert_eq!(delays, [5, 10, 20, 40, 60, 60]);
+
+        let err = ArchiveError::BandwidthExceeded {
+            retry_after: Some(Duration::from_secs(50)),
+        };
+        assert_eq!(policy.delay(1, &err), Duration::from_secs(50));
+        // Retry-After doesn't make it wait longer than max_delay.
+        let err = ArchiveError::BandwidthExceeded {
+            retry_after: Some(Duration::from_secs(24 * 60 * 60)),
+        };
+        assert_eq!(policy.delay(1, &err), Duration::from_secs(60));
+
+        let jittery = RetryPolicy::default();
+        for _ in 0..100 {
+            let delay = jittery.delay(2, &ArchiveError::LoginRequired);
+            assert!(
+                Duration::from_secs(10) <= delay && delay < Duration::from_millis(12_500),
+                "{:?}",
+                delay
+            );
+        }
+        for jitter in [f64::NAN, f64::INFINITY, -1.0, 1e300] {
+            let policy = RetryPolicy {
+                jitter,
+                max_delay: Duration::MAX,
+                ..RetryPolicy::default()
+            };
+            let delay = policy.delay(1, &ArchiveError::LoginRequired);
+            assert!(delay < Duration::from_secs(10), "{}: {:?}", jitter, delay);
+        }
+    }
+
+    #[test]
+    fn retries_only_retryable_errors() {
+        let policy = RetryPolicy::default();
+        let err = ArchiveError::BandwidthExceeded { retry_after: None };
+        assert!(policy.should_retry(1, &err));
+        assert!(policy.should_retry(2, &err));
+        assert!(!policy.should_retry(3, &err));
+        assert!(policy.should_retry(1, &ArchiveError::Timeout));
+        assert!(policy.should_retry(1, &ArchiveError::Network("reset".to_string())));
+        assert!(!policy.should_retry(1, &ArchiveError::UnableToArchive));
+        assert!(!RetryPolicy::never().should_retry(1, &err));
+    }
+}
@@ -0,0 +1,213 @@
+//! `wayback-archiver reverify`, which keeps a long-lived results file trustworthy by
+//! re-checking that its snapshots are still served. Snapshots rarely disappear soon
+//! after they're made, but over the years archives reorganize their storage and
+//! exclude sites on request, so the older a snapshot is, the more often it's checked:
+//! every year while it's under a year old, every 180 days until it's 5 years old,
+//! every 90 days until it's 10, and every 30 days after that.
+//!
+//! A snapshot that's no longer served is replaced with the Wayback Machine's capture
+//! closest to it, if it has one that is; otherwise its result is recorded as failed,
+//! so the URL is archived again by the next `--merge` run.
+use crate::codes::SNAPSHOT_GONE;
+use crate::output::{Output, ResultsFile};
+use crate::remote;
+use chrono::{Duration, NaiveDateTime, Utc};
+use clap::Clap;
+use std::path::Path;
+use wayback_archiver::{ArchiveError, ArchivingResult, Outcome, WaybackClient};
+
+/// How often snapshots are re-checked by age, as (younger than, re-checked every) in
+/// days, youngest first. Older snapshots are re-checked every `OLDEST_INTERVAL_DAYS`.
+const TIERS: &[(i64, i64)] = &[(365, 365), (5 * 365, 180), (10 * 365, 90)];
+const OLDEST_INTERVAL_DAYS: i64 = 30;
+
+/// Results are written out after this many checks, so little is lost if the run is
+/// interrupted.
+const WRITE_EVERY: usize = 25;
+
+#[derive(Clap)]
+pub struct ReverifyOpts {
+    /// The results file to re-verify, which is updated in place.
+    results: String,
+    /// The most snapshots to check in this run, most overdue first, to spread
+    /// a large inventory's checks over several runs.
+    #[clap(long)]
+    limit: Option<usize>,
+    /// If set, results are written as compact JSON rather than
+    /// pretty-printed.
+    #[clap(long)]
+    minify: bool,
+}
+
+/// What re-checking a snapshot found.
+#[derive(Debug, PartialEq)]
+enum Verdict {
+    Verified,
+    /// The snapshot wasn't served, and was replaced with this one.
+    Repaired(String),
+    Gone,
+}
+
+pub async fn run(
+    opts: &ReverifyOpts,
+    client: WaybackClient,
+) -> Result<(), Box<dyn std::error::Error>> {
+    // A missing results file would otherwise read as empty, and be written out.
+    if !remote::is_remote(&opts.results) && !Path::new(&opts.results).exists() {
+        return Err(format!("{} doesn't exist", opts.results).into());
+    }
+    let mut file = ResultsFile::read(&opts.results, false).await?;
+    let output = Output {
+        minify: opts.minify,
+        runs: (!file.runs.is_empty()).then(|| file.runs.clone()),
+        fsync: false,
+    };
+    let now = Utc::now().naive_utc();
+    let mut due: Vec<(NaiveDateTime, String)> = file
+        .results
+        .iter()
+        .filter_map(|(url, result)| Some((next_check(result)?, url.clone())))
+        .filter(|(next_check, _)| *next_check <= now)
+        .collect();
+    due.sort();
+    let overdue = due.len();
+    due.truncate(opts.limit.unwrap_or(usize::MAX));
+    eprintln!(
+        "Re-verifying {} of {} snapshots ({} due)",
+        due.len(),
+        file.results.len(),
+        overdue
+    );
+
+    let (mut verified, mut repaired, mut gone, mut unchecked) = (0, 0, 0, 0);
+    for (checked, (_, url)) in due.iter().enumerate() {
+        let result = file.results.get_mut(url).expect("due result");
+        match reverify(&client, url, result).await {
+            Ok(Verdict::Verified) => verified += 1,
+            Ok(Verdict::Repaired(snapshot_url)) => {
+                eprintln!("Repaired {} -> {}", url, snapshot_url);
+                repaired += 1;
+            }
+            Ok(Verdict::Gone) => {
+                eprintln!("Snapshot of {} is gone", url);
+                gone += 1;
+            }
+            Err(err) => {
+                eprintln!("Unable to re-verify {}: {}", url, err);
+                unchecked += 1;
+            }
+        }
+        if (checked + 1) % WRITE_EVERY == 0 {
+            output.write(&file.results, &opts.results).await?;
+        }
+    }
+    output.write(&file.results, &opts.results).await?;
+    eprintln!(
+        "{} verified, {} repaired, {} gone, {} unchecked",
+        verified, repaired, gone, unchecked
+    );
+    Ok(())
+}
+
+/// How often a snapshot that's `age` old is re-checked.
+fn interval(age: Duration) -> Duration {
+    let days = TIERS
+        .iter()
+        .find(|(younger_than, _)| age < Duration::days(*younger_than))
+        .map_or(OLDEST_INTERVAL_DAYS, |(_, every)| *every);
+    Duration::days(days)
+}
+
+/// When `result`'s snapshot is next due to be re-checked, if it has one. Pending
+/// snapshots are left to `--verify-pending`.
+fn next_check(result: &ArchivingResult) -> Option<NaiveDateTime> {
+    let ts = result.outcome.ts().filter(|_| !result.pending)?;
+    let last_checked = result.verified_at.unwrap_or(ts);
+    Some(last_checked + interval(Utc::now().naive_utc() - ts))
+}
+
+/// Re-checks `url`'s snapshot (in `result`), repairing `result` if it's no longer
+/// served.
+async fn reverify(
+    client: &WaybackClient,
+    url: &str,
+    result: &mut ArchivingResult,
+) -> Result<Verdict, ArchiveError> {
+    let snapshot_url = result.outcome.url().expect("due snapshot").to_string();
+    let ts = result.outcome.ts().expect("due snapshot");
+    let now = Utc::now().naive_utc();
+    if client.verify_snapshot(&snapshot_url).await? {
+        result.verified_at = Some(now);
+        return Ok(Verdict::Verified);
+    }
+    // The capture may have moved (e.g. to another timestamp), or a nearby one may
+    // still be served.
+    let replacement = match client.fetch_snapshot_closest_to(url, ts.and_utc()).await {
+        Ok(snapshot) => Some(snapshot),
+        Err(ArchiveError::NoExistingSnapshot) => None,
+        Err(err) => return Err(err),
+    };
+    if let Some(replacement) =
+        replacement.filter(|replacement| replacement.available && replacement.url != snapshot_url)
+    {
+        if client.verify_snapshot(&replacement.url).await? {
+            result.outcome = Outcome::ExistingSnapshot {
+                url: replacement.url.clone(),
+                ts: replacement.timestamp.naive_utc(),
+            };
+            result.verified_at = Some(now);
+            return Ok(Verdict::Repaired(replacement.url));
+        }
+    }
+    result.outcome = Outcome::Failed {
+        error: format!("Snapshot {} is no longer available", snapshot_url),
+    };
+    result.error_code = Some(SNAPSHOT_GONE.to_string());
+    result.verified_at = Some(now);
+    Ok(Verdict::Gone)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn schedules_older_snapshots_more_often() {
+        assert_eq!(interval(Duration::days(30)), Duration::days(365));
+        assert_eq!(interval(Duration::days(3 * 365)), Duration::days(180));
+        assert_eq!(interval(Duration::days(7 * 365)), Duration::days(90));
+        assert_eq!(interval(Duration::days(20 * 365)), Duration::days(30));
+
+        let now = Utc::now().naive_utc();
+        let captured = |ts: NaiveDateTime| {
+            ArchivingResult::new(Outcome::NewCapture {
+                url: "https://web.archive.org/web/2015/https://example.com/".to_string(),
+                ts,
+            })
+        };
+        // A decade-old snapshot is due a month after it was last checked.
+        let old = ArchivingResult {
+            verified_at: Some(now - Duration::days(40)),
+            ..captured(now - Duration::days(11 * 365))
+        };
+        assert!(next_check(&old).is_some_and(|next| next <= now));
+        let checked = ArchivingResult {
+            verified_at: Some(now - Duration::days(10)),
+            ..old
+        };
+        assert!(next_check(&checked).is_some_and(|next| next > now));
+        // A recent snapshot isn't due until it's a year old.
+        assert!(next_check(&captured(now - Duration::days(200))).is_some_and(|next| next > now));
+        let pending = ArchivingResult {
+            pending: true,
+            ..captured(now - Duration::days(2 * 365))
+        };
+        assert_eq!(next_check(&pending), None);
+        assert_eq!(
+            next_check(&ArchivingResult::new(Outcome::Failed {
+                error: "Timed out".to_string()
+            })),
+            None
+        );
+    }
+}
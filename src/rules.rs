@@ -0,0 +1,207 @@
+use crate::config::deserialize_duration;
+use regex::Regex;
+use serde::{Deserialize, Deserializer};
+use std::time::Duration;
+use wayback_archiver::normalize_url;
+
+/// A rule from the `[[rules]]` sections of the config file, applied to every URL
+/// that matches all of its patterns.
+///
+/// Patterns are globs where `*` matches any run of characters; `host` is matched
+/// case-insensitively, and `query` against the query string without its leading `?`.
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Rule {
+    #[serde(default, deserialize_with = "deserialize_glob")]
+    host: Option<Regex>,
+    #[serde(default, deserialize_with = "deserialize_glob")]
+    path: Option<Regex>,
+    #[serde(default, deserialize_with = "deserialize_glob")]
+    query: Option<Regex>,
+    #[serde(default)]
+    action: Option<RuleAction>,
+    /// Minimum time between new captures of the matching URLs' domains, overriding
+    /// the capture cooldown.
+    #[serde(default, deserialize_with = "deserialize_optional_duration")]
+    cooldown: Option<Duration>,
+    /// How often the matching URLs should be re-archived: results (and existing
+    /// Wayback Machine snapshots) older than this are replaced with new captures.
+    #[serde(default, deserialize_with = "deserialize_optional_duration")]
+    every: Option<Duration>,
+    /// A label added to the results of matching URLs.
+    #[serde(default)]
+    tag: Option<String>,
+    /// The archive to archive the matching URLs in, rather than the Wayback Machine
+    /// (see `Config::archive`).
+    #[serde(default)]
+    archive: Option<String>,
+    /// Whether the matching URLs are media pages, handed to `--media-handler` (by
+    /// default, pages on well-known video and audio hosts are).
+    #[serde(default)]
+    media: Option<bool>,
+}
+
+#[derive(Clone, Copy, Debug, Deserialize, PartialEq)]
+#[serde(rename_all = "kebab-case")]
+pub enum RuleAction {
+    /// Never archive the URL.
+    Skip,
+    /// Archive the URL even if it was archived recently or hasn't changed.
+    Force,
+}
+
+/// The combined effect of all the rules matching a URL. Where rules conflict, the
+/// later one wins.
+#[derive(Debug, Default, PartialEq)]
+pub struct RuleOutcome {
+    pub action: Option<RuleAction>,
+    pub cooldown: Option<Duration>,
+    pub every: Option<Duration>,
+    pub tags: Vec<String>,
+    pub archive: Option<String>,
+    pub media: Option<bool>,
+}
+
+impl Rule {
+    pub fn archive(&self) -> Option<&str> {
+        self.archive.as_deref()
+    }
+
+    fn matches(&self, url: &reqwest::Url) -> bool {
+        let matches = |pattern: &Option<Regex>, value: &str| {
+            pattern
+                .as_ref()
+                .is_none_or(|pattern| pattern.is_match(value))
+        };
+        matches(&self.host, url.host_str().unwrap_or_default())
+            && matches(&self.path, url.path())
+            && matches(&self.query, url.query().unwrap_or_default())
+    }
+}
+
+/// Evaluates `rules` against `url`.
+pub fn evaluate(rules: &[Rule], url: &str) -> RuleOutcome {
+    let mut outcome = RuleOutcome::default();
+    let url = match reqwest::Url::parse(&normalize_url(url)) {
+        Ok(url) => url,
+        Err(_) => return outcome,
+    };
+    for rule in rules.iter().filter(|rule| rule.matches(&url)) {
+        outcome.action = rule.action.or(outcome.action);
+        outcome.cooldown = rule.cooldown.or(outcome.cooldown);
+        outcome.every = rule.every.or(outcome.every);
+        outcome.tags.extend(rule.tag.clone());
+        outcome.archive = rule.archive.clone().or(outcome.archive);
+        outcome.media = rule.media.or(outcome.media);
+    }
+    outcome
+}
+
+fn deserialize_glob<'de, D>(deserializer: D) -> Result<Option<Regex>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    let glob = String::deserialize(deserializer)?;
+    let pattern: Vec<String> = glob.split('*').map(regex::escape).collect();
+    Regex::new(&format!("(?i)^{}$", pattern.join(".*")))
+        .map(Some)
+        .map_err(serde::de::Error::custom)
+}
+
+fn deserialize_optional_duration<'de, D>(deserializer: D) -> Result<Option<Duration>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    deserialize_duration(deserializer).map(Some)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::Config;
+
+    fn rules(config: &str) -> Vec<Rule> {
+        Config::parse("test.toml", config.as_bytes()).unwrap().rules
+    }
+
+    #[test]
+    fn applies_matching_rules_in_order() {
+        let rules = rules(
+            r#"
+            [[rules]]
+            host = "*.gov"
+            tag = "government"
+            cooldown = "30s"
+            every = "1y"
+
+            [[rules]]
+            host = "*.gov"
+            path = "/drafts/*"
+            action = "skip"
+
+            [[rules]]
+            query = "*utm_*"
+            action = "force"
+
+            [[rules]]
+            host = "*.pt"
+            archive = "arquivo.pt"
+
+            [[rules]]
+            host = "podcasts.example.com"
+            media = true
+            "#,
+        );
+        assert_eq!(
+            evaluate(&rules, "https://www.example.GOV/drafts/a?x=1"),
+            RuleOutcome {
+                action: Some(RuleAction::Skip),
+                cooldown: Some(Duration::from_secs(30)),
+                every: Some(Duration::from_secs(365 * 24 * 60 * 60)),
+                tags: vec!["government".to_string()],
+                archive: None,
+                media: None,
+            }
+        );
+        assert_eq!(
+            evaluate(&rules, "https://www.example.pt/")
+                .archive
+                .as_deref(),
+            Some("arquivo.pt")
+        );
+        assert_eq!(
+            evaluate(&rules, "https://podcasts.example.com/1").media,
+            Some(true)
+        );
+        assert_eq!(
+            evaluate(&rules, "example.com/?utm_source=feed").action,
+            Some(RuleAction::Force)
+        );
+        assert_eq!(
+            evaluate(&rules, "https://example.com/"),
+            RuleOutcome::default()
+        );
+    }
+
+    #[test]
+    fn rejects_unknown_archives() {
+        let config = |archive: &str| {
+            Config::parse(
+                "test.toml",
+                format!(
+                    "[archives.ukwa]\ntimegate = \"https://www.webarchive.org.uk/wayback/archive/\"\n\n\
+                     [[rules]]\nhost = \"*.uk\"\narchive = \"{}\"\n",
+                    archive
+                )
+                .as_bytes(),
+            )
+        };
+        assert!(config("ukwa").is_ok());
+        assert!(config("arquivo.pt").is_ok());
+        assert!(config("archive.today").is_ok());
+        assert!(config("wayback").is_ok());
+        assert!(config("nope").is_err());
+        // Plugins need a plugin_dir to be found in.
+        assert!(config("plugin:cms").is_err());
+    }
+}
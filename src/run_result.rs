@@ -0,0 +1,103 @@
+//! What came of each URL in a run: the library's result for it, or a skip before it
+//! was archived. Skips are only reported for the run (in its summary and metrics, and
+//! the results that `stream`, `serve` and `lambda` respond with); a URL's saved result
+//! is never replaced by a skip.
+use crate::codes::SkipReason;
+use serde::{Serialize, Serializer};
+use wayback_archiver::ArchivingResult;
+
+#[derive(Clone, Debug, PartialEq, Serialize)]
+#[serde(untagged)]
+pub enum RunResult {
+    Archived(Box<ArchivingResult>),
+    Skipped(Skip),
+}
+
+/// Why a URL was skipped, and the tags the config rules gave it. Written like a
+/// result, with the `skipped` outcome and the skip's `reason` code.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Skip {
+    pub reason: SkipReason,
+    pub tags: Vec<String>,
+}
+
+impl RunResult {
+    /// The URL of the snapshot, if there is one.
+    pub fn snapshot_url(&self) -> Option<&str> {
+        match self {
+            RunResult::Archived(result) => result.outcome.url(),
+            RunResult::Skipped(_) => None,
+        }
+    }
+
+    /// Why archiving failed, if it did.
+    pub fn error(&self) -> Option<&str> {
+        match self {
+            RunResult::Archived(result) => result.outcome.error(),
+            RunResult::Skipped(_) => None,
+        }
+    }
+
+    pub fn tags(&self) -> &[String] {
+        match self {
+            RunResult::Archived(result) => &result.tags,
+            RunResult::Skipped(skip) => &skip.tags,
+        }
+    }
+}
+
+impl From<ArchivingResult> for RunResult {
+    fn from(result: ArchivingResult) -> Self {
+        RunResult::Archived(Box::new(result))
+    }
+}
+
+impl From<Skip> for RunResult {
+    fn from(skip: Skip) -> Self {
+        RunResult::Skipped(skip)
+    }
+}
+
+impl Serialize for Skip {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        #[derive(Serialize)]
+        struct Skipped<'a> {
+            outcome: &'static str,
+            reason: &'static str,
+            #[serde(skip_serializing_if = "<[String]>::is_empty")]
+            tags: &'a [String],
+        }
+
+        Skipped {
+            outcome: "skipped",
+            reason: self.reason.code(),
+            tags: &self.tags,
+        }
+        .serialize(serializer)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn writes_skips_like_results() {
+        let skip = RunResult::from(Skip {
+            reason: SkipReason::Rule,
+            tags: vec!["noise".to_string()],
+        });
+        assert_eq!(
+            serde_json::to_value(&skip).unwrap(),
+            serde_json::json!({"outcome": "skipped", "reason": "rule", "tags": ["noise"]})
+        );
+        let skip = RunResult::from(Skip {
+            reason: SkipReason::Duplicate,
+            tags: Vec::new(),
+        });
+        assert_eq!(
+            serde_json::to_value(&skip).unwrap(),
+            serde_json::json!({"outcome": "skipped", "reason": "duplicate"})
+        );
+    }
+}
@@ -0,0 +1,260 @@
+//! Classification of the responses of the Save Page Now endpoint.
+//!
+//! Requests to `/save/<url>` are answered with a chain of redirects, and what matters is
+//! where that chain ends up: normally at the new snapshot (`/web/<timestamp>/<url>`), but
+//! sometimes at a notice on the save page itself or at the archive.org login page.
+use reqwest::Url;
+use std::net::IpAddr;
+
+#[derive(Debug, PartialEq)]
+pub(crate) enum SaveOutcome {
+    /// The chain ended at the snapshot with this URL.
+    Snapshot(String),
+    /// The chain ended at the URL the snapshot will have, but which doesn't exist yet.
+    PendingSnapshot(String),
+    /// Save Page Now answered with a notice instead of redirecting to a snapshot,
+    /// usually because the URL was already captured very recently.
+    Notice,
+    /// The chain ended at the archive.org login page.
+    LoginRequired,
+    BandwidthExceeded,
+    ServiceUnavailable,
+    UnableToArchive,
+    /// The Wayback Machine's crawler couldn't reach the page's site (for this reason).
+    OriginUnreachable(String),
+    /// The chain ended somewhere unexpected (with this URL).
+    UnexpectedRedirect(String),
+    UnexpectedStatus(u16),
+}
+
+/// Classifies the final response of a save request, given its status code and URL
+/// (after following redirects), and the Save Page Now endpoint it was made to.
+pub(crate) fn classify_save_response(status: u16, final_url: &Url, save: &str) -> SaveOutcome {
+    let is_snapshot = is_snapshot_path(final_url.path());
+    match status {
+        200 if is_snapshot => SaveOutcome::Snapshot(final_url.to_string()),
+        // Sometimes, the snapshot URL returns a 404, even though the archival was successful.
+        // Probably due to a race condition in the Wayback machine; these URLs do (eventually) exist.
+        404 if is_snapshot => SaveOutcome::PendingSnapshot(final_url.to_string()),
+        200 => {
+            let save = Url::parse(save).ok();
+            let save_host = save.as_ref().and_then(|save| save.host_str());
+            let save_path = save.as_ref().map_or("/save", |save| save.path());
+            let host = final_url.host_str().unwrap_or_default();
+            // The login page is on the Save Page Now host's parent domain (archive.org
+            // for web.archive.org).
+            let parent = save_host
+                .filter(|save_host| {
+                    !save_host.starts_with('[') && save_host.parse::<IpAddr>().is_err()
+                })
+                .and_then(|save_host| save_host.split_once('.'))
+                .map(|(_, parent)| parent)
+                .filter(|parent| parent.contains('.'));
+            let is_service = save_host == Some(host)
+                || parent.is_some_and(|parent| {
+                    host == parent || host.ends_with(&format!(".{}", parent))
+                });
+            if is_service && final_url.path().starts_with("/account/login") {
+                SaveOutcome::LoginRequired
+            } else if is_service && final_url.path().starts_with(save_path) {
+                SaveOutcome::Notice
+            } else {
+                SaveOutcome::UnexpectedRedirect(final_url.to_string())
+            }
+        }
+        509 => SaveOutcome::BandwidthExceeded,
+        502..=504 => SaveOutcome::ServiceUnavailable,
+        // Cloudflare-style "origin unreachable" statuses, relayed from the crawler.
+        520 | 523 => SaveOutcome::OriginUnreachable(format!("HTTP {}", status)),
+        // There may be more status codes that indicate archive failure, but this was the most common.
+        403 => SaveOutcome::UnableToArchive,
+        _ => SaveOutcome::UnexpectedStatus(status),
+    }
+}
+
+/// Whether `path` is a snapshot's (`/web/<timestamp>[<modifier>]/...`).
+fn is_snapshot_path(path: &str) -> bool {
+    let timestamp = match path.strip_prefix("/web/") {
+        Some(timestamp) => timestamp,
+        None => return false,
+    };
+    let modifier = timestamp.trim_start_matches(|c: char| c.is_ascii_digit());
+    modifier.len() < timestamp.len()
+        && modifier
+            .trim_start_matches(|c: char| c.is_ascii_lowercase() || c == '_')
+            .starts_with('/')
+}
+
+/// The first Save Page Now error code (`error:` followed by lowercase words joined by
+/// dashes, at the start of a word) in `body`.
+fn spn_error_code(body: &str) -> Option<&str> {
+    const PREFIX: &str = "error:";
+    body.match_indices(PREFIX).find_map(|(start, _)| {
+        let after_word = body[..start]
+            .chars()
+            .next_back()
+            .is_some_and(|c| c.is_alphanumeric() || c == '_');
+        let name = &body[start + PREFIX.len()..];
+        let len = name
+            .find(|c: char| !(c.is_ascii_lowercase() || c == '-'))
+            .unwrap_or(name.len());
+        (!after_word && len > 0).then(|| &body[start..start + PREFIX.len() + len])
+    })
+}
+
+/// Looks for a Save Page Now error code (e.g. `error:no-access`) in the body of an
+/// error response, and classifies it. Unknown codes are ignored.
+pub(crate) fn classify_spn_error(body: &str) -> Option<SaveOutcome> {
+    let code = spn_error_code(body)?;
+    match code {
+        // The site refused the crawler, or couldn't be reached or loaded.
+        "error:no-access"
+        | "error:unauthorized"
+        | "error:network-authentication-required"
+        | "error:cannot-fetch"
+        | "error:invalid-host-resolution"
+        | "error:browsing-timeout"
+        | "error:read-timeout"
+        | "error:bad-gateway"
+        | "error:gateway-timeout"
+        | "error:service-unavailable"
+        | "error:internal-server-error"
+        | "error:invalid-server-response"
+        | "error:protocol-error"
+        | "error:too-many-redirects" => Some(SaveOutcome::OriginUnreachable(code.to_string())),
+        "error:bandwidth-limit-exceeded"
+        | "error:too-many-requests"
+        | "error:user-session-limit" => Some(SaveOutcome::BandwidthExceeded),
+        "error:celery" | "error:soft-time-limit-exceeded" | "error:proxy-error" => {
+            Some(SaveOutcome::ServiceUnavailable)
+        }
+        "error:blocked"
+        | "error:blocked-url"
+        | "error:blocked-client-ip"
+        | "error:filesize-limit"
+        | "error:invalid-url-syntax"
+        | "error:not-found"
+        | "error:too-many-daily-captures" => Some(SaveOutcome::UnableToArchive),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn classify(status: u16, url: &str) -> SaveOutcome {
+        classify_save_response(
+            status,
+            &Url::parse(url).unwrap(),
+            "https://web.archive.org/save",
+        )
+    }
+
+    #[test]
+    fn redirect_to_snapshot() {
+        let url = "https://web.archive.org/web/20210907123456/https://example.com/";
+        assert_eq!(classify(200, url), SaveOutcome::Snapshot(url.into()));
+    }
+
+    #[test]
+    fn redirect_to_snapshot_not_yet_available() {
+        let url = "https://web.archive.org/web/20210907123456/https://example.com/";
+        assert_eq!(classify(404, url), SaveOutcome::PendingSnapshot(url.into()));
+    }
+
+    #[test]
+    fn redirect_to_raw_snapshot() {
+        let url = "https://web.archive.org/web/20210907123456id_/https://example.com/";
+        assert_eq!(classify(200, url), SaveOutcome::Snapshot(url.into()));
+    }
+
+    #[test]
+    fn notice_on_save_page() {
+        assert_eq!(
+            classify(200, "https://web.archive.org/save/https://example.com/"),
+            SaveOutcome::Notice
+        );
+    }
+
+    #[test]
+    fn redirect_to_login() {
+        assert_eq!(
+            classify(
+                200,
+                "https://archive.org/account/login?referer=https%3A%2F%2Fweb.archive.org%2Fsave"
+            ),
+            SaveOutcome::LoginRequired
+        );
+    }
+
+    #[test]
+    fn notices_of_configured_endpoint() {
+        let classify =
+            |url: &str, save: &str| classify_save_response(200, &Url::parse(url).unwrap(), save);
+        let save = "http://127.0.0.1:8080/wayback/save";
+        assert_eq!(
+            classify(
+                "http://127.0.0.1:8080/wayback/save/https://example.com/",
+                save
+            ),
+            SaveOutcome::Notice
+        );
+        let url = "https://web.archive.org/save/https://example.com/";
+        assert_eq!(
+            classify(url, save),
+            SaveOutcome::UnexpectedRedirect(url.into())
+        );
+        assert_eq!(
+            classify(
+                "https://login.example.org/account/login",
+                "https://wayback.example.org/save"
+            ),
+            SaveOutcome::LoginRequired
+        );
+    }
+
+    #[test]
+    fn redirect_elsewhere() {
+        let url = "https://example.com/somewhere-else";
+        assert_eq!(
+            classify(200, url),
+            SaveOutcome::UnexpectedRedirect(url.into())
+        );
+    }
+
+    #[test]
+    fn unexpected_404() {
+        assert_eq!(
+            classify(404, "https://web.archive.org/save/https://example.com/"),
+            SaveOutcome::UnexpectedStatus(404)
+        );
+    }
+
+    #[test]
+    fn error_statuses() {
+        let url = "https://web.archive.org/save/https://example.com/";
+        assert_eq!(classify(509, url), SaveOutcome::BandwidthExceeded);
+        assert_eq!(classify(503, url), SaveOutcome::ServiceUnavailable);
+        assert_eq!(classify(403, url), SaveOutcome::UnableToArchive);
+        assert_eq!(
+            classify(523, url),
+            SaveOutcome::OriginUnreachable("HTTP 523".into())
+        );
+        assert_eq!(classify(418, url), SaveOutcome::UnexpectedStatus(418));
+    }
+
+    #[test]
+    fn spn_error_codes() {
+        assert_eq!(
+            classify_spn_error("<p>Job failed (error:no-access)</p>"),
+            Some(SaveOutcome::OriginUnreachable("error:no-access".into()))
+        );
+        assert_eq!(
+            classify_spn_error("status_ext: error:blocked-url"),
+            Some(SaveOutcome::UnableToArchive)
+        );
+        assert_eq!(classify_spn_error("error:something-new"), None);
+        assert_eq!(classify_spn_error("<html>Not found</html>"), None);
+    }
+}
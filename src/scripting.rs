@@ -0,0 +1,243 @@
+//! Hooks written in [Rhai](https://rhai.rs) (`--script`), for policies too niche to be
+//! flags. A script defines any of these functions, which are called for every URL:
+//!
+//! - `transform(url)` returns the URL to archive instead (e.g. without tracking
+//!   parameters), before anything else is done with it.
+//! - `classify(url)` returns a tag (or an array of tags) to label the URL with, like
+//!   config rules' `tag`.
+//! - `decide(url, tags)` returns `"skip"` or `"force"`, overriding config rules, or
+//!   `()` to leave the decision to them.
+//! - `post_process(url, result)` returns the URL's result (a map, as it's written to
+//!   results files), changed as needed, or `()` to leave it as it is.
+//!
+//! Only available when built with the `scripting` feature. Errors in hooks are
+//! reported, and the hook ignored for that URL. So are hooks that run away (looping
+//! forever, or recursing too deeply), which are stopped.
+use crate::rules::RuleAction;
+use wayback_archiver::ArchivingResult;
+
+#[cfg(feature = "scripting")]
+use rhai::{Dynamic, Engine, Scope, AST};
+
+/// How many operations a hook may run for each URL before it's stopped.
+#[cfg(feature = "scripting")]
+const MAX_OPERATIONS: u64 = 1_000_000;
+/// How deeply a hook's functions may call each other.
+#[cfg(feature = "scripting")]
+const MAX_CALL_LEVELS: usize = 32;
+
+/// An engine that stops scripts that run away, rather than hanging (or crashing) the
+/// run.
+#[cfg(feature = "scripting")]
+fn engine() -> Engine {
+    let mut engine = Engine::new();
+    engine
+        .set_max_operations(MAX_OPERATIONS)
+        .set_max_call_levels(MAX_CALL_LEVELS);
+    engine
+}
+
+#[cfg(feature = "scripting")]
+pub struct Script {
+    engine: Engine,
+    ast: AST,
+}
+
+#[cfg(feature = "scripting")]
+impl Script {
+    pub fn load(path: &str) -> Result<Script, Box<dyn std::error::Error>> {
+        let engine = engine();
+        let ast = engine
+            .compile_file(path.into())
+            .map_err(|err| format!("Unable to load --script {}: {}", path, err))?;
+        Ok(Script { engine, ast })
+    }
+
+    /// Calls the script's `name` function with `args`, if it defines one.
+    fn call(
+        &self,
+        name: &str,
+        args: impl rhai::FuncArgs,
+        arity: usize,
+    ) -> Result<Option<Dynamic>, String> {
+        let defined = self
+            .ast
+            .iter_functions()
+            .any(|function| function.name == name && function.params.len() == arity);
+        if !defined {
+            return Ok(None);
+        }
+        self.engine
+            .call_fn::<Dynamic>(&mut Scope::new(), &self.ast, name, args)
+            .map(|value| Some(value).filter(|value| !value.is_unit()))
+            .map_err(|err| format!("{}: {}", name, err))
+    }
+
+    pub fn transform(&self, url: &str) -> Result<Option<String>, String> {
+        match self.call("transform", (url.to_string(),), 1)? {
+            Some(value) => value
+                .into_string()
+                .map(Some)
+                .map_err(type_error("transform")),
+            None => Ok(None),
+        }
+    }
+
+    pub fn classify(&self, url: &str) -> Result<Vec<String>, String> {
+        let value = match self.call("classify", (url.to_string(),), 1)? {
+            Some(value) => value,
+            None => return Ok(Vec::new()),
+        };
+        if value.is_string() {
+            return value
+                .into_string()
+                .map(|tag| vec![tag])
+                .map_err(type_error("classify"));
+        }
+        value
+            .into_array()
+            .map_err(type_error("classify"))?
+            .into_iter()
+            .map(|tag| tag.into_string().map_err(type_error("classify")))
+            .collect()
+    }
+
+    pub fn decide(&self, url: &str, tags: &[String]) -> Result<Option<RuleAction>, String> {
+        let tags: rhai::Array = tags.iter().cloned().map(Dynamic::from).collect();
+        let decision = match self.call("decide", (url.to_string(), tags), 2)? {
+            Some(value) => value.into_string().map_err(type_error("decide"))?,
+            None => return Ok(None),
+        };
+        match decision.as_str() {
+            "skip" => Ok(Some(RuleAction::Skip)),
+            "force" => Ok(Some(RuleAction::Force)),
+            _ => Err(format!(
+                "decide: expected \"skip\", \"force\", or (), got {:?}",
+                decision
+            )),
+        }
+    }
+
+    pub fn post_process(
+        &self,
+        url: &str,
+        result: &ArchivingResult,
+    ) -> Result<Option<ArchivingResult>, String> {
+        let value = rhai::serde::to_dynamic(result).map_err(|err| err.to_string())?;
+        match self.call("post_process", (url.to_string(), value), 2)? {
+            Some(value) => rhai::serde::from_dynamic(&value)
+                .map(Some)
+                .map_err(|err| format!("post_process: {}", err)),
+            None => Ok(None),
+        }
+    }
+}
+
+#[cfg(feature = "scripting")]
+fn type_error(hook: &'static str) -> impl Fn(&str) -> String {
+    move |actual| format!("{}: unexpected return type {}", hook, actual)
+}
+
+#[cfg(not(feature = "scripting"))]
+pub struct Script;
+
+#[cfg(not(feature = "scripting"))]
+impl Script {
+    pub fn load(_path: &str) -> Result<Script, Box<dyn std::error::Error>> {
+        Err("--script requires wayback-archiver to be built with the scripting feature".into())
+    }
+
+    pub fn transform(&self, _url: &str) -> Result<Option<String>, String> {
+        unreachable!("Script can't be constructed without the scripting feature")
+    }
+
+    pub fn classify(&self, _url: &str) -> Result<Vec<String>, String> {
+        unreachable!("Script can't be constructed without the scripting feature")
+    }
+
+    pub fn decide(&self, _url: &str, _tags: &[String]) -> Result<Option<RuleAction>, String> {
+        unreachable!("Script can't be constructed without the scripting feature")
+    }
+
+    pub fn post_process(
+        &self,
+        _url: &str,
+        _result: &ArchivingResult,
+    ) -> Result<Option<ArchivingResult>, String> {
+        unreachable!("Script can't be constructed without the scripting feature")
+    }
+}
+
+#[cfg(all(test, feature = "scripting"))]
+mod tests {
+    use super::*;
+    use wayback_archiver::Outcome;
+
+    fn script(source: &str) -> Script {
+        let engine = engine();
+        let ast = engine.compile(source).unwrap();
+        Script { engine, ast }
+    }
+
+    #[test]
+    fn calls_hooks() {
+        let script = script(
+            r#"
+            fn transform(url) { url.replace("?utm_source=feed", ""); url }
+            fn classify(url) { if url.contains("/blog/") { ["blog", "weekly"] } else { () } }
+            fn decide(url, tags) { if tags.contains("blog") { "force" } }
+            fn post_process(url, result) { result.tags = ["scripted"]; result }
+            "#,
+        );
+        assert_eq!(
+            script.transform("https://example.com/blog/1?utm_source=feed"),
+            Ok(Some("https://example.com/blog/1".to_string()))
+        );
+        let tags = script.classify("https://example.com/blog/1").unwrap();
+        assert_eq!(tags, ["blog", "weekly"]);
+        assert_eq!(script.classify("https://example.com/"), Ok(Vec::new()));
+        assert_eq!(
+            script.decide("https://example.com/blog/1", &tags),
+            Ok(Some(RuleAction::Force))
+        );
+        assert_eq!(script.decide("https://example.com/", &[]), Ok(None));
+
+        let result = ArchivingResult::new(Outcome::NewCapture {
+            url: "https://web.archive.org/web/20210101000000/https://example.com/".to_string(),
+            ts: "2021-01-01T00:00:00".parse().unwrap(),
+        });
+        let processed = script
+            .post_process("https://example.com/", &result)
+            .unwrap()
+            .unwrap();
+        assert_eq!(
+            processed,
+            ArchivingResult {
+                tags: vec!["scripted".to_string()],
+                ..result
+            }
+        );
+    }
+
+    #[test]
+    fn skips_undefined_hooks_and_reports_errors() {
+        let script = script(r#"fn decide(url, tags) { "maybe" } fn classify(url) { 42 }"#);
+        assert_eq!(script.transform("https://example.com/"), Ok(None));
+        assert!(script.decide("https://example.com/", &[]).is_err());
+        assert!(script.classify("https://example.com/").is_err());
+    }
+
+    #[test]
+    fn stops_runaway_hooks() {
+        let script = script(
+            r#"
+            fn transform(url) { loop {} }
+            fn classify(url) { classify(url) }
+            "#,
+        );
+        let err = script.transform("https://example.com/").unwrap_err();
+        assert!(err.contains("operations"), "{}", err);
+        let err = script.classify("https://example.com/").unwrap_err();
+        assert!(err.contains("Stack overflow"), "{}", err);
+    }
+}
@@ -0,0 +1,154 @@
+//! Lookup of secrets (archive.org keys, webhook secrets, provider tokens).
+//!
+//! Secrets passed as command line arguments leak into shell history and `ps`
+//! output, so they are instead resolved by name from (in order):
+//!
+//! 1. A file named by the `<NAME>_FILE` environment variable. The file must not
+//!    be readable by group or others.
+//! 2. The `<NAME>` environment variable.
+//! 3. The system keyring (service `wayback-archiver`, user `<NAME>`), when built
+//!    with the `keyring` feature.
+use std::env;
+use std::fs;
+use std::path::Path;
+
+#[cfg(feature = "keyring")]
+const KEYRING_SERVICE: &str = "wayback-archiver";
+
+/// Resolves the secret called `name`, returning `None` if no source provides it.
+pub fn resolve_secret(name: &str) -> Result<Option<String>, SecretError> {
+    if let Some(path) = env::var_os(format!("{}_FILE", name)) {
+        return read_secret_file(path).map(Some);
+    }
+    if let Ok(value) = env::var(name) {
+        return Ok(Some(value));
+    }
+    from_keyring(name)
+}
+
+/// Reads a secret from `path`, refusing files with group/other permissions.
+fn read_secret_file<P: AsRef<Path>>(path: P) -> Result<String, SecretError> {
+    let path = path.as_ref();
+    check_permissions(path)?;
+    let contents = fs::read_to_string(path)
+        .map_err(|err| SecretError::Io(format!("{}: {}", path.display(), err)))?;
+    Ok(contents.trim_end_matches(&['\r', '\n'][..]).to_string())
+}
+
+#[cfg(unix)]
+fn check_permissions(path: &Path) -> Result<(), SecretError> {
+    use std::os::unix::fs::PermissionsExt;
+
+    let metadata = fs::metadata(path)
+        .map_err(|err| SecretError::Io(format!("{}: {}", path.display(), err)))?;
+    let mode = metadata.permissions().mode() & 0o777;
+    if mode & 0o077 != 0 {
+        return Err(SecretError::InsecurePermissions {
+            path: path.display().to_string(),
+            mode,
+        });
+    }
+    Ok(())
+}
+
+#[cfg(not(unix))]
+fn check_permissions(_path: &Path) -> Result<(), SecretError> {
+    Ok(())
+}
+
+#[cfg(feature = "keyring")]
+fn from_keyring(name: &str) -> Result<Option<String>, SecretError> {
+    let entry = keyring::Entry::new(KEYRING_SERVICE, name)
+        .map_err(|err| SecretError::Keyring(err.to_string()))?;
+    match entry.get_password() {
+        Ok(secret) => Ok(Some(secret)),
+        Err(keyring::Error::NoEntry) => Ok(None),
+        Err(err) => Err(SecretError::Keyring(err.to_string())),
+    }
+}
+
+#[cfg(not(feature = "keyring"))]
+fn from_keyring(_name: &str) -> Result<Option<String>, SecretError> {
+    Ok(None)
+}
+
+#[derive(Debug, PartialEq)]
+pub enum SecretError {
+    Io(String),
+    InsecurePermissions { path: String, mode: u32 },
+    Keyring(String),
+}
+
+impl std::fmt::Display for SecretError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            SecretError::Io(err) => write!(f, "Unable to read secret: {}", err),
+            SecretError::InsecurePermissions { path, mode } => write!(
+                f,
+                "Secret file {} has permissions {:o}; it must not be accessible by group or others",
+                path, mode
+            ),
+            SecretError::Keyring(err) => write!(f, "Keyring error: {}", err),
+        }
+    }
+}
+
+impl std::error::Error for SecretError {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn resolves_secrets_from_files_then_the_environment() {
+        let dir = std::env::temp_dir().join(format!("secrets-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("key");
+        fs::write(&path, "from-file\r\n").unwrap();
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            fs::set_permissions(&path, fs::Permissions::from_mode(0o600)).unwrap();
+        }
+
+        assert_eq!(resolve_secret("SECRETS_TEST_UNSET"), Ok(None));
+        env::set_var("SECRETS_TEST_KEY", "from-env");
+        assert_eq!(
+            resolve_secret("SECRETS_TEST_KEY"),
+            Ok(Some("from-env".to_string()))
+        );
+        // The file takes precedence, without its trailing newline.
+        env::set_var("SECRETS_TEST_KEY_FILE", &path);
+        assert_eq!(
+            resolve_secret("SECRETS_TEST_KEY"),
+            Ok(Some("from-file".to_string()))
+        );
+
+        env::set_var("SECRETS_TEST_MISSING_FILE", dir.join("missing"));
+        assert!(matches!(
+            resolve_secret("SECRETS_TEST_MISSING"),
+            Err(SecretError::Io(_))
+        ));
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn refuses_files_others_can_read() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let dir = std::env::temp_dir().join(format!("secrets-shared-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("key");
+        fs::write(&path, "secret").unwrap();
+        fs::set_permissions(&path, fs::Permissions::from_mode(0o644)).unwrap();
+        assert_eq!(
+            read_secret_file(&path),
+            Err(SecretError::InsecurePermissions {
+                path: path.display().to_string(),
+                mode: 0o644,
+            })
+        );
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,266 @@
+//! `wayback-archiver seed`, which enumerates the historical URLs of a site from the
+//! Wayback CDX index (and optionally Common Crawl's), and writes those without a recent
+//! capture, one per line, to be archived. It's meant for rescuing a site that's
+//! announced it's shutting down:
+//!
+//! ```text
+//! wayback-archiver seed --from-cdx dead-site.com --status 200 --mimetype text/html \
+//!     | wayback-archiver --out results.json
+//! ```
+use crate::config::{format_duration, parse_duration};
+use chrono::Utc;
+use clap::Clap;
+use futures::TryStreamExt;
+use serde::Deserialize;
+use std::collections::HashSet;
+use std::io::Write;
+use std::path::PathBuf;
+use std::time::Duration;
+use wayback_archiver::{
+    normalize_url, ArchiveOptions, CdxFilter, WaybackClient, DEFAULT_USER_AGENT,
+};
+
+/// Common Crawl's index server, which lists its crawls in `collinfo.json`.
+const COMMON_CRAWL_INDEX: &str = "https://index.commoncrawl.org";
+
+#[derive(Clap)]
+pub struct SeedOpts {
+    /// The site (domain) to enumerate the URLs of, including its subdomains.
+    #[clap(long)]
+    from_cdx: String,
+    /// Only URLs that were captured with this HTTP status (e.g. 200).
+    #[clap(long)]
+    status: Option<u16>,
+    /// Only URLs that were captured with this MIME type (e.g. text/html).
+    #[clap(long)]
+    mimetype: Option<String>,
+    /// URLs captured more recently than this (e.g. "30d") are left out.
+    /// Defaults to the archiver's maximum snapshot age, 90 days.
+    #[clap(long, parse(try_from_str = parse_duration))]
+    max_age: Option<Duration>,
+    /// Also enumerates the site's URLs in a Common Crawl index, to find pages
+    /// the Wayback Machine never captured.
+    #[clap(long)]
+    common_crawl: bool,
+    /// The Common Crawl crawl to look URLs up in (e.g. "CC-MAIN-2024-33").
+    /// Defaults to the latest.
+    #[clap(long)]
+    crawl: Option<String>,
+    /// Writes the URLs to this file, rather than stdout.
+    #[clap(long)]
+    out: Option<PathBuf>,
+}
+
+/// A crawl in Common Crawl's `collinfo.json`.
+#[derive(Deserialize)]
+struct Crawl {
+    id: String,
+    #[serde(rename = "cdx-api")]
+    cdx_api: String,
+}
+
+#[derive(Deserialize)]
+struct CrawlPages {
+    pages: usize,
+}
+
+/// A capture in a Common Crawl index, as it's listed with `output=json`.
+#[derive(Deserialize)]
+struct CrawlRecord {
+    url: String,
+    #[serde(default)]
+    status: Option<String>,
+    #[serde(default)]
+    mime: Option<String>,
+}
+
+pub async fn run(opts: &SeedOpts, client: WaybackClient) -> Result<(), Box<dyn std::error::Error>> {
+    let filter = CdxFilter {
+        status: opts.status,
+        mimetype: opts.mimetype.clone(),
+        since: None,
+    };
+    let max_age = match opts.max_age {
+        Some(max_age) => chrono::Duration::from_std(max_age)?,
+        None => ArchiveOptions::default().max_snapshot_age,
+    };
+    let list_error = |err| format!("Unable to list captures of {}: {}", opts.from_cdx, err);
+    // The index lists each URL once (rather than every capture of it), so the URLs
+    // captured recently are listed first, and then the rest are written as they come.
+    eprintln!("Listing recent captures of {} ...", opts.from_cdx);
+    let recent = CdxFilter {
+        since: Some(Utc::now() - max_age),
+        ..filter.clone()
+    };
+    let mut recent_urls = Box::pin(client.list_site_urls(&opts.from_cdx, &recent));
+    let mut seen = HashSet::new();
+    while let Some(page) = recent_urls.try_next().await.map_err(list_error)? {
+        seen.extend(page.iter().map(|record| url_key(&record.original)));
+    }
+
+    let mut out: Box<dyn Write> = match &opts.out {
+        Some(path) => Box::new(std::fs::File::create(path)?),
+        None => Box::new(std::io::stdout()),
+    };
+    let mut missing = 0;
+    let mut write_missing = |url: &str| -> std::io::Result<()> {
+        missing += 1;
+        writeln!(out, "{}", url)
+    };
+    eprintln!("Listing captures of {} ...", opts.from_cdx);
+    let mut urls = Box::pin(client.list_site_urls(&opts.from_cdx, &filter));
+    while let Some(page) = urls.try_next().await.map_err(list_error)? {
+        for record in page {
+            if seen.insert(url_key(&record.original)) {
+                write_missing(&record.original)?;
+            }
+        }
+    }
+    let archived = seen.len();
+    if opts.common_crawl {
+        for url in common_crawl_urls(opts, &filter).await? {
+            if seen.insert(url_key(&url)) {
+                write_missing(&url)?;
+            }
+        }
+    }
+    out.flush()?;
+    eprintln!(
+        "{} of {} URLs ({} in the Wayback Machine) have no capture from the last {}",
+        missing,
+        seen.len(),
+        archived,
+        format_duration(max_age.to_std()?)
+    );
+    Ok(())
+}
+
+/// `url`, keyed so that spellings of it (`http` and `https`, or with and without
+/// `www.`) are counted once.
+fn url_key(url: &str) -> String {
+    let url = normalize_url(url);
+    let url = url
+        .strip_prefix("https://")
+        .or_else(|| url.strip_prefix("http://"))
+        .unwrap_or(&url);
+    url.strip_prefix("www.").unwrap_or(url).to_string()
+}
+
+/// The URLs on the site in a Common Crawl index that match `filter`.
+async fn common_crawl_urls(
+    opts: &SeedOpts,
+    filter: &CdxFilter,
+) -> Result<Vec<String>, Box<dyn std::error::Error>> {
+    let http = reqwest::Client::builder()
+        .user_agent(DEFAULT_USER_AGENT)
+        .build()?;
+    let crawls: Vec<Crawl> = http
+        .get(format!("{}/collinfo.json", COMMON_CRAWL_INDEX))
+        .send()
+        .await?
+        .error_for_status()?
+        .json()
+        .await?;
+    // Crawls are listed newest first.
+    let crawl = match &opts.crawl {
+        Some(id) => crawls
+            .into_iter()
+            .find(|crawl| &crawl.id == id)
+            .ok_or_else(|| format!("No Common Crawl crawl named {}", id))?,
+        None => crawls
+            .into_iter()
+            .next()
+            .ok_or("Common Crawl lists no crawls")?,
+    };
+    eprintln!("Listing captures of {} in {} ...", opts.from_cdx, crawl.id);
+    let query = [
+        ("url", opts.from_cdx.as_str()),
+        ("matchType", "domain"),
+        ("output", "json"),
+    ];
+    let pages: CrawlPages = http
+        .get(&crawl.cdx_api)
+        .query(&query)
+        .query(&[("showNumPages", "true")])
+        .send()
+        .await?
+        .error_for_status()?
+        .json()
+        .await?;
+    let mut urls = Vec::new();
+    for page in 0..pages.pages {
+        let resp = http
+            .get(&crawl.cdx_api)
+            .query(&query)
+            .query(&[("page", page)])
+            .send()
+            .await?;
+        // A page with no captures is reported as not found.
+        if resp.status() == reqwest::StatusCode::NOT_FOUND {
+            continue;
+        }
+        let body = resp.error_for_status()?.text().await?;
+        urls.extend(parse_crawl_records(&body, filter)?);
+    }
+    Ok(urls)
+}
+
+/// The URLs of the records (JSON lines) in a page of a Common Crawl index that match
+/// `filter`.
+fn parse_crawl_records(body: &str, filter: &CdxFilter) -> Result<Vec<String>, serde_json::Error> {
+    let mut urls = Vec::new();
+    for line in body.lines().filter(|line| !line.trim().is_empty()) {
+        let record: CrawlRecord = serde_json::from_str(line)?;
+        let status_matches = filter
+            .status
+            .is_none_or(|status| record.status.as_deref() == Some(status.to_string().as_str()));
+        let mimetype_matches = filter
+            .mimetype
+            .as_ref()
+            .is_none_or(|mimetype| record.mime.as_ref() == Some(mimetype));
+        if status_matches && mimetype_matches {
+            urls.push(record.url);
+        }
+    }
+    Ok(urls)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn keys_spellings_of_a_url_alike() {
+        assert_eq!(
+            url_key("https://www.dead-site.com/a"),
+            url_key("http://dead-site.com/a")
+        );
+        assert_ne!(
+            url_key("http://dead-site.com/a"),
+            url_key("http://dead-site.com/b")
+        );
+    }
+
+    #[test]
+    fn filters_common_crawl_records() {
+        let body = r#"{"url": "https://dead-site.com/", "status": "200", "mime": "text/html"}
+{"url": "https://dead-site.com/logo.png", "status": "200", "mime": "image/png"}
+{"url": "https://dead-site.com/old", "status": "404", "mime": "text/html"}
+"#;
+        let filter = CdxFilter {
+            status: Some(200),
+            mimetype: Some("text/html".to_string()),
+            since: None,
+        };
+        assert_eq!(
+            parse_crawl_records(body, &filter).unwrap(),
+            ["https://dead-site.com/"]
+        );
+        assert_eq!(
+            parse_crawl_records(body, &CdxFilter::default())
+                .unwrap()
+                .len(),
+            3
+        );
+    }
+}
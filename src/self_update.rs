@@ -0,0 +1,224 @@
+//! `wayback-archiver self-update`, which replaces the running binary with the latest
+//! GitHub release, for machines without cargo. Only available when built with the
+//! `self-update` feature.
+//!
+//! Releases are expected to have an asset per platform named
+//! `wayback-archiver-<arch>-<os>` (e.g. `wayback-archiver-x86_64-linux`), along with
+//! a `<asset>.sha256` checksum file and a `<asset>.minisig` minisign signature. Both
+//! are verified before the binary is replaced, the signature against the release
+//! public key embedded in the binary when it was built (from the
+//! `WAYBACK_ARCHIVER_RELEASE_KEY` environment variable: the base64 line of the key
+//! file). Builds without one can't self-update.
+use clap::Clap;
+
+#[derive(Clap)]
+pub struct SelfUpdateOpts {
+    /// If set, only reports whether a newer release is available.
+    #[clap(long)]
+    check: bool,
+}
+
+#[cfg(feature = "self-update")]
+const LATEST_RELEASE_URL: &str =
+    "https://api.github.com/repos/bcongdon/wayback-archiver/releases/latest";
+
+/// The public key that releases are signed with.
+#[cfg(feature = "self-update")]
+const RELEASE_PUBLIC_KEY: Option<&str> = option_env!("WAYBACK_ARCHIVER_RELEASE_KEY");
+
+#[cfg(feature = "self-update")]
+#[derive(serde::Deserialize)]
+struct Release {
+    tag_name: String,
+    assets: Vec<Asset>,
+}
+
+#[cfg(feature = "self-update")]
+#[derive(serde::Deserialize)]
+struct Asset {
+    name: String,
+    browser_download_url: String,
+}
+
+#[cfg(feature = "self-update")]
+pub async fn run(opts: &SelfUpdateOpts) -> Result<(), Box<dyn std::error::Error>> {
+    let current = env!("CARGO_PKG_VERSION");
+    // The GitHub API rejects requests without a user agent.
+    let client = reqwest::Client::builder()
+        .user_agent(concat!("wayback-archiver/", env!("CARGO_PKG_VERSION")))
+        .build()?;
+    let release: Release = client
+        .get(LATEST_RELEASE_URL)
+        .send()
+        .await?
+        .error_for_status()?
+        .json()
+        .await?;
+    let latest = release.tag_name.trim_start_matches('v');
+    if !is_newer(latest, current)? {
+        eprintln!("wayback-archiver {} is up to date", current);
+        return Ok(());
+    }
+    if opts.check {
+        eprintln!(
+            "wayback-archiver {} is available (installed: {})",
+            latest, current
+        );
+        return Ok(());
+    }
+    let public_key = RELEASE_PUBLIC_KEY
+        .ok_or("this build has no release public key to verify updates with; update it manually")?;
+
+    let name = format!(
+        "wayback-archiver-{}-{}",
+        std::env::consts::ARCH,
+        std::env::consts::OS
+    );
+    let asset = |name: &str| {
+        release
+            .assets
+            .iter()
+            .find(|asset| asset.name == name)
+            .ok_or_else(|| format!("release {} has no {} asset", release.tag_name, name))
+    };
+    let binary = asset(&name)?;
+    let checksum = asset(&format!("{}.sha256", name))?;
+    let signature = asset(&format!("{}.minisig", name))?;
+
+    eprintln!("Downloading {} ...", binary.browser_download_url);
+    let bytes = client
+        .get(&binary.browser_download_url)
+        .send()
+        .await?
+        .error_for_status()?
+        .bytes()
+        .await?;
+    let text = |asset: &Asset| {
+        let request = client.get(&asset.browser_download_url);
+        async move { request.send().await?.error_for_status()?.text().await }
+    };
+    let checksum = text(checksum).await?;
+    let signature = text(signature).await?;
+    verify_asset(&name, &bytes, &checksum, &signature, public_key)?;
+
+    replace_current_exe(&bytes)?;
+    eprintln!("Updated wayback-archiver {} to {}", current, latest);
+    Ok(())
+}
+
+/// Whether `version` is newer than `current`, by semver precedence (so pre-releases
+/// like `1.2.0-rc.1` are older than `1.2.0`).
+#[cfg(feature = "self-update")]
+fn is_newer(version: &str, current: &str) -> Result<bool, String> {
+    let parse = |version: &str| {
+        semver::Version::parse(version)
+            .map_err(|err| format!("invalid version {}: {}", version, err))
+    };
+    Ok(parse(version)? > parse(current)?)
+}
+
+/// Checks the downloaded asset `name` against its `checksum` file (formatted like
+/// `sha256sum`'s output: the hash, then the name) and its minisign `signature` by
+/// `public_key` (base64).
+#[cfg(feature = "self-update")]
+fn verify_asset(
+    name: &str,
+    contents: &[u8],
+    checksum: &str,
+    signature: &str,
+    public_key: &str,
+) -> Result<(), String> {
+    use sha2::{Digest, Sha256};
+
+    let expected = checksum
+        .split_whitespace()
+        .next()
+        .unwrap_or_default()
+        .to_ascii_lowercase();
+    let actual: String = Sha256::digest(contents)
+        .iter()
+        .map(|byte| format!("{:02x}", byte))
+        .collect();
+    if actual != expected {
+        return Err(format!(
+            "checksum mismatch for {}: expected {}, got {}",
+            name, expected, actual
+        ));
+    }
+    let public_key = minisign::PublicKey::from_base64(public_key)
+        .map_err(|err| format!("invalid release public key: {}", err))?;
+    crate::signing::verify_bytes(&public_key, signature, contents)
+        .map_err(|err| format!("{} is NOT validly signed: {}", name, err))?;
+    Ok(())
+}
+
+/// Replaces the running binary with `contents`. The new binary is written alongside
+/// it and renamed into place, so an interrupted update leaves the old one working.
+#[cfg(feature = "self-update")]
+fn replace_current_exe(contents: &[u8]) -> std::io::Result<()> {
+    use std::fs;
+
+    let exe = std::env::current_exe()?;
+    let update = exe.with_extension("update");
+    fs::write(&update, contents)?;
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        fs::set_permissions(&update, fs::Permissions::from_mode(0o755))?;
+    }
+    // Windows can't replace a running binary, but it can rename it out of the way.
+    #[cfg(windows)]
+    fs::rename(&exe, exe.with_extension("old"))?;
+    fs::rename(&update, &exe)
+}
+
+#[cfg(not(feature = "self-update"))]
+pub async fn run(_opts: &SelfUpdateOpts) -> Result<(), Box<dyn std::error::Error>> {
+    Err("self-update requires wayback-archiver to be built with the self-update feature".into())
+}
+
+#[cfg(all(test, feature = "self-update"))]
+mod tests {
+    use super::*;
+    use crate::signing::sign_bytes;
+    use minisign::KeyPair;
+    use sha2::{Digest, Sha256};
+
+    #[test]
+    fn compares_versions() {
+        assert_eq!(is_newer("0.2.0", "0.1.1"), Ok(true));
+        assert_eq!(is_newer("0.1.10", "0.1.9"), Ok(true));
+        assert_eq!(is_newer("0.1.1", "0.1.1"), Ok(false));
+        assert_eq!(is_newer("0.2.0-rc.1", "0.2.0"), Ok(false));
+        assert_eq!(is_newer("0.2.0", "0.2.0-rc.1"), Ok(true));
+        assert_eq!(is_newer("0.2.0-rc.2", "0.2.0-rc.1"), Ok(true));
+        assert!(is_newer("latest", "0.1.1").is_err());
+    }
+
+    #[test]
+    fn rejects_tampered_assets() {
+        let KeyPair { pk, sk } = KeyPair::generate_unencrypted_keypair().unwrap();
+        let public_key = pk.to_base64();
+        let contents = b"\x7fELF binary";
+        let checksum: String = Sha256::digest(contents)
+            .iter()
+            .map(|byte| format!("{:02x}", byte))
+            .collect();
+        let checksum = format!("{}  wayback-archiver-x86_64-linux\n", checksum);
+        let signature = sign_bytes(&sk, contents, "file:wayback-archiver-x86_64-linux").unwrap();
+        let verify = |contents: &[u8], checksum: &str, signature: &str| {
+            verify_asset("asset", contents, checksum, signature, &public_key)
+        };
+        assert_eq!(verify(contents, &checksum, &signature), Ok(()));
+
+        let err = verify(b"tampered", &checksum, &signature).unwrap_err();
+        assert!(err.starts_with("checksum mismatch"), "{}", err);
+
+        // A matching checksum (e.g. also replaced by an attacker) isn't enough.
+        let other = KeyPair::generate_unencrypted_keypair().unwrap();
+        let forged = sign_bytes(&other.sk, contents, "file:asset").unwrap();
+        let err = verify(contents, &checksum, &forged).unwrap_err();
+        assert!(err.contains("NOT validly signed"), "{}", err);
+        assert!(verify(contents, &checksum, "not a signature").is_err());
+    }
+}
@@ -0,0 +1,499 @@
+//! `wayback-archiver serve`, a small HTTP server that archives URLs on request, with
+//! these endpoints:
+//!
+//! - `POST /github`: GitHub push webhooks, whose newly added links are archived (see
+//!   `github`).
+//! - `GET /bookmarklet?url=<url>&token=<token>`: archives `url` and responds with a
+//!   page that redirects to its snapshot, for a bookmarklet to open. The token must
+//!   match `BOOKMARKLET_TOKEN` (see `wayback_archiver::secrets`). Only public http(s)
+//!   URLs are accepted: excluded ones, and those whose host resolves to a local
+//!   network address, are refused.
+//!
+//! URLs go through the same checks as a run's (see `prearchive`), and are archived
+//! one at a time by a single worker, in the order they arrive, so that bursts of
+//! requests don't flood Save Page Now. At most `QUEUE_CAPACITY` URLs wait at once;
+//! bookmarklet requests are refused while the queue is full, and if a URL isn't
+//! archived within `BOOKMARKLET_WAIT`, the page says it's still queued; GitHub pushes
+//! are refused too, to be redelivered later.
+//!
+//! At most `MAX_CONNECTIONS` requests are handled at once, and each must be sent
+//! within `SOCKET_TIMEOUT`, with a body of at most `MAX_BODY`. Each result is logged, and
+//! checked against the alert thresholds (see `alerts`).
+use crate::alerts::{AlertOpts, Monitor};
+use crate::codes::SkipReason;
+use crate::config::Config;
+use crate::display::display_url;
+use crate::exclusions::{is_internal_address, Exclusions};
+use crate::github::{verify_signature, PushEvent};
+use crate::run_result::RunResult;
+use clap::Clap;
+use std::collections::HashMap;
+use std::sync::Arc;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::{TcpListener, TcpStream};
+use tokio::sync::{mpsc, oneshot, Semaphore};
+use wayback_archiver::secrets::resolve_secret;
+use wayback_archiver::WaybackClient;
+
+/// A URL to archive, and where to send its result, if anywhere.
+type Job = (String, Option<oneshot::Sender<RunResult>>);
+
+/// How many URLs can wait to be archived.
+const QUEUE_CAPACITY: usize = 1000;
+
+/// How long a bookmarklet request waits for its URL to be archived.
+const BOOKMARKLET_WAIT: std::time::Duration = std::time::Duration::from_secs(60);
+
+/// The largest request accepted. GitHub caps webhook payloads at 25MB, but push
+/// payloads are rarely more than a few KB.
+const MAX_BODY: usize = 1024 * 1024;
+
+/// How many connections are handled at once. Others wait to be accepted.
+const MAX_CONNECTIONS: usize = 64;
+
+/// How long a client has to send its request, and to read the response.
+const SOCKET_TIMEOUT: std::time::Duration = std::time::Duration::from_secs(30);
+
+#[derive(Clap)]
+pub struct ServeOpts {
+    /// The address to listen on.
+    #[clap(long, default_value = "127.0.0.1:8080")]
+    listen: String,
+    #[clap(flatten)]
+    alerts: AlertOpts,
+}
+
+struct Server {
+    /// URLs waiting to be archived by the worker.
+    queue: mpsc::Sender<Job>,
+    exclusions: Arc<Exclusions>,
+    http: reqwest::Client,
+    github_secret: Option<String>,
+    github_token: Option<String>,
+    bookmarklet_token: Option<String>,
+}
+
+struct Request {
+    method: String,
+    target: String,
+    /// Headers, with lowercase names.
+    headers: HashMap<String, String>,
+    body: Vec<u8>,
+}
+
+struct Response {
+    status: &'static str,
+    content_type: &'static str,
+    body: String,
+}
+
+impl Response {
+    fn text(status: &'static str, body: &str) -> Self {
+        Response {
+            status,
+            content_type: "text/plain",
+            body: body.to_string(),
+        }
+    }
+
+    fn html(status: &'static str, body: String) -> Self {
+        Response {
+            status,
+            content_type: "text/html; charset=utf-8",
+            body,
+        }
+    }
+}
+
+pub async fn run(
+    opts: &ServeOpts,
+    client: WaybackClient,
+    config: Config,
+    exclusions: Exclusions,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let github_secret = resolve_secret("GITHUB_WEBHOOK_SECRET")?;
+    if github_secret.is_none() {
+        eprintln!("GITHUB_WEBHOOK_SECRET isn't set, so GitHub webhooks will be rejected");
+    }
+    let bookmarklet_token = resolve_secret("BOOKMARKLET_TOKEN")?;
+    if bookmarklet_token.is_none() {
+        eprintln!("BOOKMARKLET_TOKEN isn't set, so bookmarklet requests will be rejected");
+    }
+    let (queue, mut jobs) = mpsc::channel::<Job>(QUEUE_CAPACITY);
+    let mut monitor = Monitor::new(&opts.alerts);
+    let exclusions = Arc::new(exclusions);
+    let server = Arc::new(Server {
+        queue,
+        exclusions: exclusions.clone(),
+        http: reqwest::Client::new(),
+        github_secret,
+        github_token: resolve_secret("GITHUB_TOKEN")?,
+        bookmarklet_token,
+    });
+    tokio::spawn(async move {
+        while let Some((url, reply)) = jobs.recv().await {
+            let started = std::time::Instant::now();
+            let result =
+                crate::prearchive::check_and_archive(&client, &config, &exclusions, &url).await;
+            monitor
+                .record(started.elapsed(), result.error().is_some())
+                .await;
+            match (result.snapshot_url(), result.error()) {
+                (Some(snapshot_url), _) => eprintln!("{} -> {}", url, snapshot_url),
+                (_, Some(error)) => eprintln!("{} failed: {}", url, error),
+                _ => eprintln!("{} skipped", url),
+            }
+            if let Some(reply) = reply {
+                // The requester may have given up waiting.
+                let _ = reply.send(result);
+            }
+        }
+    });
+
+    let listener = TcpListener::bind(&opts.listen).await?;
+    eprintln!("Listening on {}", listener.local_addr()?);
+    let connections = Arc::new(Semaphore::new(MAX_CONNECTIONS));
+    loop {
+        let permit = connections.clone().acquire_owned().await?;
+        let (stream, _) = listener.accept().await?;
+        let server = server.clone();
+        tokio::spawn(async move {
+            if let Err(err) = handle_connection(stream, &server).await {
+                eprintln!("Error handling request: {}", err);
+            }
+            drop(permit);
+        });
+    }
+}
+
+async fn handle_connection(mut stream: TcpStream, server: &Arc<Server>) -> std::io::Result<()> {
+    let response = match tokio::time::timeout(SOCKET_TIMEOUT, read_request(&mut stream)).await {
+        Ok(Ok(Some(request))) => respond(server, request).await,
+        Ok(Ok(None)) => Response::text("400 Bad Request", "Malformed request"),
+        Ok(Err(err)) => return Err(err),
+        Err(_) => Response::text("408 Request Timeout", "Request timed out"),
+    };
+    let written = format!(
+        "HTTP/1.1 {}\r\nContent-Type: {}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+        response.status,
+        response.content_type,
+        response.body.len(),
+        response.body
+    );
+    let writing = async {
+        stream.write_all(written.as_bytes()).await?;
+        stream.shutdown().await
+    };
+    tokio::time::timeout(SOCKET_TIMEOUT, writing)
+        .await
+        .unwrap_or_else(|_| {
+            Err(std::io::Error::new(
+                std::io::ErrorKind::TimedOut,
+                "timed out writing the response",
+            ))
+        })
+}
+
+/// Reads a request from `stream`, returning `None` if it's malformed or too large.
+async fn read_request(stream: &mut TcpStream) -> std::io::Result<Option<Request>> {
+    let mut buffer = Vec::new();
+    let mut chunk = [0; 8192];
+    let header_end = loop {
+        if let Some(end) = buffer.windows(4).position(|window| window == b"\r\n\r\n") {
+            break end;
+        }
+        let read = stream.read(&mut chunk).await?;
+        if read == 0 || buffer.len() > MAX_BODY {
+            return Ok(None);
+        }
+        buffer.extend_from_slice(&chunk[..read]);
+    };
+    let head = String::from_utf8_lossy(&buffer[..header_end]).to_string();
+    let mut lines = head.split("\r\n");
+    let mut request_line = lines.next().unwrap_or_default().split_whitespace();
+    let (method, target) = match (request_line.next(), request_line.next()) {
+        (Some(method), Some(target)) => (method.to_string(), target.to_string()),
+        _ => return Ok(None),
+    };
+    let headers: HashMap<String, String> = lines
+        .filter_map(|line| line.split_once(':'))
+        .map(|(name, value)| (name.trim().to_ascii_lowercase(), value.trim().to_string()))
+        .collect();
+    let content_length = match headers.get("content-length").map(|length| length.parse()) {
+        Some(Ok(length)) if length <= MAX_BODY => length,
+        Some(_) => return Ok(None),
+        None => 0,
+    };
+    let mut body = buffer.split_off(header_end + 4);
+    while body.len() < content_length {
+        let read = stream.read(&mut chunk).await?;
+        if read == 0 {
+            return Ok(None);
+        }
+        body.extend_from_slice(&chunk[..read]);
+    }
+    body.truncate(content_length);
+    Ok(Some(Request {
+        method,
+        target,
+        headers,
+        body,
+    }))
+}
+
+async fn respond(server: &Arc<Server>, request: Request) -> Response {
+    let (path, query) = request
+        .target
+        .split_once('?')
+        .unwrap_or((request.target.as_str(), ""));
+    match (request.method.as_str(), path) {
+        ("POST", "/github") => github_webhook(server, request),
+        ("GET", "/bookmarklet") => bookmarklet(server, query).await,
+        _ => Response::text("404 Not Found", "Not found"),
+    }
+}
+
+async fn bookmarklet(server: &Server, query: &str) -> Response {
+    let params: HashMap<String, String> =
+        reqwest::Url::parse(&format!("http://localhost/?{}", query))
+            .map(|url| url.query_pairs().into_owned().collect())
+            .unwrap_or_default();
+    let authorized = match (&server.bookmarklet_token, params.get("token")) {
+        (Some(expected), Some(token)) => constant_time_eq(expected.as_bytes(), token.as_bytes()),
+        _ => false,
+    };
+    if !authorized {
+        return Response::text("403 Forbidden", "Invalid token");
+    }
+    let url = match params.get("url") {
+        Some(url) if !url.is_empty() => url,
+        _ => return Response::text("400 Bad Request", "Missing url"),
+    };
+    let parsed = match reqwest::Url::parse(url) {
+        Ok(parsed) if matches!(parsed.scheme(), "http" | "https") => parsed,
+        _ => return Response::text("400 Bad Request", "Only http(s) URLs can be archived"),
+    };
+    if let Some(reason) = crate::prearchive::excluded(&server.exclusions, url) {
+        return Response::text(
+            "403 Forbidden",
+            &format!("Not archived ({})", reason.code()),
+        );
+    }
+    if resolves_internally(&parsed).await {
+        return Response::text(
+            "403 Forbidden",
+            &format!("Not archived ({})", SkipReason::InternalHost.code()),
+        );
+    }
+    let (reply, result) = oneshot::channel();
+    match server.queue.try_send((url.clone(), Some(reply))) {
+        Ok(()) => {}
+        Err(mpsc::error::TrySendError::Full(_)) => {
+            return Response::text(
+                "503 Service Unavailable",
+                "Too many URLs are waiting to be archived; try again later",
+            )
+        }
+        Err(mpsc::error::TrySendError::Closed(_)) => {
+            return Response::text("503 Service Unavailable", "Archiving worker stopped")
+        }
+    }
+    let error = match tokio::time::timeout(BOOKMARKLET_WAIT, result).await {
+        Err(_) => {
+            return Response::html(
+                "202 Accepted",
+                format!(
+                    "<!DOCTYPE html>\n<html><head><title>Queued</title></head>\
+                     <body>{} is queued, and will be archived shortly</body></html>\n",
+                    escape_html(&display_url(url))
+                ),
+            )
+        }
+        Ok(Ok(result)) => match (result.snapshot_url(), result.error()) {
+            (Some(snapshot_url), _) => {
+                let snapshot_url = escape_html(snapshot_url);
+                return Response::html(
+                    "200 OK",
+                    format!(
+                        "<!DOCTYPE html>\n<html><head><meta http-equiv=\"refresh\" content=\"0; url={0}\">\
+                         <title>Archived</title></head>\
+                         <body>Archived as <a href=\"{0}\">{0}</a></body></html>\n",
+                        snapshot_url
+                    ),
+                );
+            }
+            (_, Some(error)) => error.to_string(),
+            _ => "No snapshot was made".to_string(),
+        },
+        Ok(Err(_)) => "Archiving worker stopped".to_string(),
+    };
+    Response::html(
+        "502 Bad Gateway",
+        format!(
+            "<!DOCTYPE html>\n<html><head><title>Unable to archive</title></head>\
+             <body>Unable to archive {}: {}</body></html>\n",
+            escape_html(&display_url(url)),
+            escape_html(&error)
+        ),
+    )
+}
+
+fn github_webhook(server: &Arc<Server>, request: Request) -> Response {
+    let secret = match &server.github_secret {
+        Some(secret) => secret,
+        None => return Response::text("403 Forbidden", "GitHub webhooks aren't configured"),
+    };
+    let signature = request.headers.get("x-hub-signature-256");
+    if !verify_signature(secret, &request.body, signature.map(String::as_str)) {
+        return Response::text("401 Unauthorized", "Invalid signature");
+    }
+    match request.headers.get("x-github-event").map(String::as_str) {
+        Some("push") => {}
+        Some("ping") => return Response::text("200 OK", "pong"),
+        _ => return Response::text("200 OK", "Ignored"),
+    }
+    let event: PushEvent = match serde_json::from_slice(&request.body) {
+        Ok(event) => event,
+        Err(err) => return Response::text("400 Bad Request", &err.to_string()),
+    };
+    if !event.is_to_default_branch() {
+        return Response::text("200 OK", "Ignored (not the default branch)");
+    }
+    // Refused deliveries can be redelivered from GitHub once the queue has drained.
+    if server.queue.capacity() == 0 {
+        return Response::text(
+            "503 Service Unavailable",
+            "Too many URLs are waiting to be archived; try again later",
+        );
+    }
+    // GitHub gives up on deliveries after 10 seconds, so the files are fetched (and
+    // their links archived) after responding.
+    let server = server.clone();
+    tokio::spawn(async move {
+        match event
+            .added_links(&server.http, server.github_token.as_deref())
+            .await
+        {
+            Ok(links) => {
+                eprintln!("GitHub push added {} links", links.len());
+                for link in links {
+                    // Waits for room in the queue, rather than dropping links.
+                    let _ = server.queue.send((link, None)).await;
+                }
+            }
+            Err(err) => eprintln!("Unable to read GitHub push: {}", err),
+        }
+    });
+    Response::text("202 Accepted", "Accepted")
+}
+
+/// Whether `url`'s host resolves to a local network address, so that the bookmarklet
+/// can't be used to reach internal services through hostnames. Hosts that don't
+/// resolve are left to fail when they're archived.
+async fn resolves_internally(url: &reqwest::Url) -> bool {
+    let (host, port) = match (url.host_str(), url.port_or_known_default()) {
+        (Some(host), Some(port)) => (host.trim_start_matches('[').trim_end_matches(']'), port),
+        _ => return false,
+    };
+    match tokio::net::lookup_host((host, port)).await {
+        Ok(mut addresses) => addresses.any(|address| is_internal_address(address.ip())),
+        Err(_) => false,
+    }
+}
+
+/// Compares secrets in constant time, so that they can't be guessed byte by byte.
+pub fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    a.len() == b.len() && a.iter().zip(b).fold(0, |diff, (a, b)| diff | (a ^ b)) == 0
+}
+
+fn escape_html(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            '\'' => escaped.push_str("&#39;"),
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use wayback_archiver::{ArchivingResult, Outcome};
+
+    fn server(capacity: usize) -> (Server, mpsc::Receiver<Job>) {
+        let path = std::env::temp_dir().join(format!("serve-exclusions-{}", std::process::id()));
+        std::fs::write(&path, "opted-out.example\n").unwrap();
+        let exclusions = Exclusions::load(path.to_str().unwrap()).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        let (queue, jobs) = mpsc::channel(capacity);
+        let server = Server {
+            queue,
+            exclusions: Arc::new(exclusions),
+            http: reqwest::Client::new(),
+            github_secret: None,
+            github_token: None,
+            bookmarklet_token: Some("secret".to_string()),
+        };
+        (server, jobs)
+    }
+
+    #[tokio::test]
+    async fn bookmarklet_refuses_unsafe_urls() {
+        let (server, _jobs) = server(1);
+        let request = |query: &'static str| bookmarklet(&server, query);
+
+        let response = request("url=https%3A%2F%2Fexample.com%2F&token=wrong").await;
+        assert_eq!(response.status, "403 Forbidden");
+        let response = request("url=file%3A%2F%2F%2Fetc%2Fpasswd&token=secret").await;
+        assert_eq!(response.status, "400 Bad Request");
+        let response = request("url=http%3A%2F%2F192.168.1.1%2F&token=secret").await;
+        assert_eq!(response.status, "403 Forbidden");
+        assert_eq!(response.body, "Not archived (internal-host)");
+        let response = request("url=https%3A%2F%2Fopted-out.example%2F&token=secret").await;
+        assert_eq!(response.body, "Not archived (opted-out)");
+        // Hostnames are resolved, in case they point at the server's network.
+        let url = |url| reqwest::Url::parse(url).unwrap();
+        assert!(resolves_internally(&url("http://localhost:8080/")).await);
+        assert!(!resolves_internally(&url("http://203.0.113.7/")).await);
+    }
+
+    #[tokio::test]
+    async fn bookmarklet_queues_urls_without_waiting_for_room() {
+        let (server, mut jobs) = server(1);
+        let query = "url=http%3A%2F%2F203.0.113.7%2Fpage&token=secret";
+
+        let worker = async {
+            let (url, reply) = jobs.recv().await.unwrap();
+            assert_eq!(url, "http://203.0.113.7/page");
+            let result = ArchivingResult::new(Outcome::NewCapture {
+                url: "https://web.archive.org/web/2021/http://203.0.113.7/page".to_string(),
+                ts: chrono::Utc::now().naive_utc(),
+            });
+            reply.unwrap().send(result.into()).unwrap();
+        };
+        let (response, ()) = tokio::join!(bookmarklet(&server, query), worker);
+        assert_eq!(response.status, "200 OK");
+        assert!(response
+            .body
+            .contains("https://web.archive.org/web/2021/http://203.0.113.7/page"));
+
+        // With the queue full, requests are refused rather than left waiting.
+        server.queue.try_send((String::new(), None)).unwrap();
+        let response = bookmarklet(&server, query).await;
+        assert_eq!(response.status, "503 Service Unavailable");
+    }
+
+    #[test]
+    fn escapes_html() {
+        assert_eq!(
+            escape_html(r#"https://example.com/?a=1&b="<x>"'"#),
+            "https://example.com/?a=1&amp;b=&quot;&lt;x&gt;&quot;&#39;"
+        );
+    }
+}
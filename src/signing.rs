@@ -0,0 +1,162 @@
+//! `wayback-archiver sign` and `verify-signature`, which produce and check detached
+//! signatures over results files, so that published archive inventories can be
+//! verified as untampered by third parties.
+//!
+//! Signatures and keys are in the minisign format, so keys can be generated with
+//! `minisign -G`, and signatures checked with `minisign -V` as well as
+//! `verify-signature`. A password-protected secret key's password is read from
+//! `MINISIGN_PASSWORD` (see `wayback_archiver::secrets`), or prompted for.
+use crate::remote;
+use clap::Clap;
+use minisign::{PublicKey, SecretKey, SignatureBox};
+use std::fs;
+use std::io::Cursor;
+use wayback_archiver::secrets::resolve_secret;
+
+#[derive(Clap)]
+pub struct SignOpts {
+    /// The results file to sign (a local path or an object store URL).
+    #[clap(short, long)]
+    input: String,
+    /// The minisign secret key to sign with.
+    #[clap(short = 's', long)]
+    secret_key: String,
+    /// Where to write the signature. Defaults to the input path followed by
+    /// `.minisig`.
+    #[clap(short, long)]
+    out: Option<String>,
+}
+
+#[derive(Clap)]
+pub struct VerifySignatureOpts {
+    /// The results file to verify (a local path or an object store URL).
+    #[clap(short, long)]
+    input: String,
+    /// The minisign public key file of the signer.
+    #[clap(short = 'p', long, required_unless_present = "public-key-base64")]
+    public_key: Option<String>,
+    /// The signer's public key itself (the base64 line of the key file),
+    /// instead of --public-key.
+    #[clap(short = 'P', long, conflicts_with = "public-key")]
+    public_key_base64: Option<String>,
+    /// The signature to check. Defaults to the input path followed by
+    /// `.minisig`.
+    #[clap(short, long)]
+    signature: Option<String>,
+}
+
+pub async fn sign(opts: &SignOpts) -> Result<(), Box<dyn std::error::Error>> {
+    let secret_key = SecretKey::from_file(&opts.secret_key, resolve_secret("MINISIGN_PASSWORD")?)
+        .map_err(|err| format!("Unable to read {}: {}", opts.secret_key, err))?;
+    let contents = read(&opts.input).await?;
+    let file_name = opts.input.rsplit('/').next().unwrap_or(&opts.input);
+    let trusted_comment = format!(
+        "timestamp:{}\tfile:{}",
+        chrono::Utc::now().timestamp(),
+        file_name
+    );
+    let signature = sign_bytes(&secret_key, &contents, &trusted_comment)?;
+    let out = opts
+        .out
+        .clone()
+        .unwrap_or_else(|| format!("{}.minisig", opts.input));
+    write(&out, signature).await?;
+    eprintln!("Signed {} ({})", opts.input, out);
+    Ok(())
+}
+
+pub async fn verify(opts: &VerifySignatureOpts) -> Result<(), Box<dyn std::error::Error>> {
+    let public_key = match (&opts.public_key, &opts.public_key_base64) {
+        (_, Some(base64)) => PublicKey::from_base64(base64)?,
+        (Some(path), None) => {
+            PublicKey::from_file(path).map_err(|err| format!("Unable to read {}: {}", path, err))?
+        }
+        (None, None) => unreachable!("clap requires a public key"),
+    };
+    let contents = read(&opts.input).await?;
+    let signature_path = opts
+        .signature
+        .clone()
+        .unwrap_or_else(|| format!("{}.minisig", opts.input));
+    let signature = String::from_utf8(read(&signature_path).await?)?;
+    let trusted_comment = verify_bytes(&public_key, &signature, &contents)
+        .map_err(|err| format!("{} is NOT validly signed: {}", opts.input, err))?;
+    eprintln!(
+        "{} is validly signed (trusted comment: {})",
+        opts.input, trusted_comment
+    );
+    Ok(())
+}
+
+/// Signs `contents`, returning the signature in the minisign format.
+pub(crate) fn sign_bytes(
+    secret_key: &SecretKey,
+    contents: &[u8],
+    trusted_comment: &str,
+) -> Result<String, minisign::PError> {
+    let public_key = PublicKey::from_secret_key(secret_key)?;
+    let signature = minisign::sign(
+        Some(&public_key),
+        secret_key,
+        contents,
+        Some(trusted_comment),
+        Some("signature from wayback-archiver"),
+    )?;
+    Ok(signature.into_string())
+}
+
+/// Checks `signature` over `contents`, returning its trusted comment if it's valid.
+pub(crate) fn verify_bytes(
+    public_key: &PublicKey,
+    signature: &str,
+    contents: &[u8],
+) -> Result<String, minisign::PError> {
+    let signature = SignatureBox::from_string(signature)?;
+    minisign::verify(
+        public_key,
+        &signature,
+        Cursor::new(contents),
+        true,
+        false,
+        false,
+    )?;
+    signature.trusted_comment()
+}
+
+async fn read(path: &str) -> Result<Vec<u8>, Box<dyn std::error::Error>> {
+    if remote::is_remote(path) {
+        return match remote::read(path).await? {
+            Some(contents) => Ok(contents.into_bytes()),
+            None => Err(format!("{} doesn't exist", path).into()),
+        };
+    }
+    fs::read(path).map_err(|err| format!("Unable to read {}: {}", path, err).into())
+}
+
+async fn write(path: &str, contents: String) -> Result<(), Box<dyn std::error::Error>> {
+    if remote::is_remote(path) {
+        return remote::write(path, contents).await;
+    }
+    Ok(fs::write(path, contents)?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use minisign::KeyPair;
+
+    #[test]
+    fn signs_and_verifies() {
+        let KeyPair { pk, sk } = KeyPair::generate_unencrypted_keypair().unwrap();
+        let contents = b"{\"https://example.com\": {}}\n";
+        let signature = sign_bytes(&sk, contents, "file:results.json").unwrap();
+        assert_eq!(
+            verify_bytes(&pk, &signature, contents).unwrap(),
+            "file:results.json"
+        );
+        assert!(verify_bytes(&pk, &signature, b"{}\n").is_err());
+
+        let other = KeyPair::generate_unencrypted_keypair().unwrap();
+        assert!(verify_bytes(&other.pk, &signature, contents).is_err());
+    }
+}
@@ -0,0 +1,30 @@
+use crate::endpoints::Endpoints;
+use crate::timestamp::format_wayback_timestamp;
+use chrono::{DateTime, Utc};
+
+/// The URL of the snapshot of `url` captured at (or, if there is none at that exact
+/// moment, closest to) `timestamp`, in the Wayback Machine at `endpoints`.
+pub fn snapshot_url(endpoints: &Endpoints, url: &str, timestamp: DateTime<Utc>) -> String {
+    format!(
+        "{}/web/{}/{}",
+        endpoints.web,
+        format_wayback_timestamp(timestamp),
+        url
+    )
+}
+
+/// Like `snapshot_url`, but for the original archived content, without the Wayback
+/// Machine's toolbar or rewritten links (the `id_` form).
+pub fn raw_snapshot_url(endpoints: &Endpoints, url: &str, timestamp: DateTime<Utc>) -> String {
+    format!(
+        "{}/web/{}id_/{}",
+        endpoints.web,
+        format_wayback_timestamp(timestamp),
+        url
+    )
+}
+
+/// A URL that always redirects to the most recent snapshot of `url` (the `/web/2/` form).
+pub fn latest_snapshot_url(endpoints: &Endpoints, url: &str) -> String {
+    format!("{}/web/2/{}", endpoints.web, url)
+}
@@ -0,0 +1,410 @@
+//! Save Page Now 2 (SPN2): the authenticated capture API, which is throttled far less
+//! than anonymous captures. Requests are authenticated with archive.org S3-style keys
+//! (see https://archive.org/account/s3.php).
+//!
+//! Captures are submitted with `POST /save`, and complete asynchronously: the response
+//! only has a job ID, whose progress is then polled at `/save/status/<job_id>`.
+use crate::client::network_error;
+use crate::metrics::{timed, Endpoint};
+use crate::snapshot_url::snapshot_url;
+use crate::timestamp::parse_wayback_timestamp;
+use crate::{
+    body_snippet, retry_after, spn_code_error, ArchiveError, ArchiveOptions, Endpoints, Snapshot,
+    WaybackClient,
+};
+use reqwest::header::{ACCEPT, AUTHORIZATION};
+use serde::Deserialize;
+use std::collections::BTreeMap;
+use std::time::Duration;
+
+/// A capture's status is first polled this long after it's submitted, then at
+/// doubling intervals of up to `MAX_POLL_INTERVAL`, until `POLL_TIMEOUT` has passed.
+const FIRST_POLL_INTERVAL: Duration = Duration::from_secs(2);
+const MAX_POLL_INTERVAL: Duration = Duration::from_secs(30);
+const POLL_TIMEOUT: Duration = Duration::from_secs(300);
+
+/// A capture submitted to SPN2.
+#[derive(Clone, Debug, PartialEq)]
+pub struct SaveJob {
+    pub job_id: String,
+    /// The URL being captured, as SPN2 understood it.
+    pub url: String,
+    /// A note from SPN2 about the capture, if any (e.g. that the URL was captured
+    /// recently).
+    pub message: Option<String>,
+}
+
+/// A completed SPN2 capture.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Capture {
+    pub snapshot: Snapshot,
+    /// The capture jobs of the page's outlinks, by URL, if they were captured too
+    /// (see `ArchiveOptions::capture_outlinks`).
+    pub outlink_jobs: BTreeMap<String, String>,
+    /// A screenshot of the page, if one was taken (see
+    /// `ArchiveOptions::capture_screenshot`).
+    pub screenshot_url: Option<String>,
+}
+
+/// The progress of an SPN2 capture.
+#[derive(Clone, Debug, PartialEq)]
+pub enum CaptureStatus {
+    /// The capture is still in progress.
+    Pending,
+    /// The capture completed.
+    Success(Capture),
+    /// The capture failed.
+    Error(ArchiveError),
+}
+
+#[derive(Deserialize)]
+struct SaveResponse {
+    job_id: Option<String>,
+    url: Option<String>,
+    message: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct StatusResponse {
+    status: String,
+    timestamp: Option<String>,
+    original_url: Option<String>,
+    message: Option<String>,
+    /// The links SPN2 found on the page: a list of URLs, or, if they were captured
+    /// too, an object of their capture job IDs by URL.
+    outlinks: Option<serde_json::Value>,
+    screenshot: Option<String>,
+}
+
+impl WaybackClient {
+    /// Submits `url` to SPN2, which requires the client to have credentials (see
+    /// `with_credentials`). The capture completes asynchronously; see `capture_status`.
+    /// Of `options`, only `capture_outlinks`, `capture_screenshot`, and
+    /// `js_behavior_timeout` apply.
+    pub async fn save_page(
+        &self,
+        url: &str,
+        options: &ArchiveOptions,
+    ) -> Result<SaveJob, ArchiveError> {
+        let credentials = self.credentials().ok_or(ArchiveError::LoginRequired)?;
+        let mut form = vec![("url", url)];
+        if options.capture_outlinks {
+            form.push(("capture_outlinks", "1"));
+        }
+        if options.capture_screenshot {
+            form.push(("capture_screenshot", "1"));
+        }
+        let js_behavior_timeout = options
+            .js_behavior_timeout
+            .map(|timeout| timeout.as_secs().to_string());
+        if let Some(timeout) = &js_behavior_timeout {
+            form.push(("js_behavior_timeout", timeout));
+        }
+        self.wait_to_save().await?;
+        let request = self
+            .http()
+            .post(self.endpoints().save.clone())
+            .header(ACCEPT, "application/json")
+            .header(
+                AUTHORIZATION,
+                format!("LOW {}:{}", credentials.access_key, credentials.secret_key),
+            )
+            .form(&form);
+        let (status, retry_after, body) =
+            timed(Endpoint::Save, self.send_and_read(request)).await?;
+        parse_save_job(status, retry_after, &body)
+    }
+
+    /// Checks on the SPN2 capture with `job_id`.
+    pub async fn capture_status(&self, job_id: &str) -> Result<CaptureStatus, ArchiveError> {
+        let mut request = self
+            .http()
+            .get(format!("{}/status/{}", self.endpoints().save, job_id))
+            .header(ACCEPT, "application/json");
+        if let Some(credentials) = self.credentials() {
+            request = request.header(
+                AUTHORIZATION,
+                format!("LOW {}:{}", credentials.access_key, credentials.secret_key),
+            );
+        }
+        let (status, retry_after, body) =
+            timed(Endpoint::SaveStatus, self.send_and_read(request)).await?;
+        parse_capture_status(&self.endpoints(), status, retry_after, &body)
+    }
+
+    /// Waits for the SPN2 capture with `job_id` to complete, polling its status with
+    /// backoff, and returns the finished capture. Each status polled (including the final
+    /// one) is passed to `on_status` as it comes in, to report progress.
+    ///
+    /// Temporary failures to poll are retried; gives up with an error if the capture
+    /// is still pending after 5 minutes.
+    pub async fn wait_for_capture<F>(
+        &self,
+        job_id: &str,
+        mut on_status: F,
+    ) -> Result<Capture, ArchiveError>
+    where
+        F: FnMut(&CaptureStatus),
+    {
+        let mut waited = Duration::ZERO;
+        let mut attempt = 0;
+        while waited < POLL_TIMEOUT {
+            let delay = poll_delay(attempt);
+            self.sleep(delay).await?;
+            waited += delay;
+            attempt += 1;
+            let status = match self.capture_status(job_id).await {
+                Ok(status) => status,
+                Err(err) if err.is_retryable() => continue,
+                Err(err) => return Err(err),
+            };
+            on_status(&status);
+            match status {
+                CaptureStatus::Pending => {}
+                CaptureStatus::Success(capture) => return Ok(capture),
+                CaptureStatus::Error(err) => return Err(err),
+            }
+        }
+        Err(ArchiveError::Unknown(format!(
+            "Capture job {} still pending after {}s",
+            job_id,
+            POLL_TIMEOUT.as_secs()
+        )))
+    }
+
+    /// Captures `url` with SPN2, waiting for the capture to complete.
+    pub(crate) async fn capture(
+        &self,
+        url: &str,
+        options: &ArchiveOptions,
+    ) -> Result<Capture, ArchiveError> {
+        let job = self.save_page(url, options).await?;
+        self.wait_for_capture(&job.job_id, |_| {}).await
+    }
+
+    /// Makes an SPN2 request, returning the response's status, `Retry-After`, and body.
+    async fn send_and_read(
+        &self,
+        request: reqwest::RequestBuilder,
+    ) -> Result<(u16, Option<Duration>, String), ArchiveError> {
+        let resp = self.send(request).await?;
+        let status = resp.status().as_u16();
+        let retry_after = retry_after(resp.headers());
+        let body = self.read_text(resp).await?.map_err(network_error)?;
+        Ok((status, retry_after, body))
+    }
+}
+
+/// How long to wait before the `attempt`th poll of a capture's status (from 0).
+fn poll_delay(attempt: u32) -> Duration {
+    FIRST_POLL_INTERVAL
+        .saturating_mul(2u32.saturating_pow(attempt))
+        .min(MAX_POLL_INTERVAL)
+}
+
+/// Interprets SPN2's response (with `status` and `retry_after`) to a capture request.
+fn parse_save_job(
+    status: u16,
+    retry_after: Option<Duration>,
+    body: &str,
+) -> Result<SaveJob, ArchiveError> {
+    if let Some(err) = spn_code_error(status, retry_after, body) {
+        return Err(err);
+    }
+    match status {
+        401 | 403 => return Err(ArchiveError::LoginRequired),
+        429 => return Err(ArchiveError::BandwidthExceeded { retry_after }),
+        500..=599 => {
+            return Err(ArchiveError::ServiceUnavailable {
+                status,
+                snippet: body_snippet(body),
+                retry_after,
+            })
+        }
+        _ => {}
+    }
+    let resp: SaveResponse =
+        serde_json::from_str(body).map_err(|err| ArchiveError::ParseError(err.to_string()))?;
+    match (resp.job_id, resp.url) {
+        (Some(job_id), Some(url)) => Ok(SaveJob {
+            job_id,
+            url,
+            message: resp.message.filter(|message| !message.is_empty()),
+        }),
+        _ => Err(match resp.message {
+            Some(message) => ArchiveError::Unknown(message),
+            None => ArchiveError::Http {
+                status,
+                snippet: body_snippet(body),
+            },
+        }),
+    }
+}
+
+/// Interprets SPN2's response (with `status` and `retry_after`) to a status request,
+/// made to the Wayback Machine at `endpoints`.
+fn parse_capture_status(
+    endpoints: &Endpoints,
+    status: u16,
+    retry_after: Option<Duration>,
+    body: &str,
+) -> Result<CaptureStatus, ArchiveError> {
+    if !(200..300).contains(&status) {
+        return Err(ArchiveError::ServiceUnavailable {
+            status,
+            snippet: body_snippet(body),
+            retry_after,
+        });
+    }
+    let resp: StatusResponse =
+        serde_json::from_str(body).map_err(|err| ArchiveError::ParseError(err.to_string()))?;
+    match resp.status.as_str() {
+        "pending" => Ok(CaptureStatus::Pending),
+        "success" => {
+            let (timestamp, url) = match (resp.timestamp, resp.original_url) {
+                (Some(timestamp), Some(url)) => (parse_wayback_timestamp(&timestamp)?, url),
+                _ => {
+                    return Err(ArchiveError::ParseError(
+                        "successful capture without a timestamp and URL".to_string(),
+                    ))
+                }
+            };
+            Ok(CaptureStatus::Success(Capture {
+                snapshot: Snapshot {
+                    url: snapshot_url(endpoints, &url, timestamp),
+                    timestamp,
+                    status: Some(200),
+                    available: true,
+                },
+                outlink_jobs: match resp.outlinks {
+                    Some(serde_json::Value::Object(jobs)) => jobs
+                        .into_iter()
+                        .filter_map(|(url, job_id)| Some((url, job_id.as_str()?.to_string())))
+                        .collect(),
+                    _ => BTreeMap::new(),
+                },
+                screenshot_url: resp.screenshot,
+            }))
+        }
+        state => {
+            let message = match resp.message {
+                Some(message) => message,
+                None => format!("Capture {}", state),
+            };
+            Ok(CaptureStatus::Error(
+                spn_code_error(status, retry_after, body).unwrap_or(ArchiveError::Unknown(message)),
+            ))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_save_responses() {
+        assert_eq!(
+            parse_save_job(
+                200,
+                None,
+                r#"{"url": "https://example.com", "job_id": "spn2-abc", "message": ""}"#
+            ),
+            Ok(SaveJob {
+                job_id: "spn2-abc".to_string(),
+                url: "https://example.com".to_string(),
+                message: None,
+            })
+        );
+        assert_eq!(
+            parse_save_job(
+                200,
+                None,
+                r#"{"status": "error", "status_ext": "error:too-many-daily-captures", "message": "..."}"#
+            ),
+            Err(ArchiveError::UnableToArchive)
+        );
+        assert_eq!(
+            parse_save_job(429, Some(Duration::from_secs(60)), "Too many requests"),
+            Err(ArchiveError::BandwidthExceeded {
+                retry_after: Some(Duration::from_secs(60))
+            })
+        );
+        assert_eq!(
+            parse_save_job(401, None, r#"{"message": "You need to be logged in"}"#),
+            Err(ArchiveError::LoginRequired)
+        );
+    }
+
+    #[test]
+    fn backs_off_polling() {
+        let delays: Vec<u64> = (0..7)
+            .map(|attempt| poll_delay(attempt).as_secs())
+            .collect();
+        assert_eq!(delays, [2, 4, 8, 16, 30, 30, 30]);
+        assert_eq!(poll_delay(u32::MAX), MAX_POLL_INTERVAL);
+    }
+
+    #[test]
+    fn parses_capture_statuses() {
+        assert_eq!(
+            parse_capture_status(
+                &Endpoints::default(),
+                200,
+                None,
+                r#"{"status": "pending", "job_id": "spn2-abc"}"#
+            ),
+            Ok(CaptureStatus::Pending)
+        );
+        match parse_capture_status(
+            &Endpoints::default(),
+            200,
+            None,
+            r#"{"status": "success", "timestamp": "20210102030405", "original_url": "https://example.com/"}"#,
+        ) {
+            Ok(CaptureStatus::Success(capture)) => {
+                assert!(capture
+                    .snapshot
+                    .url
+                    .ends_with("/web/20210102030405/https://example.com/"));
+                assert!(capture.outlink_jobs.is_empty());
+                assert_eq!(capture.screenshot_url, None);
+            }
+            other => panic!("unexpected status {:?}", other),
+        }
+        match parse_capture_status(
+            &Endpoints::default(),
+            200,
+            None,
+            r#"{"status": "success", "timestamp": "20210102030405", "original_url": "https://example.com/",
+                "outlinks": {"https://example.com/about": "spn2-def"},
+                "screenshot": "http://web.archive.org/screenshot/20210102030405/example.com"}"#,
+        ) {
+            Ok(CaptureStatus::Success(capture)) => {
+                assert_eq!(
+                    capture.outlink_jobs,
+                    BTreeMap::from([(
+                        "https://example.com/about".to_string(),
+                        "spn2-def".to_string()
+                    )])
+                );
+                assert_eq!(
+                    capture.screenshot_url.as_deref(),
+                    Some("http://web.archive.org/screenshot/20210102030405/example.com")
+                );
+            }
+            other => panic!("unexpected status {:?}", other),
+        }
+        assert_eq!(
+            parse_capture_status(
+                &Endpoints::default(),
+                200,
+                None,
+                r#"{"status": "error", "status_ext": "error:no-access", "message": "..."}"#
+            ),
+            Ok(CaptureStatus::Error(ArchiveError::OriginUnreachable(
+                "error:no-access".to_string()
+            )))
+        );
+    }
+}
@@ -0,0 +1,171 @@
+//! Input URLs read from a SQLite database (`--input-sqlite`), so the work list can come
+//! straight from an application's database, with results optionally written back to it
+//! (`--update-query`). Only available when built with the `sqlite` feature.
+//!
+//! The update query is run once per result, and may use any of these parameters:
+//! `:url`, `:outcome` (e.g. `new-capture`, or `failed`), `:snapshot_url`, `:ts` (when
+//! the snapshot was captured), and `:error`. Parameters without a value are `NULL`.
+use wayback_archiver::ArchivingResult;
+#[cfg(feature = "sqlite")]
+use wayback_archiver::Outcome;
+
+#[cfg(feature = "sqlite")]
+pub struct SqliteInput {
+    conn: rusqlite::Connection,
+    update_query: Option<String>,
+}
+
+#[cfg(feature = "sqlite")]
+impl SqliteInput {
+    pub fn open(
+        path: &str,
+        update_query: Option<String>,
+    ) -> Result<SqliteInput, Box<dyn std::error::Error>> {
+        let conn = rusqlite::Connection::open_with_flags(
+            path,
+            rusqlite::OpenFlags::SQLITE_OPEN_READ_WRITE,
+        )
+        .map_err(|err| format!("Unable to open {}: {}", path, err))?;
+        if let Some(update_query) = &update_query {
+            // Catch mistakes in the query before any archiving is done.
+            conn.prepare(update_query)
+                .map_err(|err| format!("Invalid --update-query: {}", err))?;
+        }
+        Ok(SqliteInput { conn, update_query })
+    }
+
+    /// The URLs in the first column of `query`'s rows. Rows whose first column is
+    /// `NULL` are skipped.
+    pub fn urls(&self, query: &str) -> Result<Vec<String>, Box<dyn std::error::Error>> {
+        let mut stmt = self
+            .conn
+            .prepare(query)
+            .map_err(|err| format!("Invalid --query: {}", err))?;
+        let urls = stmt
+            .query_map([], |row| row.get::<_, Option<String>>(0))?
+            .filter_map(Result::transpose)
+            .collect::<Result<_, _>>()?;
+        Ok(urls)
+    }
+
+    /// Runs the update query (if there is one) for `url`'s `result`.
+    pub fn record(
+        &self,
+        url: &str,
+        result: &ArchivingResult,
+    ) -> Result<(), Box<dyn std::error::Error>> {
+        let update_query = match &self.update_query {
+            Some(update_query) => update_query,
+            None => return Ok(()),
+        };
+        let outcome = match &result.outcome {
+            Outcome::NewCapture { .. } => "new-capture",
+            Outcome::ExistingSnapshot { .. } => "existing-snapshot",
+            Outcome::StaleFallback { .. } => "stale-fallback",
+            Outcome::Failed { .. } => "failed",
+        };
+        let ts = result
+            .outcome
+            .ts()
+            .map(|ts| ts.format("%Y-%m-%dT%H:%M:%S").to_string());
+        let params: [(&str, Option<&str>); 5] = [
+            (":url", Some(url)),
+            (":outcome", Some(outcome)),
+            (":snapshot_url", result.outcome.url()),
+            (":ts", ts.as_deref()),
+            (":error", result.outcome.error()),
+        ];
+        let mut stmt = self.conn.prepare_cached(update_query)?;
+        // Only the parameters the query uses can be bound.
+        for (name, value) in params {
+            if let Some(index) = stmt.parameter_index(name)? {
+                stmt.raw_bind_parameter(index, value)?;
+            }
+        }
+        stmt.raw_execute()?;
+        Ok(())
+    }
+}
+
+#[cfg(not(feature = "sqlite"))]
+pub struct SqliteInput;
+
+#[cfg(not(feature = "sqlite"))]
+impl SqliteInput {
+    pub fn open(
+        _path: &str,
+        _update_query: Option<String>,
+    ) -> Result<SqliteInput, Box<dyn std::error::Error>> {
+        Err("--input-sqlite requires wayback-archiver to be built with the sqlite feature".into())
+    }
+
+    pub fn urls(&self, _query: &str) -> Result<Vec<String>, Box<dyn std::error::Error>> {
+        unreachable!("SqliteInput can't be constructed without the sqlite feature")
+    }
+
+    pub fn record(
+        &self,
+        _url: &str,
+        _result: &ArchivingResult,
+    ) -> Result<(), Box<dyn std::error::Error>> {
+        unreachable!("SqliteInput can't be constructed without the sqlite feature")
+    }
+}
+
+#[cfg(all(test, feature = "sqlite"))]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn reads_urls_and_writes_results_back() {
+        let dir = std::env::temp_dir().join(format!("sqlite-input-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("app.db");
+        let path = path.to_str().unwrap();
+        let fixture = rusqlite::Connection::open(path).unwrap();
+        fixture
+            .execute_batch(
+                "CREATE TABLE pages (url TEXT, snapshot TEXT, archived_at TEXT);
+                 INSERT INTO pages (url) VALUES ('https://example.com/a'), (NULL),
+                     ('https://example.com/b');",
+            )
+            .unwrap();
+
+        assert!(SqliteInput::open(path, Some("UPDATE nowhere SET x = 1".to_string())).is_err());
+        let update =
+            "UPDATE pages SET snapshot = :snapshot_url, archived_at = :ts WHERE url = :url";
+        let input = SqliteInput::open(path, Some(update.to_string())).unwrap();
+        assert!(input.urls("SELECT nothing FROM nowhere").is_err());
+        let urls = input.urls("SELECT url FROM pages ORDER BY rowid").unwrap();
+        assert_eq!(urls, ["https://example.com/a", "https://example.com/b"]);
+
+        let captured = ArchivingResult::new(Outcome::NewCapture {
+            url: "https://web.archive.org/web/20210102030405/https://example.com/a".to_string(),
+            ts: "2021-01-02T03:04:05".parse().unwrap(),
+        });
+        input.record("https://example.com/a", &captured).unwrap();
+        let failed = ArchivingResult::new(Outcome::Failed {
+            error: "Bandwidth Exceeded".to_string(),
+        });
+        input.record("https://example.com/b", &failed).unwrap();
+        let rows: Vec<(String, Option<String>, Option<String>)> = fixture
+            .prepare("SELECT url, snapshot, archived_at FROM pages WHERE url IS NOT NULL")
+            .unwrap()
+            .query_map([], |row| Ok((row.get(0)?, row.get(1)?, row.get(2)?)))
+            .unwrap()
+            .collect::<Result<_, _>>()
+            .unwrap();
+        assert_eq!(
+            rows,
+            [
+                (
+                    "https://example.com/a".to_string(),
+                    captured.outcome.url().map(str::to_string),
+                    Some("2021-01-02T03:04:05".to_string())
+                ),
+                ("https://example.com/b".to_string(), None, None),
+            ]
+        );
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
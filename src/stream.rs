@@ -0,0 +1,328 @@
+//! `wayback-archiver stream`, which runs as a long-lived stream processor: it archives
+//! each URL consumed from a Kafka or NATS topic, and publishes its result to another.
+//! Only available when built with the `streaming` feature.
+//!
+//! Input messages are URLs, one per message. Each result is published as a JSON object
+//! with the URL as its only key, like a results file (see `--out`) with one entry, so
+//! that results can be collected into one.
+//!
+//! Kafka input is read from every partition of the topic, starting with new messages
+//! (or the earliest retained ones, with `--from-beginning`). Consumer groups aren't
+//! supported, so several archivers reading one topic each archive every URL; with
+//! NATS, use `--queue-group` to share the input instead. With `--offsets-file`, the
+//! offset of each partition is saved once the result of a message is published, and
+//! consumption resumes from there after a restart. Kafka results are keyed by URL, so
+//! that each URL's results land in the same partition, in order.
+//!
+//! URLs go through the same checks as a run's (see `prearchive`), and those skipped
+//! are published as skipped results. Temporary failures are
+//! retried per the config file's `[pacing]`. Captures are checked against the alert
+//! thresholds (see `alerts`). Errors consuming or publishing a message are reported,
+//! and the stream carries on.
+use crate::alerts::AlertOpts;
+use crate::config::Config;
+use crate::exclusions::Exclusions;
+use clap::Clap;
+use std::path::PathBuf;
+
+#[derive(Clap)]
+pub struct StreamOpts {
+    /// The NATS server to connect to (e.g. nats://localhost:4222).
+    #[clap(long, conflicts_with = "kafka", required_unless_present = "kafka")]
+    nats: Option<String>,
+    /// Comma-separated Kafka bootstrap brokers (e.g. localhost:9092).
+    #[clap(long)]
+    kafka: Option<String>,
+    /// The topic (or NATS subject) to consume URLs from.
+    #[clap(long)]
+    input_topic: String,
+    /// The topic (or NATS subject) to publish results to.
+    #[clap(long)]
+    output_topic: String,
+    /// With NATS, a queue group to subscribe in, so that several archivers
+    /// share the input rather than each archiving every URL.
+    #[clap(long, requires = "nats")]
+    queue_group: Option<String>,
+    /// With Kafka, consume the input topic from its earliest retained
+    /// message, rather than only new ones.
+    #[clap(long, requires = "kafka")]
+    from_beginning: bool,
+    /// With Kafka, a file to save the input topic's offsets in after each
+    /// result is published, to resume from after a restart.
+    #[clap(long, requires = "kafka")]
+    offsets_file: Option<PathBuf>,
+    #[clap(flatten)]
+    alerts: AlertOpts,
+}
+
+#[cfg(feature = "streaming")]
+type StreamError = Box<dyn std::error::Error>;
+
+/// A consumed message.
+#[cfg(feature = "streaming")]
+struct Message {
+    payload: Vec<u8>,
+    /// The Kafka partition and offset it was read from.
+    offset: Option<(i32, i64)>,
+}
+
+/// The next offset to consume from each partition of the input topic, as saved in
+/// `--offsets-file`.
+#[cfg(feature = "streaming")]
+#[derive(Debug, Default, PartialEq, serde::Deserialize, serde::Serialize)]
+struct Offsets(std::collections::BTreeMap<i32, i64>);
+
+#[cfg(feature = "streaming")]
+impl Offsets {
+    fn load(path: &std::path::Path) -> Result<Offsets, StreamError> {
+        match std::fs::read(path) {
+            Ok(contents) => serde_json::from_slice(&contents)
+                .map_err(|err| format!("Invalid offsets file {}: {}", path.display(), err).into()),
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => Ok(Offsets::default()),
+            Err(err) => Err(err.into()),
+        }
+    }
+
+    /// Records that the message at `offset` of `partition` was handled.
+    fn commit(&mut self, partition: i32, offset: i64) {
+        let next = self.0.entry(partition).or_default();
+        *next = (*next).max(offset + 1);
+    }
+
+    /// Writes the offsets to `path`, through a temporary file so that a crash can't
+    /// leave it half-written.
+    fn save(&self, path: &std::path::Path) -> std::io::Result<()> {
+        let temp = path.with_extension("tmp");
+        std::fs::write(&temp, serde_json::to_vec(self)?)?;
+        std::fs::rename(&temp, path)
+    }
+}
+
+/// Where results are published.
+#[cfg(feature = "streaming")]
+enum Sink {
+    Nats {
+        client: async_nats::Client,
+        subject: String,
+    },
+    /// A client for each partition of the output topic.
+    Kafka(Vec<rskafka::client::partition::PartitionClient>),
+}
+
+#[cfg(feature = "streaming")]
+impl Sink {
+    async fn publish(&self, url: &str, payload: Vec<u8>) -> Result<(), StreamError> {
+        use rskafka::client::partition::Compression;
+        use rskafka::record::Record;
+        use std::collections::hash_map::DefaultHasher;
+        use std::hash::{Hash, Hasher};
+
+        match self {
+            Sink::Nats { client, subject } => {
+                client.publish(subject.clone(), payload.into()).await?;
+                client.flush().await?;
+            }
+            Sink::Kafka(partitions) => {
+                let mut hasher = DefaultHasher::new();
+                url.hash(&mut hasher);
+                let partition = &partitions[hasher.finish() as usize % partitions.len()];
+                let record = Record {
+                    key: Some(url.as_bytes().to_vec()),
+                    value: Some(payload),
+                    headers: Default::default(),
+                    timestamp: chrono::Utc::now(),
+                };
+                partition
+                    .produce(vec![record], Compression::NoCompression)
+                    .await?;
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(feature = "streaming")]
+pub async fn run(
+    opts: &StreamOpts,
+    client: wayback_archiver::WaybackClient,
+    config: &Config,
+    exclusions: &Exclusions,
+) -> Result<(), Box<dyn std::error::Error>> {
+    use futures::stream::{self, LocalBoxStream, StreamExt};
+    use rskafka::client::consumer::{StartOffset, StreamConsumerBuilder};
+    use rskafka::client::partition::UnknownTopicHandling;
+    use rskafka::client::ClientBuilder;
+    use std::collections::BTreeMap;
+    use std::sync::Arc;
+
+    let client = client.with_retry_policy(config.pacing.retry_policy());
+    let mut offsets = match &opts.offsets_file {
+        Some(path) => Offsets::load(path)?,
+        None => Offsets::default(),
+    };
+    // Messages, from whichever system the input is consumed from.
+    let (mut messages, sink): (LocalBoxStream<Result<Message, StreamError>>, Sink) =
+        match (&opts.nats, &opts.kafka) {
+            (Some(server), _) => {
+                let nats = async_nats::connect(server.as_str()).await?;
+                let subscriber = match &opts.queue_group {
+                    Some(group) => {
+                        nats.queue_subscribe(opts.input_topic.clone(), group.clone())
+                            .await?
+                    }
+                    None => nats.subscribe(opts.input_topic.clone()).await?,
+                };
+                let messages = subscriber
+                    .map(|message| {
+                        Ok(Message {
+                            payload: message.payload.to_vec(),
+                            offset: None,
+                        })
+                    })
+                    .boxed_local();
+                let sink = Sink::Nats {
+                    client: nats,
+                    subject: opts.output_topic.clone(),
+                };
+                (messages, sink)
+            }
+            (None, Some(brokers)) => {
+                let brokers = brokers.split(',').map(str::to_string).collect();
+                let kafka = ClientBuilder::new(brokers).build().await?;
+                let topics = kafka.list_topics().await?;
+                let partitions = |name: &str| {
+                    topics
+                        .iter()
+                        .find(|topic| topic.name == name)
+                        .map(|topic| topic.partitions.clone())
+                        .filter(|partitions| !partitions.is_empty())
+                        .ok_or_else(|| format!("Kafka topic {} doesn't exist", name))
+                };
+                let start = if opts.from_beginning {
+                    StartOffset::Earliest
+                } else {
+                    StartOffset::Latest
+                };
+                let mut consumers = Vec::new();
+                for partition in partitions(&opts.input_topic)? {
+                    let client = kafka
+                        .partition_client(
+                            opts.input_topic.clone(),
+                            partition,
+                            UnknownTopicHandling::Retry,
+                        )
+                        .await?;
+                    let start = match offsets.0.get(&partition) {
+                        Some(&offset) => StartOffset::At(offset),
+                        None => start,
+                    };
+                    consumers.push(
+                        StreamConsumerBuilder::new(Arc::new(client), start)
+                            .build()
+                            .map(move |record| {
+                                let (record, _high_watermark) = record?;
+                                Ok(Message {
+                                    payload: record.record.value.unwrap_or_default(),
+                                    offset: Some((partition, record.offset)),
+                                })
+                            }),
+                    );
+                }
+                let mut producers = Vec::new();
+                for partition in partitions(&opts.output_topic)? {
+                    producers.push(
+                        kafka
+                            .partition_client(
+                                opts.output_topic.clone(),
+                                partition,
+                                UnknownTopicHandling::Retry,
+                            )
+                            .await?,
+                    );
+                }
+                (
+                    stream::select_all(consumers).boxed_local(),
+                    Sink::Kafka(producers),
+                )
+            }
+            (None, None) => unreachable!("clap requires --nats or --kafka"),
+        };
+
+    let mut monitor = crate::alerts::Monitor::new(&opts.alerts);
+    eprintln!("Archiving URLs from {} ...", opts.input_topic);
+    while let Some(message) = messages.next().await {
+        let message = match message {
+            Ok(message) => message,
+            Err(err) => {
+                eprintln!("Error consuming {}: {}", opts.input_topic, err);
+                // Don't spin on an input that keeps failing (e.g. an unreachable broker).
+                tokio::time::sleep(std::time::Duration::from_secs(1)).await;
+                continue;
+            }
+        };
+        let url = std::str::from_utf8(&message.payload).map(str::trim);
+        match url {
+            Ok("") => {}
+            Ok(url) => {
+                let started = std::time::Instant::now();
+                let result =
+                    crate::prearchive::check_and_archive(&client, config, exclusions, url).await;
+                monitor
+                    .record(started.elapsed(), result.error().is_some())
+                    .await;
+                match (result.snapshot_url(), result.error()) {
+                    (Some(snapshot_url), _) => eprintln!("{} -> {}", url, snapshot_url),
+                    (_, Some(error)) => eprintln!("{} failed: {}", url, error),
+                    _ => eprintln!("{} skipped", url),
+                }
+                let payload = serde_json::to_vec(&BTreeMap::from([(url, &result)]))?;
+                if let Err(err) = sink.publish(url, payload).await {
+                    eprintln!("Unable to publish the result of {}: {}", url, err);
+                    continue;
+                }
+            }
+            Err(_) => eprintln!("Ignoring a message that isn't UTF-8"),
+        }
+        if let (Some(path), Some((partition, offset))) = (&opts.offsets_file, message.offset) {
+            offsets.commit(partition, offset);
+            if let Err(err) = offsets.save(path) {
+                eprintln!("Unable to save offsets to {}: {}", path.display(), err);
+            }
+        }
+    }
+    Ok(())
+}
+
+#[cfg(not(feature = "streaming"))]
+pub async fn run(
+    _opts: &StreamOpts,
+    _client: wayback_archiver::WaybackClient,
+    _config: &Config,
+    _exclusions: &Exclusions,
+) -> Result<(), Box<dyn std::error::Error>> {
+    Err("stream requires wayback-archiver to be built with the streaming feature".into())
+}
+
+#[cfg(all(test, feature = "streaming"))]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn saves_and_resumes_offsets() {
+        let dir = std::env::temp_dir().join(format!("stream-offsets-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("offsets.json");
+        assert_eq!(Offsets::load(&path).unwrap(), Offsets::default());
+
+        let mut offsets = Offsets::default();
+        offsets.commit(0, 41);
+        offsets.commit(1, 7);
+        // Messages handled out of order don't move a partition's offset back.
+        offsets.commit(0, 12);
+        offsets.save(&path).unwrap();
+        let saved = Offsets::load(&path).unwrap();
+        assert_eq!(saved.0.get(&0), Some(&42));
+        assert_eq!(saved.0.get(&1), Some(&8));
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
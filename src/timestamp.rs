@@ -0,0 +1,81 @@
+//! Handling of the Wayback Machine's 14-digit `YYYYMMDDhhmmss` timestamps, as used in
+//! snapshot URLs and by the availability and CDX APIs. Timestamps are always UTC.
+use crate::ArchiveError;
+use chrono::{DateTime, NaiveDateTime, TimeZone, Utc};
+
+const WAYBACK_TIMESTAMP_FORMAT: &str = "%Y%m%d%H%M%S";
+
+/// Fills in the components missing from a partial timestamp (e.g. `2016` or `201603`),
+/// which the Wayback Machine treats as the start of that period.
+const PADDING: &str = "00000101000000";
+
+/// Parses a Wayback timestamp. Partial timestamps with fewer than 14 digits are
+/// accepted, and refer to the start of the period they name.
+pub fn parse_wayback_timestamp(ts: &str) -> Result<DateTime<Utc>, ArchiveError> {
+    if ts.len() < 4 || ts.len() > PADDING.len() || !ts.bytes().all(|b| b.is_ascii_digit()) {
+        return Err(ArchiveError::ParseError(format!(
+            "invalid wayback timestamp {:?}",
+            ts
+        )));
+    }
+    let padded = format!("{}{}", ts, &PADDING[ts.len()..]);
+    let naive = NaiveDateTime::parse_from_str(&padded, WAYBACK_TIMESTAMP_FORMAT)
+        .map_err(|err| ArchiveError::ParseError(err.to_string()))?;
+    Ok(Utc.from_utc_datetime(&naive))
+}
+
+/// Formats `ts` as a 14-digit Wayback timestamp. Sub-second precision is dropped.
+pub fn format_wayback_timestamp(ts: DateTime<Utc>) -> String {
+    ts.format(WAYBACK_TIMESTAMP_FORMAT).to_string()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn utc(rfc3339: &str) -> DateTime<Utc> {
+        rfc3339.parse().unwrap()
+    }
+
+    #[test]
+    fn round_trips_full_timestamps() {
+        for ts in &["19960101000000", "20210907123456", "20991231235959"] {
+            let parsed = parse_wayback_timestamp(ts).unwrap();
+            assert_eq!(format_wayback_timestamp(parsed), *ts);
+        }
+    }
+
+    #[test]
+    fn round_trips_datetimes() {
+        let dt = utc("2016-02-29T23:59:01Z");
+        assert_eq!(
+            parse_wayback_timestamp(&format_wayback_timestamp(dt)).unwrap(),
+            dt
+        );
+    }
+
+    #[test]
+    fn drops_subsecond_precision() {
+        let dt = utc("2020-05-04T03:02:01.999Z");
+        assert_eq!(format_wayback_timestamp(dt), "20200504030201");
+    }
+
+    #[test]
+    fn parses_partial_timestamps() {
+        assert_eq!(
+            parse_wayback_timestamp("2016").unwrap(),
+            utc("2016-01-01T00:00:00Z")
+        );
+        assert_eq!(
+            parse_wayback_timestamp("20160315").unwrap(),
+            utc("2016-03-15T00:00:00Z")
+        );
+    }
+
+    #[test]
+    fn rejects_invalid_timestamps() {
+        for ts in &["", "201", "2016-03-15", "202113010000000", "20211301000000"] {
+            assert!(parse_wayback_timestamp(ts).is_err(), "accepted {:?}", ts);
+        }
+    }
+}
@@ -0,0 +1,115 @@
+//! The [Memento TimeTravel](https://timetravel.mementoweb.org) aggregator, which
+//! searches many public web archives (the Wayback Machine, archive.today, national
+//! archives, ...) for snapshots of a URL at once, through its JSON API.
+use crate::client::network_error;
+use crate::timestamp::format_wayback_timestamp;
+use crate::{body_snippet, ArchiveError, Snapshot, WaybackClient};
+use chrono::{DateTime, Utc};
+use serde::Deserialize;
+
+const API: &str = "https://timetravel.mementoweb.org/api/json";
+
+#[derive(Deserialize)]
+struct TimeTravelResponse {
+    mementos: TimeTravelMementos,
+}
+
+#[derive(Deserialize)]
+struct TimeTravelMementos {
+    last: TimeTravelMemento,
+}
+
+#[derive(Deserialize)]
+struct TimeTravelMemento {
+    datetime: DateTime<Utc>,
+    /// The memento's URLs, in the archives that have it at that time.
+    uri: Vec<String>,
+}
+
+impl WaybackClient {
+    /// The newest snapshot of `url` in any of the public web archives TimeTravel
+    /// aggregates (including the Wayback Machine), if there is one.
+    pub async fn latest_memento_anywhere(
+        &self,
+        url: &str,
+    ) -> Result<Option<Snapshot>, ArchiveError> {
+        let request = self.http().get(format!(
+            "{}/{}/{}",
+            API,
+            format_wayback_timestamp(Utc::now()),
+            url
+        ));
+        let resp = self.send(request).await?;
+        let status = resp.status().as_u16();
+        if status == 429 || status >= 500 {
+            return Err(self.service_unavailable(resp).await);
+        }
+        let body = self.read_text(resp).await?.map_err(network_error)?;
+        parse_timetravel(status, &body)
+    }
+}
+
+/// The host of the archive holding the snapshot at `snapshot_url` (e.g. `archive.ph`).
+pub(crate) fn holding_archive(snapshot_url: &str) -> Option<String> {
+    let parsed = reqwest::Url::parse(snapshot_url).ok()?;
+    parsed.host_str().map(str::to_string)
+}
+
+/// Interprets TimeTravel's response (with `status`) to a lookup.
+fn parse_timetravel(status: u16, body: &str) -> Result<Option<Snapshot>, ArchiveError> {
+    match status {
+        200 => {}
+        // No archive has the URL.
+        404 => return Ok(None),
+        _ => {
+            return Err(ArchiveError::Http {
+                status,
+                snippet: body_snippet(body),
+            })
+        }
+    }
+    let response: TimeTravelResponse =
+        serde_json::from_str(body).map_err(|err| ArchiveError::ParseError(err.to_string()))?;
+    let TimeTravelMemento { datetime, uri } = response.mementos.last;
+    Ok(uri.into_iter().next().map(|url| Snapshot {
+        url,
+        timestamp: datetime,
+        status: None,
+        available: true,
+    }))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::TimeZone;
+
+    #[test]
+    fn parses_timetravel() {
+        let body = r#"{
+            "original_uri": "https://example.com/",
+            "mementos": {
+                "first": {"datetime": "2002-01-20T14:25:10Z",
+                          "uri": ["https://web.archive.org/web/20020120142510/http://example.com/"]},
+                "last": {"datetime": "2024-05-01T12:00:00Z",
+                         "uri": ["https://archive.ph/20240501120000/https://example.com/"]}
+            },
+            "timegate_uri": "https://timetravel.mementoweb.org/timegate/https://example.com/"
+        }"#;
+        let snapshot = parse_timetravel(200, body).unwrap().unwrap();
+        assert_eq!(
+            snapshot.url,
+            "https://archive.ph/20240501120000/https://example.com/"
+        );
+        assert_eq!(
+            snapshot.timestamp,
+            Utc.with_ymd_and_hms(2024, 5, 1, 12, 0, 0).unwrap()
+        );
+        assert_eq!(
+            holding_archive(&snapshot.url),
+            Some("archive.ph".to_string())
+        );
+        assert_eq!(parse_timetravel(404, ""), Ok(None));
+        assert!(parse_timetravel(400, "Bad request").is_err());
+    }
+}
@@ -0,0 +1,382 @@
+//! Local copies of pages, in [WARC](https://iipc.github.io/warc-specifications/specifications/warc-format/warc-1.1/)
+//! files (the format web archives themselves store captures in), for keeping your
+//! own copies alongside public archives'. They can be replayed with tools like
+//! [pywb](https://github.com/webrecorder/pywb) or [ReplayWeb.page](https://replayweb.page).
+//!
+//! Each capture is written to its own (uncompressed) WARC file: a `warcinfo` record,
+//! then a `response` record for the page and, optionally, one for each of its assets
+//! (images, scripts, and stylesheets). WARC files can also be uploaded to an Internet
+//! Archive item as they're written, to publish them.
+use crate::timestamp::format_wayback_timestamp;
+use crate::{
+    ArchiveError, ArchiveOptions, ArchiveProvider, ArchivingResult, IaItem, Outcome, Snapshot,
+    WaybackClient,
+};
+use chrono::{DateTime, Utc};
+use futures::future::{BoxFuture, FutureExt};
+use regex::Regex;
+use reqwest::header::CONTENT_TYPE;
+use std::collections::hash_map::RandomState;
+use std::fs::{self, File, OpenOptions};
+use std::hash::{BuildHasher, Hasher};
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::sync::LazyLock;
+
+/// At most this many of a page's assets are captured.
+const MAX_ASSETS: usize = 100;
+
+/// The URLs of the images, scripts, and stylesheets an HTML page loads.
+static ASSET: LazyLock<Regex> = LazyLock::new(|| {
+    Regex::new(r#"(?i)<(?:img|script|link|source)\b[^>]*?\s(?:src|href)\s*=\s*["']([^"']+)["']"#)
+        .unwrap()
+});
+
+/// A directory of local WARC files, as an `ArchiveProvider`. Its snapshots' URLs are
+/// the WARC files' `file://` URLs (or, when they're uploaded to an item, their
+/// download URLs), and results record their paths (`ArchivingResult::archive_path`).
+///
+/// Local WARCs aren't indexed, so every archival captures the page again.
+///
+/// ```no_run
+/// # async fn example() -> Result<(), wayback_archiver::ArchiveError> {
+/// use std::sync::Arc;
+/// use wayback_archiver::{LocalWarc, WaybackClient};
+///
+/// let warcs = LocalWarc::new("warcs").with_assets();
+/// let client = WaybackClient::new().with_provider(Arc::new(warcs));
+/// let result = client.archive_url("https://example.com/").await?;
+/// println!("Saved to {}", result.archive_path.unwrap());
+/// # Ok(())
+/// # }
+/// ```
+#[derive(Clone, Debug, PartialEq)]
+pub struct LocalWarc {
+    dir: PathBuf,
+    assets: bool,
+    item: Option<IaItem>,
+}
+
+impl LocalWarc {
+    /// WARC files in `dir`, which is created if it doesn't exist.
+    pub fn new(dir: impl Into<PathBuf>) -> Self {
+        LocalWarc {
+            dir: dir.into(),
+            assets: false,
+            item: None,
+        }
+    }
+
+    /// Captures pages' images, scripts, and stylesheets too.
+    pub fn with_assets(self) -> Self {
+        LocalWarc {
+            assets: true,
+            ..self
+        }
+    }
+
+    /// Uploads each WARC file to `item` (see `WaybackClient::upload_to_item`), which
+    /// requires the client to have credentials. Archiving fails if the upload does,
+    /// though the WARC file is kept.
+    pub fn with_item(self, item: IaItem) -> Self {
+        LocalWarc {
+            item: Some(item),
+            ..self
+        }
+    }
+
+    /// Writes a WARC file of `responses` (the page's first), captured at `date`,
+    /// returning its path.
+    fn write(&self, date: DateTime<Utc>, responses: &[Response]) -> io::Result<PathBuf> {
+        fs::create_dir_all(&self.dir)?;
+        let host = reqwest::Url::parse(&responses[0].url)
+            .ok()
+            .and_then(|url| url.host_str().map(str::to_string))
+            .unwrap_or_default();
+        let (path, mut file) = create_unique(&self.dir, &format_wayback_timestamp(date), &host)?;
+        let filename = path.file_name().unwrap_or_default().to_string_lossy();
+        let warcinfo_id = record_id();
+        let info = format!(
+            "software: wayback-archiver/{}\r\nformat: WARC File Format 1.1\r\n",
+            env!("CARGO_PKG_VERSION")
+        );
+        write_record(
+            &mut file,
+            &[
+                ("WARC-Type", "warcinfo"),
+                ("WARC-Record-ID", &warcinfo_id),
+                ("WARC-Date", &format_warc_date(date)),
+                ("WARC-Filename", &filename),
+                ("Content-Type", "application/warc-fields"),
+            ],
+            info.as_bytes(),
+        )?;
+        for response in responses {
+            write_record(
+                &mut file,
+                &[
+                    ("WARC-Type", "response"),
+                    ("WARC-Record-ID", &record_id()),
+                    ("WARC-Date", &format_warc_date(response.date)),
+                    ("WARC-Target-URI", &response.url),
+                    ("WARC-Warcinfo-ID", &warcinfo_id),
+                    ("Content-Type", "application/http;msgtype=response"),
+                ],
+                &response.block(),
+            )?;
+        }
+        file.sync_all()?;
+        fs::canonicalize(path)
+    }
+}
+
+/// A response, as it's recorded in a WARC file.
+struct Response {
+    url: String,
+    date: DateTime<Utc>,
+    /// The status line and headers, each ending with CRLF.
+    head: Vec<u8>,
+    content_type: Option<String>,
+    body: Vec<u8>,
+}
+
+impl Response {
+    /// The HTTP message, as a response record's block.
+    fn block(&self) -> Vec<u8> {
+        [self.head.as_slice(), b"\r\n", &self.body].concat()
+    }
+
+    fn is_html(&self) -> bool {
+        self.content_type
+            .as_ref()
+            .is_some_and(|content_type| content_type.contains("html"))
+    }
+}
+
+impl WaybackClient {
+    /// Fetches `url` for a WARC file.
+    async fn fetch_for_warc(&self, url: &str) -> Result<(u16, Response), ArchiveError> {
+        let date = Utc::now();
+        let resp = self.send(self.http().get(url)).await?;
+        let status = resp.status();
+        let mut head = format!(
+            "{:?} {} {}\r\n",
+            resp.version(),
+            status.as_u16(),
+            status.canonical_reason().unwrap_or_default()
+        )
+        .into_bytes();
+        for (name, value) in resp.headers() {
+            // The body is recorded as it was received, after the transfer encoding
+            // (e.g. chunking) was removed.
+            if name == reqwest::header::TRANSFER_ENCODING {
+                continue;
+            }
+            head.extend_from_slice(name.as_str().as_bytes());
+            head.extend_from_slice(b": ");
+            head.extend_from_slice(value.as_bytes());
+            head.extend_from_slice(b"\r\n");
+        }
+        let content_type = resp
+            .headers()
+            .get(CONTENT_TYPE)
+            .and_then(|value| value.to_str().ok())
+            .map(str::to_string);
+        let final_url = resp.url().to_string();
+        let body = self
+            .read(resp.bytes())
+            .await?
+            .map_err(crate::client::network_error)?;
+        Ok((
+            status.as_u16(),
+            Response {
+                url: final_url,
+                date,
+                head,
+                content_type,
+                body: body.to_vec(),
+            },
+        ))
+    }
+
+    /// Captures `url` (and, if `warc` says to, its assets) in a new WARC file.
+    async fn save_to_warc(
+        &self,
+        warc: &LocalWarc,
+        url: &str,
+    ) -> Result<ArchivingResult, ArchiveError> {
+        let (status, page) = self.fetch_for_warc(url).await?;
+        if status >= 400 {
+            return Err(ArchiveError::OriginUnreachable(format!("HTTP {}", status)));
+        }
+        let assets = match warc.assets && page.is_html() {
+            true => asset_urls(&page.url, &String::from_utf8_lossy(&page.body)),
+            false => Vec::new(),
+        };
+        let mut responses = vec![page];
+        for asset in assets {
+            // Unreachable assets don't spoil the page's capture.
+            if let Ok((_, response)) = self.fetch_for_warc(&asset).await {
+                responses.push(response);
+            }
+        }
+        let date = responses[0].date;
+        let path = warc
+            .write(date, &responses)
+            .map_err(|err| ArchiveError::Unknown(format!("Unable to write WARC: {}", err)))?;
+        let warc_url = match &warc.item {
+            Some(item) => self.upload_to_item(item, &path).await?,
+            None => reqwest::Url::from_file_path(&path)
+                .map_err(|_| ArchiveError::Unknown(format!("Invalid WARC path {:?}", path)))?
+                .to_string(),
+        };
+        Ok(ArchivingResult {
+            archive_path: Some(path.display().to_string()),
+            ..ArchivingResult::new(Outcome::NewCapture {
+                url: warc_url,
+                ts: date.naive_utc(),
+            })
+        })
+    }
+}
+
+impl ArchiveProvider for LocalWarc {
+    fn provider_name(&self) -> &str {
+        "warc"
+    }
+
+    fn lookup_latest<'a>(
+        &'a self,
+        _client: &'a WaybackClient,
+        _url: &'a str,
+    ) -> BoxFuture<'a, Result<Option<Snapshot>, ArchiveError>> {
+        async { Ok(None) }.boxed()
+    }
+
+    fn save<'a>(
+        &'a self,
+        client: &'a WaybackClient,
+        url: &'a str,
+        _options: &'a ArchiveOptions,
+        _previous: Option<&'a Snapshot>,
+    ) -> BoxFuture<'a, Result<ArchivingResult, ArchiveError>> {
+        client.save_to_warc(self, url).boxed()
+    }
+}
+
+/// The (absolute, deduplicated) URLs of the assets of the HTML page `html` at
+/// `page_url`.
+fn asset_urls(page_url: &str, html: &str) -> Vec<String> {
+    let base = match reqwest::Url::parse(page_url) {
+        Ok(base) => base,
+        Err(_) => return Vec::new(),
+    };
+    let mut urls: Vec<String> = Vec::new();
+    for asset in ASSET.captures_iter(html) {
+        let url = match base.join(&asset[1]) {
+            Ok(url) if url.scheme() == "http" || url.scheme() == "https" => url.to_string(),
+            _ => continue,
+        };
+        if !urls.contains(&url) {
+            urls.push(url);
+        }
+        if urls.len() == MAX_ASSETS {
+            break;
+        }
+    }
+    urls
+}
+
+/// Creates a new WARC file in `dir`, named for its `timestamp` and `host` (with a
+/// suffix if one of that name already exists).
+fn create_unique(dir: &Path, timestamp: &str, host: &str) -> io::Result<(PathBuf, File)> {
+    let mut suffix = 0;
+    loop {
+        let name = match suffix {
+            0 => format!("{}-{}.warc", timestamp, host),
+            _ => format!("{}-{}-{}.warc", timestamp, host, suffix),
+        };
+        let path = dir.join(name);
+        match OpenOptions::new().write(true).create_new(true).open(&path) {
+            Ok(file) => return Ok((path, file)),
+            Err(err) if err.kind() == io::ErrorKind::AlreadyExists => suffix += 1,
+            Err(err) => return Err(err),
+        }
+    }
+}
+
+/// Writes a WARC record with `headers` (besides the version and length) and `block`.
+fn write_record(out: &mut impl Write, headers: &[(&str, &str)], block: &[u8]) -> io::Result<()> {
+    let mut head = String::from("WARC/1.1\r\n");
+    for (name, value) in headers {
+        head.push_str(&format!("{}: {}\r\n", name, value));
+    }
+    head.push_str(&format!("Content-Length: {}\r\n\r\n", block.len()));
+    out.write_all(head.as_bytes())?;
+    out.write_all(block)?;
+    out.write_all(b"\r\n\r\n")
+}
+
+fn format_warc_date(date: DateTime<Utc>) -> String {
+    date.format("%Y-%m-%dT%H:%M:%SZ").to_string()
+}
+
+/// A new, random record ID (a version 4 UUID URN).
+fn record_id() -> String {
+    // Each `RandomState` is seeded with fresh random keys.
+    let random = || RandomState::new().build_hasher().finish();
+    let bits = (u128::from(random()) << 64) | u128::from(random());
+    let bits = (bits & !(0xf000 << 64) | (0x4000 << 64)) & !(0xc << 60) | (0x8 << 60);
+    let hex = format!("{:032x}", bits);
+    format!(
+        "<urn:uuid:{}-{}-{}-{}-{}>",
+        &hex[..8],
+        &hex[8..12],
+        &hex[12..16],
+        &hex[16..20],
+        &hex[20..]
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::TimeZone;
+
+    #[test]
+    fn writes_warc_records() {
+        let html = r#"<link rel="stylesheet" href="/style.css"><img alt="" src='logo.png'>
+<script src="https://cdn.example.net/app.js"></script><img src="data:image/png;base64,AA==">
+<a href="/about">About</a><img src="/style.css">"#;
+        assert_eq!(
+            asset_urls("https://example.com/blog/post", html),
+            vec![
+                "https://example.com/style.css",
+                "https://example.com/blog/logo.png",
+                "https://cdn.example.net/app.js",
+            ]
+        );
+
+        let id = record_id();
+        assert_eq!(id.len(), "<urn:uuid:>".len() + 36);
+        assert_eq!(&id[24..25], "4");
+        assert_ne!(id, record_id());
+
+        let mut record = Vec::new();
+        let date = Utc.with_ymd_and_hms(2024, 5, 1, 12, 0, 0).unwrap();
+        write_record(
+            &mut record,
+            &[
+                ("WARC-Type", "response"),
+                ("WARC-Date", &format_warc_date(date)),
+            ],
+            b"HTTP/1.1 200 OK\r\n\r\nhi",
+        )
+        .unwrap();
+        assert_eq!(
+            String::from_utf8(record).unwrap(),
+            "WARC/1.1\r\nWARC-Type: response\r\nWARC-Date: 2024-05-01T12:00:00Z\r\n\
+             Content-Length: 21\r\n\r\nHTTP/1.1 200 OK\r\n\r\nhi\r\n\r\n"
+        );
+    }
+}